@@ -0,0 +1,43 @@
+/* C interface to the bmpf bootstrap particle filter. */
+#ifndef BMPF_H
+#define BMPF_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define BPF_OK 0
+#define BPF_EINVAL (-1)
+#define BPF_EPANIC (-2)
+
+typedef struct bpf bpf;
+
+typedef struct bpf_estimate {
+    double best_x;
+    double best_y;
+    double best_weight;
+    double mean_x;
+    double mean_y;
+    int has_mean;
+} bpf_estimate;
+
+/* sampler is one of "fixed32", "fixed64", "logm", "naive", "optimal",
+ * "regular", "reservoir", "rounding", or an alias of one. A nonzero seed
+ * reseeds the calling thread's generator. Returns NULL on bad arguments. */
+bpf *bpf_new(const char *sampler, size_t nparticles, int sort,
+             int best_particle, size_t resample_interval, uint32_t seed);
+/* Returns BPF_EINVAL, leaving the filter as it was, unless dt is positive
+ * and every input finite. */
+int bpf_observe(bpf *bpf, double dt, double gps_x, double gps_y,
+                double imu_r, double imu_t);
+int bpf_estimate(const bpf *bpf, bpf_estimate *out);
+void bpf_free(bpf *bpf);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BMPF_H */
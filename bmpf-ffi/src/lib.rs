@@ -0,0 +1,213 @@
+//! C interface to the bootstrap particle filter. See `include/bmpf.h`.
+//!
+//! The random number generator is thread-local, so a filter should be
+//! created and driven from a single thread.
+
+use std::{
+    ffi::{CStr, c_char, c_int},
+    panic::{AssertUnwindSafe, catch_unwind},
+    ptr,
+};
+
+use bmpf_rs::{
+    estimate::EstimateMode,
+    resample::Resampler,
+    seed_thread_rng,
+    sim::SimParams,
+    types::{ACoord, BpfState, CCoord, Estimate},
+};
+
+pub const BPF_OK: c_int = 0;
+pub const BPF_EINVAL: c_int = -1;
+pub const BPF_EPANIC: c_int = -2;
+
+/// Estimate returned to C callers. The mean is always filled in;
+/// `has_mean` is zero when the filter was created with `best_particle`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BpfEstimate {
+    pub best_x: f64,
+    pub best_y: f64,
+    pub best_weight: f64,
+    pub mean_x: f64,
+    pub mean_y: f64,
+    pub has_mean: c_int,
+}
+
+impl From<Estimate> for BpfEstimate {
+    fn from(est: Estimate) -> Self {
+        Self {
+            best_x: est.best.x,
+            best_y: est.best.y,
+            best_weight: est.best_weight,
+            mean_x: est.mean.x,
+            mean_y: est.mean.y,
+            has_mean: (est.mode != EstimateMode::BestParticle) as c_int,
+        }
+    }
+}
+
+/// Opaque filter handle
+pub struct Bpf {
+    state: BpfState,
+    last: BpfEstimate,
+    t: f64,
+}
+
+/// Create a filter with `nparticles` particles resampled by `sampler`
+/// (`"fixed32"`, `"fixed64"`, `"logm"`, `"naive"`, `"optimal"`,
+/// `"regular"`, `"reservoir"` or `"rounding"`, or an alias of one). A
+/// nonzero `seed` reseeds the calling thread's generator. Returns null on
+/// bad arguments.
+///
+/// # Safety
+///
+/// `sampler` must be null or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bpf_new(
+    sampler: *const c_char,
+    nparticles: usize,
+    sort: c_int,
+    best_particle: c_int,
+    resample_interval: usize,
+    seed: u32,
+) -> *mut Bpf {
+    if sampler.is_null() || nparticles == 0 || resample_interval == 0 {
+        return ptr::null_mut();
+    }
+    // SAFETY: checked non-null above, caller guarantees termination.
+    let Ok(sampler) = unsafe { CStr::from_ptr(sampler) }.to_str() else {
+        return ptr::null_mut();
+    };
+    if Resampler::try_new(sampler, nparticles).is_none() {
+        return ptr::null_mut();
+    }
+    catch_unwind(|| {
+        if seed != 0 {
+            seed_thread_rng(seed);
+        }
+        let mut state = BpfState::new(
+            sampler,
+            sort != 0,
+            nparticles,
+            0,
+            best_particle != 0,
+            resample_interval,
+            SimParams::default(),
+        );
+        state.init_particles();
+        Box::into_raw(Box::new(Bpf {
+            state,
+            last: BpfEstimate::default(),
+            t: 0.0,
+        }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Advance the filter by `dt` seconds given a GPS fix `(gps_x, gps_y)` and
+/// an IMU reading `(imu_r, imu_t)`. Returns `BPF_EINVAL`, leaving the
+/// filter as it was, unless `dt` is positive and every input finite.
+///
+/// # Safety
+///
+/// `bpf` must be null or a live handle from [`bpf_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bpf_observe(
+    bpf: *mut Bpf,
+    dt: f64,
+    gps_x: f64,
+    gps_y: f64,
+    imu_r: f64,
+    imu_t: f64,
+) -> c_int {
+    // SAFETY: caller guarantees the handle is live and not aliased.
+    let Some(bpf) = (unsafe { bpf.as_mut() }) else {
+        return BPF_EINVAL;
+    };
+    let finite = [dt, gps_x, gps_y, imu_r, imu_t]
+        .iter()
+        .all(|v| v.is_finite());
+    if !finite || dt <= 0.0 {
+        return BPF_EINVAL;
+    }
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        bpf.t += dt;
+        let est = bpf.state.observe(
+            bpf.t,
+            dt,
+            CCoord { x: gps_x, y: gps_y },
+            ACoord { r: imu_r, t: imu_t },
+        );
+        bpf.last = est.into();
+    }));
+    match res {
+        Ok(()) => BPF_OK,
+        Err(_) => BPF_EPANIC,
+    }
+}
+
+/// Copy the estimate from the most recent [`bpf_observe`] into `out`.
+///
+/// # Safety
+///
+/// `bpf` must be null or a live handle and `out` null or writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bpf_estimate(bpf: *const Bpf, out: *mut BpfEstimate) -> c_int {
+    // SAFETY: caller guarantees both pointers are valid when non-null.
+    match unsafe { (bpf.as_ref(), out.as_mut()) } {
+        (Some(bpf), Some(out)) => {
+            *out = bpf.last;
+            BPF_OK
+        }
+        _ => BPF_EINVAL,
+    }
+}
+
+/// Destroy a filter. Null is ignored.
+///
+/// # Safety
+///
+/// `bpf` must be null or a handle from [`bpf_new`] not already freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bpf_free(bpf: *mut Bpf) {
+    if !bpf.is_null() {
+        // SAFETY: handle came from Box::into_raw in bpf_new.
+        drop(unsafe { Box::from_raw(bpf) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        unsafe {
+            assert!(bpf_new(c"bogus".as_ptr(), 10, 0, 0, 1, 1).is_null());
+            assert!(bpf_new(ptr::null(), 10, 0, 0, 1, 1).is_null());
+            let alias = bpf_new(c"multinomial".as_ptr(), 10, 0, 0, 1, 1);
+            assert!(!alias.is_null());
+            bpf_free(alias);
+
+            let bpf = bpf_new(c"naive".as_ptr(), 100, 0, 0, 1, 7);
+            assert!(!bpf.is_null());
+            for _ in 0..5 {
+                assert_eq!(bpf_observe(bpf, 0.1, 1.0, -1.0, 0.1, 0.0), BPF_OK);
+            }
+            assert_eq!(bpf_observe(bpf, 0.0, 1.0, -1.0, 0.1, 0.0), BPF_EINVAL);
+            assert_eq!(
+                bpf_observe(bpf, f64::INFINITY, 1.0, -1.0, 0.1, 0.0),
+                BPF_EINVAL
+            );
+            assert_eq!(bpf_observe(bpf, 0.1, f64::NAN, -1.0, 0.1, 0.0), BPF_EINVAL);
+            let mut est = BpfEstimate::default();
+            assert_eq!(bpf_estimate(bpf, &mut est), BPF_OK);
+            assert_eq!(est.has_mean, 1);
+            assert!(est.mean_x.is_finite() && est.mean_y.is_finite());
+            assert!(est.best_weight > 0.0);
+            bpf_free(bpf);
+            bpf_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Resampling time for each algorithm under each particle ordering.
+//!
+//! `cargo bench -p bmpf-rs --bench resample`
+
+use bmpf_rs::{
+    resample::{Ordering, Resample, Resampler, Weights},
+    seed_thread_rng,
+    types::{ParticleInfo, Particles},
+    uniform,
+};
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+
+const SAMPLERS: [&str; 8] = Resampler::NAMES;
+const ORDERS: [Ordering; 4] = [
+    Ordering::None,
+    Ordering::Shuffle,
+    Ordering::SortDescending,
+    Ordering::Heapify,
+];
+
+/// Skewed weights, roughly what a filter sees after a sharp measurement
+fn particles(m: usize) -> (Particles, Weights) {
+    seed_thread_rng(4647);
+    let data: Vec<ParticleInfo> = (0..m)
+        .map(|_| ParticleInfo {
+            weight: uniform().powi(8),
+            ..Default::default()
+        })
+        .collect();
+    let weights = Weights::sum(&data);
+    (Particles { data }, weights)
+}
+
+fn resample(c: &mut Criterion) {
+    for m in [1000, 100_000] {
+        let mut group = c.benchmark_group(format!("resample/{}", m));
+        group.throughput(Throughput::Elements(m as u64));
+        if m > 10_000 {
+            group.sample_size(10);
+        }
+        let (from, weights) = particles(m);
+        for sampler in SAMPLERS {
+            // Naive is quadratic; keep it to the small case.
+            if sampler == "naive" && m > 10_000 {
+                continue;
+            }
+            for order in ORDERS {
+                let mut resampler = Resampler::new(sampler, m);
+                let mut to = from.clone();
+                group.bench_function(BenchmarkId::new(sampler, format!("{:?}", order)), |b| {
+                    b.iter_batched_ref(
+                        || from.clone(),
+                        |particle| resampler.resample(weights, m, particle, m, &mut to, order),
+                        BatchSize::LargeInput,
+                    )
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, resample);
+criterion_main!(benches);
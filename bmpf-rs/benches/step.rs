@@ -0,0 +1,40 @@
+//! Filter step time over particle counts well past the caches, moving,
+//! weighing and averaging the particles in separate passes or in blocks.
+//!
+//! `cargo bench -p bmpf-rs --bench step`
+
+use bmpf_rs::{
+    seed_thread_rng,
+    sim::SimParams,
+    types::{ACoord, BpfState, CCoord, L2_BLOCK},
+};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+
+fn step(c: &mut Criterion) {
+    let gps = CCoord { x: 1.0, y: -2.0 };
+    let imu = ACoord { r: 1.0, t: 0.3 };
+    for n in [100_000, 4_000_000] {
+        let mut group = c.benchmark_group(format!("step/{}", n));
+        group.throughput(Throughput::Elements(n as u64));
+        group.sample_size(10);
+        for block in [None, Some(L2_BLOCK)] {
+            seed_thread_rng(4687);
+            let mut bpf = BpfState::new("regular", false, n, 0, false, 1, SimParams::default());
+            bpf.reporter = None;
+            bpf.block = block;
+            bpf.init_particles();
+            let name = block.map_or("passes".to_string(), |b| format!("blocks of {}", b));
+            let mut t = 0.0;
+            group.bench_function(BenchmarkId::from_parameter(name), |b| {
+                b.iter(|| {
+                    t += 0.1;
+                    bpf.observe(t, 0.1, gps, imu)
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, step);
+criterion_main!(benches);
@@ -1,10 +1,31 @@
-use bmpf_rs::types::BpfState;
+use bmpf_rs::{
+    GaussianSource, Stream,
+    bootstrap::BootstrapBands,
+    estimate::EstimateMode,
+    frame::Frame,
+    init::InitDistribution,
+    interval::AutoInterval,
+    io::Line,
+    map::{ObstaclePolicy, OccupancyMap},
+    memory::{MemoryFootprint, parse_bytes, physical_memory},
+    progress::{Monitor, PhaseTimes, Progress},
+    proposal::Linearized,
+    report::{BackgroundReporter, Backpressure, FileReporter, hash_file},
+    resample::{Ordering, OutputWeights, ResamplerRegistry},
+    sim::{
+        BOX_DIM, Bounds, DirnTable, GpsEllipse, GpsLikelihood, ImuMode, Integration, MAX_SPEED,
+        NDIRNS, NoiseScaling, SimParams, SpeedBounds, Topology,
+    },
+    types::{BpfState, L2_BLOCK, WeightFloor},
+};
 use clap::Parser;
 use std::{
     f64::consts::PI,
     fs::File,
-    io::{self, BufRead},
+    io::{self, BufRead, Write},
+    num::NonZeroUsize,
     path::Path,
+    sync::Arc,
 };
 
 #[derive(Parser, Debug)]
@@ -14,18 +35,27 @@ struct Args {
     #[arg(long, default_value_t = 100)]
     nparticles: usize,
 
-    /// Sampler name
-    #[arg(long)]
-    sampler: String,
+    /// Sampler name, or help to list them
+    #[arg(long, required_unless_present = "inspect")]
+    sampler: Option<String>,
+
+    /// Report statistics about the data file and exit
+    #[arg(long, default_value_t = false)]
+    inspect: bool,
 
-    /// File path
+    /// File path, needed unless listing samplers
     #[arg(long)]
-    file: String,
+    file: Option<String>,
 
     /// Sort?
     #[arg(long, default_value_t = false)]
     sort: bool,
 
+    /// Ordering before resampling: none, shuffle, sort or heapify.
+    /// Overrides the per-resampler ordering picked by --sort.
+    #[arg(long)]
+    order: Option<Ordering>,
+
     /// Report particles?
     #[arg(long, default_value_t = 1000)]
     report_particles: i32,
@@ -34,13 +64,148 @@ struct Args {
     #[arg(long, default_value_t = false)]
     best_particle: bool,
 
+    /// Position estimate to report: best, mean or map. Overrides
+    /// --best-particle
+    #[arg(long)]
+    estimate: Option<EstimateMode>,
+
+    /// Move particles with the measurement-informed linearized proposal
+    /// instead of the motion model
+    #[arg(long, default_value_t = false)]
+    linearized_proposal: bool,
+
+    /// Weight particles in log space
+    #[arg(long, default_value_t = false)]
+    log_weights: bool,
+
+    /// Raise normalized weights below WEIGHT, or below FRACTION of the
+    /// largest with rel:FRACTION, to it on every weighted step
+    #[arg(long)]
+    weight_floor: Option<WeightFloor>,
+
+    /// Master seed the filter's random number stream derives from
+    #[arg(long)]
+    seed: Option<u32>,
+
+    /// Record the run's configuration, estimates and error metrics in this
+    /// SQLite database
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    results: Option<String>,
+
+    /// Write every step and the generator positions to this file, for
+    /// replaying the run with `replay::Replayer`
+    #[arg(long)]
+    record: Option<String>,
+
+    /// Draw Gaussian noise from Box-Muller instead of the Ziggurat
+    #[arg(long, default_value_t = false)]
+    box_muller: bool,
+
+    /// Axis and heading conventions of the data, and of the printed
+    /// positions: native, ros, compass or ZERO:SENSE:UNIT[:flip-y]
+    #[arg(long, default_value = "native")]
+    frame: Frame,
+
+    /// Where particles start: uniform, gaussian:X,Y,SD or
+    /// ring:X,Y,INNER,OUTER
+    #[arg(long, default_value = "uniform", value_parser = check_init)]
+    init: String,
+
+    /// Also print the central credible interval holding this much weight
+    #[arg(long)]
+    credible_interval: Option<f64>,
+
+    /// Instead of reporting particles, write bootstrap bands about the
+    /// mean of each reported cloud to this file, resampled by --sampler
+    #[arg(long)]
+    bootstrap_bands: Option<String>,
+
+    /// Resamplings behind each bootstrap band
+    #[arg(long, default_value_t = 100)]
+    bootstrap_replicates: usize,
+
+    /// Weights of resampled particles: uniform, or carry-scaled to keep
+    /// their parents' normalized weights
+    #[arg(long, default_value = "uniform")]
+    output_weights: OutputWeights,
+
+    /// Refuse to run if the filter would take more memory than this, in
+    /// bytes with an optional K, M, G or T suffix. Without a limit, runs
+    /// larger than physical memory only warn.
+    #[arg(long, value_parser = parse_bytes)]
+    memory_limit: Option<usize>,
+
     /// Resampling interval
     #[arg(long, default_value_t = 1)]
     resample_interval: usize,
 
-    /// Fast direction
-    #[arg(long, default_value_t = 0)]
-    fast_direction: i32,
+    /// Move, weigh and sum particles into the mean this many at a time,
+    /// while each block is in cache; 0 picks a block filling 256 KiB
+    #[arg(long)]
+    block: Option<usize>,
+
+    /// Print the time per step spent propagating, weighing, estimating and
+    /// resampling to stderr at the end
+    #[arg(long, default_value_t = false)]
+    phase_times: bool,
+
+    /// Check the particles every step and stop at the first NaN, infinity,
+    /// particle outside the box or weights not summing to one
+    #[arg(long, default_value_t = false)]
+    validate: bool,
+
+    /// Print the steps run so far to stderr every second
+    #[arg(long, default_value_t = false)]
+    progress: bool,
+
+    /// Stop after this many seconds, keeping the output of the steps run
+    #[arg(long)]
+    time_limit: Option<f64>,
+
+    /// Adapt the resampling interval to how fast the effective sample
+    /// size decays, starting from --resample-interval
+    #[arg(long, default_value_t = false)]
+    auto_interval: bool,
+
+    /// Use direction lookup tables instead of exact trig
+    #[arg(long, default_value_t = false)]
+    fast_direction: bool,
+
+    /// Wrap positions around the box instead of bouncing off its walls
+    #[arg(long, default_value_t = false)]
+    toroidal: bool,
+
+    /// Half-width of the box, which must hold the whole track
+    #[arg(long, default_value_t = BOX_DIM)]
+    box_dim: f64,
+
+    #[arg(long, default_value_t = MAX_SPEED)]
+    max_speed: f64,
+
+    /// Drawn speeds beyond 0 or --max-speed: clamp, reflect or redraw
+    #[arg(long, default_value = "clamp")]
+    speed_bounds: SpeedBounds,
+
+    /// Process noise discretization: per-step or sqrt-dt:NOMINAL_DT
+    #[arg(long, default_value = "per-step")]
+    noise_scaling: NoiseScaling,
+
+    /// Position integration scheme: euler or midpoint
+    #[arg(long, default_value = "euler")]
+    integration: Integration,
+
+    /// Text occupancy map of obstacles covering the box
+    #[arg(long, value_parser = load_map)]
+    map: Option<Arc<OccupancyMap>>,
+
+    /// What happens to particles moving into obstacles: bounce or zero-weight
+    #[arg(long, default_value = "bounce")]
+    obstacle_policy: ObstaclePolicy,
+
+    /// Number of headings in the direction lookup tables
+    #[arg(long, default_value_t = NonZeroUsize::new(NDIRNS).unwrap())]
+    ndirns: NonZeroUsize,
 
     #[arg(long, default_value_t = PI / 32f64)]
     avar: f64,
@@ -51,11 +216,48 @@ struct Args {
     #[arg(long, default_value_t = 1.0f64)]
     gps_var: f64,
 
+    /// Anisotropic GPS noise as SD_X,SD_Y[,RHO], in place of --gps-var
+    #[arg(long)]
+    gps_ellipse: Option<GpsEllipse>,
+
+    /// GPS likelihood: gaussian, student-t:NU or mixture:OUTLIER
+    #[arg(long, default_value = "gaussian")]
+    gps_likelihood: GpsLikelihood,
+
+    /// GPS likelihood outside the box: hard, or soft:SCALE to decay by e
+    /// every SCALE outside
+    #[arg(long, default_value = "hard")]
+    bounds: Bounds,
+
     #[arg(long, default_value_t = 0.5f64)]
     imu_r_var: f64,
 
     #[arg(long, default_value_t = PI / 8.0f64)]
     imu_a_var: f64,
+
+    /// What the IMU readings give: full, heading or speed. The likelihood
+    /// drops the rest, which may be NaN in the data.
+    #[arg(long, default_value = "full")]
+    imu_mode: ImuMode,
+
+    /// Speed noise about commanded speeds, for data with control columns
+    #[arg(long, default_value_t = 0.05f64)]
+    control_r_var: f64,
+
+    /// Heading noise about commanded turns
+    #[arg(long, default_value_t = PI / 64f64)]
+    control_a_var: f64,
+}
+
+fn load_map(path: &str) -> Result<Arc<OccupancyMap>, String> {
+    OccupancyMap::load(path)
+        .map(Arc::new)
+        .map_err(|e| format!("could not load map {}: {}", path, e))
+}
+
+/// Parsed again in `run`, since `InitDistribution` cannot be cloned
+fn check_init(s: &str) -> Result<String, String> {
+    s.parse::<InitDistribution>().map(|_| s.to_string())
 }
 
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
@@ -67,43 +269,301 @@ where
 }
 
 fn main() {
-    let args = Args::parse();
+    if let Err(e) = run(Args::parse()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let registry = ResamplerRegistry::default();
+    let sampler = match args.sampler.as_deref() {
+        Some("help") => {
+            print!("{}", registry);
+            return Ok(());
+        }
+        Some(name) => match registry.get(name) {
+            Some(info) => info.name,
+            None => {
+                return Err(format!(
+                    "Unknown sampler {}; --sampler help lists them",
+                    name
+                ));
+            }
+        },
+        None => "",
+    };
+    let Some(file) = args.file.clone() else {
+        return Err("--file is required".to_string());
+    };
+    if let Some(seed) = args.seed {
+        bmpf_rs::seed_thread_rng(Stream::Filter.seed(seed));
+    }
+    if args.box_muller {
+        bmpf_rs::set_gaussian_source(GaussianSource::BoxMuller);
+    }
+    let params = SimParams {
+        topology: if args.toroidal {
+            Topology::Toroidal
+        } else {
+            Topology::Bounded
+        },
+        box_dim: args.box_dim,
+        max_speed: args.max_speed,
+        speed_bounds: args.speed_bounds,
+        map: args.map,
+        obstacle_policy: args.obstacle_policy,
+        avar: args.avar,
+        rvar: args.rvar,
+        gps_var: args.gps_var,
+        gps_ellipse: args.gps_ellipse,
+        gps_likelihood: args.gps_likelihood,
+        bounds: args.bounds,
+        imu_r_var: args.imu_r_var,
+        imu_a_var: args.imu_a_var,
+        imu_mode: args.imu_mode,
+        control_r_var: args.control_r_var,
+        control_a_var: args.control_a_var,
+        noise_scaling: args.noise_scaling,
+        integration: args.integration,
+        fast_direction: args.fast_direction,
+        dirn: DirnTable::new(args.ndirns.get()),
+    };
+    if args.inspect {
+        let ins = bmpf_rs::io::inspect(&file, &params)
+            .map_err(|e| format!("Could not inspect {}: {}", file, e))?;
+        print!("{}", ins);
+        return Ok(());
+    }
+
+    if let Some(footprint) = MemoryFootprint::estimate(sampler, args.nparticles) {
+        match args.memory_limit {
+            Some(limit) => footprint
+                .check(limit)
+                .map_err(|e| format!("Refusing to run: {}", e))?,
+            None => {
+                if let Some(physical) = physical_memory()
+                    && let Err(e) = footprint.check(physical)
+                {
+                    eprintln!("Warning: {} of physical memory", e);
+                }
+            }
+        }
+    }
 
     let mut state = BpfState::new(
-        &args.sampler,
+        sampler,
         args.sort,
         args.nparticles,
         args.report_particles,
         args.best_particle,
         args.resample_interval,
+        params,
     );
 
-    state.init_particles();
+    if let Some(order) = args.order {
+        state.order = order;
+    }
+    if let Some(mode) = args.estimate {
+        state.estimate_mode = mode;
+    }
+    if args.linearized_proposal {
+        state.proposal = Some(Box::new(Linearized));
+    }
+    if args.auto_interval {
+        state.auto_interval = Some(AutoInterval::default());
+    }
+    state.block = args.block.map(|b| if b == 0 { L2_BLOCK } else { b });
+    if args.phase_times {
+        state.phase_times = Some(PhaseTimes::default());
+    }
+    state.validate = args.validate;
+    state.set_output_weights(args.output_weights);
+    state.log_weights = args.log_weights;
+    state.weight_floor = args.weight_floor;
+    state.credible_level = args.credible_interval;
+    state.frame = args.frame;
+    state.reporter = Some(match &args.bootstrap_bands {
+        Some(path) => {
+            let out = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+            let bands = BootstrapBands::new(
+                sampler,
+                args.bootstrap_replicates,
+                args.credible_interval.unwrap_or(0.9),
+                io::BufWriter::new(out),
+            );
+            Box::new(BackgroundReporter::new(bands, 4, Backpressure::Block))
+        }
+        None => Box::new(BackgroundReporter::new(
+            FileReporter::default(),
+            4,
+            Backpressure::Block,
+        )),
+    });
+    let mut header = state.run_header();
+    header.seed = args.seed;
+    header.input = hash_file(&file).ok().map(|h| (file.clone(), h));
+    print!("{}", header);
+    #[cfg(feature = "sqlite")]
+    let mut db = args
+        .results
+        .as_ref()
+        .map(|path| bmpf_rs::results::ResultsDb::open(path).map_err(|e| format!("{}: {}", path, e)))
+        .transpose()?;
+    #[cfg(feature = "sqlite")]
+    let mut run = db
+        .as_mut()
+        .map(|db| db.begin_run(&header))
+        .transpose()
+        .map_err(|e| format!("Could not record the run: {}", e))?;
+    let mut init: InitDistribution = args.init.parse()?;
+    let stuck = state.init_particles_with(&mut init);
+    if stuck > 0 {
+        eprintln!(
+            "{} of {} particles start outside the box or inside obstacles",
+            stuck, args.nparticles
+        );
+    }
+    if args.record.is_some() {
+        state.start_recording();
+    }
+    let mut monitor = Monitor::default();
+    if args.progress {
+        monitor.callback = Some(Box::new(|p: Progress| {
+            eprintln!("{} steps in {:.1}s", p.done, p.elapsed.as_secs_f64())
+        }));
+    }
+    if let Some(secs) = args.time_limit {
+        let cancel = monitor.cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs_f64(secs));
+            cancel.cancel();
+        });
+    }
+    let mut steps = 0;
+    let mut clamped = 0;
+    let mut held = 0;
     let mut t_ms;
     let mut t_last = 0;
     let mut t = 0.0;
-    if let Ok(mut lines) = read_lines(args.file) {
-        // Read first line to initialize
-        if let Some(Ok(first_line)) = lines.next() {
-            t_ms = state.parse_line(first_line);
-            t = t_ms as f64 * (1.0 / 1000.0);
+    let bad = |line: &str, e: io::Error| format!("Bad data line {:?}: {}", line, e);
+    let mut lines = read_lines(&file)
+        .map_err(|e| format!("Could not read {}: {}", file, e))?
+        .map(|line| line.map_err(|e| format!("Could not read {}: {}", file, e)));
+    // Read first line to initialize
+    if let Some(first_line) = lines.next() {
+        let first_line = first_line?;
+        let parsed = Line::parse(&first_line).map_err(|e| bad(&first_line, e))?;
+        t_ms = parsed.t_ms();
+        t = t_ms as f64 * (1.0 / 1000.0);
+        match parsed {
+            Line::Fused(_) => {
+                state
+                    .parse_line(&first_line)
+                    .map_err(|e| bad(&first_line, e))?;
+            }
+            Line::Tagged(tagged) => {
+                state.observe_reading(t, 0.0, tagged.reading);
+            }
         }
+    }
 
-        for line in lines.map_while(Result::ok) {
-            t_ms = state.parse_line(line);
-            let t0 = t_ms as f64 * (1.0 / 1000.0);
-            let dt = t0 - t;
-            let mut report = false;
-            if state.report_particles > 0 {
-                report = t_ms - t_last >= state.report_particles;
+    for line in lines {
+        let line = line?;
+        let parsed = Line::parse(&line).map_err(|e| bad(&line, e))?;
+        t_ms = parsed.t_ms();
+        let t0 = t_ms as f64 * (1.0 / 1000.0);
+        let dt = t0 - t;
+        let mut report = false;
+        if state.report_particles > 0 {
+            report = t_ms - t_last >= state.report_particles;
+        }
+        // Tagged files step once per reading and never report particles.
+        let est = match parsed {
+            Line::Fused(_) => {
+                state.parse_line(&line).map_err(|e| bad(&line, e))?;
+                state.bpf_step(t0, dt, report)
             }
-            t = t0;
-            print!("{} {}", state.vehicle.x, state.vehicle.y);
-            state.bpf_step(t, dt, report);
-            if report {
-                t_last = t_ms;
+            Line::Tagged(tagged) => {
+                report = false;
+                match state.observe_reading(t0, dt, tagged.reading) {
+                    Some(est) => est,
+                    None => continue,
+                }
             }
-            println!();
+        };
+        state
+            .validation()
+            .map_err(|e| format!("Invalid particles {}", e))?;
+        t = t0;
+        clamped += est.clamped;
+        held += est.held;
+        let est = state.frame.estimate_out(&est);
+        let vehicle = state.frame.position_out(state.vehicle);
+        #[cfg(feature = "sqlite")]
+        if let Some(run) = &mut run {
+            run.step(t0, vehicle, &est)
+                .map_err(|e| format!("Could not record step {}: {}", t0, e))?;
+        }
+        print!("{} {}", vehicle.x, vehicle.y);
+        #[cfg(feature = "diagnostic-print")]
+        print!(
+            "  {} {} {}  {} {} {}",
+            est.best_weight, est.best.x, est.best.y, est.worst_weight, est.worst.x, est.worst.y
+        );
+        #[cfg(not(feature = "diagnostic-print"))]
+        print!("  {} {}", est.best.x, est.best.y);
+        // The C layout has a third column unless tracking the best particle.
+        if est.mode != EstimateMode::BestParticle {
+            let posn = est.position();
+            print!("  {} {}", posn.x, posn.y);
+        }
+        if let Some((lo, hi)) = est.interval {
+            print!("  {} {} {} {}", lo.x, lo.y, hi.x, hi.y);
+        }
+        if report {
+            t_last = t_ms;
+        }
+        println!();
+        steps += 1;
+        if !monitor.step(steps, None) {
+            eprintln!("Stopped after {} steps", steps);
+            break;
         }
     }
+    if state.weight_floor.is_some() {
+        eprintln!("{} particle weights raised to the floor", clamped);
+    }
+    if held > 0 {
+        eprintln!("{} particle moves held, unable to bounce clear", held);
+    }
+    if let Some(times) = state.phase_times {
+        let [propagate, weight, estimate, resample] = times.per_step();
+        eprintln!(
+            "per step over {} steps: propagate {:?} weight {:?} estimate {:?} resample {:?}",
+            times.steps, propagate, weight, estimate, resample
+        );
+    }
+    if let Some(reporter) = &mut state.reporter {
+        reporter
+            .flush()
+            .map_err(|e| format!("Could not write particle reports: {}", e))?;
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(run) = run {
+        run.finish()
+            .map_err(|e| format!("Could not record the run: {}", e))?;
+    }
+    if let Some(path) = &args.record
+        && let Some(recording) = state.stop_recording()
+    {
+        File::create(path)
+            .and_then(|f| {
+                let mut w = io::BufWriter::new(f);
+                recording.write(&mut w)?;
+                w.flush()
+            })
+            .map_err(|e| format!("Could not write recording {}: {}", path, e))?;
+    }
+    Ok(())
 }
@@ -0,0 +1,142 @@
+//! Goodness of fit of one of the generator's distributions: chi-square
+//! over a histogram and Kolmogorov-Smirnov over the raw draws, each with
+//! its p-value, and a verdict.
+
+use bmpf_rs::{seed_thread_rng, with_rng};
+use clap::Parser;
+use std::{process::exit, str::FromStr};
+use ziggurat_rs::{
+    histogram::{Histogram, ks_statistic, pooled_chi_square},
+    math::{chi_square_sf, exp_cdf, gamma_p, ks_sf, norm_cdf},
+};
+
+#[derive(Clone, Copy, Debug)]
+enum Dist {
+    Uniform,
+    Normal,
+    Exp,
+    Poly(i32),
+    Gamma(f64),
+}
+
+impl FromStr for Dist {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let parse = |what: &str| -> Result<&str, String> {
+            arg.ok_or_else(|| format!("{} needs {}, as {}:{}", name, what, name, what))
+        };
+        match name {
+            "uniform" => Ok(Dist::Uniform),
+            "normal" => Ok(Dist::Normal),
+            "exp" => Ok(Dist::Exp),
+            "poly" => parse("N")?
+                .parse()
+                .map(Dist::Poly)
+                .map_err(|e| format!("poly:N: {}", e)),
+            "gamma" => match parse("k")?.parse() {
+                Ok(k) if k > 0.0 => Ok(Dist::Gamma(k)),
+                Ok(_) => Err("gamma:k needs k > 0".to_string()),
+                Err(e) => Err(format!("gamma:k: {}", e)),
+            },
+            _ => Err(format!(
+                "unknown distribution {}; expected uniform, normal, exp, poly:N or gamma:k",
+                s
+            )),
+        }
+    }
+}
+
+impl Dist {
+    fn sample(self) -> f64 {
+        with_rng(|z| match self {
+            Dist::Uniform => z.uniform(),
+            Dist::Normal => z.normal(),
+            Dist::Exp => z.exponential(),
+            Dist::Poly(n) => z.polynomial(n),
+            Dist::Gamma(k) => z.gamma(k),
+        })
+    }
+
+    fn cdf(self, x: f64) -> f64 {
+        match self {
+            Dist::Uniform => x.clamp(0.0, 1.0),
+            Dist::Normal => norm_cdf(x),
+            Dist::Exp => exp_cdf(x),
+            Dist::Poly(n) => 1.0 - (1.0 - x.clamp(0.0, 1.0)).powi(n + 1),
+            Dist::Gamma(k) => gamma_p(k, x),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Distribution to test: uniform, normal, exp, poly:N or gamma:k
+    #[arg(long)]
+    dist: Dist,
+
+    /// Number of draws
+    #[arg(long, default_value_t = 1_000_000)]
+    n: usize,
+
+    /// Histogram bins for the chi-square test, before pooling sparse ones
+    #[arg(long, default_value_t = 100)]
+    bins: usize,
+
+    /// Fail when either p-value falls below this
+    #[arg(long, default_value_t = 0.001)]
+    alpha: f64,
+
+    /// Seed for the random number generator
+    #[arg(long)]
+    seed: Option<u32>,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Some(seed) = args.seed {
+        seed_thread_rng(seed);
+    }
+    let dist = args.dist;
+    let draws: Vec<f64> = (0..args.n).map(|_| dist.sample()).collect();
+
+    // Bins over the range drawn, with the mass beyond it in the outflow
+    // bins at either end.
+    let lo = draws.iter().cloned().fold(f64::INFINITY, f64::min);
+    let hi = draws.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let mut hist = Histogram::new(lo, hi, args.bins);
+    for &x in &draws {
+        hist.add(x);
+    }
+    let n = args.n as f64;
+    let mut observed = vec![hist.underflow()];
+    let mut expected = vec![n * dist.cdf(lo)];
+    observed.extend((0..args.bins).map(|i| hist.count(i)));
+    expected.extend(hist.expected_counts(n, |x| dist.cdf(x)));
+    observed.push(hist.overflow());
+    expected.push(n * (1.0 - dist.cdf(hi)));
+    let (chi2, bins) = pooled_chi_square(&observed, &expected, 5.0);
+    let chi2_p = chi_square_sf(chi2, (bins - 1) as f64);
+
+    let d = ks_statistic(&draws, |x| dist.cdf(x));
+    let ks_p = ks_sf(d, args.n);
+
+    println!("{:?}, {} draws", dist, args.n);
+    println!(
+        "chi-square {:.2} on {} degrees of freedom, p = {:.4}",
+        chi2,
+        bins - 1,
+        chi2_p
+    );
+    println!("Kolmogorov-Smirnov D {:.6}, p = {:.4}", d, ks_p);
+    if chi2_p < args.alpha || ks_p < args.alpha {
+        println!("FAIL at alpha {}", args.alpha);
+        exit(1);
+    }
+    println!("pass at alpha {}", args.alpha);
+}
@@ -0,0 +1,67 @@
+use bmpf_rs::io::{
+    geo::{Fix, LocalPlane},
+    gpx, nmea,
+};
+use clap::Parser;
+use std::{
+    fs::File,
+    io::{self, BufReader},
+};
+
+/// Convert a real GPS log to the tagged data format on stdout
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// NMEA or GPX log, told apart by a .gpx extension
+    file: String,
+
+    /// Origin of the plane as LAT,LON or LAT,LON,METRES_PER_UNIT, the
+    /// first fix by default
+    #[arg(long)]
+    origin: Option<LocalPlane>,
+
+    /// Metres per filter unit when the origin is the first fix
+    #[arg(long, default_value_t = 1.0f64)]
+    metres_per_unit: f64,
+}
+
+fn read(path: &str) -> io::Result<Vec<Fix>> {
+    let file = BufReader::new(File::open(path)?);
+    if path.to_ascii_lowercase().ends_with(".gpx") {
+        gpx::read(file)
+    } else {
+        nmea::read(file)
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let fixes = read(&args.file).unwrap_or_else(|e| panic!("Could not read {}: {}", args.file, e));
+    let Some(plane) = args.origin.or_else(|| {
+        LocalPlane::at_first(&fixes).map(|plane| LocalPlane {
+            metres_per_unit: args.metres_per_unit,
+            ..plane
+        })
+    }) else {
+        eprintln!("{}: no fixes", args.file);
+        return;
+    };
+    let readings = plane
+        .readings(&fixes)
+        .unwrap_or_else(|e| panic!("Could not convert {}: {}", args.file, e));
+    let mut extent = 0f64;
+    for tagged in &readings {
+        if let bmpf_rs::io::Reading::Gps(c) = tagged.reading {
+            extent = extent.max(c.x.abs()).max(c.y.abs());
+        }
+        println!("{}", tagged);
+    }
+    // The filter's box has to hold the track.
+    eprintln!(
+        "{} fixes about {},{}; run bpf with --box-dim {}",
+        fixes.len(),
+        plane.lat,
+        plane.lon,
+        extent.ceil() + 1.0
+    );
+}
@@ -0,0 +1,64 @@
+use bmpf_rs::{interval::sweep, resample::Resampler, seed_thread_rng, sim::SimParams};
+use clap::Parser;
+
+/// Run every resampler along one simulated track at each fixed resampling
+/// interval, and with the interval adapted to the effective sample size,
+/// and tabulate how each does.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Number of particles
+    #[arg(long, default_value_t = 1000)]
+    nparticles: usize,
+
+    /// Steps along the track
+    #[arg(long, default_value_t = 2000)]
+    steps: usize,
+
+    /// Seconds between steps
+    #[arg(long, default_value_t = 0.01)]
+    dt: f64,
+
+    /// Fixed intervals to try, from 1 up to this
+    #[arg(long, default_value_t = 16)]
+    max_interval: usize,
+
+    /// Resampler to sweep, or all of them
+    #[arg(long)]
+    sampler: Option<String>,
+
+    #[arg(long, default_value_t = 1)]
+    seed: u32,
+}
+
+fn main() {
+    let args = Args::parse();
+    let intervals: Vec<usize> = (1..=args.max_interval).collect();
+    let names = match &args.sampler {
+        Some(name) => vec![name.as_str()],
+        None => Resampler::NAMES.to_vec(),
+    };
+    println!(
+        "{:<10} {:>8} {:>10} {:>10} {:>10}",
+        "resampler", "interval", "rms", "resamples", "mean ess"
+    );
+    for name in names {
+        // The same track for every resampler
+        seed_thread_rng(args.seed);
+        let params = SimParams::default();
+        for r in sweep(
+            name,
+            args.nparticles,
+            &params,
+            args.steps,
+            args.dt,
+            &intervals,
+        ) {
+            let interval = r.interval.map_or("auto".to_string(), |i| i.to_string());
+            println!(
+                "{:<10} {:>8} {:>10.4} {:>10} {:>10.4}",
+                name, interval, r.rms, r.resamples, r.mean_ess
+            );
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use bmpf_rs::{polynomial, uniform};
+use bmpf_rs::{polynomial, uniform, with_rng};
 use clap::Parser;
+use ziggurat_rs::order_stats::min_of_n;
 
 static NBINS: f64 = 500f64;
 static N: i32 = 50;
@@ -12,17 +13,6 @@ struct Args {
     iterations: usize,
 }
 
-fn minrand(n: usize) -> f64 {
-    let mut x = uniform();
-    for _i in 0..n {
-        let y = uniform();
-        if y < x {
-            x = y;
-        }
-    }
-    x
-}
-
 fn main() {
     let mut real = [0i32; NBINS as usize];
     let mut sim = [0i32; NBINS as usize];
@@ -31,7 +21,7 @@ fn main() {
     let args = Args::parse();
     let n = args.iterations;
     for _i in 0..n {
-        let mut x0 = minrand(N as usize);
+        let mut x0 = with_rng(|z| min_of_n(z, N as usize + 1));
         real[(NBINS * x0).floor() as usize] += 1;
         x0 = 1.0f64 - ((uniform()).powf(1.0 / (N as f64 + 1.0f64)));
         sim[(NBINS * x0).floor() as usize] += 1;
@@ -0,0 +1,74 @@
+use bmpf_rs::{
+    replicates::{Config, replicate},
+    resample::Resampler,
+    sim::SimParams,
+};
+use clap::Parser;
+
+/// Run each resampler over many independently seeded tracks, in parallel,
+/// and tabulate the mean RMS error with its 95% confidence interval.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Number of particles
+    #[arg(long, default_value_t = 1000)]
+    nparticles: usize,
+
+    /// Steps along each track
+    #[arg(long, default_value_t = 2000)]
+    steps: usize,
+
+    /// Seconds between steps
+    #[arg(long, default_value_t = 0.01)]
+    dt: f64,
+
+    /// Independent replicates per resampler
+    #[arg(long, default_value_t = 20)]
+    replicates: u32,
+
+    /// Seed of the first replicate; the rest follow on
+    #[arg(long, default_value_t = 1)]
+    seed: u32,
+
+    /// Threads to run on, or one per core with 0
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// Resampler to run, or all of them
+    #[arg(long)]
+    sampler: Option<String>,
+
+    /// Steps between resamplings
+    #[arg(long, default_value_t = 1)]
+    resample_interval: usize,
+}
+
+fn main() {
+    let args = Args::parse();
+    let names = match &args.sampler {
+        Some(name) => vec![name.as_str()],
+        None => Resampler::NAMES.to_vec(),
+    };
+    let seeds: Vec<u32> = (0..args.replicates)
+        .map(|i| args.seed.wrapping_add(i))
+        .collect();
+    println!(
+        "{:<10} {:>10} {:>10} {:>10} {:>4}",
+        "resampler", "mean rms", "± 95%", "sd", "n"
+    );
+    for name in names {
+        let config = Config {
+            resampler: name.to_string(),
+            nparticles: args.nparticles,
+            resample_interval: args.resample_interval,
+            params: SimParams::default(),
+            steps: args.steps,
+            dt: args.dt,
+        };
+        let s = replicate(&config, &seeds, args.threads).summary;
+        println!(
+            "{:<10} {:>10.4} {:>10.4} {:>10.4} {:>4}",
+            name, s.mean, s.half_width, s.sd, s.n
+        );
+    }
+}
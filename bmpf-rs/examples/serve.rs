@@ -0,0 +1,109 @@
+#[cfg(feature = "mqtt")]
+use bmpf_rs::mqtt::MqttPublisher;
+use bmpf_rs::{
+    net::{Encoding, NetPublisher, Receiver},
+    report::{Publisher, WriterPublisher},
+    sim::SimParams,
+    types::BpfState,
+};
+use clap::Parser;
+use std::io;
+
+/// Run the filter as a service, taking records and publishing estimates
+/// over the network
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Address to take records on
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    listen: String,
+
+    /// Publish estimates to this address instead of stdout
+    #[arg(long)]
+    publish: Option<String>,
+
+    /// Publish estimates to the MQTT broker at this address
+    #[cfg(feature = "mqtt")]
+    #[arg(long, conflicts_with = "publish")]
+    mqtt: Option<String>,
+
+    /// MQTT topic to publish on
+    #[cfg(feature = "mqtt")]
+    #[arg(long, default_value = "bmpf/estimate")]
+    topic: String,
+
+    /// Use TCP instead of UDP, both ways
+    #[arg(long, default_value_t = false)]
+    tcp: bool,
+
+    /// Message encoding: binary or json
+    #[arg(long, default_value = "binary")]
+    encoding: Encoding,
+
+    #[arg(long, default_value_t = 100)]
+    nparticles: usize,
+
+    #[arg(long, default_value = "regular")]
+    sampler: String,
+}
+
+fn publisher(args: &Args) -> io::Result<Box<dyn Publisher>> {
+    #[cfg(feature = "mqtt")]
+    if let Some(broker) = &args.mqtt {
+        let id = format!("bmpf-{}", std::process::id());
+        return Ok(Box::new(MqttPublisher::new(
+            broker,
+            &id,
+            &args.topic,
+            args.encoding,
+        )?));
+    }
+    Ok(match &args.publish {
+        Some(peer) if args.tcp => Box::new(NetPublisher::tcp(peer, args.encoding)?),
+        Some(peer) => Box::new(NetPublisher::udp(peer, args.encoding)?),
+        None => Box::new(WriterPublisher::stdout()),
+    })
+}
+
+fn main() {
+    let args = Args::parse();
+    let receiver = if args.tcp {
+        Receiver::tcp(&args.listen, args.encoding)
+    } else {
+        Receiver::udp(&args.listen, args.encoding)
+    };
+    let mut receiver =
+        receiver.unwrap_or_else(|e| panic!("Could not listen on {}: {}", args.listen, e));
+    let mut publisher = publisher(&args).unwrap_or_else(|e| panic!("Could not publish: {}", e));
+
+    let mut state = BpfState::new(
+        &args.sampler,
+        false,
+        args.nparticles,
+        0,
+        false,
+        1,
+        SimParams::default(),
+    );
+    state.reporter = None;
+    state.init_particles();
+    let mut last_t_ms = None;
+    loop {
+        let rec = receiver
+            .recv()
+            .unwrap_or_else(|e| panic!("Receive failed: {}", e));
+        state.load_record(rec);
+        let Some(last) = last_t_ms.replace(rec.t_ms) else {
+            continue;
+        };
+        let dt = (rec.t_ms - last) as f64 / 1000.0;
+        let est = state.bpf_step(rec.t_ms as f64 / 1000.0, dt, false);
+        let est = state.frame.estimate_out(&est);
+        if let Err(e) = publisher
+            .publish(rec.t_ms, &est)
+            .and_then(|_| publisher.flush())
+        {
+            eprintln!("Dropped the estimate at {} ms: {}", rec.t_ms, e);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use bmpf_rs::{
+    gaussian,
+    resample::{Ordering, Resampler, variance::resampler_variance},
+    seed_thread_rng,
+    types::ParticleInfo,
+    uniform,
+};
+use clap::Parser;
+
+/// Resample one frozen weight vector many times with every resampler and
+/// tabulate the noise each adds.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Number of particles in and out
+    #[arg(long, default_value_t = 1000)]
+    nparticles: usize,
+
+    /// Resamplings per resampler
+    #[arg(long, default_value_t = 1000)]
+    runs: usize,
+
+    /// Deviation of the log weights; larger is more degenerate
+    #[arg(long, default_value_t = 1.0)]
+    spread: f64,
+
+    /// Ordering before resampling: none, shuffle, sort or heapify
+    #[arg(long, default_value = "none")]
+    order: Ordering,
+
+    #[arg(long, default_value_t = 1)]
+    seed: u32,
+}
+
+fn main() {
+    let args = Args::parse();
+    seed_thread_rng(args.seed);
+    let particles: Vec<ParticleInfo> = (0..args.nparticles)
+        .map(|_| {
+            let mut p = ParticleInfo {
+                weight: gaussian(args.spread).exp(),
+                ..Default::default()
+            };
+            p.state.posn.x = uniform() * 2.0 - 1.0;
+            p.state.posn.y = uniform() * 2.0 - 1.0;
+            p
+        })
+        .collect();
+    println!(
+        "{:<10} {:>14} {:>14} {:>14} {:>12}",
+        "resampler", "offspring var", "multinomial", "estimate var", "bias"
+    );
+    for name in Resampler::NAMES {
+        let r = resampler_variance(name, &particles, args.nparticles, args.runs, args.order);
+        println!(
+            "{:<10} {:>14.4} {:>14.4} {:>14.4e} {:>12.4e}",
+            r.resampler, r.offspring, r.multinomial, r.estimate, r.bias
+        );
+    }
+}
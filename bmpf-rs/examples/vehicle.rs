@@ -1,25 +1,202 @@
-use bmpf_rs::types::VehicleState;
+use bmpf_rs::{
+    Stream,
+    io::{Reading, Tagged},
+    scenario::{Scenario, Script},
+    seed_thread_rng,
+    sim::{GpsEllipse, ImuMode, SimParams},
+    types::{Control, VehicleState},
+};
+use clap::Parser;
+use ziggurat_rs::{PoissonProcess, Ziggurat};
 
-fn run() {
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Write one sensor per line, with a GPS fix every this many 10 ms
+    /// steps, instead of fused records
+    #[arg(long)]
+    gps_every: Option<usize>,
+
+    /// IMU reading every this many steps, with --gps-every
+    #[arg(long, default_value_t = 1)]
+    imu_every: usize,
+
+    /// Drive the vehicle by commands, weaving at this speed, and write
+    /// them as control columns or lines
+    #[arg(long)]
+    controls: Option<f64>,
+
+    /// Drive the vehicle through the waypoints and stops in this scenario
+    /// file instead, writing the commands as --controls does
+    #[arg(long)]
+    scenario: Option<String>,
+
+    /// Seconds of track to write
+    #[arg(long, default_value_t = 10.0)]
+    duration: f64,
+
+    /// GPS outages starting at this many per second, with --gps-every
+    #[arg(long)]
+    dropout_rate: Option<f64>,
+
+    /// Length of each GPS outage in seconds
+    #[arg(long, default_value_t = 1.0)]
+    dropout_secs: f64,
+
+    /// Space GPS fixes by random gaps averaging --gps-every steps, rather
+    /// than exactly that many
+    #[arg(long)]
+    jitter: bool,
+
+    /// Master seed the vehicle's noise and the outage and jitter
+    /// schedules each derive their own from, as the filter's does
+    #[arg(long)]
+    seed: Option<u32>,
+
+    /// Anisotropic GPS noise as SD_X,SD_Y[,RHO] in place of the default
+    #[arg(long)]
+    gps_ellipse: Option<GpsEllipse>,
+
+    /// What the IMU reads: full, heading or speed, the rest written as NaN
+    #[arg(long, default_value = "full")]
+    imu_mode: ImuMode,
+}
+
+/// Next event time from `events`, or never
+fn next_event(events: &mut Option<PoissonProcess<Ziggurat>>) -> f64 {
+    events
+        .as_mut()
+        .and_then(Iterator::next)
+        .unwrap_or(f64::INFINITY)
+}
+
+fn run(args: &Args) {
     let mut t = 0.0f64;
     let dt = 0.01f64;
+    let params = SimParams {
+        gps_ellipse: args.gps_ellipse,
+        imu_mode: args.imu_mode,
+        ..SimParams::default()
+    };
+
+    if let Some(seed) = args.seed {
+        seed_thread_rng(Stream::Simulator.seed(seed));
+    }
+    let mut script = args.scenario.as_ref().map(|path| {
+        Script::new(
+            Scenario::load(path)
+                .unwrap_or_else(|e| panic!("Could not load scenario {}: {}", path, e)),
+        )
+    });
+    let mut vehicle = match &script {
+        Some(script) => script.initial_state(),
+        None => {
+            let mut vehicle = VehicleState::default();
+            vehicle.init_state(&params);
+            vehicle
+        }
+    };
 
-    let mut vehicle = VehicleState::default();
-    vehicle.init_state();
+    // Each schedule draws from its own stream, so the same seed replays
+    // the same outages and fix times without moving the noise.
+    let master = args.seed.unwrap_or_default();
+    let mut dropouts = args
+        .dropout_rate
+        .map(|rate| PoissonProcess::new(Ziggurat::new(Stream::Dropouts.seed(master)), rate));
+    let mut dropout_start = next_event(&mut dropouts);
+    let mut fixes = args.gps_every.filter(|_| args.jitter).map(|every| {
+        PoissonProcess::new(
+            Ziggurat::new(Stream::Jitter.seed(master)),
+            1.0 / (dt * every as f64),
+        )
+    });
+    let mut next_fix = next_event(&mut fixes);
 
-    while t <= 10.0f64 {
+    let mut step = 0;
+    while t <= args.duration {
         let msec = (t * 1000f64 + 0.5f64).floor();
-        vehicle.update_state(dt, 0);
-        let gps = vehicle.gps_measure();
-        let imu = vehicle.imu_measure(dt);
+        // Turn one way then the other, a second each way.
+        let control = match &mut script {
+            Some(script) => Some(script.control(&vehicle, dt)),
+            None => args.controls.map(|speed| Control {
+                speed,
+                turn_rate: if (step / 100) % 2 == 0 { 0.5 } else { -0.5 },
+            }),
+        };
+        match control {
+            Some(control) => vehicle.update_controlled(&params, control, dt),
+            None => vehicle.update_state(&params, dt, 0),
+        };
+        step += 1;
+        let Some(gps_every) = args.gps_every else {
+            let gps = vehicle.gps_measure(&params);
+            let imu = vehicle.imu_measure(&params, dt);
+            print!(
+                "{} {} {} {} {} {} {}",
+                msec, vehicle.posn.x, vehicle.posn.y, gps.x, gps.y, imu.r, imu.t
+            );
+            match control {
+                Some(control) => println!(" {} {}", control.speed, control.turn_rate),
+                None => println!(),
+            }
+            t += dt;
+            continue;
+        };
+        let t_ms = msec as i32;
+        if let Some(control) = control {
+            println!(
+                "{}",
+                Tagged {
+                    t_ms,
+                    reading: Reading::Control(control),
+                }
+            );
+        }
         println!(
-            "{} {} {} {} {} {} {}",
-            msec, vehicle.posn.x, vehicle.posn.y, gps.x, gps.y, imu.r, imu.t
+            "{}",
+            Tagged {
+                t_ms,
+                reading: Reading::Truth(vehicle.posn),
+            }
         );
+        // Each IMU reading covers the steps since the last one.
+        if step % args.imu_every == 0 {
+            let imu = vehicle.imu_measure(&params, dt * args.imu_every as f64);
+            println!(
+                "{}",
+                Tagged {
+                    t_ms,
+                    reading: Reading::Imu(imu),
+                }
+            );
+        }
+        while t >= dropout_start + args.dropout_secs {
+            dropout_start = next_event(&mut dropouts);
+        }
+        let due = if fixes.is_some() {
+            // One fix however many arrivals fell in this step.
+            let due = t >= next_fix;
+            while t >= next_fix {
+                next_fix = next_event(&mut fixes);
+            }
+            due
+        } else {
+            step % gps_every == 0
+        };
+        if due && t < dropout_start {
+            let gps = vehicle.gps_measure(&params);
+            println!(
+                "{}",
+                Tagged {
+                    t_ms,
+                    reading: Reading::Gps(gps),
+                }
+            );
+        }
         t += dt;
     }
 }
 
 fn main() {
-    run();
+    run(&Args::parse());
 }
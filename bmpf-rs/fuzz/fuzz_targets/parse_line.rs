@@ -0,0 +1,10 @@
+#![no_main]
+
+use bmpf_rs::{sim::SimParams, types::BpfState};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut state = BpfState::new("naive", false, 1, 0, false, 1, SimParams::default());
+    let line = String::from_utf8_lossy(data);
+    let _ = state.parse_line(&line);
+});
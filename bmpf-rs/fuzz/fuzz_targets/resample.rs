@@ -0,0 +1,45 @@
+#![no_main]
+
+//! Input layout: a selector byte (resampler in the low three bits, ordering
+//! in the next two), a byte of `n`, then little-endian `f64` weights.
+//! Weights may be NaN, infinite, negative or zero.
+
+use bmpf_rs::{
+    resample::{Ordering, Resample, Resampler, Weights},
+    types::{ParticleInfo, Particles},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let name = Resampler::NAMES[(data[0] & 7) as usize % Resampler::NAMES.len()];
+    let order = [
+        Ordering::None,
+        Ordering::Shuffle,
+        Ordering::SortDescending,
+        Ordering::Heapify,
+    ][((data[0] >> 3) & 3) as usize];
+    let n = data[1] as usize;
+    let mut particle = Particles {
+        data: data[2..]
+            .chunks_exact(8)
+            .map(|b| ParticleInfo {
+                weight: f64::from_le_bytes(b.try_into().unwrap()),
+                ..Default::default()
+            })
+            .collect(),
+    };
+    let m = particle.data.len();
+    let mut new_particle = Particles {
+        data: vec![ParticleInfo::default(); n],
+    };
+    let mut resampler = Resampler::new(name, m);
+    let weights = Weights::sum(&particle.data);
+    let best = resampler.resample(weights, m, &mut particle, n, &mut new_particle, order);
+    assert!(n == 0 || best < n);
+    if m > 0 {
+        assert!(new_particle.data.iter().all(|p| p.weight.is_finite()));
+    }
+});
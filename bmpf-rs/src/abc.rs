@@ -0,0 +1,241 @@
+//! Likelihood-free weighting: particles are scored by how close their
+//! simulated measurements come to the observed ones, for models that can
+//! be simulated but whose likelihood is unknown (approximate Bayesian
+//! computation).
+
+use crate::{
+    sim::{SimParams, angle_delta},
+    types::{ACoord, CCoord, VehicleState},
+};
+use std::str::FromStr;
+
+/// Distance between what a particle would measure and what was observed
+pub trait Discrepancy: Send {
+    fn distance(
+        &mut self,
+        params: &SimParams,
+        state: &VehicleState,
+        dt: f64,
+        gps: CCoord,
+        imu: ACoord,
+    ) -> f64;
+}
+
+/// Simulates a GPS fix and IMU reading from the particle and measures the
+/// Euclidean distance to the observed ones, each component in units of
+/// the deviation the filter assumes for it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Simulated;
+
+impl Discrepancy for Simulated {
+    fn distance(
+        &mut self,
+        params: &SimParams,
+        state: &VehicleState,
+        dt: f64,
+        gps: CCoord,
+        imu: ACoord,
+    ) -> f64 {
+        let sim_gps = state.gps_measure(params);
+        let sim_imu = state.imu_measure(params, dt);
+        let (sd_r, sd_t) = params.imu_likelihood_sd(dt);
+        let (dx, dy) = (
+            params.delta(sim_gps.x, gps.x),
+            params.delta(sim_gps.y, gps.y),
+        );
+        let (dx, dy) = match params.gps_ellipse {
+            Some(ellipse) => ellipse.whiten(dx, dy),
+            None => (dx / params.gps_var, dy / params.gps_var),
+        };
+        // What the IMU does not give is NaN on both sides.
+        let mode = params.imu_mode;
+        let dr = if mode.has_speed() {
+            (sim_imu.r - imu.r) / sd_r
+        } else {
+            0.0
+        };
+        let dt = if mode.has_heading() {
+            angle_delta(sim_imu.t, imu.t) / sd_t
+        } else {
+            0.0
+        };
+        (dx * dx + dy * dy + dr * dr + dt * dt).sqrt()
+    }
+}
+
+/// How a particle's weight falls off with its distance
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AbcKernel {
+    /// Keep particles within epsilon, drop the rest
+    Uniform,
+    /// `exp(-(d / epsilon)^2 / 2)`
+    #[default]
+    Gaussian,
+}
+
+impl FromStr for AbcKernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(Self::Uniform),
+            "gaussian" => Ok(Self::Gaussian),
+            _ => Err(format!("unknown abc kernel {}", s)),
+        }
+    }
+}
+
+/// Tolerance schedule
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Epsilon {
+    Fixed(f64),
+    /// Recomputed every step as this quantile of the particles' distances,
+    /// so roughly that fraction survives a uniform kernel.
+    Quantile(f64),
+}
+
+/// Likelihood-free weighting settings and scratch space, set as
+/// `BpfState::abc`
+pub struct Abc {
+    pub discrepancy: Box<dyn Discrepancy>,
+    pub kernel: AbcKernel,
+    pub epsilon: Epsilon,
+    current: f64,
+    distances: Vec<f64>,
+    sorted: Vec<f64>,
+}
+
+impl Abc {
+    pub fn new(discrepancy: Box<dyn Discrepancy>, kernel: AbcKernel, epsilon: Epsilon) -> Self {
+        Self {
+            discrepancy,
+            kernel,
+            epsilon,
+            current: f64::NAN,
+            distances: Vec::new(),
+            sorted: Vec::new(),
+        }
+    }
+
+    /// Tolerance used by the last step, NaN before the first
+    pub fn current_epsilon(&self) -> f64 {
+        self.current
+    }
+
+    /// Size the scratch space for `n` particles.
+    pub(crate) fn heap_bytes(&self) -> usize {
+        (self.distances.capacity() + self.sorted.capacity()) * size_of::<f64>()
+    }
+
+    pub(crate) fn prepare(&mut self, n: usize) {
+        self.distances.resize(n, 0.0);
+        self.sorted.resize(n, 0.0);
+    }
+
+    /// Score particle `i` in the current step.
+    pub(crate) fn measure(
+        &mut self,
+        i: usize,
+        params: &SimParams,
+        state: &VehicleState,
+        dt: f64,
+        gps: CCoord,
+        imu: ACoord,
+    ) {
+        self.distances[i] = self.discrepancy.distance(params, state, dt, gps, imu);
+    }
+
+    /// Fix this step's epsilon and return each particle's log kernel
+    /// weight.
+    pub(crate) fn log_kernels(&mut self) -> &[f64] {
+        let eps = match self.epsilon {
+            Epsilon::Fixed(eps) => eps,
+            Epsilon::Quantile(q) if !self.sorted.is_empty() => {
+                self.sorted.copy_from_slice(&self.distances);
+                let n = self.sorted.len();
+                let k = ((q.clamp(0.0, 1.0) * n as f64).ceil() as usize).clamp(1, n) - 1;
+                *self.sorted.select_nth_unstable_by(k, f64::total_cmp).1
+            }
+            Epsilon::Quantile(_) => f64::NAN,
+        };
+        // A zero tolerance would turn exact matches into 0 / 0.
+        let eps = eps.max(f64::MIN_POSITIVE);
+        self.current = eps;
+        for d in &mut self.distances {
+            *d = match self.kernel {
+                AbcKernel::Uniform if *d <= eps => 0.0,
+                AbcKernel::Uniform => f64::NEG_INFINITY,
+                AbcKernel::Gaussian => -0.5 * (*d / eps) * (*d / eps),
+            };
+        }
+        &self.distances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BpfState;
+
+    fn filter(abc: Abc, resample_interval: usize) -> BpfState {
+        let mut bpf = BpfState::new(
+            "regular",
+            false,
+            200,
+            0,
+            false,
+            resample_interval,
+            SimParams::default(),
+        );
+        bpf.reporter = None;
+        bpf.abc = Some(abc);
+        bpf.init_particles();
+        bpf
+    }
+
+    #[test]
+    fn adaptive_abc_tracks() {
+        crate::seed_thread_rng(4654);
+        let params = SimParams::default();
+        let mut vehicle = VehicleState::default();
+        vehicle.init_state(&params);
+        let abc = Abc::new(
+            Box::new(Simulated),
+            AbcKernel::Gaussian,
+            Epsilon::Quantile(0.5),
+        );
+        let mut bpf = filter(abc, 1);
+        let (dt, mut sq) = (0.1, 0.0);
+        for i in 0..200 {
+            vehicle.update_state(&params, dt, 0);
+            let gps = vehicle.gps_measure(&params);
+            let imu = vehicle.imu_measure(&params, dt);
+            let est = bpf.observe(i as f64 * dt, dt, gps, imu);
+            // Kernel weights have no incremental variance to report.
+            assert_eq!((est.weight_variance, est.log_dimension), (None, None));
+            let eps = bpf.abc.as_ref().unwrap().current_epsilon();
+            assert!(eps.is_finite() && eps > 0.0);
+            if i >= 20 {
+                sq += (est.mean.x - vehicle.posn.x).powi(2) + (est.mean.y - vehicle.posn.y).powi(2);
+            }
+        }
+        let rms = (sq / 180.0).sqrt();
+        // Simulated GPS noise doubles the variance ABC sees, so it trails
+        // the exact likelihood, but a lost filter would be off by far more.
+        assert!(rms < 3.0, "{}", rms);
+    }
+
+    #[test]
+    fn uniform_kernel_drops_distant_particles() {
+        crate::seed_thread_rng(4654);
+        let abc = Abc::new(Box::new(Simulated), AbcKernel::Uniform, Epsilon::Fixed(3.0));
+        // Every other step skips resampling, leaving the weights to check.
+        let mut bpf = filter(abc, 2);
+        let gps = CCoord { x: 1.0, y: 1.0 };
+        bpf.observe(0.0, 0.1, gps, ACoord::default());
+        let alive = bpf.particles().iter().filter(|p| p.weight > 0.0).count();
+        assert!(alive > 0 && alive < 200, "{}", alive);
+        assert_eq!(bpf.abc.as_ref().unwrap().current_epsilon(), 3.0);
+        assert_eq!("uniform".parse(), Ok(AbcKernel::Uniform));
+    }
+}
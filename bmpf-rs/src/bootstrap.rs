@@ -0,0 +1,175 @@
+//! Bootstrap bands about the weighted mean of a particle cloud. The cloud
+//! is resampled again and again by one of the resamplers, and the spread
+//! of the resampled means shows how much the estimate moves from the
+//! resampling alone: narrow for a resampler that adds little noise, wide
+//! for one that adds a lot.
+
+use crate::{
+    report::ParticleReporter,
+    resample::{Ordering, Resample, Resampler, Weights},
+    types::{CCoord, ParticleInfo, Particles},
+};
+use std::io::{self, Write};
+
+/// Central band holding `level` of the resampled means of one cloud
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub t: f64,
+    /// Weighted mean of the cloud itself
+    pub mean: CCoord,
+    pub lo: CCoord,
+    pub hi: CCoord,
+}
+
+/// Value below fraction `p` of `sorted`, by nearest rank
+fn quantile(sorted: &[f64], p: f64) -> f64 {
+    sorted[((p * (sorted.len() - 1) as f64).round() as usize).min(sorted.len() - 1)]
+}
+
+/// Resamples each reported cloud `replicates` times and writes its band as
+/// a line `t mean_x mean_y lo_x lo_y hi_x hi_y`
+pub struct BootstrapBands<W: Write + Send> {
+    resampler: Resampler,
+    pub order: Ordering,
+    pub replicates: usize,
+    /// Weight of the resampled means inside the band
+    pub level: f64,
+    from: Particles,
+    to: Particles,
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    out: W,
+}
+
+impl<W: Write + Send> BootstrapBands<W> {
+    /// Bands from `replicates` resamplings by the resampler called
+    /// `resampler`, holding `level` of the resampled means
+    pub fn new(resampler: &str, replicates: usize, level: f64, out: W) -> Self {
+        assert!(
+            replicates > 0,
+            "bootstrap bands need at least one replicate"
+        );
+        Self {
+            resampler: Resampler::new(resampler, 0),
+            order: Ordering::None,
+            replicates,
+            level,
+            from: Particles { data: Vec::new() },
+            to: Particles { data: Vec::new() },
+            xs: Vec::with_capacity(replicates),
+            ys: Vec::with_capacity(replicates),
+            out,
+        }
+    }
+
+    /// Band of the cloud `particles` at time `t`
+    pub fn band(&mut self, t: f64, particles: &[ParticleInfo]) -> Band {
+        let n = particles.len();
+        let total = Weights::sum(particles);
+        let invtotal = 1.0 / total.total();
+        let mean = CCoord {
+            x: particles
+                .iter()
+                .map(|p| p.weight * p.state.posn.x)
+                .sum::<f64>()
+                * invtotal,
+            y: particles
+                .iter()
+                .map(|p| p.weight * p.state.posn.y)
+                .sum::<f64>()
+                * invtotal,
+        };
+        self.to.data.resize(n, ParticleInfo::default());
+        self.xs.clear();
+        self.ys.clear();
+        for _ in 0..self.replicates {
+            // Resamplers may reorder the cloud they draw from.
+            self.from.data.clear();
+            self.from.data.extend_from_slice(particles);
+            self.resampler
+                .resample(total, n, &mut self.from, n, &mut self.to, self.order);
+            let copies = &self.to.data;
+            self.xs
+                .push(copies.iter().map(|p| p.state.posn.x).sum::<f64>() / n as f64);
+            self.ys
+                .push(copies.iter().map(|p| p.state.posn.y).sum::<f64>() / n as f64);
+        }
+        self.xs.sort_by(f64::total_cmp);
+        self.ys.sort_by(f64::total_cmp);
+        let (p_lo, p_hi) = (0.5 * (1.0 - self.level), 0.5 * (1.0 + self.level));
+        Band {
+            t,
+            mean,
+            lo: CCoord {
+                x: quantile(&self.xs, p_lo),
+                y: quantile(&self.ys, p_lo),
+            },
+            hi: CCoord {
+                x: quantile(&self.xs, p_hi),
+                y: quantile(&self.ys, p_hi),
+            },
+        }
+    }
+}
+
+impl<W: Write + Send> ParticleReporter for BootstrapBands<W> {
+    fn report(&mut self, t: f64, particles: &[ParticleInfo]) -> io::Result<()> {
+        if particles.is_empty() {
+            return Ok(());
+        }
+        let b = self.band(t, particles);
+        writeln!(
+            self.out,
+            "{} {} {} {} {} {} {}",
+            b.t, b.mean.x, b.mean.y, b.lo.x, b.lo.y, b.hi.x, b.hi.y
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bands_measure_resampling_noise() {
+        crate::seed_thread_rng(4682);
+        let cloud: Vec<ParticleInfo> = (0..500)
+            .map(|i| {
+                let mut p = ParticleInfo {
+                    weight: crate::uniform(),
+                    ..Default::default()
+                };
+                p.state.posn = CCoord {
+                    x: crate::gaussian(1.0),
+                    y: 5.0 + crate::gaussian(2.0),
+                };
+                if i % 50 == 0 {
+                    p.weight = 0.0;
+                }
+                p
+            })
+            .collect();
+        let band = |name: &str| BootstrapBands::new(name, 200, 0.9, Vec::new()).band(1.0, &cloud);
+        // Multinomial resampling is unbiased but noisy, while `regular`
+        // draws no random numbers at all and so never moves far from the
+        // mean.
+        let naive = band("naive");
+        assert!(naive.lo.x < naive.mean.x && naive.mean.x < naive.hi.x);
+        assert!(naive.lo.y < naive.mean.y && naive.mean.y < naive.hi.y);
+        let regular = band("regular");
+        assert_eq!(regular.lo, regular.hi);
+        assert!((regular.lo.y - regular.mean.y).abs() < 0.1 * (naive.hi.y - naive.lo.y));
+
+        let mut bands = BootstrapBands::new("logm", 10, 0.5, Vec::new());
+        bands.report(2.5, &cloud).unwrap();
+        bands.report(3.0, &[]).unwrap();
+        let out = String::from_utf8(bands.out).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert_eq!(out.split_whitespace().count(), 7);
+        assert!(out.starts_with("2.5 "));
+    }
+}
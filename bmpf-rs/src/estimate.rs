@@ -0,0 +1,435 @@
+use crate::{
+    fp::{atan2, cos, exp, powf, sin},
+    types::{ACoord, CCoord, ParticleInfo},
+};
+use std::str::FromStr;
+
+/// Which position a step reports as the filter's estimate. Every step
+/// computes all of them; the mode only picks the headline one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EstimateMode {
+    /// The highest weighted particle after resampling, as the C code
+    /// reports with `best_particle`
+    BestParticle,
+    /// Weighted mean of the particles before resampling
+    #[default]
+    WeightedMean,
+    /// Peak of a kernel density estimate of the weighted particles. Unlike
+    /// the mean it stays on one mode of a split cloud.
+    Map,
+}
+
+impl FromStr for EstimateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "best" => Ok(Self::BestParticle),
+            "mean" => Ok(Self::WeightedMean),
+            "map" => Ok(Self::Map),
+            _ => Err(format!("unknown estimate mode {}", s)),
+        }
+    }
+}
+
+/// Index of the heaviest particle, the first of any tied
+pub fn argmax(particles: &[ParticleInfo]) -> Option<usize> {
+    best_worst(particles).map(|(best, _)| best)
+}
+
+/// Index of the lightest particle, the first of any tied
+pub fn argmin(particles: &[ParticleInfo]) -> Option<usize> {
+    best_worst(particles).map(|(_, worst)| worst)
+}
+
+/// Indices of the heaviest and lightest particles in one pass, the first
+/// of any tied
+pub fn best_worst(particles: &[ParticleInfo]) -> Option<(usize, usize)> {
+    let first = particles.first()?;
+    let (mut best, mut worst) = (0, 0);
+    let (mut best_weight, mut worst_weight) = (first.weight, first.weight);
+    for (i, p) in particles.iter().enumerate().skip(1) {
+        if p.weight > best_weight {
+            best = i;
+            best_weight = p.weight;
+        } else if p.weight < worst_weight {
+            worst = i;
+            worst_weight = p.weight;
+        }
+    }
+    Some((best, worst))
+}
+
+/// Indices of the `k` heaviest particles, heaviest first and ties in index
+/// order, or of all of them if there are fewer than `k`
+pub fn top_k(particles: &[ParticleInfo], k: usize) -> Vec<usize> {
+    let heavier = |&a: &usize, &b: &usize| {
+        particles[b]
+            .weight
+            .total_cmp(&particles[a].weight)
+            .then(a.cmp(&b))
+    };
+    let mut indices: Vec<usize> = (0..particles.len()).collect();
+    if k < indices.len() {
+        if k > 0 {
+            indices.select_nth_unstable_by(k - 1, heavier);
+        }
+        indices.truncate(k);
+    }
+    indices.sort_unstable_by(heavier);
+    indices
+}
+
+/// Weighted mean speed and heading. Headings are averaged as unit vectors,
+/// so a cloud split either side of `±π` heads towards `π` rather than
+/// zero. NaN without any weight.
+pub fn velocity(particles: &[ParticleInfo]) -> ACoord {
+    let (mut w, mut r, mut c, mut s) = (0.0, 0.0, 0.0, 0.0);
+    for p in particles {
+        let vel = p.state.vel();
+        w += p.weight;
+        r += p.weight * vel.r;
+        c += p.weight * cos(vel.t);
+        s += p.weight * sin(vel.t);
+    }
+    ACoord {
+        r: r / w,
+        t: if w > 0.0 { atan2(s, c) } else { f64::NAN },
+    }
+}
+
+/// Fraction of the particles pinned at the `max_speed` clip. A large
+/// fraction means the speed noise or the speed limit does not fit the
+/// data. NaN without particles.
+pub fn clipped_fraction(particles: &[ParticleInfo], max_speed: f64) -> f64 {
+    let pinned = particles
+        .iter()
+        .filter(|p| p.state.vel().r >= max_speed)
+        .count();
+    pinned as f64 / particles.len() as f64
+}
+
+/// Moments of one step's incremental weights, the factor each particle's
+/// weight is multiplied by, taken under the weights before the step. Their
+/// relative variance is how much the step alone shrinks the effective
+/// sample size: about by `1 + relative_variance()`.
+#[derive(Clone, Copy, Debug)]
+pub struct WeightMoments {
+    /// Log of the scale `first` and `second` are kept at, for `add_log`
+    shift: f64,
+    total: f64,
+    first: f64,
+    second: f64,
+}
+
+impl Default for WeightMoments {
+    fn default() -> Self {
+        Self {
+            shift: f64::NEG_INFINITY,
+            total: 0.0,
+            first: 0.0,
+            second: 0.0,
+        }
+    }
+}
+
+impl WeightMoments {
+    /// Count a particle of weight `prev` before the step and `weight`
+    /// after it. Particles that weighed nothing have no increment.
+    pub fn add(&mut self, prev: f64, weight: f64) {
+        if prev > 0.0 {
+            self.total += prev;
+            self.first += weight;
+            self.second += weight * weight / prev;
+        }
+    }
+
+    /// As `add`, with the weight after the step as a log, which may
+    /// underflow. Do not mix with `add` in one step.
+    pub fn add_log(&mut self, prev: f64, log_weight: f64) {
+        if log_weight > self.shift {
+            let scale = exp(self.shift - log_weight);
+            self.first *= scale;
+            self.second *= scale * scale;
+            self.shift = log_weight;
+        }
+        self.add(prev, exp(log_weight - self.shift));
+    }
+
+    /// Variance of the incremental weights over their squared mean, NaN
+    /// if they were all zero
+    pub fn relative_variance(&self) -> f64 {
+        if self.first == 0.0 {
+            return f64::NAN;
+        }
+        (self.total * self.second / (self.first * self.first) - 1.0).max(0.0)
+    }
+}
+
+/// Mean shift refinements of the grid peak
+const REFINE_STEPS: usize = 5;
+
+/// Gridded kernel density estimate of particle positions, used to find the
+/// most probable position in linear time. Scratch space is sized up front
+/// so repeated calls do not allocate. Ignores the seam of a toroidal world.
+#[derive(Clone, Debug)]
+pub struct Kde {
+    cells: usize,
+    density: Vec<f64>,
+    scratch: Vec<f64>,
+    kernel: Vec<f64>,
+}
+
+impl Default for Kde {
+    fn default() -> Self {
+        Self::new(32)
+    }
+}
+
+impl Kde {
+    /// A density grid of `cells` by `cells` over the particle cloud.
+    pub fn new(cells: usize) -> Self {
+        assert!(cells > 0, "kde grid must have at least one cell");
+        Self {
+            cells,
+            density: vec![0.0; cells * cells],
+            scratch: vec![0.0; cells * cells],
+            kernel: Vec::with_capacity(2 * cells + 1),
+        }
+    }
+
+    /// Bytes of grid the estimator holds
+    pub(crate) fn heap_bytes(&self) -> usize {
+        (self.density.capacity() + self.scratch.capacity() + self.kernel.capacity())
+            * size_of::<f64>()
+    }
+
+    /// Position of highest estimated density, with Silverman's rule for the
+    /// bandwidth on each axis. `None` when no particle has positive weight.
+    pub fn peak(&mut self, particles: &[ParticleInfo]) -> Option<CCoord> {
+        let (mut w, mut w2, mut mx, mut my) = (0.0, 0.0, 0.0, 0.0);
+        let (mut lo, mut hi) = (
+            CCoord {
+                x: f64::INFINITY,
+                y: f64::INFINITY,
+            },
+            CCoord {
+                x: f64::NEG_INFINITY,
+                y: f64::NEG_INFINITY,
+            },
+        );
+        for p in particles.iter().filter(|p| p.weight > 0.0) {
+            let posn = p.state.posn;
+            w += p.weight;
+            w2 += p.weight * p.weight;
+            mx += p.weight * posn.x;
+            my += p.weight * posn.y;
+            lo.x = lo.x.min(posn.x);
+            lo.y = lo.y.min(posn.y);
+            hi.x = hi.x.max(posn.x);
+            hi.y = hi.y.max(posn.y);
+        }
+        if !(w > 0.0 && w.is_finite()) {
+            return None;
+        }
+        let mean = CCoord {
+            x: mx / w,
+            y: my / w,
+        };
+        let (mut vx, mut vy) = (0.0, 0.0);
+        for p in particles.iter().filter(|p| p.weight > 0.0) {
+            let posn = p.state.posn;
+            vx += p.weight * (posn.x - mean.x) * (posn.x - mean.x);
+            vy += p.weight * (posn.y - mean.y) * (posn.y - mean.y);
+        }
+        let n_eff = w * w / w2;
+        let rule = 1.06 * powf(n_eff, -0.2);
+        let h = CCoord {
+            x: rule * (vx / w).sqrt(),
+            y: rule * (vy / w).sqrt(),
+        };
+        if !(h.x > 0.0 && h.y > 0.0) {
+            // Collapsed onto a point or a line: nothing to smooth.
+            return Some(mean);
+        }
+
+        let n = self.cells;
+        let dx = (hi.x - lo.x) / n as f64;
+        let dy = (hi.y - lo.y) / n as f64;
+        let cell = |v: f64, lo: f64, d: f64| (((v - lo) / d) as usize).min(n - 1);
+        self.density.fill(0.0);
+        for p in particles.iter().filter(|p| p.weight > 0.0) {
+            let posn = p.state.posn;
+            let (i, j) = (cell(posn.x, lo.x, dx), cell(posn.y, lo.y, dy));
+            self.density[j * n + i] += p.weight;
+        }
+        // Separable Gaussian blur: rows into scratch, then columns back.
+        fill_kernel(&mut self.kernel, h.x / dx, n);
+        blur(&self.density, &mut self.scratch, &self.kernel, n, n, 1);
+        fill_kernel(&mut self.kernel, h.y / dy, n);
+        blur(&self.scratch, &mut self.density, &self.kernel, n, 1, n);
+        let top = (0..n * n)
+            .max_by(|&a, &b| self.density[a].total_cmp(&self.density[b]))
+            .unwrap_or(0);
+        let mut peak = CCoord {
+            x: lo.x + ((top % n) as f64 + 0.5) * dx,
+            y: lo.y + ((top / n) as f64 + 0.5) * dy,
+        };
+
+        // The grid only places the peak to within a cell; mean shift
+        // climbs the unbinned density from there.
+        for _ in 0..REFINE_STEPS {
+            let (mut sw, mut sx, mut sy) = (0.0, 0.0, 0.0);
+            for p in particles.iter().filter(|p| p.weight > 0.0) {
+                let u = (p.state.posn.x - peak.x) / h.x;
+                let v = (p.state.posn.y - peak.y) / h.y;
+                let k = p.weight * exp(-0.5 * (u * u + v * v));
+                sw += k;
+                sx += k * p.state.posn.x;
+                sy += k * p.state.posn.y;
+            }
+            if sw <= 0.0 {
+                break;
+            }
+            peak = CCoord {
+                x: sx / sw,
+                y: sy / sw,
+            };
+        }
+        Some(peak)
+    }
+}
+
+/// Gaussian kernel with standard deviation `sd` cells, truncated at three
+/// deviations or `n` cells.
+fn fill_kernel(kernel: &mut Vec<f64>, sd: f64, n: usize) {
+    let radius = ((3.0 * sd).ceil() as usize).min(n);
+    kernel.clear();
+    for k in 0..=2 * radius {
+        let u = (k as f64 - radius as f64) / sd;
+        kernel.push(exp(-0.5 * u * u));
+    }
+}
+
+/// Convolve `n` lines of `n` cells with `kernel`, where consecutive cells
+/// of a line are `step` apart and consecutive lines `stride` apart.
+fn blur(src: &[f64], dst: &mut [f64], kernel: &[f64], n: usize, stride: usize, step: usize) {
+    let radius = kernel.len() / 2;
+    for line in 0..n {
+        let base = line * stride;
+        for i in 0..n {
+            let from = i.saturating_sub(radius);
+            let to = (i + radius).min(n - 1);
+            let mut sum = 0.0;
+            for j in from..=to {
+                sum += kernel[j + radius - i] * src[base + j * step];
+            }
+            dst[base + i * step] = sum;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cloud(points: &[(f64, f64, f64)]) -> Vec<ParticleInfo> {
+        points
+            .iter()
+            .map(|&(x, y, weight)| {
+                let mut p = ParticleInfo {
+                    weight,
+                    ..Default::default()
+                };
+                p.state.posn = CCoord { x, y };
+                p
+            })
+            .collect()
+    }
+
+    #[test]
+    fn peak_picks_the_heavier_mode() {
+        crate::seed_thread_rng(4650);
+        let mut points = Vec::new();
+        for i in 0..400 {
+            let cx = if i % 4 == 0 { -5.0 } else { 5.0 };
+            points.push((cx + crate::gaussian(0.5), 2.0 + crate::gaussian(0.5), 1.0));
+        }
+        let particles = cloud(&points);
+        let peak = Kde::default().peak(&particles).unwrap();
+        assert!((peak.x - 5.0).abs() < 0.5, "{:?}", peak);
+        assert!((peak.y - 2.0).abs() < 0.5, "{:?}", peak);
+        // The mean falls between the modes, where there are no particles.
+        let mean: f64 = particles.iter().map(|p| p.state.posn.x).sum::<f64>() / 400.0;
+        assert!(mean.abs() < 3.5);
+    }
+
+    #[test]
+    fn extremes() {
+        let particles = cloud(&[
+            (0.0, 0.0, 0.2),
+            (1.0, 0.0, 0.5),
+            (2.0, 0.0, 0.1),
+            (3.0, 0.0, 0.5),
+            (4.0, 0.0, 0.1),
+        ]);
+        assert_eq!(best_worst(&particles), Some((1, 2)));
+        assert_eq!(argmax(&particles), Some(1));
+        assert_eq!(argmin(&particles), Some(2));
+        assert_eq!(argmax(&[]), None);
+        assert_eq!(top_k(&particles, 3), vec![1, 3, 0]);
+        assert_eq!(top_k(&particles, 0), Vec::<usize>::new());
+        assert_eq!(top_k(&particles, 9), vec![1, 3, 0, 2, 4]);
+    }
+
+    #[test]
+    fn degenerate_clouds() {
+        let mut kde = Kde::new(4);
+        assert_eq!(kde.peak(&[]), None);
+        assert_eq!(kde.peak(&cloud(&[(1.0, 1.0, 0.0)])), None);
+        let one = kde.peak(&cloud(&[(1.0, 2.0, 1.0), (3.0, 2.0, 0.0)]));
+        assert_eq!(one, Some(CCoord { x: 1.0, y: 2.0 }));
+        assert_eq!("map".parse(), Ok(EstimateMode::Map));
+    }
+
+    #[test]
+    fn velocity_wraps() {
+        use crate::types::VehicleState;
+        use std::f64::consts::PI;
+        let mut particles = cloud(&[(0.0, 0.0, 0.25), (0.0, 0.0, 0.25), (0.0, 0.0, 0.5)]);
+        for (p, (r, t)) in particles
+            .iter_mut()
+            .zip([(1.0, PI - 0.1), (2.0, -PI + 0.1), (2.0, PI)])
+        {
+            p.state = VehicleState::new(p.state.posn, ACoord { r, t });
+        }
+        let vel = velocity(&particles);
+        assert!((vel.r - 1.75).abs() < 1e-12);
+        assert!(PI - vel.t.abs() < 1e-12, "{}", vel.t);
+        assert_eq!(clipped_fraction(&particles, 2.0), 2.0 / 3.0);
+        assert!(velocity(&[]).r.is_nan() && clipped_fraction(&[], 2.0).is_nan());
+    }
+
+    #[test]
+    fn incremental_weight_moments() {
+        // Halving two of the weights: increments 1 and 1/2 weighing 0.4
+        // and 0.6, so mean 0.7 and mean square 0.55.
+        let prev = [0.1, 0.2, 0.3, 0.4];
+        let incr = [1.0, 0.5, 1.0, 0.5];
+        let (mut linear, mut log) = (WeightMoments::default(), WeightMoments::default());
+        for (&w, &k) in prev.iter().zip(&incr) {
+            linear.add(w, w * k);
+            // Far below what the linear weights could hold
+            log.add_log(w, (w * k).ln() - 2000.0);
+        }
+        let want = 0.55 / (0.7 * 0.7) - 1.0;
+        assert!((linear.relative_variance() - want).abs() < 1e-12);
+        assert!((log.relative_variance() - want).abs() < 1e-12);
+        let mut flat = WeightMoments::default();
+        flat.add(0.5, 0.25);
+        flat.add(0.0, 0.0);
+        flat.add(0.5, 0.25);
+        assert_eq!(flat.relative_variance(), 0.0);
+        assert!(WeightMoments::default().relative_variance().is_nan());
+    }
+}
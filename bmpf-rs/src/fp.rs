@@ -0,0 +1,54 @@
+//! Transcendental functions on the filter path. The platform libm differs
+//! in the last bit between targets, which over a run is enough to send
+//! resampling down a different branch. With the `strict-fp` feature these
+//! come from the pure-Rust libm instead, as do the ziggurat's, and a fixed
+//! seed gives bit-identical results on x86_64 and aarch64, for comparison
+//! against recorded C traces. The rest is plain IEEE arithmetic in a fixed
+//! order, which Rust never contracts into fused multiply-adds.
+//!
+//! Covers the CPU filter; the GPU kernels keep their own precision.
+
+#[cfg(feature = "strict-fp")]
+mod imp {
+    pub use libm::{atan2, cos, exp, log as ln, log1p as ln_1p, pow as powf, sin};
+}
+
+#[cfg(not(feature = "strict-fp"))]
+mod imp {
+    #[inline]
+    pub fn exp(x: f64) -> f64 {
+        x.exp()
+    }
+
+    #[inline]
+    pub fn ln(x: f64) -> f64 {
+        x.ln()
+    }
+
+    #[inline]
+    pub fn ln_1p(x: f64) -> f64 {
+        x.ln_1p()
+    }
+
+    #[inline]
+    pub fn powf(x: f64, y: f64) -> f64 {
+        x.powf(y)
+    }
+
+    #[inline]
+    pub fn sin(x: f64) -> f64 {
+        x.sin()
+    }
+
+    #[inline]
+    pub fn cos(x: f64) -> f64 {
+        x.cos()
+    }
+
+    #[inline]
+    pub fn atan2(y: f64, x: f64) -> f64 {
+        y.atan2(x)
+    }
+}
+
+pub use imp::*;
@@ -0,0 +1,304 @@
+//! Coordinate frames of external data. The filter itself moves a particle
+//! with heading `t` along `(cos t, -sin t)`: screen style, so headings turn
+//! clockwise from +x when +y is drawn upwards. Data in any other
+//! convention, such as ROS's counterclockwise yaw or a compass course, is
+//! converted through a `Frame` on the way in and back on the way out, rather
+//! than coming out mirrored.
+
+use crate::{
+    io::{Reading, Record},
+    sim::normalize_angle,
+    types::{ACoord, CCoord, Control, Estimate},
+};
+use std::{f64::consts::PI, str::FromStr};
+
+/// Axis a heading of zero points along
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HeadingZero {
+    #[default]
+    PosX,
+    PosY,
+}
+
+/// Sense in which headings grow, clockwise meaning from +x towards -y of
+/// the data's own axes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Rotation {
+    #[default]
+    Clockwise,
+    Counterclockwise,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AngleUnit {
+    #[default]
+    Radians,
+    Degrees,
+}
+
+/// Axis and angle conventions of external data. The default is the
+/// filter's own, which converts nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Frame {
+    /// The data's +y is the filter's -y
+    pub flip_y: bool,
+    pub heading_zero: HeadingZero,
+    pub rotation: Rotation,
+    pub angle_unit: AngleUnit,
+}
+
+impl Frame {
+    /// ROS REP 103: x east or forward, y north or left, yaw in radians
+    /// counterclockwise from +x
+    pub fn ros() -> Self {
+        Self {
+            rotation: Rotation::Counterclockwise,
+            ..Self::default()
+        }
+    }
+
+    /// x east, y north, course in degrees clockwise from north, as GPS
+    /// receivers report it
+    pub fn compass() -> Self {
+        Self {
+            heading_zero: HeadingZero::PosY,
+            angle_unit: AngleUnit::Degrees,
+            ..Self::default()
+        }
+    }
+
+    fn is_native(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Sign and offset taking a heading in radians to the filter's
+    fn heading_map(&self) -> (f64, f64) {
+        // Counterclockwise angle from +x in the data's axes, as a sign and
+        // offset on the heading
+        let (sign, offset) = match (self.heading_zero, self.rotation) {
+            (HeadingZero::PosX, Rotation::Counterclockwise) => (1.0, 0.0),
+            (HeadingZero::PosX, Rotation::Clockwise) => (-1.0, 0.0),
+            (HeadingZero::PosY, Rotation::Counterclockwise) => (1.0, 0.5 * PI),
+            (HeadingZero::PosY, Rotation::Clockwise) => (-1.0, 0.5 * PI),
+        };
+        // The filter turns clockwise in its own axes, so the same sense as
+        // the data only once y is flipped.
+        if self.flip_y {
+            (sign, offset)
+        } else {
+            (-sign, -offset)
+        }
+    }
+
+    fn radians_in(&self, a: f64) -> f64 {
+        match self.angle_unit {
+            AngleUnit::Radians => a,
+            AngleUnit::Degrees => a.to_radians(),
+        }
+    }
+
+    fn radians_out(&self, a: f64) -> f64 {
+        match self.angle_unit {
+            AngleUnit::Radians => a,
+            AngleUnit::Degrees => a.to_degrees(),
+        }
+    }
+
+    pub fn position_in(&self, c: CCoord) -> CCoord {
+        if self.flip_y {
+            CCoord { x: c.x, y: -c.y }
+        } else {
+            c
+        }
+    }
+
+    pub fn position_out(&self, c: CCoord) -> CCoord {
+        self.position_in(c)
+    }
+
+    pub fn heading_in(&self, h: f64) -> f64 {
+        if self.is_native() {
+            return h;
+        }
+        let (sign, offset) = self.heading_map();
+        normalize_angle(sign * self.radians_in(h) + offset)
+    }
+
+    pub fn heading_out(&self, t: f64) -> f64 {
+        if self.is_native() {
+            return t;
+        }
+        let (sign, offset) = self.heading_map();
+        let h = normalize_angle(sign * (t - offset));
+        self.radians_out(h)
+    }
+
+    /// A rate of change of heading, such as a commanded turn rate
+    pub fn turn_rate_in(&self, rate: f64) -> f64 {
+        if self.is_native() {
+            return rate;
+        }
+        self.heading_map().0 * self.radians_in(rate)
+    }
+
+    pub fn velocity_in(&self, a: ACoord) -> ACoord {
+        ACoord {
+            r: a.r,
+            t: self.heading_in(a.t),
+        }
+    }
+
+    pub fn velocity_out(&self, a: ACoord) -> ACoord {
+        ACoord {
+            r: a.r,
+            t: self.heading_out(a.t),
+        }
+    }
+
+    pub fn reading_in(&self, reading: Reading) -> Reading {
+        match reading {
+            Reading::Gps(c) => Reading::Gps(self.position_in(c)),
+            Reading::Imu(a) => Reading::Imu(self.velocity_in(a)),
+            Reading::Control(c) => Reading::Control(self.control_in(c)),
+            Reading::Truth(c) => Reading::Truth(self.position_in(c)),
+        }
+    }
+
+    pub fn control_in(&self, c: Control) -> Control {
+        Control {
+            speed: c.speed,
+            turn_rate: self.turn_rate_in(c.turn_rate),
+        }
+    }
+
+    pub fn record_in(&self, rec: Record) -> Record {
+        Record {
+            t_ms: rec.t_ms,
+            vehicle: self.position_in(rec.vehicle),
+            gps: self.position_in(rec.gps),
+            imu: self.velocity_in(rec.imu),
+            control: rec.control.map(|c| self.control_in(c)),
+        }
+    }
+
+    /// An estimate in the data's axes
+    pub fn estimate_out(&self, est: &Estimate) -> Estimate {
+        Estimate {
+            best: self.position_out(est.best),
+            worst: self.position_out(est.worst),
+            mean: self.position_out(est.mean),
+            map: self.position_out(est.map),
+            velocity: est.velocity.map(|v| self.velocity_out(v)),
+            interval: est.interval.map(|(lo, hi)| {
+                let (lo, hi) = (self.position_out(lo), self.position_out(hi));
+                // Flipping y swaps which corner is lower.
+                (
+                    CCoord {
+                        x: lo.x,
+                        y: lo.y.min(hi.y),
+                    },
+                    CCoord {
+                        x: hi.x,
+                        y: lo.y.max(hi.y),
+                    },
+                )
+            }),
+            ..*est
+        }
+    }
+}
+
+impl FromStr for Frame {
+    type Err = String;
+
+    /// Parse `native`, `ros`, `compass`, or `ZERO:SENSE:UNIT` with `ZERO`
+    /// one of `x` and `y`, `SENSE` one of `cw` and `ccw` and `UNIT` one of
+    /// `rad` and `deg`, optionally followed by `:flip-y`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => return Ok(Self::default()),
+            "ros" => return Ok(Self::ros()),
+            "compass" => return Ok(Self::compass()),
+            _ => {}
+        }
+        let fields: Vec<&str> = s.split(':').collect();
+        let (zero, sense, unit, flip_y) = match fields[..] {
+            [zero, sense, unit] => (zero, sense, unit, false),
+            [zero, sense, unit, "flip-y"] => (zero, sense, unit, true),
+            _ => return Err(format!("unknown frame {}", s)),
+        };
+        Ok(Self {
+            flip_y,
+            heading_zero: match zero {
+                "x" => HeadingZero::PosX,
+                "y" => HeadingZero::PosY,
+                _ => return Err(format!("unknown heading zero {}", zero)),
+            },
+            rotation: match sense {
+                "cw" => Rotation::Clockwise,
+                "ccw" => Rotation::Counterclockwise,
+                _ => return Err(format!("unknown rotation {}", sense)),
+            },
+            angle_unit: match unit {
+                "rad" => AngleUnit::Radians,
+                "deg" => AngleUnit::Degrees,
+                _ => return Err(format!("unknown angle unit {}", unit)),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimParams, types::VehicleState};
+
+    /// Where a vehicle at the origin heading `h` in `frame` is a second
+    /// later, back in `frame`
+    fn after_one_second(frame: &Frame, h: f64) -> CCoord {
+        let params = SimParams {
+            control_r_var: 0.0,
+            control_a_var: 0.0,
+            ..SimParams::default()
+        };
+        let vel = ACoord {
+            r: 1.0,
+            t: frame.heading_in(h),
+        };
+        let mut state = VehicleState::new(CCoord::default(), vel);
+        let ahead = Control {
+            speed: 1.0,
+            turn_rate: 0.0,
+        };
+        state.update_controlled(&params, ahead, 1.0);
+        frame.position_out(state.posn)
+    }
+
+    #[test]
+    fn headings_point_the_right_way() {
+        let close = |a: CCoord, x: f64, y: f64| (a.x - x).abs() < 1e-12 && (a.y - y).abs() < 1e-12;
+        // ROS yaw of a quarter turn faces +y.
+        assert!(close(after_one_second(&Frame::ros(), 0.5 * PI), 0.0, 1.0));
+        // A compass course of 90 faces east, and of 0 north.
+        assert!(close(after_one_second(&Frame::compass(), 90.0), 1.0, 0.0));
+        assert!(close(after_one_second(&Frame::compass(), 0.0), 0.0, 1.0));
+        // Screen coordinates with y down and headings clockwise on screen
+        let screen: Frame = "x:ccw:rad:flip-y".parse().unwrap();
+        assert!(close(after_one_second(&screen, 0.5 * PI), 0.0, 1.0));
+        // The filter's own frame, where a quarter turn faces -y
+        assert!(close(
+            after_one_second(&Frame::default(), 0.5 * PI),
+            0.0,
+            -1.0
+        ));
+
+        for frame in [Frame::ros(), Frame::compass(), screen] {
+            let h = frame.heading_out(frame.heading_in(1.0));
+            assert!((h - 1.0).abs() < 1e-9, "{:?} {}", frame, h);
+        }
+        // Turning counterclockwise in ROS is turning the other way here.
+        assert_eq!(Frame::ros().turn_rate_in(0.5), -0.5);
+        assert_eq!("native".parse::<Frame>(), Ok(Frame::default()));
+        assert!("y:cw".parse::<Frame>().is_err());
+    }
+}
@@ -0,0 +1,870 @@
+//! Whole filter steps on the GPU. Particles stay on the device between
+//! steps: one kernel moves and weights them, one normalizes the weights
+//! and reduces them to an estimate, and one resamples. Only the estimate
+//! comes back each step; [`GpuSync`] decides when the particles do too.
+//!
+//! Each particle draws its noise from a Philox counter-based generator
+//! keyed by the filter's seed and counted by particle and step, so a run
+//! does not depend on how the device schedules threads.
+
+use crate::{
+    rand32,
+    sim::{
+        Bounds, GpsEllipse, GpsLikelihood, ImuMode, Integration, SimParams, SpeedBounds, Topology,
+    },
+    types::{ACoord, CCoord, Particles, VehicleState},
+    uniform,
+};
+use std::str::FromStr;
+use wgpu::util::DeviceExt;
+
+/// The default adapter's device and queue, if there is a usable GPU.
+pub(crate) fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+    let adapter =
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).ok()
+}
+
+/// Storage buffer binding visible to compute shaders
+pub(crate) fn storage(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Uniform buffer binding visible to compute shaders
+pub(crate) fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Copy `bytes` bytes out of `buffer`, which must be `MAP_READ`.
+pub(crate) fn read_back(device: &wgpu::Device, buffer: &wgpu::Buffer, bytes: u64) -> Vec<u8> {
+    let slice = buffer.slice(..bytes);
+    slice.map_async(wgpu::MapMode::Read, |_| ());
+    device.poll(wgpu::Maintain::Wait);
+    let data = slice.get_mapped_range().to_vec();
+    buffer.unmap();
+    data
+}
+
+const WORKGROUP: u32 = 64;
+
+const SHADER: &str = r#"
+const PI: f32 = 3.14159265358979;
+// Stands in for -inf, which WGSL constants cannot hold.
+const IMPOSSIBLE: f32 = -3.0e38;
+
+struct Params {
+    n: u32,
+    step: u32,
+    seed: u32,
+    toroidal: u32,
+    midpoint: u32,
+    box_dim: f32,
+    max_speed: f32,
+    r_sd: f32,
+    a_sd: f32,
+    dt: f32,
+    gps_sd_x: f32,
+    imu_r_sd: f32,
+    imu_t_sd: f32,
+    gps_x: f32,
+    gps_y: f32,
+    imu_r: f32,
+    imu_t: f32,
+    u0: f32,
+    gps_sd_y: f32,
+    gps_rho: f32,
+}
+
+struct Estimate {
+    mean: vec2<f32>,
+    best: vec2<f32>,
+    worst: vec2<f32>,
+    best_weight: f32,
+    worst_weight: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// x, y, speed, heading
+@group(0) @binding(1) var<storage, read_write> state: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> next: array<vec4<f32>>;
+// Log weights after `propagate`, normalized weights after `reduce`
+@group(0) @binding(3) var<storage, read_write> weight: array<f32>;
+@group(0) @binding(4) var<storage, read_write> cdf: array<f32>;
+@group(0) @binding(5) var<storage, read_write> estimate: Estimate;
+
+// High and low words of a * b
+fn mulhilo(a: u32, b: u32) -> vec2<u32> {
+    let a0 = a & 0xffffu;
+    let a1 = a >> 16u;
+    let b0 = b & 0xffffu;
+    let b1 = b >> 16u;
+    let p01 = a0 * b1;
+    let p10 = a1 * b0;
+    let mid = ((a0 * b0) >> 16u) + (p01 & 0xffffu) + (p10 & 0xffffu);
+    return vec2(a1 * b1 + (p01 >> 16u) + (p10 >> 16u) + (mid >> 16u), a * b);
+}
+
+// Philox2x32-10
+fn philox(counter: vec2<u32>, seed: u32) -> vec2<u32> {
+    var c = counter;
+    var k = seed;
+    for (var round = 0u; round < 10u; round++) {
+        let hl = mulhilo(0xd256d193u, c.x);
+        c = vec2(hl.x ^ k ^ c.y, hl.y);
+        k += 0x9e3779b9u;
+    }
+    return c;
+}
+
+// Two standard normal draws for particle i in this step
+fn normals(i: u32) -> vec2<f32> {
+    let bits = philox(vec2(i, params.step), params.seed);
+    let u1 = (f32(bits.x >> 8u) + 1.0) / 16777216.0;
+    let u2 = f32(bits.y >> 8u) / 16777216.0;
+    let r = sqrt(-2.0 * log(u1));
+    return vec2(r * cos(2.0 * PI * u2), r * sin(2.0 * PI * u2));
+}
+
+fn normalize_angle(t: f32) -> f32 {
+    return t - 2.0 * PI * floor(t / (2.0 * PI));
+}
+
+fn angle_delta(a: f32, b: f32) -> f32 {
+    return normalize_angle(a - b + PI) - PI;
+}
+
+fn wrap(x: f32) -> f32 {
+    let side = 2.0 * params.box_dim;
+    return x + params.box_dim - side * floor((x + params.box_dim) / side) - params.box_dim;
+}
+
+// Squared Mahalanobis length of a GPS error
+fn gps_distance_sq(dx: f32, dy: f32) -> f32 {
+    let u = dx / params.gps_sd_x;
+    let rho = params.gps_rho;
+    let v = (dy / params.gps_sd_y - rho * u) / sqrt(1.0 - rho * rho);
+    return u * u + v * v;
+}
+
+// Move s with speed r and heading t, or report the walls it would cross:
+// 1 for x, 2 for y, 3 for both.
+fn bounce(s: vec4<f32>, r: f32, t: f32, out: ptr<function, vec4<f32>>) -> u32 {
+    var v = vec2(r, t);
+    if (params.midpoint != 0u) {
+        v = vec2(0.5 * (s.z + r), normalize_angle(s.w + 0.5 * angle_delta(t, s.w)));
+    }
+    let x0 = s.x + v.x * cos(v.y) * params.dt;
+    let y0 = s.y - v.x * sin(v.y) * params.dt;
+    if (params.toroidal != 0u) {
+        *out = vec4(wrap(x0), wrap(y0), r, t);
+        return 0u;
+    }
+    let x1 = clamp(x0, -params.box_dim, params.box_dim);
+    let y1 = clamp(y0, -params.box_dim, params.box_dim);
+    if (x0 != x1 || y0 != y1) {
+        if (y0 == y1) {
+            return 1u;
+        } else if (x0 == x1) {
+            return 2u;
+        }
+        return 3u;
+    }
+    *out = vec4(x1, y1, r, t);
+    return 0u;
+}
+
+@compute @workgroup_size(64)
+fn propagate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.n) {
+        return;
+    }
+    let s = state[i];
+    let g = normals(i);
+    var r = clamp(s.z + g.x * params.r_sd, 0.0, params.max_speed);
+    var t = normalize_angle(s.w + g.y * params.a_sd);
+    var moved = s;
+    var b = bounce(s, r, t, &moved);
+    if (b != 0u) {
+        r = s.z;
+        t = s.w;
+        b = bounce(s, r, t, &moved);
+        if (b == 1u) {
+            b = bounce(s, r, normalize_angle(PI - t), &moved);
+        } else if (b == 2u) {
+            b = bounce(s, r, normalize_angle(2.0 * PI - t), &moved);
+        } else if (b == 3u) {
+            b = bounce(s, r, normalize_angle(PI + t), &moved);
+        }
+    }
+    state[i] = moved;
+
+    let dx = moved.x - params.gps_x;
+    let dy = moved.y - params.gps_y;
+    var lw = -0.5 * gps_distance_sq(dx, dy);
+    if (params.toroidal != 0u) {
+        lw = -0.5 * gps_distance_sq(wrap(dx), wrap(dy));
+    } else if (abs(moved.x) > params.box_dim || abs(moved.y) > params.box_dim) {
+        lw = IMPOSSIBLE;
+    }
+    let dr = moved.z - params.imu_r;
+    let d = abs(moved.w - params.imu_t);
+    let dth = min(d, abs(d - 2.0 * PI));
+    lw += -0.5 * dr * dr / (params.imu_r_sd * params.imu_r_sd)
+        - 0.5 * dth * dth / (params.imu_t_sd * params.imu_t_sd);
+    let prior = weight[i];
+    if (prior > 0.0) {
+        weight[i] = max(lw + log(prior), IMPOSSIBLE);
+    } else {
+        weight[i] = IMPOSSIBLE;
+    }
+}
+
+const REDUCE: u32 = 256u;
+
+var<workgroup> scratch: array<vec4<f32>, 256>;
+var<workgroup> trig: array<vec4<f32>, 256>;
+var<workgroup> extreme: array<vec4<f32>, 256>;
+var<workgroup> index: array<vec2<u32>, 256>;
+
+// Normalize the log weights, accumulate the estimate and lay out the
+// normalized cumulative weights for `resample`. Runs as one workgroup,
+// each thread owning a contiguous chunk of particles.
+@compute @workgroup_size(256)
+fn reduce(@builtin(local_invocation_index) tid: u32) {
+    let n = params.n;
+    let chunk = (n + REDUCE - 1u) / REDUCE;
+    let lo = min(tid * chunk, n);
+    let hi = min(lo + chunk, n);
+
+    var top = IMPOSSIBLE;
+    for (var i = lo; i < hi; i++) {
+        top = max(top, weight[i]);
+    }
+    scratch[tid] = vec4(top, 0.0, 0.0, 0.0);
+    workgroupBarrier();
+    for (var stride = REDUCE / 2u; stride > 0u; stride /= 2u) {
+        if (tid < stride) {
+            scratch[tid].x = max(scratch[tid].x, scratch[tid + stride].x);
+        }
+        workgroupBarrier();
+    }
+    top = scratch[0].x;
+    workgroupBarrier();
+
+    // Sum, weighted x and y, and the seam-safe angle sums for a torus
+    let k = PI / params.box_dim;
+    var acc = vec4(0.0);
+    var ang = vec4(0.0);
+    var ext = vec4(-1.0, 0.0, 2.0, 0.0);
+    var idx = vec2(0u, 0u);
+    for (var i = lo; i < hi; i++) {
+        let w = exp(weight[i] - top);
+        weight[i] = w;
+        let s = state[i];
+        acc += vec4(w, w * s.x, w * s.y, 0.0);
+        ang += w * vec4(cos(k * s.x), sin(k * s.x), cos(k * s.y), sin(k * s.y));
+        if (w > ext.x) {
+            ext.x = w;
+            idx.x = i;
+        }
+        if (w < ext.z) {
+            ext.z = w;
+            idx.y = i;
+        }
+    }
+    acc.w = acc.x;
+    scratch[tid] = acc;
+    trig[tid] = ang;
+    extreme[tid] = ext;
+    index[tid] = idx;
+    workgroupBarrier();
+
+    // Inclusive scan of the chunk sums in `w`, so each thread knows where
+    // its chunk starts. The other totals then reduce alongside.
+    for (var stride = 1u; stride < REDUCE; stride *= 2u) {
+        var sum = 0.0;
+        if (tid >= stride) {
+            sum = scratch[tid - stride].w;
+        }
+        workgroupBarrier();
+        scratch[tid].w += sum;
+        workgroupBarrier();
+    }
+    let start = scratch[tid].w - acc.x;
+    workgroupBarrier();
+    for (var stride = REDUCE / 2u; stride > 0u; stride /= 2u) {
+        if (tid < stride) {
+            scratch[tid] += vec4(scratch[tid + stride].xyz, 0.0);
+            trig[tid] += trig[tid + stride];
+            let a = extreme[tid];
+            let b = extreme[tid + stride];
+            if (b.x > a.x || (b.x == a.x && index[tid + stride].x < index[tid].x)) {
+                extreme[tid].x = b.x;
+                index[tid].x = index[tid + stride].x;
+            }
+            if (b.z < a.z || (b.z == a.z && index[tid + stride].y < index[tid].y)) {
+                extreme[tid].z = b.z;
+                index[tid].y = index[tid + stride].y;
+            }
+        }
+        workgroupBarrier();
+    }
+    let total = scratch[0].x;
+
+    var running = start;
+    for (var i = lo; i < hi; i++) {
+        running += weight[i];
+        cdf[i] = running / total;
+        weight[i] /= total;
+    }
+    if (tid == 0u) {
+        if (params.toroidal != 0u) {
+            estimate.mean = vec2(atan2(trig[0].y, trig[0].x), atan2(trig[0].w, trig[0].z)) / k;
+        } else {
+            estimate.mean = scratch[0].yz / total;
+        }
+        estimate.best = state[index[0].x].xy;
+        estimate.best_weight = extreme[0].x / total;
+        estimate.worst = state[index[0].y].xy;
+        estimate.worst_weight = extreme[0].z / total;
+    }
+}
+
+// Systematic resampling from `state` into `next`, with equal weights
+@compute @workgroup_size(64)
+fn resample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    let n = params.n;
+    if (i >= n) {
+        return;
+    }
+    let u = (f32(i) + params.u0) / f32(n);
+    var lo = 0u;
+    var hi = n - 1u;
+    while (lo < hi) {
+        let mid = (lo + hi) / 2u;
+        if (cdf[mid] > u) {
+            hi = mid;
+        } else {
+            lo = mid + 1u;
+        }
+    }
+    next[i] = state[lo];
+    weight[i] = 1.0 / f32(n);
+    if (i == 0u) {
+        estimate.best = state[lo].xy;
+        estimate.best_weight = 1.0 / f32(n);
+        estimate.worst = state[lo].xy;
+        estimate.worst_weight = 1.0 / f32(n);
+    }
+}
+"#;
+
+/// When particles resident on the GPU are copied back to the host
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GpuSync {
+    /// Only estimates come back; `BpfState::particles` goes stale until
+    /// `BpfState::sync_gpu`.
+    #[default]
+    EstimatesOnly,
+    /// After every step
+    EveryStep,
+    /// After every `n`th step
+    Every(usize),
+}
+
+impl GpuSync {
+    /// Whether step number `step`, counting from 1, copies particles back
+    pub fn due(self, step: u64) -> bool {
+        match self {
+            Self::EstimatesOnly => false,
+            Self::EveryStep => true,
+            Self::Every(n) => n > 0 && step.is_multiple_of(n as u64),
+        }
+    }
+}
+
+impl FromStr for GpuSync {
+    type Err = String;
+
+    /// Parse `estimates`, `every-step` or `every:N`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "estimates" => Ok(Self::EstimatesOnly),
+            None if s == "every-step" => Ok(Self::EveryStep),
+            Some(("every", n)) => Ok(Self::Every(
+                n.parse()
+                    .map_err(|e| format!("bad sync interval {}: {}", n, e))?,
+            )),
+            _ => Err(format!("unknown GPU sync policy {}", s)),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    n: u32,
+    step: u32,
+    seed: u32,
+    toroidal: u32,
+    midpoint: u32,
+    box_dim: f32,
+    max_speed: f32,
+    r_sd: f32,
+    a_sd: f32,
+    dt: f32,
+    gps_sd_x: f32,
+    imu_r_sd: f32,
+    imu_t_sd: f32,
+    gps_x: f32,
+    gps_y: f32,
+    imu_r: f32,
+    imu_t: f32,
+    u0: f32,
+    gps_sd_y: f32,
+    gps_rho: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct GpuEstimate {
+    pub mean: [f32; 2],
+    pub best: [f32; 2],
+    pub worst: [f32; 2],
+    pub best_weight: f32,
+    pub worst_weight: f32,
+}
+
+/// Device buffers for `n` particles
+struct Buffers {
+    n: usize,
+    /// Ping-pong particle states; `bind_groups[k]` reads `states[k]`
+    states: [wgpu::Buffer; 2],
+    weight: wgpu::Buffer,
+    estimate: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+/// Filter steps on the first GPU wgpu finds. Attach with
+/// `BpfState::enable_gpu`.
+pub struct GpuFilter {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    layout: wgpu::BindGroupLayout,
+    propagate: wgpu::ComputePipeline,
+    reduce: wgpu::ComputePipeline,
+    resample: wgpu::ComputePipeline,
+    params: wgpu::Buffer,
+    buffers: Option<Buffers>,
+    /// Which of the ping-pong buffers holds the particles
+    current: usize,
+    /// Whether the device particles are newer than the host's
+    resident: bool,
+    seed: u32,
+    step: u64,
+}
+
+impl GpuFilter {
+    /// Whether the GPU kernels implement the model `params` describes.
+    /// They leave out obstacle maps, direction tables, non-Gaussian GPS
+    /// likelihoods, soft bounds, speed bounds other than clamping and
+    /// degraded IMUs.
+    pub fn supports(params: &SimParams) -> bool {
+        params.map.is_none()
+            && !params.fast_direction
+            && params.gps_likelihood == GpsLikelihood::Gaussian
+            && params.bounds == Bounds::Hard
+            && params.speed_bounds == SpeedBounds::Clamp
+            && params.imu_mode == ImuMode::Full
+    }
+
+    /// Set up the kernels, or `None` if there is no usable GPU. The seed
+    /// comes from the thread's generator.
+    pub fn new() -> Option<Self> {
+        let (device, queue) = device()?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("filter"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("filter"),
+            entries: &[
+                uniform_entry(0),
+                storage(1, false),
+                storage(2, false),
+                storage(3, false),
+                storage(4, false),
+                storage(5, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("filter"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let (propagate, reduce, resample) = (
+            pipeline("propagate"),
+            pipeline("reduce"),
+            pipeline("resample"),
+        );
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: bytemuck::bytes_of(&<Params as bytemuck::Zeroable>::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Some(Self {
+            device,
+            queue,
+            layout,
+            propagate,
+            reduce,
+            resample,
+            params,
+            buffers: None,
+            current: 0,
+            resident: false,
+            seed: rand32(),
+            step: 0,
+        })
+    }
+
+    /// Steps run so far
+    pub fn steps(&self) -> u64 {
+        self.step
+    }
+
+    /// Whether the device holds particles newer than the host's
+    pub fn is_resident(&self) -> bool {
+        self.resident
+    }
+
+    fn buffers(&self, n: usize) -> Buffers {
+        let buffer = |label, size: usize, usage| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size.max(4) as u64,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let usage = wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC;
+        let states = [
+            buffer("state", n * 16, usage),
+            buffer("state", n * 16, usage),
+        ];
+        let weight = buffer("weight", n * 4, usage);
+        let cdf = buffer("cdf", n * 4, wgpu::BufferUsages::STORAGE);
+        let estimate = buffer("estimate", size_of::<GpuEstimate>(), usage);
+        let readback = buffer(
+            "readback",
+            size_of::<GpuEstimate>() + n * 20,
+            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        );
+        let bind_group = |from: usize| {
+            let entries = [
+                &self.params,
+                &states[from],
+                &states[1 - from],
+                &weight,
+                &cdf,
+                &estimate,
+            ];
+            let entries: Vec<_> = entries
+                .iter()
+                .enumerate()
+                .map(|(i, b)| wgpu::BindGroupEntry {
+                    binding: i as u32,
+                    resource: b.as_entire_binding(),
+                })
+                .collect();
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("filter"),
+                layout: &self.layout,
+                entries: &entries,
+            })
+        };
+        let bind_groups = [bind_group(0), bind_group(1)];
+        Buffers {
+            n,
+            states,
+            weight,
+            estimate,
+            readback,
+            bind_groups,
+        }
+    }
+
+    /// Copy the host's particles to the device.
+    pub(crate) fn upload(&mut self, particles: &Particles) {
+        let n = particles.len();
+        if self.buffers.as_ref().is_none_or(|b| b.n != n) {
+            self.buffers = Some(self.buffers(n));
+        }
+        let buffers = self.buffers.as_ref().unwrap();
+        let (mut states, mut weights) = (Vec::with_capacity(n), Vec::with_capacity(n));
+        for p in &particles.data {
+            let vel = p.state.vel();
+            states.push([
+                p.state.posn.x as f32,
+                p.state.posn.y as f32,
+                vel.r as f32,
+                vel.t as f32,
+            ]);
+            weights.push(p.weight as f32);
+        }
+        self.current = 0;
+        self.queue
+            .write_buffer(&buffers.states[0], 0, bytemuck::cast_slice(&states));
+        self.queue
+            .write_buffer(&buffers.weight, 0, bytemuck::cast_slice(&weights));
+        self.resident = true;
+    }
+
+    /// Copy the device's particles back to the host, which becomes the
+    /// source of truth until the next step.
+    pub(crate) fn download(&mut self, particles: &mut Particles) {
+        let Some(buffers) = self.buffers.as_ref().filter(|_| self.resident) else {
+            return;
+        };
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        let offset = size_of::<GpuEstimate>() as u64;
+        Self::copy_particles(&mut encoder, buffers, self.current, offset);
+        self.queue.submit([encoder.finish()]);
+        let bytes = read_back(
+            &self.device,
+            &buffers.readback,
+            offset + buffers.n as u64 * 20,
+        );
+        Self::unpack(&bytes[offset as usize..], buffers.n, particles);
+        self.resident = false;
+    }
+
+    fn copy_particles(
+        encoder: &mut wgpu::CommandEncoder,
+        buffers: &Buffers,
+        current: usize,
+        offset: u64,
+    ) {
+        let states = buffers.n as u64 * 16;
+        let (readback, current) = (&buffers.readback, &buffers.states[current]);
+        encoder.copy_buffer_to_buffer(current, 0, readback, offset, states);
+        encoder.copy_buffer_to_buffer(
+            &buffers.weight,
+            0,
+            readback,
+            offset + states,
+            buffers.n as u64 * 4,
+        );
+    }
+
+    fn unpack(bytes: &[u8], n: usize, particles: &mut Particles) {
+        let (states, weights) = bytes.split_at(n * 16);
+        let states: &[[f32; 4]] = bytemuck::cast_slice(states);
+        let weights: &[f32] = bytemuck::cast_slice(&weights[..n * 4]);
+        for ((p, s), &w) in particles.data.iter_mut().zip(states).zip(weights) {
+            let posn = CCoord {
+                x: s[0] as f64,
+                y: s[1] as f64,
+            };
+            let vel = ACoord {
+                r: s[2] as f64,
+                t: s[3] as f64,
+            };
+            p.state = VehicleState::new(posn, vel);
+            p.weight = w as f64;
+        }
+    }
+
+    /// Run one step on the device particles, uploading `particles` first
+    /// if the device has none. With `download` the particles come back
+    /// into `particles` afterwards and the host becomes the source of
+    /// truth again.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn step(
+        &mut self,
+        params: &SimParams,
+        particles: &mut Particles,
+        dt: f64,
+        gps: CCoord,
+        imu: ACoord,
+        resample: bool,
+        download: bool,
+    ) -> GpuEstimate {
+        if !self.resident {
+            self.upload(particles);
+        }
+        self.step += 1;
+        let buffers = self.buffers.as_ref().unwrap();
+        let n = buffers.n;
+        let scale = params.process_noise_scale(dt) * 9.0;
+        let (imu_r_sd, imu_t_sd) = params.imu_likelihood_sd(dt);
+        let gps_ellipse = params.gps_ellipse.unwrap_or(GpsEllipse {
+            sd_x: params.gps_var,
+            sd_y: params.gps_var,
+            rho: 0.0,
+        });
+        let uniforms = Params {
+            n: n as u32,
+            step: self.step as u32,
+            seed: self.seed,
+            toroidal: (params.topology == Topology::Toroidal) as u32,
+            midpoint: (params.integration == Integration::Midpoint) as u32,
+            box_dim: params.box_dim as f32,
+            max_speed: params.max_speed as f32,
+            r_sd: (params.rvar * scale) as f32,
+            a_sd: (params.avar * scale) as f32,
+            dt: dt as f32,
+            gps_sd_x: gps_ellipse.sd_x as f32,
+            imu_r_sd: imu_r_sd as f32,
+            imu_t_sd: imu_t_sd as f32,
+            gps_x: gps.x as f32,
+            gps_y: gps.y as f32,
+            imu_r: imu.r as f32,
+            imu_t: imu.t as f32,
+            u0: uniform() as f32,
+            gps_sd_y: gps_ellipse.sd_y as f32,
+            gps_rho: gps_ellipse.rho as f32,
+        };
+        self.queue
+            .write_buffer(&self.params, 0, bytemuck::bytes_of(&uniforms));
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        let groups = (n as u32).div_ceil(WORKGROUP);
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_bind_group(0, &buffers.bind_groups[self.current], &[]);
+            pass.set_pipeline(&self.propagate);
+            pass.dispatch_workgroups(groups, 1, 1);
+            pass.set_pipeline(&self.reduce);
+            pass.dispatch_workgroups(1, 1, 1);
+            if resample {
+                pass.set_pipeline(&self.resample);
+                pass.dispatch_workgroups(groups, 1, 1);
+            }
+        }
+        let current = if resample {
+            1 - self.current
+        } else {
+            self.current
+        };
+        let offset = size_of::<GpuEstimate>() as u64;
+        encoder.copy_buffer_to_buffer(&buffers.estimate, 0, &buffers.readback, 0, offset);
+        let mut bytes = offset;
+        if download {
+            Self::copy_particles(&mut encoder, buffers, current, offset);
+            bytes += n as u64 * 20;
+        }
+        self.queue.submit([encoder.finish()]);
+        let data = read_back(&self.device, &buffers.readback, bytes);
+        self.current = current;
+        if download {
+            Self::unpack(&data[offset as usize..], n, particles);
+            self.resident = false;
+        }
+        bytemuck::pod_read_unaligned(&data[..offset as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BpfState;
+
+    fn filter(sync: GpuSync) -> Option<BpfState> {
+        crate::seed_thread_rng(4657);
+        let mut bpf = BpfState::new("regular", false, 500, 0, false, 1, SimParams::default());
+        bpf.reporter = None;
+        bpf.init_particles();
+        bpf.gpu_sync = sync;
+        if !bpf.enable_gpu() {
+            eprintln!("no GPU adapter, skipping");
+            return None;
+        }
+        Some(bpf)
+    }
+
+    #[test]
+    fn gpu_filter_tracks() {
+        let Some(mut bpf) = filter(GpuSync::EstimatesOnly) else {
+            return;
+        };
+        let params = SimParams::default();
+        let mut vehicle = VehicleState::default();
+        vehicle.init_state(&params);
+        let (dt, mut sq) = (0.1, 0.0);
+        for i in 0..200 {
+            vehicle.update_state(&params, dt, 0);
+            let gps = vehicle.gps_measure(&params);
+            let imu = vehicle.imu_measure(&params, dt);
+            let est = bpf.observe(i as f64 * dt, dt, gps, imu);
+            assert!(est.mean.x.is_finite() && est.mean.y.is_finite());
+            if i >= 20 {
+                sq += (est.mean.x - vehicle.posn.x).powi(2) + (est.mean.y - vehicle.posn.y).powi(2);
+            }
+        }
+        let rms = (sq / 180.0).sqrt();
+        assert!(rms < 1.0, "{}", rms);
+    }
+
+    #[test]
+    fn sync_policy_decides_when_particles_return() {
+        let Some(mut bpf) = filter(GpuSync::Every(2)) else {
+            return;
+        };
+        let before: Vec<_> = bpf.particles().iter().map(|p| p.state.posn).collect();
+        let (gps, imu) = (CCoord { x: 1.0, y: 1.0 }, ACoord { r: 0.5, t: 0.5 });
+        bpf.observe(0.0, 0.1, gps, imu);
+        let stale: Vec<_> = bpf.particles().iter().map(|p| p.state.posn).collect();
+        assert_eq!(before, stale);
+        let est = bpf.observe(0.1, 0.1, gps, imu);
+        let moved: Vec<_> = bpf.particles().iter().map(|p| p.state.posn).collect();
+        assert_ne!(before, moved);
+        // Resampled every step, so the host sees equal weights and the
+        // estimate's first particle.
+        let n = bpf.particles().len() as f64;
+        assert!(
+            bpf.particles()
+                .iter()
+                .all(|p| (p.weight * n - 1.0).abs() < 1e-5)
+        );
+        assert!((est.best.x - moved[0].x).abs() < 1e-6);
+        assert_eq!(GpuSync::Every(2), "every:2".parse().unwrap());
+    }
+}
@@ -0,0 +1,205 @@
+//! Distributions particles start from. Uniform over the box assumes
+//! nothing about where the vehicle is; when something is known, starting
+//! the particles there lets the filter converge in far fewer steps.
+
+use crate::{
+    fp::{cos, sin},
+    gaussian,
+    sim::SimParams,
+    types::{CCoord, VehicleState, initial_velocity},
+    uniform,
+};
+use std::{f64::consts::PI, str::FromStr};
+
+/// Where `BpfState::init_particles_with` puts the particles. The built-in
+/// distributions redraw positions outside the box or inside obstacles,
+/// giving up eventually, and give each particle the usual random starting
+/// velocity.
+#[derive(Default)]
+pub enum InitDistribution {
+    /// Uniform over the box
+    #[default]
+    Uniform,
+    /// Normal about `mean` with standard deviation `sd` along each axis
+    Gaussian { mean: CCoord, sd: f64 },
+    /// Uniform over the annulus between radii `inner` and `outer` about
+    /// `center`
+    Ring {
+        center: CCoord,
+        inner: f64,
+        outer: f64,
+    },
+    /// Whole states drawn by a closure, such as resampled from an earlier
+    /// run's particles
+    Samples(Box<dyn FnMut() -> VehicleState>),
+}
+
+impl InitDistribution {
+    fn position(&self) -> CCoord {
+        match *self {
+            Self::Gaussian { mean, sd } => CCoord {
+                x: mean.x + gaussian(sd),
+                y: mean.y + gaussian(sd),
+            },
+            Self::Ring {
+                center,
+                inner,
+                outer,
+            } => {
+                // Uniform over the area, so the radius squared is uniform.
+                let r = (inner * inner + uniform() * (outer * outer - inner * inner)).sqrt();
+                let a = uniform() * 2.0 * PI;
+                CCoord {
+                    x: center.x + r * cos(a),
+                    y: center.y + r * sin(a),
+                }
+            }
+            Self::Uniform | Self::Samples(_) => unreachable!("not drawn by position"),
+        }
+    }
+
+    /// Draw one particle's starting state, and whether it is inside the
+    /// box and out of obstacles. Closures are trusted to say where to
+    /// start.
+    pub fn sample(&mut self, params: &SimParams) -> (VehicleState, bool) {
+        match self {
+            Self::Uniform => {
+                let mut state = VehicleState::default();
+                let free = state.init_state(params);
+                (state, free)
+            }
+            Self::Samples(draw) => (draw(), true),
+            _ => {
+                let free = |posn: CCoord| {
+                    posn.x.abs() <= params.box_dim
+                        && posn.y.abs() <= params.box_dim
+                        && !params.blocked(posn.x, posn.y)
+                };
+                let mut posn = self.position();
+                for _ in 1..1000 {
+                    if free(posn) {
+                        break;
+                    }
+                    posn = self.position();
+                }
+                (VehicleState::new(posn, initial_velocity()), free(posn))
+            }
+        }
+    }
+}
+
+impl FromStr for InitDistribution {
+    type Err = String;
+
+    /// Parse `uniform`, `gaussian:X,Y,SD` or `ring:X,Y,INNER,OUTER`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "uniform" {
+            return Ok(Self::Uniform);
+        }
+        let (name, args) = s
+            .split_once(':')
+            .ok_or_else(|| format!("unknown initial distribution {}", s))?;
+        let args = args
+            .split(',')
+            .map(|a| {
+                a.trim()
+                    .parse::<f64>()
+                    .map_err(|e| format!("bad {} argument {:?}: {}", name, a, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match (name, &args[..]) {
+            ("gaussian", &[x, y, sd]) if sd >= 0.0 => Ok(Self::Gaussian {
+                mean: CCoord { x, y },
+                sd,
+            }),
+            ("ring", &[x, y, inner, outer]) if 0.0 <= inner && inner <= outer => Ok(Self::Ring {
+                center: CCoord { x, y },
+                inner,
+                outer,
+            }),
+            ("gaussian", _) => Err(format!("{} is not gaussian:X,Y,SD with SD >= 0", s)),
+            ("ring", _) => Err(format!(
+                "{} is not ring:X,Y,INNER,OUTER with 0 <= INNER <= OUTER",
+                s
+            )),
+            _ => Err(format!("unknown initial distribution {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ACoord, BpfState};
+
+    #[test]
+    fn initial_distributions() {
+        crate::seed_thread_rng(4677);
+        let params = SimParams::default();
+        let mut bpf = BpfState::new("regular", false, 2000, 0, false, 1, params.clone());
+        bpf.reporter = None;
+
+        let mut ring: InitDistribution = "ring:1,-2,3,4".parse().unwrap();
+        bpf.init_particles_with(&mut ring);
+        let radii: Vec<f64> = bpf
+            .particles()
+            .iter()
+            .map(|p| (p.state.posn.x - 1.0).hypot(p.state.posn.y + 2.0))
+            .collect();
+        assert!(radii.iter().all(|r| (3.0..=4.0).contains(r)));
+        // Half the area lies beyond the radius sqrt(12.5).
+        let outer = radii.iter().filter(|&&r| r > 12.5f64.sqrt()).count();
+        assert!((outer as f64 / 2000.0 - 0.5).abs() < 0.05, "{}", outer);
+
+        let mut near = InitDistribution::Gaussian {
+            mean: CCoord { x: 5.0, y: 5.0 },
+            sd: 0.5,
+        };
+        bpf.init_particles_with(&mut near);
+        let n = bpf.particles().len() as f64;
+        let mean_x = bpf.particles().iter().map(|p| p.state.posn.x).sum::<f64>() / n;
+        assert!((mean_x - 5.0).abs() < 0.1, "{}", mean_x);
+        assert!(bpf.particles().iter().all(|p| p.weight == 1.0 / n));
+
+        let start = VehicleState::new(CCoord { x: 7.0, y: 8.0 }, ACoord { r: 1.0, t: 0.0 });
+        let mut fixed = InitDistribution::Samples(Box::new(move || start));
+        bpf.init_particles_with(&mut fixed);
+        assert!(bpf.particles().iter().all(|p| p.state.posn.x == 7.0));
+
+        assert!("gaussian:1,2".parse::<InitDistribution>().is_err());
+        assert!("ring:0,0,2,1".parse::<InitDistribution>().is_err());
+        assert!(matches!("uniform".parse(), Ok(InitDistribution::Uniform)));
+    }
+
+    #[test]
+    fn stuck_starts_are_counted() {
+        crate::seed_thread_rng(4622);
+        let params = SimParams {
+            map: Some(std::sync::Arc::new(crate::map::OccupancyMap::new(
+                2,
+                1,
+                vec![true, true],
+            ))),
+            ..Default::default()
+        };
+        let mut bpf = BpfState::new("regular", false, 20, 0, false, 1, params.clone());
+        bpf.reporter = None;
+        assert_eq!(bpf.init_particles(), 20);
+        let mut near: InitDistribution = "gaussian:0,0,1".parse().unwrap();
+        assert_eq!(bpf.init_particles_with(&mut near), 20);
+
+        // Half the box free: a spot is found there.
+        let half = SimParams {
+            map: Some(std::sync::Arc::new(crate::map::OccupancyMap::new(
+                2,
+                1,
+                vec![true, false],
+            ))),
+            ..Default::default()
+        };
+        let mut state = VehicleState::default();
+        assert!(state.init_state(&half));
+        assert!(state.posn.x >= 0.0);
+        assert!(!state.init_state(&params));
+    }
+}
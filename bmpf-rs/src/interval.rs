@@ -0,0 +1,258 @@
+//! How often to resample. Resampling every step throws away weight
+//! information and adds the resampler's noise; resampling too rarely lets
+//! the weights collapse onto a few particles. The right interval depends
+//! on how fast the effective sample size decays, which varies with the
+//! data and the resampler, so `AutoInterval` measures the decay as the
+//! filter runs and `sweep` compares fixed intervals on one simulated track.
+
+use crate::{
+    fp::ln,
+    progress::Monitor,
+    sim::SimParams,
+    types::{ACoord, BpfState, CCoord, ParticleInfo, VehicleState},
+};
+
+/// Effective sample size of `particles` as a fraction of their number:
+/// one for equal weights, `1 / n` for all the weight on one particle, and
+/// one when there is no weight at all, as nothing has degenerated
+pub fn ess_fraction(particles: &[ParticleInfo]) -> f64 {
+    let (sum, sum_sq) = particles.iter().fold((0.0, 0.0), |(s, s2), p| {
+        (s + p.weight, s2 + p.weight * p.weight)
+    });
+    if sum_sq == 0.0 {
+        return 1.0;
+    }
+    sum * sum / (particles.len() as f64 * sum_sq)
+}
+
+/// Picks `BpfState`'s resample interval from the effective sample size,
+/// so that it decays to about `target` between resamplings
+#[derive(Clone, Debug)]
+pub struct AutoInterval {
+    /// Fraction of the particles left effective when resampling is due
+    pub target: f64,
+    pub min: usize,
+    pub max: usize,
+    /// Weight of the newest step in the running decay rate
+    pub smoothing: f64,
+    /// Mean `-ln` of the effective fraction lost per weighted step
+    rate: Option<f64>,
+    /// Effective fraction before the next step weighs the particles
+    last: f64,
+}
+
+impl Default for AutoInterval {
+    fn default() -> Self {
+        Self {
+            target: 0.5,
+            min: 1,
+            max: 64,
+            smoothing: 0.1,
+            rate: None,
+            last: 1.0,
+        }
+    }
+}
+
+impl AutoInterval {
+    /// Take the effective fraction just after a step weighed the particles.
+    pub fn observe(&mut self, ess: f64) {
+        let decay = ln(self.last / ess).max(0.0);
+        self.rate = Some(match self.rate {
+            None => decay,
+            Some(rate) => rate + self.smoothing * (decay - rate),
+        });
+        self.last = ess;
+    }
+
+    /// Take the effective fraction just after resampling.
+    pub fn resampled(&mut self, ess: f64) {
+        self.last = ess;
+    }
+
+    /// Running decay rate, `None` before the first weighted step
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Weighted steps between resamplings at the current decay rate
+    pub fn interval(&self) -> usize {
+        match self.rate {
+            None => self.min,
+            Some(rate) if rate <= 0.0 => self.max,
+            Some(rate) => {
+                let steps = -ln(self.target) / rate;
+                (steps.min(self.max as f64) as usize).clamp(self.min, self.max)
+            }
+        }
+    }
+}
+
+/// How one resample interval did on the sweep's track
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IntervalReport {
+    /// The fixed interval, or `None` for `AutoInterval`
+    pub interval: Option<usize>,
+    /// Root mean square distance from the estimate to the vehicle, after
+    /// the first fifth of the steps
+    pub rms: f64,
+    pub resamples: usize,
+    /// Mean effective fraction of the weighted particles
+    pub mean_ess: f64,
+    /// Steps run, fewer than asked for if the sweep was cancelled
+    pub steps: usize,
+}
+
+/// Run `nparticles` particles resampled by `resampler` along one simulated
+/// track of `steps` steps, once per interval in `intervals` and once more
+/// with `AutoInterval`.
+pub fn sweep(
+    resampler: &str,
+    nparticles: usize,
+    params: &SimParams,
+    steps: usize,
+    dt: f64,
+    intervals: &[usize],
+) -> Vec<IntervalReport> {
+    let mut monitor = Monitor::default();
+    sweep_with(
+        resampler,
+        nparticles,
+        params,
+        steps,
+        dt,
+        intervals,
+        &mut monitor,
+    )
+}
+
+/// `sweep`, counting steps over all the runs to `monitor`. Once cancelled
+/// it reports the run in progress on the steps it took, and leaves out
+/// the runs after it.
+pub fn sweep_with(
+    resampler: &str,
+    nparticles: usize,
+    params: &SimParams,
+    steps: usize,
+    dt: f64,
+    intervals: &[usize],
+    monitor: &mut Monitor,
+) -> Vec<IntervalReport> {
+    let mut vehicle = VehicleState::default();
+    vehicle.init_state(params);
+    let track: Vec<(CCoord, CCoord, ACoord)> = (0..steps)
+        .map(|_| {
+            vehicle.update_state(params, dt, 0);
+            (
+                vehicle.posn,
+                vehicle.gps_measure(params),
+                vehicle.imu_measure(params, dt),
+            )
+        })
+        .collect();
+    let total = steps * (intervals.len() + 1);
+    let run = |interval: Option<usize>, before: usize, monitor: &mut Monitor| {
+        let mut bpf = BpfState::new(
+            resampler,
+            false,
+            nparticles,
+            0,
+            false,
+            interval.unwrap_or(1),
+            params.clone(),
+        );
+        bpf.reporter = None;
+        if interval.is_none() {
+            bpf.auto_interval = Some(AutoInterval::default());
+        }
+        bpf.init_particles();
+        let burn_in = steps / 5;
+        let (mut sum_sq, mut resamples, mut ess, mut done) = (0.0, 0, 0.0, 0);
+        for (i, &(posn, gps, imu)) in track.iter().enumerate() {
+            let est = bpf.observe((i + 1) as f64 * dt, dt, gps, imu);
+            let view = bpf.debug_view();
+            resamples += view.resampled_from.is_some() as usize;
+            ess += ess_fraction(view.resampled_from.unwrap_or(view.particles));
+            if i >= burn_in {
+                let (dx, dy) = (est.mean.x - posn.x, est.mean.y - posn.y);
+                sum_sq += dx * dx + dy * dy;
+            }
+            done += 1;
+            if !monitor.step(before + done, Some(total)) {
+                break;
+            }
+        }
+        IntervalReport {
+            interval,
+            rms: (sum_sq / done.saturating_sub(burn_in).max(1) as f64).sqrt(),
+            resamples,
+            mean_ess: ess / done.max(1) as f64,
+            steps: done,
+        }
+    };
+    let mut reports = Vec::new();
+    for interval in intervals.iter().map(|&i| Some(i)).chain([None]) {
+        if monitor.cancel.is_cancelled() {
+            break;
+        }
+        reports.push(run(interval, reports.len() * steps, monitor));
+    }
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_follow_the_decay() {
+        crate::seed_thread_rng(4683);
+        let mut auto = AutoInterval::default();
+        assert_eq!(auto.interval(), 1);
+        // Losing a tenth of the effective fraction a step halves it in
+        // about six and a half steps.
+        let mut ess = 1.0;
+        for _ in 0..20 {
+            ess *= 0.9;
+            auto.observe(ess);
+        }
+        assert!((auto.rate().unwrap() + ln(0.9)).abs() < 1e-9);
+        assert_eq!(auto.interval(), 6);
+        let mut steady = AutoInterval::default();
+        steady.observe(1.0);
+        assert_eq!(steady.interval(), 64);
+
+        let equal = vec![
+            ParticleInfo {
+                weight: 0.1,
+                ..Default::default()
+            };
+            10
+        ];
+        assert!((ess_fraction(&equal) - 1.0).abs() < 1e-12);
+
+        let reports = sweep("regular", 300, &SimParams::default(), 300, 0.01, &[1, 4]);
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].resamples, 300);
+        assert_eq!(reports[1].resamples, 75);
+        assert!(reports[1].mean_ess < reports[0].mean_ess);
+        let auto = reports[2];
+        assert_eq!(auto.interval, None);
+        assert!(0 < auto.resamples && auto.resamples < 300, "{:?}", auto);
+        assert!(reports.iter().all(|r| r.rms < 1.4), "{:?}", reports);
+
+        // Cancelling partway through the second run keeps what it did.
+        let mut monitor = Monitor::default();
+        let cancel = monitor.cancel.clone();
+        monitor.callback = Some(Box::new(move |p| {
+            if p.done == 150 {
+                cancel.cancel();
+            }
+        }));
+        monitor.every = std::time::Duration::ZERO;
+        let params = SimParams::default();
+        let reports = sweep_with("regular", 100, &params, 100, 0.01, &[1, 2], &mut monitor);
+        assert_eq!(reports.len(), 2);
+        assert_eq!((reports[0].steps, reports[1].steps), (100, 50));
+    }
+}
@@ -0,0 +1,211 @@
+//! Geodetic fixes from real GPS logs and their projection onto the
+//! filter's plane: x east and y north of an origin, in metres or a
+//! multiple of them.
+
+use super::{Reading, Tagged, invalid};
+use crate::{
+    sim::normalize_angle,
+    types::{ACoord, CCoord},
+};
+use std::{f64::consts::PI, io, str::FromStr};
+
+/// WGS84 semi-major axis in metres
+const WGS84_A: f64 = 6_378_137.0;
+/// WGS84 first eccentricity squared
+const WGS84_E2: f64 = 6.694_379_990_14e-3;
+
+/// One position from a GPS log
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fix {
+    /// Milliseconds since some epoch; only differences matter
+    pub t_ms: i64,
+    /// Degrees north
+    pub lat: f64,
+    /// Degrees east
+    pub lon: f64,
+    /// Speed over ground in metres per second and course in degrees
+    /// clockwise from true north, when the log has them
+    pub velocity: Option<(f64, f64)>,
+}
+
+/// Local tangent plane at an origin, accurate to a few metres over tens of
+/// kilometres
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LocalPlane {
+    /// Degrees north
+    pub lat: f64,
+    /// Degrees east
+    pub lon: f64,
+    /// Metres per filter unit, to fit a log into the filter's box
+    pub metres_per_unit: f64,
+}
+
+impl LocalPlane {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self {
+            lat,
+            lon,
+            metres_per_unit: 1.0,
+        }
+    }
+
+    /// Plane at the first fix, if any
+    pub fn at_first(fixes: &[Fix]) -> Option<Self> {
+        fixes.first().map(|fix| Self::new(fix.lat, fix.lon))
+    }
+
+    /// Metres per radian of latitude and of longitude at the origin
+    fn radii(&self) -> (f64, f64) {
+        let phi = self.lat.to_radians();
+        let w = 1.0 - WGS84_E2 * phi.sin().powi(2);
+        let meridian = WGS84_A * (1.0 - WGS84_E2) / w.powf(1.5);
+        let normal = WGS84_A / w.sqrt();
+        (meridian, normal * phi.cos())
+    }
+
+    pub fn project(&self, lat: f64, lon: f64) -> CCoord {
+        let (north, east) = self.radii();
+        // The short way around the antimeridian
+        let dlon = (lon - self.lon + 180.0).rem_euclid(360.0) - 180.0;
+        CCoord {
+            x: dlon.to_radians() * east / self.metres_per_unit,
+            y: (lat - self.lat).to_radians() * north / self.metres_per_unit,
+        }
+    }
+
+    /// Latitude and longitude of a point on the plane
+    pub fn unproject(&self, c: CCoord) -> (f64, f64) {
+        let (north, east) = self.radii();
+        let lat = self.lat + (c.y * self.metres_per_unit / north).to_degrees();
+        let lon = self.lon + (c.x * self.metres_per_unit / east).to_degrees();
+        (lat, (lon + 180.0).rem_euclid(360.0) - 180.0)
+    }
+
+    /// The filter's measurement stream for `fixes`, timed from the first:
+    /// a GPS reading per fix, preceded by an IMU reading of its velocity
+    /// where the log has one.
+    pub fn readings(&self, fixes: &[Fix]) -> io::Result<Vec<Tagged>> {
+        let Some(t0) = fixes.first().map(|fix| fix.t_ms) else {
+            return Ok(Vec::new());
+        };
+        let mut out = Vec::with_capacity(fixes.len());
+        for fix in fixes {
+            let t_ms = i32::try_from(fix.t_ms - t0).map_err(|_| {
+                invalid(format!(
+                    "fix {} ms from the first is out of range",
+                    fix.t_ms - t0
+                ))
+            })?;
+            if let Some((speed, course)) = fix.velocity {
+                // Course runs clockwise from north; the filter's headings run
+                // clockwise from east.
+                let imu = ACoord {
+                    r: speed / self.metres_per_unit,
+                    t: normalize_angle(course.to_radians() - 0.5 * PI),
+                };
+                out.push(Tagged {
+                    t_ms,
+                    reading: Reading::Imu(imu),
+                });
+            }
+            out.push(Tagged {
+                t_ms,
+                reading: Reading::Gps(self.project(fix.lat, fix.lon)),
+            });
+        }
+        Ok(out)
+    }
+}
+
+impl FromStr for LocalPlane {
+    type Err = String;
+
+    /// Parse `LAT,LON` or `LAT,LON,METRES_PER_UNIT`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s
+            .split(',')
+            .map(|f| {
+                f.trim()
+                    .parse::<f64>()
+                    .map_err(|e| format!("bad origin field {:?}: {}", f, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match fields[..] {
+            [lat, lon] => Ok(Self::new(lat, lon)),
+            [lat, lon, metres_per_unit] => Ok(Self {
+                lat,
+                lon,
+                metres_per_unit,
+            }),
+            _ => Err(format!("origin {:?} is not LAT,LON[,METRES_PER_UNIT]", s)),
+        }
+    }
+}
+
+/// Days from 1970-01-01 to a proleptic Gregorian date
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_plane_distances() {
+        let plane = LocalPlane::new(45.0, 7.0);
+        // A minute of latitude is about a nautical mile at 45 degrees.
+        let c = plane.project(45.0 + 1.0 / 60.0, 7.0);
+        assert!(c.x.abs() < 1e-9 && (c.y - 1852.2).abs() < 0.5, "{:?}", c);
+        // East-west distances shrink with the cosine of the latitude.
+        let c = plane.project(45.0, 7.001);
+        assert!((c.x - 78.85).abs() < 0.05, "{:?}", c);
+        let (lat, lon) = plane.unproject(CCoord {
+            x: 500.0,
+            y: -300.0,
+        });
+        let back = plane.project(lat, lon);
+        assert!((back.x - 500.0).abs() < 1e-6 && (back.y + 300.0).abs() < 1e-6);
+
+        // Across the antimeridian, and in kilometres
+        let plane: LocalPlane = "0,179.999,1000".parse().unwrap();
+        let c = plane.project(0.0, -179.999);
+        assert!((c.x - 0.2226).abs() < 1e-3, "{:?}", c);
+        assert!("45".parse::<LocalPlane>().is_err());
+    }
+
+    #[test]
+    fn readings_from_fixes() {
+        let fixes = [
+            Fix {
+                t_ms: 1_000,
+                lat: 45.0,
+                lon: 7.0,
+                velocity: Some((2.0, 90.0)),
+            },
+            Fix {
+                t_ms: 2_000,
+                lat: 45.0,
+                lon: 7.0,
+                velocity: None,
+            },
+        ];
+        let plane = LocalPlane::at_first(&fixes).unwrap();
+        let readings = plane.readings(&fixes).unwrap();
+        assert_eq!(readings.len(), 3);
+        // Due east is heading zero.
+        let Reading::Imu(imu) = readings[0].reading else {
+            panic!("{:?}", readings[0])
+        };
+        assert_eq!(imu.r, 2.0);
+        assert!(imu.t.abs() < 1e-12 || (imu.t - 2.0 * PI).abs() < 1e-12);
+        assert_eq!(readings[1].reading, Reading::Gps(CCoord::default()));
+        assert_eq!(readings[2].t_ms, 1_000);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+    }
+}
@@ -0,0 +1,151 @@
+//! GPX tracks: the timed `trkpt` points of every track, with speed and
+//! course where the file has GPX 1.0's `speed` and `course` elements.
+//! Only as much XML as GPX needs is understood.
+
+use super::{
+    geo::{Fix, days_from_civil},
+    invalid,
+};
+use std::io::{self, Read};
+
+/// Value of attribute `name` in a start tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(at) = rest.find(name) {
+        let preceded = rest[..at].ends_with(|c: char| c.is_whitespace());
+        rest = &rest[at + name.len()..];
+        let Some(value) = rest.trim_start().strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        if preceded {
+            return value[1..].split(quote).next();
+        }
+    }
+    None
+}
+
+/// Text of the first `name` element in `body`
+fn element<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let start = body.find(&format!("<{}>", name))? + name.len() + 2;
+    let len = body[start..].find(&format!("</{}>", name))?;
+    Some(body[start..start + len].trim())
+}
+
+/// Milliseconds since the Unix epoch of an ISO 8601 time such as
+/// `2024-05-01T12:00:00.250Z` or `2024-05-01T14:00:00+02:00`
+fn timestamp(s: &str) -> io::Result<i64> {
+    let bad = || invalid(format!("bad GPX time {:?}", s));
+    let (date, time) = s.split_once('T').ok_or_else(bad)?;
+    let num = |s: &str| s.parse::<i64>().map_err(|_| bad());
+    let [year, month, day] = date.splitn(3, '-').collect::<Vec<_>>()[..] else {
+        return Err(bad());
+    };
+    let (time, offset_ms) = match time.find(['Z', '+', '-']) {
+        Some(at) if &time[at..] == "Z" => (&time[..at], 0),
+        Some(at) => {
+            let (hh, mm) = time[at + 1..].split_once(':').ok_or_else(bad)?;
+            let offset = (num(hh)? * 60 + num(mm)?) * 60_000;
+            let sign = if time[at..].starts_with('-') { -1 } else { 1 };
+            (&time[..at], sign * offset)
+        }
+        None => (time, 0),
+    };
+    let [hh, mm, ss] = time.splitn(3, ':').collect::<Vec<_>>()[..] else {
+        return Err(bad());
+    };
+    let seconds = ss.parse::<f64>().map_err(|_| bad())?;
+    let days = days_from_civil(num(year)?, num(month)?, num(day)?);
+    let minutes = (days * 24 + num(hh)?) * 60 + num(mm)?;
+    Ok(minutes * 60_000 + (seconds * 1000.0).round() as i64 - offset_ms)
+}
+
+/// Read the fixes in a GPX file, timed from the Unix epoch. Every track
+/// point must have a time.
+pub fn read<R: Read>(mut reader: R) -> io::Result<Vec<Fix>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let mut fixes = Vec::new();
+    let mut rest = text.as_str();
+    while let Some(at) = rest.find("<trkpt") {
+        rest = &rest[at + "<trkpt".len()..];
+        let tag_end = rest
+            .find('>')
+            .ok_or_else(|| invalid("unterminated trkpt".to_string()))?;
+        let tag = &rest[..tag_end];
+        let body = if tag.ends_with('/') {
+            ""
+        } else {
+            let len = rest
+                .find("</trkpt>")
+                .ok_or_else(|| invalid("unterminated trkpt".to_string()))?;
+            &rest[tag_end + 1..len]
+        };
+        let coord = |name: &str| {
+            let value =
+                attribute(tag, name).ok_or_else(|| invalid(format!("trkpt without {}", name)))?;
+            value
+                .parse::<f64>()
+                .map_err(|e| invalid(format!("bad trkpt {} {:?}: {}", name, value, e)))
+        };
+        let time =
+            element(body, "time").ok_or_else(|| invalid("trkpt without time".to_string()))?;
+        let number = |name: &str| {
+            element(body, name)
+                .map(|value| {
+                    value
+                        .parse::<f64>()
+                        .map_err(|e| invalid(format!("bad trkpt {} {:?}: {}", name, value, e)))
+                })
+                .transpose()
+        };
+        let velocity = match (number("speed")?, number("course")?) {
+            (Some(speed), Some(course)) => Some((speed, course)),
+            _ => None,
+        };
+        fixes.push(Fix {
+            t_ms: timestamp(time)?,
+            lat: coord("lat")?,
+            lon: coord("lon")?,
+            velocity,
+        });
+    }
+    Ok(fixes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_points() {
+        let gpx = r#"<?xml version="1.0"?>
+<gpx version="1.0" creator="test">
+  <trk><name>walk</name><trkseg>
+    <trkpt lat="45.5" lon='-73.25'>
+      <ele>30</ele>
+      <time>2024-05-01T12:00:00Z</time>
+    </trkpt>
+    <trkpt lon="-73.2501" lat="45.5001">
+      <time>2024-05-01T14:00:01.5+02:00</time>
+      <course>270</course><speed>1.25</speed>
+    </trkpt>
+  </trkseg></trk>
+</gpx>
+"#;
+        let fixes = read(gpx.as_bytes()).unwrap();
+        assert_eq!(fixes.len(), 2);
+        assert_eq!((fixes[0].lat, fixes[0].lon), (45.5, -73.25));
+        assert_eq!(fixes[0].t_ms, 1_714_564_800_000);
+        assert_eq!(fixes[1].t_ms - fixes[0].t_ms, 1_500);
+        assert_eq!((fixes[1].lat, fixes[1].lon), (45.5001, -73.2501));
+        assert_eq!(fixes[1].velocity, Some((1.25, 270.0)));
+
+        assert!(read(r#"<trkpt lat="1" lon="2"></trkpt>"#.as_bytes()).is_err());
+        let bad = r#"<trkpt lat="1"><time>2024-05-01T12:00:00Z</time></trkpt>"#;
+        assert!(read(bad.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,418 @@
+//! Vehicle data files: one `t_ms vx vy gps_x gps_y imu_r imu_t` record per
+//! line, optionally followed by `speed turn_rate` controls, or in the
+//! tagged format one sensor per line, `t_ms gps x y`, `t_ms imu r t`,
+//! `t_ms control speed turn_rate` or `t_ms truth x y`, so each sensor keeps
+//! its own rate.
+//!
+//! Real GPS logs in NMEA or GPX are read into `geo::Fix`es and projected
+//! onto a `geo::LocalPlane` to give a tagged measurement stream.
+
+pub mod geo;
+pub mod gpx;
+pub mod nmea;
+
+use crate::{
+    sim::SimParams,
+    types::{ACoord, CCoord, Control},
+};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+/// One line of a vehicle data file
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Record {
+    pub t_ms: i32,
+    /// True vehicle position, for scoring only
+    pub vehicle: CCoord,
+    pub gps: CCoord,
+    pub imu: ACoord,
+    /// Commanded motion over the step, when the file has control columns
+    pub control: Option<Control>,
+}
+
+impl Record {
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let measures = line.split_whitespace().collect::<Vec<&str>>();
+        if measures.len() < 7 || measures.len() == 8 {
+            return Err(invalid(format!(
+                "expected 7 or 9 fields, found {}",
+                measures.len()
+            )));
+        }
+        let field = |i: usize, name: &str| {
+            measures[i]
+                .parse::<f64>()
+                .map_err(|e| invalid(format!("bad {} {:?}: {}", name, measures[i], e)))
+        };
+        let t_ms = measures[0]
+            .parse::<i32>()
+            .map_err(|e| invalid(format!("bad t_ms {:?}: {}", measures[0], e)))?;
+        Ok(Self {
+            t_ms,
+            vehicle: CCoord {
+                x: field(1, "vehicle x")?,
+                y: field(2, "vehicle y")?,
+            },
+            gps: CCoord {
+                x: field(3, "gps x")?,
+                y: field(4, "gps y")?,
+            },
+            imu: ACoord {
+                r: field(5, "imu r")?,
+                t: field(6, "imu t")?,
+            },
+            control: if measures.len() > 8 {
+                Some(Control {
+                    speed: field(7, "control speed")?,
+                    turn_rate: field(8, "control turn rate")?,
+                })
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// What one sensor reported
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reading {
+    Gps(CCoord),
+    Imu(ACoord),
+    Control(Control),
+    /// True vehicle position, for scoring only
+    Truth(CCoord),
+}
+
+/// One line of a tagged data file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tagged {
+    pub t_ms: i32,
+    pub reading: Reading,
+}
+
+impl Tagged {
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let measures = line.split_whitespace().collect::<Vec<&str>>();
+        if measures.len() < 4 {
+            return Err(invalid(format!(
+                "expected 4 fields, found {}",
+                measures.len()
+            )));
+        }
+        let field = |i: usize| {
+            measures[i].parse::<f64>().map_err(|e| {
+                invalid(format!(
+                    "bad {} field {:?}: {}",
+                    measures[1], measures[i], e
+                ))
+            })
+        };
+        let t_ms = measures[0]
+            .parse::<i32>()
+            .map_err(|e| invalid(format!("bad t_ms {:?}: {}", measures[0], e)))?;
+        let (a, b) = (field(2)?, field(3)?);
+        let reading = match measures[1] {
+            "gps" => Reading::Gps(CCoord { x: a, y: b }),
+            "imu" => Reading::Imu(ACoord { r: a, t: b }),
+            "control" => Reading::Control(Control {
+                speed: a,
+                turn_rate: b,
+            }),
+            "truth" => Reading::Truth(CCoord { x: a, y: b }),
+            tag => return Err(invalid(format!("unknown sensor {:?}", tag))),
+        };
+        Ok(Self { t_ms, reading })
+    }
+}
+
+impl fmt::Display for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reading {
+            Reading::Gps(c) => write!(f, "{} gps {} {}", self.t_ms, c.x, c.y),
+            Reading::Imu(a) => write!(f, "{} imu {} {}", self.t_ms, a.r, a.t),
+            Reading::Control(c) => write!(f, "{} control {} {}", self.t_ms, c.speed, c.turn_rate),
+            Reading::Truth(c) => write!(f, "{} truth {} {}", self.t_ms, c.x, c.y),
+        }
+    }
+}
+
+/// A line of either format
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Line {
+    Fused(Record),
+    Tagged(Tagged),
+}
+
+impl Line {
+    /// Parse a line, telling the formats apart by whether the second
+    /// field is a sensor name.
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let tagged = line
+            .split_whitespace()
+            .nth(1)
+            .is_some_and(|f| f.starts_with(|c: char| c.is_ascii_alphabetic()));
+        if tagged {
+            Tagged::parse(line).map(Self::Tagged)
+        } else {
+            Record::parse(line).map(Self::Fused)
+        }
+    }
+
+    pub fn t_ms(&self) -> i32 {
+        match self {
+            Self::Fused(rec) => rec.t_ms,
+            Self::Tagged(tagged) => tagged.t_ms,
+        }
+    }
+}
+
+pub(crate) fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Smallest and largest of a series of values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for Range {
+    fn default() -> Self {
+        Self {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Range {
+    fn add(&mut self, v: f64) {
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+    }
+
+    /// Largest magnitude in the range
+    pub fn extent(&self) -> f64 {
+        self.min.abs().max(self.max.abs())
+    }
+}
+
+/// Summary of a vehicle data file from [`inspect`]
+#[derive(Clone, Debug, Default)]
+pub struct Inspection {
+    pub records: usize,
+    /// Records carrying a GPS fix
+    pub gps_records: usize,
+    /// Lines that failed to parse, with their line numbers
+    pub bad_lines: Vec<(usize, String)>,
+    /// Time steps in seconds
+    pub dt: Range,
+    pub mean_dt: f64,
+    /// Steps where time failed to advance
+    pub non_monotonic: usize,
+    pub vehicle_x: Range,
+    pub vehicle_y: Range,
+    pub gps_x: Range,
+    pub gps_y: Range,
+    pub imu_r: Range,
+    /// Vehicle positions outside the configured box
+    pub outside_box: usize,
+    pub box_dim: f64,
+    pub warnings: Vec<String>,
+}
+
+/// Scan a vehicle data file and check it against `params`.
+pub fn inspect<P: AsRef<Path>>(path: P, params: &SimParams) -> io::Result<Inspection> {
+    inspect_reader(BufReader::new(File::open(path)?), params)
+}
+
+/// [`inspect`] for any buffered reader
+pub fn inspect_reader<R: BufRead>(reader: R, params: &SimParams) -> io::Result<Inspection> {
+    let mut ins = Inspection {
+        box_dim: params.box_dim,
+        ..Default::default()
+    };
+    let mut last_t = None;
+    let mut dt_sum = 0.0;
+    // Time steps, not counting tagged readings sharing a timestamp
+    let mut steps = 0usize;
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = match Line::parse(&line) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                ins.bad_lines.push((lineno + 1, e.to_string()));
+                continue;
+            }
+        };
+        ins.records += 1;
+        let t_ms = parsed.t_ms();
+        if let Some(t0) = last_t {
+            let dt = (t_ms - t0) as f64 / 1000.0;
+            // Tagged sensors may share a timestamp.
+            let tied = dt == 0.0 && matches!(parsed, Line::Tagged(_));
+            if dt < 0.0 || (dt == 0.0 && !tied) {
+                ins.non_monotonic += 1;
+            }
+            if !tied {
+                ins.dt.add(dt);
+                dt_sum += dt;
+                steps += 1;
+            }
+        }
+        last_t = Some(t_ms);
+        let (vehicle, gps, imu) = match parsed {
+            Line::Fused(rec) => (Some(rec.vehicle), Some(rec.gps), Some(rec.imu)),
+            Line::Tagged(Tagged { reading, .. }) => match reading {
+                Reading::Gps(gps) => (None, Some(gps), None),
+                Reading::Imu(imu) => (None, None, Some(imu)),
+                Reading::Truth(vehicle) => (Some(vehicle), None, None),
+                Reading::Control(_) => (None, None, None),
+            },
+        };
+        if let Some(vehicle) = vehicle {
+            ins.vehicle_x.add(vehicle.x);
+            ins.vehicle_y.add(vehicle.y);
+            if !params.in_box(vehicle.x, vehicle.y) {
+                ins.outside_box += 1;
+            }
+        }
+        if let Some(gps) = gps {
+            ins.gps_x.add(gps.x);
+            ins.gps_y.add(gps.y);
+            ins.gps_records += 1;
+        }
+        if let Some(imu) = imu {
+            ins.imu_r.add(imu.r);
+        }
+    }
+    if steps > 0 {
+        ins.mean_dt = dt_sum / steps as f64;
+    }
+    ins.warn(params);
+    Ok(ins)
+}
+
+impl Inspection {
+    fn warn(&mut self, params: &SimParams) {
+        if self.records == 0 {
+            self.warnings.push("no records".to_string());
+            return;
+        }
+        if !self.bad_lines.is_empty() {
+            self.warnings
+                .push(format!("{} lines failed to parse", self.bad_lines.len()));
+        }
+        if self.non_monotonic > 0 {
+            self.warnings.push(format!(
+                "time fails to advance {} times",
+                self.non_monotonic
+            ));
+        }
+        let extent = self.vehicle_x.extent().max(self.vehicle_y.extent());
+        if self.outside_box > 0 {
+            self.warnings.push(format!(
+                "{} positions lie outside box_dim {}; the data looks like box_dim {}",
+                self.outside_box,
+                params.box_dim,
+                extent.ceil()
+            ));
+        } else if extent < params.box_dim / 2.0 {
+            self.warnings.push(format!(
+                "positions only reach {:.3} of box_dim {}; was the data generated with a smaller box?",
+                extent, params.box_dim
+            ));
+        }
+        if self.imu_r.max > params.max_speed * 2.0 {
+            self.warnings.push(format!(
+                "IMU speeds reach {:.3}, over twice max_speed {}",
+                self.imu_r.max, params.max_speed
+            ));
+        }
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = |r: &Range| format!("[{}, {}]", r.min, r.max);
+        writeln!(
+            f,
+            "records: {} with gps: {}",
+            self.records, self.gps_records
+        )?;
+        writeln!(
+            f,
+            "dt: mean {} range {} non-monotonic {}",
+            self.mean_dt,
+            range(&self.dt),
+            self.non_monotonic
+        )?;
+        writeln!(
+            f,
+            "vehicle: x {} y {}",
+            range(&self.vehicle_x),
+            range(&self.vehicle_y)
+        )?;
+        writeln!(f, "gps: x {} y {}", range(&self.gps_x), range(&self.gps_y))?;
+        writeln!(f, "imu r: {}", range(&self.imu_r))?;
+        writeln!(f, "outside box_dim {}: {}", self.box_dim, self.outside_box)?;
+        for (lineno, e) in &self.bad_lines {
+            writeln!(f, "line {}: {}", lineno, e)?;
+        }
+        for w in &self.warnings {
+            writeln!(f, "warning: {}", w)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inspect_flags_small_box_data() {
+        let data = "0 1 1 1 1 0.5 0\n10 2.4 -2 2 -2 0.5 0\n10 2.5 2 2 2 0.5 0\nbad line\n";
+        let ins = inspect_reader(data.as_bytes(), &SimParams::default()).unwrap();
+        assert_eq!(ins.records, 3);
+        assert_eq!(ins.bad_lines.len(), 1);
+        assert_eq!(ins.non_monotonic, 1);
+        assert_eq!((ins.dt.min, ins.dt.max), (0.0, 0.01));
+        assert_eq!(ins.vehicle_x.max, 2.5);
+        assert!(ins.warnings.iter().any(|w| w.contains("smaller box")));
+
+        let params = SimParams {
+            box_dim: 2.0,
+            ..SimParams::default()
+        };
+        let ins = inspect_reader(data.as_bytes(), &params).unwrap();
+        assert_eq!(ins.outside_box, 2);
+        assert!(ins.warnings.iter().any(|w| w.contains("box_dim 3")));
+    }
+
+    #[test]
+    fn tagged_lines() {
+        let line = Line::parse("20 gps 1.5 -2").unwrap();
+        let Line::Tagged(tagged) = line else {
+            panic!("{:?}", line)
+        };
+        assert_eq!(tagged.reading, Reading::Gps(CCoord { x: 1.5, y: -2.0 }));
+        assert_eq!(Tagged::parse(&tagged.to_string()).unwrap(), tagged);
+        assert!(matches!(Line::parse("0 1 1 1 1 0.5 0"), Ok(Line::Fused(_))));
+        assert!(Line::parse("20 lidar 1 2").is_err());
+
+        // 1 Hz GPS among 100 Hz IMU readings
+        let data = "0 truth 1 1\n0 imu 0.5 0\n0 gps 1.1 0.9\n10 imu 0.5 0\n20 imu 0.5 0\n";
+        let ins = inspect_reader(data.as_bytes(), &SimParams::default()).unwrap();
+        assert_eq!((ins.records, ins.gps_records, ins.non_monotonic), (5, 1, 0));
+        assert_eq!((ins.dt.min, ins.mean_dt), (0.01, 0.01));
+    }
+}
@@ -0,0 +1,139 @@
+//! NMEA 0183 logs: positions from `GGA` and `RMC` sentences of any talker,
+//! with speed and course from `RMC`. Other sentences, and sentences whose
+//! checksum does not match, are skipped.
+
+use super::{geo::Fix, invalid};
+use std::io::{self, BufRead};
+
+const DAY_MS: i64 = 86_400_000;
+
+/// Fields of a sentence with a good or absent checksum, `None` otherwise
+fn fields(line: &str) -> Option<Vec<&str>> {
+    let body = line.trim().strip_prefix('$')?;
+    let body = match body.split_once('*') {
+        Some((body, sum)) => {
+            let sum = u8::from_str_radix(sum.trim(), 16).ok()?;
+            (body.bytes().fold(0, |acc, b| acc ^ b) == sum).then_some(body)?
+        }
+        None => body,
+    };
+    Some(body.split(',').collect())
+}
+
+/// Milliseconds into the day of `hhmmss.sss`
+fn time_of_day(s: &str) -> io::Result<i64> {
+    let bad = || invalid(format!("bad NMEA time {:?}", s));
+    if s.len() < 6 || !s.is_char_boundary(6) {
+        return Err(bad());
+    }
+    let part = |range: std::ops::Range<usize>| s[range].parse::<i64>().map_err(|_| bad());
+    let frac = match &s[6..] {
+        "" => 0.0,
+        frac => frac.parse::<f64>().map_err(|_| bad())?,
+    };
+    Ok(((part(0..2)? * 60 + part(2..4)?) * 60 + part(4..6)?) * 1000
+        + (frac * 1000.0).round() as i64)
+}
+
+/// Degrees from `dddmm.mmmm` and a hemisphere, negative to the south or
+/// west
+fn degrees(value: &str, hemisphere: &str) -> io::Result<f64> {
+    let v = value
+        .parse::<f64>()
+        .map_err(|e| invalid(format!("bad NMEA coordinate {:?}: {}", value, e)))?;
+    let deg = (v / 100.0).trunc();
+    let deg = deg + (v - deg * 100.0) / 60.0;
+    match hemisphere {
+        "N" | "E" => Ok(deg),
+        "S" | "W" => Ok(-deg),
+        _ => Err(invalid(format!("bad NMEA hemisphere {:?}", hemisphere))),
+    }
+}
+
+/// Fix in a `GGA` or `RMC` sentence, timed from midnight
+fn parse_fix(kind: &str, f: &[&str]) -> io::Result<Fix> {
+    let lat_at = if kind == "GGA" { 2 } else { 3 };
+    let number = |i: usize, what: &str| {
+        f[i].parse::<f64>()
+            .map_err(|e| invalid(format!("bad NMEA {} {:?}: {}", what, f[i], e)))
+    };
+    let velocity = if kind == "RMC" && !f[7].is_empty() && !f[8].is_empty() {
+        Some((number(7, "speed")? * 1852.0 / 3600.0, number(8, "course")?))
+    } else {
+        None
+    };
+    Ok(Fix {
+        t_ms: time_of_day(f[1])?,
+        lat: degrees(f[lat_at], f[lat_at + 1])?,
+        lon: degrees(f[lat_at + 2], f[lat_at + 3])?,
+        velocity,
+    })
+}
+
+/// Read the fixes in an NMEA log, timed from midnight of the first day.
+/// A `GGA` and an `RMC` at the same time make one fix.
+pub fn read<R: BufRead>(reader: R) -> io::Result<Vec<Fix>> {
+    let mut fixes: Vec<Fix> = Vec::new();
+    let (mut days, mut last_tod) = (0, None);
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line?;
+        let Some(f) = fields(&line) else {
+            continue;
+        };
+        let kind = f[0].get(2..).unwrap_or_default();
+        let (valid, lat) = match kind {
+            "GGA" if f.len() > 6 => (!matches!(f[6], "" | "0"), f[2]),
+            "RMC" if f.len() > 8 => (f[2] == "A", f[3]),
+            _ => continue,
+        };
+        if !valid || lat.is_empty() {
+            continue;
+        }
+        let mut fix =
+            parse_fix(kind, &f).map_err(|e| invalid(format!("line {}: {}", lineno + 1, e)))?;
+        // A log running past midnight steps back a whole day.
+        if last_tod.is_some_and(|last| fix.t_ms + DAY_MS / 2 < last) {
+            days += 1;
+        }
+        last_tod = Some(fix.t_ms);
+        fix.t_ms += days * DAY_MS;
+        match fixes.last_mut() {
+            Some(last) if last.t_ms == fix.t_ms => {
+                last.velocity = last.velocity.or(fix.velocity);
+            }
+            _ => fixes.push(fix),
+        }
+    }
+    Ok(fixes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gga_and_rmc() {
+        let log = "\
+$GPGGA,235959.50,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*60
+$GPRMC,235959.50,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*4D
+$GPGSV,2,1,08,01,40,083,46,02,17,308,41,12,07,344,39,14,22,228,45*75
+$GNGGA,000000.50,4807.039,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,
+$GPGGA,000001.50,4807.040,N,01131.000,E,0,00,,,M,,M,,*7A
+$GPGGA,000001.50,4807.040,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*00
+";
+        let fixes = read(log.as_bytes()).unwrap();
+        // The satellite view, the lost fix and the bad checksum are skipped.
+        assert_eq!(fixes.len(), 2);
+        assert_eq!(fixes[0].t_ms, DAY_MS - 500);
+        assert!((fixes[0].lat - (48.0 + 7.038 / 60.0)).abs() < 1e-12);
+        assert!((fixes[0].lon - (11.0 + 31.0 / 60.0)).abs() < 1e-12);
+        let (speed, course) = fixes[0].velocity.unwrap();
+        assert!((speed - 22.4 * 1852.0 / 3600.0).abs() < 1e-12 && course == 84.4);
+        // Across midnight
+        assert_eq!(fixes[1].t_ms, DAY_MS + 500);
+        assert_eq!(fixes[1].velocity, None);
+
+        assert!(read("$GPGGA,12x456,4807.038,N,01131.000,E,1,08\n".as_bytes()).is_err());
+        assert!(read("$GPGGA,123456,4807.038,Q,01131.000,E,1,08\n".as_bytes()).is_err());
+    }
+}
@@ -1,12 +1,188 @@
-use std::cell::RefCell;
-use ziggurat_rs::Ziggurat;
+use std::cell::{Cell, RefCell};
+use ziggurat_rs::{BlockSeeded, BoxMuller, SeedSequence, StreamPosition, Ziggurat};
 
+pub mod abc;
+pub mod bootstrap;
+pub mod estimate;
+pub mod fp;
+pub mod frame;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod init;
+pub mod interval;
+pub mod io;
+#[cfg(feature = "nalgebra")]
+pub mod linalg;
+pub mod map;
+pub mod memory;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod progress;
+pub mod proposal;
+#[cfg(feature = "rbpf")]
+pub mod rbpf;
+pub mod replay;
+pub mod replicates;
+pub mod report;
 pub mod resample;
+#[cfg(feature = "sqlite")]
+pub mod results;
+pub mod scenario;
 pub mod sim;
+pub mod small;
+pub mod smooth;
+#[cfg(feature = "mmap")]
+pub mod storage;
 pub mod types;
+pub mod validate;
+
+/// Generator behind `gaussian()`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GaussianSource {
+    #[default]
+    Ziggurat,
+    /// Box-Muller reference generator, for A/B checking the Ziggurat
+    BoxMuller,
+}
 
 thread_local! {
     static ZIGGURAT: RefCell<Ziggurat> = RefCell::new(Ziggurat::default());
+    static BOX_MULLER: RefCell<BoxMuller> = RefCell::new(BoxMuller::default());
+    static GAUSSIAN_SOURCE: Cell<GaussianSource> = const { Cell::new(GaussianSource::Ziggurat) };
+}
+
+/// Consumers of random numbers in a run, each drawing from its own
+/// stream derived from the run's master seed, so that changing how many
+/// draws one makes leaves the others' unchanged
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    /// The filter's thread generator
+    Filter,
+    /// Vehicle motion and sensor noise in the simulator
+    Simulator,
+    /// When simulated GPS outages start
+    Dropouts,
+    /// When jittered GPS fixes arrive
+    Jitter,
+}
+
+impl Stream {
+    pub const ALL: [Stream; 4] = [
+        Stream::Filter,
+        Stream::Simulator,
+        Stream::Dropouts,
+        Stream::Jitter,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stream::Filter => "filter",
+            Stream::Simulator => "simulator",
+            Stream::Dropouts => "dropouts",
+            Stream::Jitter => "jitter",
+        }
+    }
+
+    /// This stream's seed under `master`
+    pub fn seed(self, master: u32) -> u32 {
+        SeedSequence::new(master).seed(self.name(), 0)
+    }
+}
+
+/// Reseed this thread's generators, making the free functions below
+/// reproducible.
+pub fn seed_thread_rng(seed: u32) {
+    ZIGGURAT.with(|z| *z.borrow_mut() = Ziggurat::new(seed));
+    BOX_MULLER.with(|b| *b.borrow_mut() = BoxMuller::new(seed));
+}
+
+/// Run `f` with direct access to this thread's generator.
+///
+/// `f` must not call the free sampling functions itself.
+pub fn with_rng<R>(f: impl FnOnce(&mut Ziggurat) -> R) -> R {
+    ZIGGURAT.with(|z| f(&mut z.borrow_mut()))
+}
+
+/// Install `rng` as this thread's generator, returning the previous one.
+pub fn replace_thread_rng(rng: Ziggurat) -> Ziggurat {
+    ZIGGURAT.with(|z| z.replace(rng))
+}
+
+/// Run `f` with `rng` installed as this thread's generator, then restore
+/// the previous generator (even if `f` panics). Returns the result of `f`
+/// along with `rng` in its advanced state.
+pub fn with_thread_rng<R>(rng: Ziggurat, f: impl FnOnce() -> R) -> (R, Ziggurat) {
+    struct Restore(Option<Ziggurat>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            if let Some(rng) = self.0.take() {
+                replace_thread_rng(rng);
+            }
+        }
+    }
+
+    let mut restore = Restore(Some(replace_thread_rng(rng)));
+    let result = f();
+    let used = replace_thread_rng(restore.0.take().unwrap());
+    (result, used)
+}
+
+/// Where this thread's generators are in their streams, and which one
+/// `gaussian()` draws from
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RngPosition {
+    pub source: GaussianSource,
+    pub ziggurat: StreamPosition,
+    /// Word the next Ziggurat normal mixes into its strip index, see
+    /// [`Ziggurat::last_word`]; restoring `ziggurat` without it gives
+    /// different normals
+    pub last: u32,
+    pub box_muller: StreamPosition,
+    /// Box-Muller variate held over from the last pair
+    pub spare: Option<f64>,
+}
+
+pub fn thread_rng_position() -> RngPosition {
+    let (ziggurat, last) = ZIGGURAT.with(|z| {
+        let z = z.borrow();
+        (z.position(), z.last_word())
+    });
+    let (box_muller, spare) = BOX_MULLER.with(|b| {
+        let b = b.borrow();
+        (b.position(), b.spare())
+    });
+    RngPosition {
+        source: gaussian_source(),
+        ziggurat,
+        last,
+        box_muller,
+        spare,
+    }
+}
+
+/// Put this thread's generators back where `thread_rng_position` found
+/// them. Seeking costs time in proportion to the words drawn. The
+/// position of a block-seeded stream, which this thread's generators,
+/// seeded by word, never report, is refused and leaves them alone.
+pub fn seek_thread_rng(pos: &RngPosition) -> Result<(), BlockSeeded> {
+    let ziggurat = Ziggurat::at(pos.ziggurat, pos.last)?;
+    let box_muller = BoxMuller::at(pos.box_muller, pos.spare)?;
+    ZIGGURAT.with(|z| *z.borrow_mut() = ziggurat);
+    BOX_MULLER.with(|b| *b.borrow_mut() = box_muller);
+    set_gaussian_source(pos.source);
+    Ok(())
+}
+
+/// Select the generator `gaussian()` draws from on this thread.
+pub fn set_gaussian_source(source: GaussianSource) {
+    GAUSSIAN_SOURCE.with(|s| s.set(source));
+}
+
+pub fn gaussian_source() -> GaussianSource {
+    GAUSSIAN_SOURCE.with(|s| s.get())
 }
 
 pub fn uniform() -> f64 {
@@ -14,7 +190,10 @@ pub fn uniform() -> f64 {
 }
 
 pub fn gaussian(sigma: f64) -> f64 {
-    ZIGGURAT.with(|z| z.borrow_mut().gaussian(sigma))
+    match gaussian_source() {
+        GaussianSource::Ziggurat => ZIGGURAT.with(|z| z.borrow_mut().gaussian(sigma)),
+        GaussianSource::BoxMuller => BOX_MULLER.with(|b| b.borrow_mut().gaussian(sigma)),
+    }
 }
 
 pub fn polynomial(n: i32) -> f64 {
@@ -24,3 +203,62 @@ pub fn polynomial(n: i32) -> f64 {
 pub fn rand32() -> u32 {
     ZIGGURAT.with(|z| z.borrow_mut().rand32())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeding_is_reproducible() {
+        seed_thread_rng(5);
+        let a: Vec<f64> = (0..10).map(|_| gaussian(1.0)).collect();
+        seed_thread_rng(5);
+        let b: Vec<f64> = (0..10).map(|_| gaussian(1.0)).collect();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn scoped_rng_is_restored() {
+        seed_thread_rng(1);
+        let expected = with_rng(|z| {
+            let mut copy = Ziggurat::new(1);
+            assert_eq!(copy.rand32(), z.rand32());
+            z.rand32()
+        });
+        seed_thread_rng(1);
+        rand32();
+        let (inner, mut used) = with_thread_rng(Ziggurat::new(99), rand32);
+        assert_eq!(inner, Ziggurat::new(99).rand32());
+        let mut reference = Ziggurat::new(99);
+        reference.rand32();
+        assert_eq!(used.rand32(), reference.rand32());
+        // The outer stream carries on where it left off.
+        assert_eq!(rand32(), expected);
+    }
+
+    #[test]
+    fn seeks_only_word_seeded_streams() {
+        seed_thread_rng(6);
+        let pos = thread_rng_position();
+        let expected: Vec<u32> = (0..4).map(|_| rand32()).collect();
+        seek_thread_rng(&pos).unwrap();
+        assert_eq!((0..4).map(|_| rand32()).collect::<Vec<_>>(), expected);
+        let block = |at| StreamPosition { block: true, ..at };
+        let bad = [
+            RngPosition {
+                ziggurat: block(pos.ziggurat),
+                ..pos
+            },
+            RngPosition {
+                box_muller: block(pos.box_muller),
+                ..pos
+            },
+        ];
+        // Refusing either stream leaves both where they were.
+        for bad in bad {
+            let before = thread_rng_position();
+            assert_eq!(seek_thread_rng(&bad), Err(BlockSeeded));
+            assert_eq!(thread_rng_position(), before);
+        }
+    }
+}
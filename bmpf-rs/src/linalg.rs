@@ -0,0 +1,149 @@
+//! nalgebra interop for combining the filter with Kalman-style code.
+//! Positions and velocities convert to `Vector2`, whole states to
+//! `Vector4` `[x, y, r, t]`, and a particle cloud summarizes to a mean and
+//! covariance, from which the normalized estimation error squared (NEES)
+//! checks whether the filter's spread matches its actual error.
+
+use crate::{
+    sim::{angle_delta, normalize_angle},
+    types::{ACoord, CCoord, ParticleInfo, VehicleState},
+};
+use nalgebra::{Matrix2, Matrix4, SMatrix, SVector, Vector2, Vector4};
+
+impl From<CCoord> for Vector2<f64> {
+    fn from(c: CCoord) -> Self {
+        Vector2::new(c.x, c.y)
+    }
+}
+
+impl From<Vector2<f64>> for CCoord {
+    fn from(v: Vector2<f64>) -> Self {
+        CCoord { x: v.x, y: v.y }
+    }
+}
+
+/// `[r, t]`
+impl From<ACoord> for Vector2<f64> {
+    fn from(a: ACoord) -> Self {
+        Vector2::new(a.r, a.t)
+    }
+}
+
+impl From<Vector2<f64>> for ACoord {
+    fn from(v: Vector2<f64>) -> Self {
+        ACoord { r: v.x, t: v.y }
+    }
+}
+
+/// `[x, y, r, t]`
+impl From<VehicleState> for Vector4<f64> {
+    fn from(s: VehicleState) -> Self {
+        let vel = s.vel();
+        Vector4::new(s.posn.x, s.posn.y, vel.r, vel.t)
+    }
+}
+
+impl From<Vector4<f64>> for VehicleState {
+    fn from(v: Vector4<f64>) -> Self {
+        VehicleState::new(CCoord { x: v.x, y: v.y }, ACoord { r: v.z, t: v.w })
+    }
+}
+
+/// Weighted mean and covariance of the particle states `[x, y, r, t]`.
+/// Headings are averaged around the circle and deviate from the mean the
+/// short way round. Weights need not be normalized; `None` if they sum to
+/// zero.
+pub fn moments(particles: &[ParticleInfo]) -> Option<(Vector4<f64>, Matrix4<f64>)> {
+    let total: f64 = particles.iter().map(|p| p.weight).sum();
+    if total <= 0.0 || !total.is_finite() {
+        return None;
+    }
+    let mut mean = Vector4::zeros();
+    let (mut sin, mut cos) = (0.0, 0.0);
+    for p in particles {
+        let w = p.weight / total;
+        let s = Vector4::from(p.state);
+        mean += w * s;
+        sin += w * s.w.sin();
+        cos += w * s.w.cos();
+    }
+    mean.w = normalize_angle(sin.atan2(cos));
+    let mut cov = Matrix4::zeros();
+    for p in particles {
+        let mut d = Vector4::from(p.state) - mean;
+        d.w = angle_delta(p.state.vel().t, mean.w);
+        cov += (p.weight / total) * d * d.transpose();
+    }
+    Some((mean, cov))
+}
+
+/// NEES `e' P^-1 e` of `truth` against an estimate with mean `mean` and
+/// covariance `cov`. Averages to `D` over a run when the covariance is
+/// honest. `None` if `cov` is singular.
+pub fn nees<const D: usize>(
+    truth: SVector<f64, D>,
+    mean: SVector<f64, D>,
+    cov: SMatrix<f64, D, D>,
+) -> Option<f64> {
+    let e = truth - mean;
+    let inv = cov.try_inverse()?;
+    Some((e.transpose() * inv * e)[0])
+}
+
+/// Position NEES of the particle cloud against the true position
+pub fn position_nees(particles: &[ParticleInfo], truth: CCoord) -> Option<f64> {
+    let (mean, cov) = moments(particles)?;
+    let cov: Matrix2<f64> = cov.fixed_view::<2, 2>(0, 0).into_owned();
+    nees(truth.into(), mean.xy(), cov)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gaussian, uniform};
+
+    #[test]
+    fn conversions_round_trip() {
+        let state = VehicleState::new(CCoord { x: 1.0, y: -2.0 }, ACoord { r: 0.5, t: 3.0 });
+        let v = Vector4::from(state);
+        assert_eq!(v, Vector4::new(1.0, -2.0, 0.5, 3.0));
+        let back = VehicleState::from(v);
+        assert_eq!((back.posn, back.vel()), (state.posn, state.vel()));
+        assert_eq!(CCoord::from(Vector2::from(state.posn)), state.posn);
+        assert_eq!(ACoord::from(Vector2::from(state.vel())), state.vel());
+    }
+
+    #[test]
+    fn honest_clouds_average_nees_of_two() {
+        crate::seed_thread_rng(4662);
+        let mut particles = vec![ParticleInfo::default(); 500];
+        let (mut total, trials) = (0.0, 200);
+        for _ in 0..trials {
+            // A cloud spread 2 by 0.5 around the origin, against a truth
+            // drawn from the same spread.
+            for p in &mut particles {
+                let posn = CCoord {
+                    x: gaussian(2.0),
+                    y: gaussian(0.5),
+                };
+                let vel = ACoord {
+                    r: uniform(),
+                    t: normalize_angle(gaussian(0.1)),
+                };
+                p.state = VehicleState::new(posn, vel);
+                p.weight = 1.0;
+            }
+            let truth = CCoord {
+                x: gaussian(2.0),
+                y: gaussian(0.5),
+            };
+            total += position_nees(&particles, truth).unwrap();
+        }
+        let mean = total / trials as f64;
+        assert!((1.6..2.4).contains(&mean), "{}", mean);
+        // Headings straddling zero still have a small spread.
+        let (m, cov) = moments(&particles).unwrap();
+        assert!(angle_delta(m.w, 0.0).abs() < 0.05 && cov[(3, 3)] < 0.02);
+        assert!(moments(&[]).is_none());
+    }
+}
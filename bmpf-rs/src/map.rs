@@ -0,0 +1,136 @@
+use std::{fs, io, path::Path, str::FromStr};
+
+/// What happens to a particle whose motion would take it into an obstacle.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ObstaclePolicy {
+    /// Reflect off the obstacle the same way vehicles bounce off the box.
+    #[default]
+    Bounce,
+    /// Move through the obstacle during propagation and let the weighting
+    /// step give the particle zero weight.
+    ZeroWeight,
+}
+
+impl FromStr for ObstaclePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bounce" => Ok(Self::Bounce),
+            "zero-weight" => Ok(Self::ZeroWeight),
+            _ => Err(format!("unknown obstacle policy {}", s)),
+        }
+    }
+}
+
+/// Occupancy grid stretched over the `[-box_dim, box_dim]` square, with row 0
+/// at the top (`y = box_dim`) and column 0 at the left (`x = -box_dim`).
+#[derive(Clone, Debug)]
+pub struct OccupancyMap {
+    width: usize,
+    height: usize,
+    cells: Vec<bool>,
+}
+
+impl OccupancyMap {
+    /// Build a map from row-major cells, `true` meaning occupied.
+    pub fn new(width: usize, height: usize, cells: Vec<bool>) -> Self {
+        assert!(width > 0 && height > 0, "map must have at least one cell");
+        assert_eq!(cells.len(), width * height, "map cells do not match size");
+        Self {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    /// Parse a text map: one line per row, `#` for occupied cells and `.`
+    /// for free ones. Blank lines and lines starting with `;` are skipped.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut width = 0;
+        let mut cells = Vec::new();
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if width == 0 {
+                width = line.len();
+            } else if line.len() != width {
+                return Err(invalid(format!(
+                    "map line {} has {} cells, expected {}",
+                    lineno + 1,
+                    line.len(),
+                    width
+                )));
+            }
+            for c in line.chars() {
+                match c {
+                    '#' => cells.push(true),
+                    '.' => cells.push(false),
+                    _ => {
+                        return Err(invalid(format!(
+                            "unexpected {:?} in map line {}",
+                            c,
+                            lineno + 1
+                        )));
+                    }
+                }
+            }
+        }
+        if cells.is_empty() {
+            return Err(invalid("map has no cells".to_string()));
+        }
+        Ok(Self::new(width, cells.len() / width, cells))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether the world point `(x, y)` lies in an occupied cell of a map
+    /// covering a box of half-width `box_dim`. Points outside the box are
+    /// looked up in the nearest edge cell.
+    pub fn occupied(&self, box_dim: f64, x: f64, y: f64) -> bool {
+        let u = (x + box_dim) / (2.0 * box_dim);
+        let v = (box_dim - y) / (2.0 * box_dim);
+        let col = ((u * self.width as f64).floor().max(0.0) as usize).min(self.width - 1);
+        let row = ((v * self.height as f64).floor().max(0.0) as usize).min(self.height - 1);
+        self.cells[row * self.width + col]
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_lookup() {
+        let map = OccupancyMap::parse("; wall on the right\n..#\n..#\n").unwrap();
+        assert_eq!((map.width(), map.height()), (3, 2));
+        assert!(map.occupied(3.0, 2.5, 0.0));
+        assert!(!map.occupied(3.0, -2.5, 2.5));
+        assert!(!map.occupied(3.0, 0.0, -2.5));
+        // Out of range points clamp to the edge cells.
+        assert!(map.occupied(3.0, 10.0, 10.0));
+    }
+
+    #[test]
+    fn parse_rejects_ragged_rows() {
+        assert!(OccupancyMap::parse("..#\n.#\n").is_err());
+        assert!(OccupancyMap::parse("..x\n").is_err());
+        assert!(OccupancyMap::parse("\n").is_err());
+    }
+}
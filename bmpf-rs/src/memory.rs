@@ -0,0 +1,126 @@
+//! How much memory a filter holds, and a check to run before building one,
+//! so a run asking for more particles than fit fails up front rather than
+//! at the out-of-memory killer.
+
+use crate::{estimate::Kde, resample::Resampler, types::ParticleInfo};
+use std::{fmt, fs};
+
+/// Bytes of heap a filter holds, by what holds them
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryFootprint {
+    /// Both particle sets, the current one and the one resampled into
+    pub particles: usize,
+    /// The resampler's scratch space
+    pub resampler: usize,
+    /// Estimator grids, ABC distances and any recording
+    pub other: usize,
+}
+
+/// `bytes` in the largest binary unit that keeps it at least one
+fn human(bytes: usize) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return format!("{:.1} {}", size, unit);
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} TiB", size)
+}
+
+impl MemoryFootprint {
+    pub fn total(&self) -> usize {
+        self.particles + self.resampler + self.other
+    }
+
+    /// What `BpfState::new` holds for `nparticles` particles resampled by
+    /// `resampler`, or `None` for an unknown resampler. Memory on a GPU
+    /// is not counted.
+    pub fn estimate(resampler: &str, nparticles: usize) -> Option<Self> {
+        Some(Self {
+            particles: 2 * nparticles * size_of::<ParticleInfo>(),
+            resampler: Resampler::scratch_estimate(resampler, nparticles, nparticles)?,
+            other: Kde::default().heap_bytes(),
+        })
+    }
+
+    /// Refuse a footprint above `limit` bytes.
+    pub fn check(&self, limit: usize) -> Result<(), String> {
+        if self.total() > limit {
+            return Err(format!(
+                "the filter needs {} ({}), above the limit of {}",
+                human(self.total()),
+                self,
+                human(limit)
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for MemoryFootprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "particles {}, resampler {}, other {}",
+            human(self.particles),
+            human(self.resampler),
+            human(self.other)
+        )
+    }
+}
+
+/// Parse a size in bytes with an optional `K`, `M`, `G` or `T` suffix in
+/// powers of 1024, such as `512M`.
+pub fn parse_bytes(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 30),
+        Some((i, 'T' | 't')) => (&s[..i], 40),
+        _ => (s, 0),
+    };
+    let n: usize = digits
+        .parse()
+        .map_err(|e| format!("bad size {:?}: {}", s, e))?;
+    1usize
+        .checked_shl(shift)
+        .and_then(|unit| n.checked_mul(unit))
+        .ok_or_else(|| format!("size {:?} is too large", s))
+}
+
+/// Physical memory of this machine, where the platform says
+pub fn physical_memory() -> Option<usize> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemTotal:"))?;
+    let kib: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimParams, types::BpfState};
+
+    #[test]
+    fn footprints() {
+        for name in Resampler::NAMES {
+            let estimate = MemoryFootprint::estimate(name, 1000).unwrap();
+            let bpf = BpfState::new(name, false, 1000, 0, false, 1, SimParams::default());
+            assert_eq!(bpf.memory_footprint(), estimate, "{}", name);
+        }
+        assert!(MemoryFootprint::estimate("nope", 10).is_none());
+
+        // A hundred million particles do not fit in a gigabyte.
+        let huge = MemoryFootprint::estimate("logm", 100_000_000).unwrap();
+        let err = huge.check(parse_bytes("1G").unwrap()).unwrap_err();
+        assert!(err.contains("above the limit of 1.0 GiB"), "{}", err);
+        assert!(huge.check(usize::MAX).is_ok());
+
+        assert_eq!(parse_bytes("512M"), Ok(512 << 20));
+        assert_eq!(parse_bytes("1000"), Ok(1000));
+        assert!(parse_bytes("lots").is_err());
+        assert_eq!(human(1536), "1.5 KiB");
+    }
+}
@@ -0,0 +1,192 @@
+//! Estimates published to an MQTT broker, so existing robotics tooling can
+//! subscribe to the filter's live pose. Only as much of MQTT 3.1.1 as
+//! publishing at QoS 0 needs is spoken: a clean-session connect, publishes
+//! and a disconnect.
+
+use crate::{
+    net::{Encoding, encode_estimate, resolve},
+    report::Publisher,
+    types::Estimate,
+};
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const DISCONNECT: u8 = 0xe0;
+
+/// Append MQTT's variable-length remaining length.
+fn put_length(packet: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            packet.push(byte);
+            return;
+        }
+        packet.push(byte | 0x80);
+    }
+}
+
+fn put_str(body: &mut Vec<u8>, s: &str) {
+    body.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    body.extend_from_slice(s.as_bytes());
+}
+
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    put_length(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Publishes each estimate to `topic`, encoded as by the `net` module
+pub struct MqttPublisher {
+    broker: SocketAddr,
+    client_id: String,
+    topic: String,
+    encoding: Encoding,
+    /// Have the broker keep the latest estimate for new subscribers.
+    pub retain: bool,
+    stream: Option<TcpStream>,
+}
+
+impl MqttPublisher {
+    /// Publish to `topic` on `broker` as `client_id`, connecting on the
+    /// first publish.
+    pub fn new<A: ToSocketAddrs>(
+        broker: A,
+        client_id: &str,
+        topic: &str,
+        encoding: Encoding,
+    ) -> io::Result<Self> {
+        if client_id.len() > u16::MAX as usize || topic.len() > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "MQTT client id or topic too long",
+            ));
+        }
+        Ok(Self {
+            broker: resolve(broker)?,
+            client_id: client_id.to_string(),
+            topic: topic.to_string(),
+            encoding,
+            retain: false,
+            stream: None,
+        })
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(self.broker)?;
+        stream.set_nodelay(true)?;
+        let mut body = Vec::new();
+        put_str(&mut body, "MQTT");
+        // Protocol level 4, a clean session, and no keep alive since
+        // estimates arrive at their own pace
+        body.extend_from_slice(&[4, 0x02, 0, 0]);
+        put_str(&mut body, &self.client_id);
+        stream.write_all(&packet(CONNECT, &body))?;
+        let mut ack = [0u8; 4];
+        stream.read_exact(&mut ack)?;
+        match ack {
+            [CONNACK, 2, _, 0] => Ok(stream),
+            [CONNACK, 2, _, code] => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("MQTT broker refused the connection with code {}", code),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "MQTT broker did not acknowledge the connection",
+            )),
+        }
+    }
+}
+
+impl Publisher for MqttPublisher {
+    /// Publish the estimate for the step at `t_ms`, reconnecting once if
+    /// the broker has gone away.
+    fn publish(&mut self, t_ms: i32, est: &Estimate) -> io::Result<()> {
+        let mut body = Vec::new();
+        put_str(&mut body, &self.topic);
+        body.extend(encode_estimate(t_ms, est, self.encoding));
+        let msg = packet(PUBLISH | self.retain as u8, &body);
+        if let Some(stream) = &mut self.stream
+            && stream.write_all(&msg).is_ok()
+        {
+            return Ok(());
+        }
+        self.stream = None;
+        let stream = self.stream.insert(self.connect()?);
+        stream.write_all(&msg)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for MqttPublisher {
+    fn drop(&mut self) {
+        if let Some(stream) = &mut self.stream {
+            let _ = stream.write_all(&[DISCONNECT, 0]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{estimate::EstimateMode, types::CCoord};
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn publishes_to_a_broker() {
+        let mut length = Vec::new();
+        put_length(&mut length, 321);
+        assert_eq!(length, [0xc1, 0x02]);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broker = listener.local_addr().unwrap();
+        let fake = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut connect = [0u8; 2 + 10 + 2 + 4];
+            stream.read_exact(&mut connect).unwrap();
+            assert_eq!(&connect[..2], [CONNECT, 16]);
+            assert_eq!(&connect[2..12], b"\0\x04MQTT\x04\x02\0\0");
+            assert_eq!(&connect[12..], b"\0\x04bmpf");
+            stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+            let mut publish = Vec::new();
+            stream.read_to_end(&mut publish).unwrap();
+            publish
+        });
+
+        let mut publisher =
+            MqttPublisher::new(broker, "bmpf", "bmpf/pose", Encoding::Binary).unwrap();
+        let est = Estimate {
+            best: CCoord { x: 1.0, y: 2.0 },
+            best_weight: 0.5,
+            worst: CCoord::default(),
+            worst_weight: 0.0,
+            mean: CCoord { x: 3.0, y: 4.0 },
+            map: CCoord::default(),
+            mode: EstimateMode::BestParticle,
+            interval: None,
+            ..Default::default()
+        };
+        publisher.publish(250, &est).unwrap();
+        drop(publisher);
+        let sent = fake.join().unwrap();
+        let payload = encode_estimate(250, &est, Encoding::Binary);
+        let mut expected = vec![PUBLISH, (2 + 9 + payload.len()) as u8, 0, 9];
+        expected.extend_from_slice(b"bmpf/pose");
+        expected.extend(payload);
+        expected.extend([DISCONNECT, 0]);
+        assert_eq!(sent, expected);
+    }
+}
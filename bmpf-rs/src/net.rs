@@ -0,0 +1,515 @@
+//! Running the filter as a service: measurement records arrive over UDP or
+//! TCP and estimates go back out either way. Every message is one record,
+//! either length-prefixed binary or a line of JSON.
+//!
+//! A binary record is a little-endian `u32` payload length, then `t_ms` as
+//! an `i32` and `vehicle_x vehicle_y gps_x gps_y imu_r imu_t` as `f64`s,
+//! optionally followed by `control_speed control_turn_rate`. A JSON record
+//! is a flat object with the same names, such as
+//! `{"t_ms": 10, "gps_x": 1.5, "gps_y": -2, "imu_r": 0.5, "imu_t": 0}`, in
+//! which the vehicle and the controls may be left out. Estimates go out the
+//! same way with `t_ms x y mean_x mean_y best_x best_y best_weight`, by a
+//! `NetPublisher`.
+
+use crate::{
+    io::Record,
+    report::Publisher,
+    types::{ACoord, CCoord, Control, Estimate},
+};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+};
+
+/// Largest binary message accepted, well above any record
+const MAX_MESSAGE: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Binary,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(Self::Binary),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown encoding {}", s)),
+        }
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Frame `t_ms` and `values` as one message
+fn encode(t_ms: i32, names: &[&str], values: &[f64], encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::Binary => {
+            let len = 4 + 8 * values.len();
+            let mut msg = Vec::with_capacity(4 + len);
+            msg.extend_from_slice(&(len as u32).to_le_bytes());
+            msg.extend_from_slice(&t_ms.to_le_bytes());
+            for v in values {
+                msg.extend_from_slice(&v.to_le_bytes());
+            }
+            msg
+        }
+        Encoding::Json => {
+            let mut line = format!("{{\"t_ms\":{}", t_ms);
+            for (name, v) in names.iter().zip(values) {
+                line += &format!(",\"{}\":{}", name, v);
+            }
+            line += "}\n";
+            line.into_bytes()
+        }
+    }
+}
+
+/// Numeric members of a flat JSON object
+fn json_fields(text: &str) -> io::Result<Vec<(&str, f64)>> {
+    let bad = || invalid(format!("bad JSON record {:?}", text));
+    let body = text
+        .trim()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or_else(bad)?;
+    if body.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    body.split(',')
+        .map(|member| {
+            let (name, value) = member.split_once(':').ok_or_else(bad)?;
+            let name = name
+                .trim()
+                .strip_prefix('"')
+                .and_then(|s| s.strip_suffix('"'))
+                .ok_or_else(bad)?;
+            let value = value.trim().parse::<f64>().map_err(|_| bad())?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+const RECORD_NAMES: [&str; 8] = [
+    "vehicle_x",
+    "vehicle_y",
+    "gps_x",
+    "gps_y",
+    "imu_r",
+    "imu_t",
+    "control_speed",
+    "control_turn_rate",
+];
+
+pub fn encode_record(rec: &Record, encoding: Encoding) -> Vec<u8> {
+    let mut values = vec![
+        rec.vehicle.x,
+        rec.vehicle.y,
+        rec.gps.x,
+        rec.gps.y,
+        rec.imu.r,
+        rec.imu.t,
+    ];
+    if let Some(c) = rec.control {
+        values.extend([c.speed, c.turn_rate]);
+    }
+    encode(rec.t_ms, &RECORD_NAMES, &values, encoding)
+}
+
+/// Decode one message, without its length prefix or newline
+pub fn decode_record(msg: &[u8], encoding: Encoding) -> io::Result<Record> {
+    let (t_ms, values) = match encoding {
+        Encoding::Binary => {
+            if msg.len() != 52 && msg.len() != 68 {
+                return Err(invalid(format!("binary record of {} bytes", msg.len())));
+            }
+            let t_ms = i32::from_le_bytes(msg[..4].try_into().unwrap());
+            let values: Vec<Option<f64>> = msg[4..]
+                .chunks_exact(8)
+                .map(|b| Some(f64::from_le_bytes(b.try_into().unwrap())))
+                .collect();
+            (t_ms, values)
+        }
+        Encoding::Json => {
+            let text = std::str::from_utf8(msg).map_err(|e| invalid(e.to_string()))?;
+            let fields = json_fields(text)?;
+            let get = |name: &str| fields.iter().find(|(n, _)| *n == name).map(|&(_, v)| v);
+            let t_ms = get("t_ms").ok_or_else(|| invalid("JSON record without t_ms".into()))?;
+            (
+                t_ms as i32,
+                RECORD_NAMES.iter().map(|name| get(name)).collect(),
+            )
+        }
+    };
+    let need =
+        |i: usize| values[i].ok_or_else(|| invalid(format!("record without {}", RECORD_NAMES[i])));
+    Ok(Record {
+        t_ms,
+        vehicle: CCoord {
+            x: values[0].unwrap_or_default(),
+            y: values[1].unwrap_or_default(),
+        },
+        gps: CCoord {
+            x: need(2)?,
+            y: need(3)?,
+        },
+        imu: ACoord {
+            r: need(4)?,
+            t: need(5)?,
+        },
+        control: match values.get(6..8) {
+            Some(&[Some(speed), Some(turn_rate)]) => Some(Control { speed, turn_rate }),
+            _ => None,
+        },
+    })
+}
+
+const ESTIMATE_NAMES: [&str; 7] = [
+    "x",
+    "y",
+    "mean_x",
+    "mean_y",
+    "best_x",
+    "best_y",
+    "best_weight",
+];
+
+pub fn encode_estimate(t_ms: i32, est: &Estimate, encoding: Encoding) -> Vec<u8> {
+    let posn = est.position();
+    let values = [
+        posn.x,
+        posn.y,
+        est.mean.x,
+        est.mean.y,
+        est.best.x,
+        est.best.y,
+        est.best_weight,
+    ];
+    encode(t_ms, &ESTIMATE_NAMES, &values, encoding)
+}
+
+/// The next message on a stream, `None` at a clean end of stream
+fn read_message<R: BufRead>(reader: &mut R, encoding: Encoding) -> io::Result<Option<Vec<u8>>> {
+    match encoding {
+        Encoding::Binary => {
+            let mut len = [0u8; 4];
+            match reader.read_exact(&mut len) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                r => r?,
+            }
+            let len = u32::from_le_bytes(len) as usize;
+            if len > MAX_MESSAGE {
+                return Err(invalid(format!("message of {} bytes", len)));
+            }
+            let mut msg = vec![0u8; len];
+            reader.read_exact(&mut msg)?;
+            Ok(Some(msg))
+        }
+        Encoding::Json => {
+            let mut line = Vec::new();
+            Ok((reader.read_until(b'\n', &mut line)? > 0).then_some(line))
+        }
+    }
+}
+
+/// The message in a datagram, if it holds exactly one
+fn unframe(datagram: &[u8], encoding: Encoding) -> Option<&[u8]> {
+    match encoding {
+        Encoding::Binary => {
+            let len = u32::from_le_bytes(datagram.get(..4)?.try_into().unwrap()) as usize;
+            (datagram.len() == 4 + len).then(|| &datagram[4..])
+        }
+        Encoding::Json => Some(datagram),
+    }
+}
+
+enum Inbound {
+    Udp(UdpSocket),
+    /// One producer at a time; when it goes away the next to connect takes
+    /// its place.
+    Tcp {
+        listener: TcpListener,
+        stream: Option<BufReader<TcpStream>>,
+    },
+}
+
+/// Measurement records from the network, in time order
+pub struct Receiver {
+    inbound: Inbound,
+    encoding: Encoding,
+    last_t_ms: Option<i32>,
+    /// Records dropped for arriving no later than one already taken
+    pub out_of_order: usize,
+    /// Messages dropped for not decoding
+    pub malformed: usize,
+}
+
+impl Receiver {
+    fn new(inbound: Inbound, encoding: Encoding) -> Self {
+        Self {
+            inbound,
+            encoding,
+            last_t_ms: None,
+            out_of_order: 0,
+            malformed: 0,
+        }
+    }
+
+    /// Take one record per datagram sent to `addr`.
+    pub fn udp<A: ToSocketAddrs>(addr: A, encoding: Encoding) -> io::Result<Self> {
+        Ok(Self::new(Inbound::Udp(UdpSocket::bind(addr)?), encoding))
+    }
+
+    /// Listen on `addr` for a producer streaming records.
+    pub fn tcp<A: ToSocketAddrs>(addr: A, encoding: Encoding) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(Self::new(
+            Inbound::Tcp {
+                listener,
+                stream: None,
+            },
+            encoding,
+        ))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.inbound {
+            Inbound::Udp(socket) => socket.local_addr(),
+            Inbound::Tcp { listener, .. } => listener.local_addr(),
+        }
+    }
+
+    fn next_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match &mut self.inbound {
+            Inbound::Udp(socket) => {
+                let mut buf = [0u8; MAX_MESSAGE + 4];
+                let n = socket.recv(&mut buf)?;
+                Ok(unframe(&buf[..n], self.encoding).map(<[u8]>::to_vec))
+            }
+            Inbound::Tcp { listener, stream } => loop {
+                let reader = match stream {
+                    Some(reader) => reader,
+                    None => stream.insert(BufReader::new(listener.accept()?.0)),
+                };
+                match read_message(reader, self.encoding) {
+                    Ok(Some(msg)) => return Ok(Some(msg)),
+                    // A broken stream can't be resynchronized, so wait for
+                    // the producer to reconnect.
+                    Ok(None) | Err(_) => *stream = None,
+                }
+            },
+        }
+    }
+
+    /// Block until the next record later than every record taken so far,
+    /// dropping late and undecodable ones on the way.
+    pub fn recv(&mut self) -> io::Result<Record> {
+        loop {
+            let Some(msg) = self.next_message()? else {
+                self.malformed += 1;
+                continue;
+            };
+            match decode_record(&msg, self.encoding) {
+                Err(_) => self.malformed += 1,
+                Ok(rec) if self.last_t_ms.is_some_and(|last| rec.t_ms <= last) => {
+                    self.out_of_order += 1
+                }
+                Ok(rec) => {
+                    self.last_t_ms = Some(rec.t_ms);
+                    return Ok(rec);
+                }
+            }
+        }
+    }
+}
+
+enum Outbound {
+    Udp {
+        socket: UdpSocket,
+        peer: SocketAddr,
+    },
+    Tcp {
+        peer: SocketAddr,
+        stream: Option<TcpStream>,
+    },
+}
+
+/// Sends each step's estimate to a subscriber
+pub struct NetPublisher {
+    outbound: Outbound,
+    encoding: Encoding,
+}
+
+pub(crate) fn resolve<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))
+}
+
+impl NetPublisher {
+    pub fn udp<A: ToSocketAddrs>(peer: A, encoding: Encoding) -> io::Result<Self> {
+        let peer = resolve(peer)?;
+        let any: SocketAddr = if peer.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        Ok(Self {
+            outbound: Outbound::Udp {
+                socket: UdpSocket::bind(any)?,
+                peer,
+            },
+            encoding,
+        })
+    }
+
+    /// Stream estimates to `peer`, connecting on the first publish.
+    pub fn tcp<A: ToSocketAddrs>(peer: A, encoding: Encoding) -> io::Result<Self> {
+        Ok(Self {
+            outbound: Outbound::Tcp {
+                peer: resolve(peer)?,
+                stream: None,
+            },
+            encoding,
+        })
+    }
+}
+
+impl Publisher for NetPublisher {
+    /// Send the estimate for the step at `t_ms`. A TCP subscriber that has
+    /// gone away is reconnected once before giving up on this estimate.
+    fn publish(&mut self, t_ms: i32, est: &Estimate) -> io::Result<()> {
+        let msg = encode_estimate(t_ms, est, self.encoding);
+        match &mut self.outbound {
+            Outbound::Udp { socket, peer } => socket.send_to(&msg, *peer).map(|_| ()),
+            Outbound::Tcp { peer, stream } => {
+                if let Some(s) = stream
+                    && s.write_all(&msg).is_ok()
+                {
+                    return Ok(());
+                }
+                *stream = None;
+                let s = stream.insert(TcpStream::connect(*peer)?);
+                s.set_nodelay(true)?;
+                s.write_all(&msg)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::estimate::EstimateMode;
+    use std::thread;
+
+    fn record(t_ms: i32) -> Record {
+        Record {
+            t_ms,
+            vehicle: CCoord { x: 1.0, y: 2.0 },
+            gps: CCoord { x: 1.5, y: -2.25 },
+            imu: ACoord { r: 0.5, t: 3.0 },
+            control: (t_ms % 2 == 0).then_some(Control {
+                speed: 1.0,
+                turn_rate: -0.5,
+            }),
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        for encoding in [Encoding::Binary, Encoding::Json] {
+            for rec in [record(10), record(11)] {
+                let msg = encode_record(&rec, encoding);
+                let mut reader = &msg[..];
+                let body = read_message(&mut reader, encoding).unwrap().unwrap();
+                assert_eq!(decode_record(&body, encoding).unwrap(), rec);
+            }
+        }
+        let rec = decode_record(
+            br#"{"t_ms": 5, "gps_x": 1, "gps_y": 2, "imu_r": 0.5, "imu_t": 0}"#,
+            Encoding::Json,
+        )
+        .unwrap();
+        assert_eq!(
+            (rec.t_ms, rec.gps, rec.control),
+            (5, CCoord { x: 1.0, y: 2.0 }, None)
+        );
+        assert!(decode_record(br#"{"t_ms": 5, "gps_x": 1}"#, Encoding::Json).is_err());
+        assert!(decode_record(&[0u8; 20], Encoding::Binary).is_err());
+    }
+
+    #[test]
+    fn udp_drops_late_records() {
+        let mut rx = Receiver::udp("127.0.0.1:0", Encoding::Binary).unwrap();
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx.connect(rx.local_addr().unwrap()).unwrap();
+        for t_ms in [10, 30, 20, 30, 40] {
+            tx.send(&encode_record(&record(t_ms), Encoding::Binary))
+                .unwrap();
+        }
+        tx.send(b"junk").unwrap();
+        tx.send(&encode_record(&record(50), Encoding::Binary))
+            .unwrap();
+        let got: Vec<i32> = (0..4).map(|_| rx.recv().unwrap().t_ms).collect();
+        assert_eq!(got, [10, 30, 40, 50]);
+        assert_eq!((rx.out_of_order, rx.malformed), (2, 1));
+    }
+
+    #[test]
+    fn tcp_producers_reconnect() {
+        let mut rx = Receiver::tcp("127.0.0.1:0", Encoding::Json).unwrap();
+        let addr = rx.local_addr().unwrap();
+        let producer = thread::spawn(move || {
+            for batch in [[10, 20], [30, 40]] {
+                // Each batch on its own connection, the first cut off
+                // halfway through a message
+                let mut stream = TcpStream::connect(addr).unwrap();
+                for t_ms in batch {
+                    stream
+                        .write_all(&encode_record(&record(t_ms), Encoding::Json))
+                        .unwrap();
+                }
+                if batch[0] == 10 {
+                    stream.write_all(b"{\"t_ms\": 25, \"gps").unwrap();
+                }
+            }
+        });
+        let got: Vec<i32> = (0..4).map(|_| rx.recv().unwrap().t_ms).collect();
+        producer.join().unwrap();
+        assert_eq!(got, [10, 20, 30, 40]);
+
+        // Estimates out to a subscriber
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut publisher =
+            NetPublisher::tcp(listener.local_addr().unwrap(), Encoding::Json).unwrap();
+        let est = Estimate {
+            best: CCoord { x: 1.0, y: 2.0 },
+            best_weight: 0.25,
+            worst: CCoord::default(),
+            worst_weight: 0.0,
+            mean: CCoord { x: 3.0, y: 4.0 },
+            map: CCoord::default(),
+            mode: EstimateMode::WeightedMean,
+            interval: None,
+            ..Default::default()
+        };
+        publisher.publish(40, &est).unwrap();
+        let mut line = String::new();
+        BufReader::new(listener.accept().unwrap().0)
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!(
+            line,
+            "{\"t_ms\":40,\"x\":3,\"y\":4,\"mean_x\":3,\"mean_y\":4,\"best_x\":1,\"best_y\":2,\"best_weight\":0.25}\n"
+        );
+    }
+}
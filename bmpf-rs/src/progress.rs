@@ -0,0 +1,201 @@
+//! Progress reports and cooperative cancellation for long runs. A driver
+//! such as `interval::sweep_with` or `Smoother::mean_path_with` calls
+//! [`Monitor::step`] between steps; it reports progress now and then and
+//! says whether to stop, and the driver then returns what it has so far
+//! rather than nothing.
+
+use std::{
+    ops::AddAssign,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Asks a run to stop at its next step. Clones share the flag, so one can
+/// be handed to another thread, a timer or a signal handler.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How far a run has got
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    pub done: usize,
+    /// Steps in the whole run, when known
+    pub total: Option<usize>,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Time left at the rate so far
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total?;
+        if self.done == 0 {
+            return None;
+        }
+        let left = total.saturating_sub(self.done) as f64;
+        Some(self.elapsed.mul_f64(left / self.done as f64))
+    }
+}
+
+/// Progress callback and cancellation token a driver checks between steps
+pub struct Monitor {
+    /// Called at most once per `every`, and when the run ends
+    pub callback: Option<Box<dyn FnMut(Progress) + Send>>,
+    pub every: Duration,
+    pub cancel: CancelToken,
+    start: Instant,
+    last: Instant,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            callback: None,
+            every: Duration::from_secs(1),
+            cancel: CancelToken::default(),
+            start: now,
+            last: now,
+        }
+    }
+}
+
+impl Monitor {
+    /// Report progress through `callback` and stop when `cancel` is set
+    pub fn new(callback: impl FnMut(Progress) + Send + 'static, cancel: CancelToken) -> Self {
+        Self {
+            callback: Some(Box::new(callback)),
+            cancel,
+            ..Self::default()
+        }
+    }
+
+    /// Note that `done` of `total` steps are done, reporting if it is time
+    /// to, and return whether to go on.
+    pub fn step(&mut self, done: usize, total: Option<usize>) -> bool {
+        if let Some(callback) = &mut self.callback {
+            let now = Instant::now();
+            let finished = total == Some(done);
+            if finished || now.duration_since(self.last) >= self.every {
+                self.last = now;
+                callback(Progress {
+                    done,
+                    total,
+                    elapsed: now.duration_since(self.start),
+                });
+            }
+        }
+        !self.cancel.is_cancelled()
+    }
+}
+
+/// Wall time a filter spent in each phase of its steps, summed over
+/// `steps`. Motion drawn particle by particle alongside weighing, as with
+/// proposals, controls or reversed time, counts as weighing; reporting
+/// counts as nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PhaseTimes {
+    pub steps: usize,
+    /// Moving the particles by the motion model
+    pub propagate: Duration,
+    /// Weighing and normalizing the particles
+    pub weight: Duration,
+    /// Summing the mean, MAP, velocity and other estimates
+    pub estimate: Duration,
+    /// Choosing whether to resample, and resampling
+    pub resample: Duration,
+}
+
+impl PhaseTimes {
+    /// Time of each phase per step, as propagate, weight, estimate and
+    /// resample
+    pub fn per_step(&self) -> [Duration; 4] {
+        let n = self.steps.max(1) as u32;
+        [
+            self.propagate / n,
+            self.weight / n,
+            self.estimate / n,
+            self.resample / n,
+        ]
+    }
+}
+
+impl AddAssign for PhaseTimes {
+    fn add_assign(&mut self, other: Self) {
+        self.steps += other.steps;
+        self.propagate += other.propagate;
+        self.weight += other.weight;
+        self.estimate += other.estimate;
+        self.resample += other.resample;
+    }
+}
+
+/// Time since `clock` was last read, restarting it, or zero when not
+/// timing
+pub(crate) fn lap(clock: &mut Option<Instant>) -> Duration {
+    match clock {
+        Some(last) => {
+            let now = Instant::now();
+            let elapsed = now.duration_since(*last);
+            *last = now;
+            elapsed
+        }
+        None => Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn reports_and_cancels() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let cancel = CancelToken::new();
+        let mut monitor = Monitor::new(
+            {
+                let seen = seen.clone();
+                move |p: Progress| seen.lock().unwrap().push(p.done)
+            },
+            cancel.clone(),
+        );
+        monitor.every = Duration::ZERO;
+        assert!(monitor.step(1, Some(4)));
+        assert!(monitor.step(2, Some(4)));
+        cancel.cancel();
+        assert!(!monitor.step(3, Some(4)));
+        assert_eq!(*seen.lock().unwrap(), [1, 2, 3]);
+
+        // Only the end is reported between long intervals.
+        seen.lock().unwrap().clear();
+        monitor.every = Duration::from_secs(3600);
+        monitor.step(3, Some(4));
+        monitor.step(4, Some(4));
+        assert_eq!(*seen.lock().unwrap(), [4]);
+
+        let p = Progress {
+            done: 25,
+            total: Some(100),
+            elapsed: Duration::from_secs(10),
+        };
+        assert_eq!(p.eta(), Some(Duration::from_secs(30)));
+        assert_eq!(Progress { total: None, ..p }.eta(), None);
+    }
+}
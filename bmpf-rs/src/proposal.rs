@@ -0,0 +1,259 @@
+//! Proposal distributions for moving particles between measurements.
+
+use crate::{
+    fp::{cos, ln, sin},
+    gaussian,
+    sim::{SimParams, angle_delta, normalize_angle},
+    types::{ACoord, CCoord, VehicleState},
+};
+use std::f64::consts::PI;
+
+/// Moves a particle given the measurements it is about to be weighted by.
+///
+/// The bootstrap filter proposes from the motion model alone, so the
+/// likelihood is the whole weight update. A proposal that looks at the
+/// measurements must return the importance correction
+/// `ln p(new | old) - ln q(new | old, gps, imu)`, which `bpf_step` adds to
+/// the log weight. The filter moves particles with `noise` 1, so
+/// [`VehicleState::log_velocity_prior`] with `noise` 1 gives the first
+/// term, and [`VehicleState::advance`] applies a drawn velocity.
+pub trait Proposal: Send {
+    fn propose(
+        &mut self,
+        params: &SimParams,
+        state: &mut VehicleState,
+        dt: f64,
+        gps: CCoord,
+        imu: ACoord,
+    ) -> f64;
+}
+
+/// The filter's motion model itself, which needs no correction.
+/// Equivalent to leaving `BpfState::proposal` unset.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bootstrap;
+
+impl Proposal for Bootstrap {
+    fn propose(
+        &mut self,
+        params: &SimParams,
+        state: &mut VehicleState,
+        dt: f64,
+        _gps: CCoord,
+        _imu: ACoord,
+    ) -> f64 {
+        state.update_state(params, dt, 1);
+        0.0
+    }
+}
+
+/// Gaussian proposal over each particle's next velocity: the motion
+/// model's velocity prior updated by the IMU reading and by the GPS fix
+/// linearized through one Euler step, as in an extended Kalman filter. A
+/// cut-down unscented particle filter. Draws are clipped and bounced like
+/// the motion model's, and the correction is taken on the unclipped draw,
+/// so it stays exact.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Linearized;
+
+/// Symmetric 2x2 matrix `[[a, b], [b, c]]`
+#[derive(Clone, Copy)]
+struct Sym2 {
+    a: f64,
+    b: f64,
+    c: f64,
+}
+
+impl Linearized {
+    /// Kalman update of mean `m` and covariance `p` with an observation
+    /// `z` of `h * v` whose noise has covariance `r`.
+    fn update(m: [f64; 2], p: Sym2, h: [[f64; 2]; 2], z: [f64; 2], r: Sym2) -> ([f64; 2], Sym2) {
+        // ph = p * h^T, s = h * p * h^T + r
+        let ph = [
+            [p.a * h[0][0] + p.b * h[0][1], p.a * h[1][0] + p.b * h[1][1]],
+            [p.b * h[0][0] + p.c * h[0][1], p.b * h[1][0] + p.c * h[1][1]],
+        ];
+        let s = Sym2 {
+            a: h[0][0] * ph[0][0] + h[0][1] * ph[1][0] + r.a,
+            b: h[0][0] * ph[0][1] + h[0][1] * ph[1][1] + r.b,
+            c: h[1][0] * ph[0][1] + h[1][1] * ph[1][1] + r.c,
+        };
+        let det = s.a * s.c - s.b * s.b;
+        let si = Sym2 {
+            a: s.c / det,
+            b: -s.b / det,
+            c: s.a / det,
+        };
+        // k = ph * s^-1
+        let k = [
+            [
+                ph[0][0] * si.a + ph[0][1] * si.b,
+                ph[0][0] * si.b + ph[0][1] * si.c,
+            ],
+            [
+                ph[1][0] * si.a + ph[1][1] * si.b,
+                ph[1][0] * si.b + ph[1][1] * si.c,
+            ],
+        ];
+        let m = [
+            m[0] + k[0][0] * z[0] + k[0][1] * z[1],
+            m[1] + k[1][0] * z[0] + k[1][1] * z[1],
+        ];
+        // p - k * ph^T
+        let p = Sym2 {
+            a: p.a - (k[0][0] * ph[0][0] + k[0][1] * ph[0][1]),
+            b: p.b - (k[0][0] * ph[1][0] + k[0][1] * ph[1][1]),
+            c: p.c - (k[1][0] * ph[1][0] + k[1][1] * ph[1][1]),
+        };
+        (m, p)
+    }
+}
+
+impl Proposal for Linearized {
+    fn propose(
+        &mut self,
+        params: &SimParams,
+        state: &mut VehicleState,
+        dt: f64,
+        gps: CCoord,
+        imu: ACoord,
+    ) -> f64 {
+        let vel = state.vel();
+        let scale = params.process_noise_scale(dt) * 9.0;
+        let (sr, st) = (params.rvar * scale, params.avar * scale);
+        let mut m = [vel.r, vel.t];
+        let mut p = Sym2 {
+            a: sr * sr,
+            b: 0.0,
+            c: st * st,
+        };
+
+        let (ir, it) = params.imu_likelihood_sd(dt);
+        // What the IMU does not give is observed with no gain.
+        let (hr, ht) = (params.imu_mode.has_speed(), params.imu_mode.has_heading());
+        let gain = |has: bool, v: f64| if has { v } else { 0.0 };
+        let z = [gain(hr, imu.r - m[0]), gain(ht, angle_delta(imu.t, m[1]))];
+        let h = [[gain(hr, 1.0), 0.0], [0.0, gain(ht, 1.0)]];
+        let r = Sym2 {
+            a: ir * ir,
+            b: 0.0,
+            c: it * it,
+        };
+        (m, p) = Self::update(m, p, h, z, r);
+
+        // Position after an Euler step at the current mean, and its
+        // Jacobian with respect to speed and heading.
+        let (sin, cos) = (sin(m[1]), cos(m[1]));
+        let x = state.posn.x + m[0] * cos * dt;
+        let y = state.posn.y - m[0] * sin * dt;
+        let h = [[cos * dt, -m[0] * sin * dt], [-sin * dt, -m[0] * cos * dt]];
+        let z = [params.delta(gps.x, x), params.delta(gps.y, y)];
+        let [[gxx, gxy], [_, gyy]] = params.gps_covariance();
+        let g = Sym2 {
+            a: gxx,
+            b: gxy,
+            c: gyy,
+        };
+        (m, p) = Self::update(m, p, h, z, g);
+
+        let l11 = p.a.sqrt();
+        let l21 = p.b / l11;
+        let l22 = (p.c - l21 * l21).max(0.0).sqrt();
+        let (u1, u2) = (gaussian(1.0), gaussian(1.0));
+        let r = m[0] + l11 * u1;
+        let t = m[1] + l21 * u1 + l22 * u2;
+        let log_q = -0.5 * (u1 * u1 + u2 * u2) - ln(l11 * l22) - ln(2.0 * PI);
+        let log_p = state.log_velocity_prior(params, r, t, dt, 1);
+        state.advance(params, params.clip_speed(r), normalize_angle(t), dt);
+        log_p - log_q
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BpfState;
+
+    fn filter(seed: u32, proposal: Option<Box<dyn Proposal>>, params: SimParams) -> BpfState {
+        crate::seed_thread_rng(seed);
+        let mut bpf = BpfState::new("regular", false, 50, 0, false, 2, params);
+        bpf.reporter = None;
+        bpf.proposal = proposal;
+        bpf.init_particles();
+        bpf
+    }
+
+    #[test]
+    fn bootstrap_matches_no_proposal() {
+        let gps = CCoord { x: 1.0, y: -2.0 };
+        let imu = ACoord { r: 0.5, t: 1.0 };
+        let mut plain = filter(4651, None, SimParams::default());
+        let plain: Vec<_> = (0..5).map(|_| plain.observe(0.0, 0.01, gps, imu)).collect();
+        let mut boot = filter(4651, Some(Box::new(Bootstrap)), SimParams::default());
+        let boot: Vec<_> = (0..5).map(|_| boot.observe(0.0, 0.01, gps, imu)).collect();
+        assert_eq!(plain, boot);
+    }
+
+    /// Effective sample size of the weights after one unresampled step
+    fn ess(proposal: Box<dyn Proposal>, params: &SimParams) -> f64 {
+        let mut bpf = filter(4652, Some(proposal), params.clone());
+        let gps = CCoord { x: 1.0, y: -2.0 };
+        bpf.observe(0.0, 0.01, gps, ACoord { r: 1.0, t: 1.0 });
+        let (w, w2) = bpf.particles().iter().fold((0.0, 0.0), |(w, w2), p| {
+            (w + p.weight, w2 + p.weight * p.weight)
+        });
+        w * w / w2
+    }
+
+    #[test]
+    fn linearized_keeps_more_particles_alive() {
+        // A sharp IMU and a vague GPS leave few bootstrap particles with
+        // any weight, and nothing the proposal cannot steer.
+        let params = SimParams {
+            gps_var: 100.0,
+            imu_r_var: 0.0005,
+            imu_a_var: 0.0005,
+            ..SimParams::default()
+        };
+        let boot = ess(Box::new(Bootstrap), &params);
+        let lin = ess(Box::new(Linearized), &params);
+        assert!(lin > 2.0 * boot, "{} vs {}", lin, boot);
+        assert!(lin > 25.0, "{}", lin);
+    }
+
+    /// Motion model moves, but particles left of the origin are ruled out.
+    struct RightHalf;
+
+    impl Proposal for RightHalf {
+        fn propose(
+            &mut self,
+            params: &SimParams,
+            state: &mut VehicleState,
+            dt: f64,
+            _gps: CCoord,
+            _imu: ACoord,
+        ) -> f64 {
+            state.update_state(params, dt, 1);
+            if state.posn.x < 0.0 {
+                f64::NEG_INFINITY
+            } else {
+                0.0
+            }
+        }
+    }
+
+    #[test]
+    fn corrections_reach_the_weights() {
+        for log_weights in [false, true] {
+            let mut bpf = filter(4651, Some(Box::new(RightHalf)), SimParams::default());
+            bpf.log_weights = log_weights;
+            // The first of every two steps leaves the weights unresampled.
+            bpf.observe(0.0, 0.01, CCoord::default(), ACoord::default());
+            let particles = bpf.particles();
+            assert!(particles.iter().any(|p| p.state.posn.x < 0.0));
+            for p in particles {
+                assert_eq!(p.state.posn.x < 0.0, p.weight == 0.0, "{}", log_weights);
+            }
+        }
+    }
+}
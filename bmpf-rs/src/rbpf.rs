@@ -0,0 +1,232 @@
+//! Rao-Blackwellized filtering: each particle carries a small
+//! linear-Gaussian sub-state, tracked exactly by its own Kalman filter
+//! conditioned on the particle's position and velocity. The GPS likelihood
+//! becomes the Kalman filter's marginal likelihood of the fix.
+
+use crate::{
+    fp::ln,
+    sim::SimParams,
+    types::{CCoord, VehicleState},
+};
+use std::f64::consts::PI;
+
+/// 2x2 matrix, row major
+pub type Mat2 = [[f64; 2]; 2];
+
+const IDENTITY: Mat2 = [[1.0, 0.0], [0.0, 1.0]];
+
+fn mul(a: &Mat2, b: &Mat2) -> Mat2 {
+    [
+        [
+            a[0][0] * b[0][0] + a[0][1] * b[1][0],
+            a[0][0] * b[0][1] + a[0][1] * b[1][1],
+        ],
+        [
+            a[1][0] * b[0][0] + a[1][1] * b[1][0],
+            a[1][0] * b[0][1] + a[1][1] * b[1][1],
+        ],
+    ]
+}
+
+fn mul_vec(a: &Mat2, v: [f64; 2]) -> [f64; 2] {
+    [
+        a[0][0] * v[0] + a[0][1] * v[1],
+        a[1][0] * v[0] + a[1][1] * v[1],
+    ]
+}
+
+fn transpose(a: &Mat2) -> Mat2 {
+    [[a[0][0], a[1][0]], [a[0][1], a[1][1]]]
+}
+
+fn add(a: &Mat2, b: &Mat2) -> Mat2 {
+    [
+        [a[0][0] + b[0][0], a[0][1] + b[0][1]],
+        [a[1][0] + b[1][0], a[1][1] + b[1][1]],
+    ]
+}
+
+fn det(a: &Mat2) -> f64 {
+    a[0][0] * a[1][1] - a[0][1] * a[1][0]
+}
+
+fn inverse(a: &Mat2) -> Mat2 {
+    let d = det(a);
+    [[a[1][1] / d, -a[0][1] / d], [-a[1][0] / d, a[0][0] / d]]
+}
+
+/// Mean and covariance of a particle's linear sub-state
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SubState {
+    pub mean: [f64; 2],
+    pub cov: Mat2,
+}
+
+/// Linear-Gaussian model of the sub-state given the particle's state:
+/// `z' = A z + w` with `w ~ N(0, Q)` between fixes, and a GPS fix
+/// `y = H z + v` with `v ~ N(0, R)` once the particle's own contribution is
+/// taken out.
+pub trait LinearSubModel: Send {
+    /// Sub-state every particle starts with
+    fn init(&self) -> SubState;
+
+    /// `(A, Q)` for a step of `dt`
+    fn transition(&self, params: &SimParams, dt: f64) -> (Mat2, Mat2);
+
+    /// `(y, H, R)` for a GPS fix, with `y` already net of the particle's
+    /// own prediction of the fix
+    fn observation(
+        &self,
+        params: &SimParams,
+        state: &VehicleState,
+        gps: CCoord,
+    ) -> ([f64; 2], Mat2, Mat2);
+}
+
+/// Random walk GPS bias: the fix is the position plus a slowly drifting
+/// offset plus the usual noise.
+#[derive(Clone, Copy, Debug)]
+pub struct GpsBias {
+    /// Prior deviation of the bias on each axis
+    pub initial_sd: f64,
+    /// Bias drift per square root second
+    pub drift: f64,
+}
+
+impl Default for GpsBias {
+    fn default() -> Self {
+        Self {
+            initial_sd: 1.0,
+            drift: 0.1,
+        }
+    }
+}
+
+impl LinearSubModel for GpsBias {
+    fn init(&self) -> SubState {
+        let v = self.initial_sd * self.initial_sd;
+        SubState {
+            mean: [0.0; 2],
+            cov: [[v, 0.0], [0.0, v]],
+        }
+    }
+
+    fn transition(&self, _params: &SimParams, dt: f64) -> (Mat2, Mat2) {
+        let q = self.drift * self.drift * dt;
+        (IDENTITY, [[q, 0.0], [0.0, q]])
+    }
+
+    fn observation(
+        &self,
+        params: &SimParams,
+        state: &VehicleState,
+        gps: CCoord,
+    ) -> ([f64; 2], Mat2, Mat2) {
+        let y = [
+            params.delta(gps.x, state.posn.x),
+            params.delta(gps.y, state.posn.y),
+        ];
+        (y, IDENTITY, params.gps_covariance())
+    }
+}
+
+/// Kalman predict and update `sub` for one step, returning the log
+/// marginal likelihood of the fix, or `-inf` where `gps_prob` rules the
+/// particle out.
+pub fn update(
+    model: &dyn LinearSubModel,
+    params: &SimParams,
+    sub: &mut SubState,
+    state: &VehicleState,
+    dt: f64,
+    gps: CCoord,
+) -> f64 {
+    let (x, y) = (state.posn.x, state.posn.y);
+    let bounds = params.log_bounds_factor(x, y);
+    if bounds == f64::NEG_INFINITY || params.blocked(x, y) {
+        return f64::NEG_INFINITY;
+    }
+    let (a, q) = model.transition(params, dt);
+    let mean = mul_vec(&a, sub.mean);
+    let cov = add(&mul(&mul(&a, &sub.cov), &transpose(&a)), &q);
+
+    let (y, h, r) = model.observation(params, state, gps);
+    let pred = mul_vec(&h, mean);
+    let nu = [y[0] - pred[0], y[1] - pred[1]];
+    let pht = mul(&cov, &transpose(&h));
+    let s = add(&mul(&h, &pht), &r);
+    let s_inv = inverse(&s);
+    let k = mul(&pht, &s_inv);
+    let kn = mul_vec(&k, nu);
+    sub.mean = [mean[0] + kn[0], mean[1] + kn[1]];
+    let kh = mul(&k, &h);
+    sub.cov = mul(
+        &[[1.0 - kh[0][0], -kh[0][1]], [-kh[1][0], 1.0 - kh[1][1]]],
+        &cov,
+    );
+
+    let m = mul_vec(&s_inv, nu);
+    -0.5 * (nu[0] * m[0] + nu[1] * m[1]) - 0.5 * ln(det(&s)) - ln(2.0 * PI) + bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ACoord, BpfState};
+
+    #[test]
+    fn update_matches_closed_form() {
+        let params = SimParams::default();
+        let model = GpsBias {
+            initial_sd: 1.0,
+            drift: 0.0,
+        };
+        let mut sub = model.init();
+        let state = VehicleState::default();
+        let ll = update(
+            &model,
+            &params,
+            &mut sub,
+            &state,
+            0.1,
+            CCoord { x: 2.0, y: 0.0 },
+        );
+        // Prior variance 1 and noise variance 1 meet halfway.
+        assert!((sub.mean[0] - 1.0).abs() < 1e-12 && sub.mean[1].abs() < 1e-12);
+        assert!((sub.cov[0][0] - 0.5).abs() < 1e-12 && sub.cov[0][1].abs() < 1e-12);
+        // Each axis sees the fix as N(0, 2).
+        let expected = -0.5 * 4.0 / 2.0 - (2.0 * PI * 2.0).ln();
+        assert!((ll - expected).abs() < 1e-12, "{} {}", ll, expected);
+    }
+
+    #[test]
+    fn particles_carry_their_bias() {
+        crate::seed_thread_rng(4655);
+        let params = SimParams::default();
+        let mut vehicle = VehicleState::default();
+        vehicle.init_state(&params);
+        let mut bpf = BpfState::new("regular", false, 200, 0, false, 1, params.clone());
+        bpf.reporter = None;
+        bpf.rbpf = Some(Box::new(GpsBias::default()));
+        bpf.init_particles();
+        let (dt, mut sq) = (0.1, 0.0);
+        for i in 0..200 {
+            vehicle.update_state(&params, dt, 0);
+            let gps = vehicle.gps_measure(&params);
+            let imu: ACoord = vehicle.imu_measure(&params, dt);
+            let est = bpf.observe(i as f64 * dt, dt, gps, imu);
+            if i >= 20 {
+                sq += (est.mean.x - vehicle.posn.x).powi(2) + (est.mean.y - vehicle.posn.y).powi(2);
+            }
+        }
+        assert!((sq / 180.0).sqrt() < 3.0);
+        let particles = bpf.particles();
+        // Resampling copied sub-states along with the particles, and the
+        // true bias is zero.
+        let first = particles[0].sub;
+        assert!(particles.iter().all(|p| p.sub.cov[0][0] < 1.0));
+        assert!(particles.iter().any(|p| p.sub != first));
+        let bias = particles.iter().map(|p| p.sub.mean[0]).sum::<f64>() / particles.len() as f64;
+        assert!(bias.abs() < 1.0, "{}", bias);
+    }
+}
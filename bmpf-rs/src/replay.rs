@@ -0,0 +1,452 @@
+//! Recording of everything a filter's steps consume, and replay of the
+//! same computation. A recording holds the particles and this thread's
+//! generator positions where it began, then each step's measurements and
+//! the generator positions it started from, so a run that goes wrong
+//! mid-stream can be stepped through again under a debugger, step for
+//! step and draw for draw.
+
+use crate::{
+    GaussianSource, RngPosition,
+    io::invalid,
+    seek_thread_rng, thread_rng_position,
+    types::{ACoord, BpfState, CCoord, Control, Estimate, ParticleInfo, StepKind, VehicleState},
+};
+use std::{
+    fmt::Display,
+    io::{self, BufRead, Write},
+    str::{FromStr, SplitWhitespace},
+};
+use ziggurat_rs::StreamPosition;
+
+const MAGIC: &str = "bmpf-recording 1";
+
+/// One filter step as it was run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordedStep {
+    pub kind: StepKind,
+    pub t: f64,
+    pub dt: f64,
+    pub report: bool,
+    pub gps: CCoord,
+    pub imu: ACoord,
+    /// Step the IMU reading covered, if no fix had weighed it yet
+    pub imu_dt: Option<f64>,
+    /// Time moved since particles were last weighted
+    pub since_fix: f64,
+    pub control: Option<Control>,
+    /// Where the generators were as the step began
+    pub rng: RngPosition,
+    /// Weighted mean the step estimated, to tell a replay that diverged
+    pub mean: CCoord,
+}
+
+/// Steps recorded by `BpfState::start_recording`
+#[derive(Clone, Default)]
+pub struct Recording {
+    /// Where the generators were when recording began
+    pub rng: RngPosition,
+    pub resample_count: usize,
+    /// The particles when recording began
+    pub particles: Vec<ParticleInfo>,
+    pub steps: Vec<RecordedStep>,
+}
+
+fn opt<T: Display>(v: Option<T>) -> String {
+    v.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+fn kind_name(kind: StepKind) -> &'static str {
+    match kind {
+        StepKind::Full => "full",
+        StepKind::Predict => "predict",
+        StepKind::Correct => "correct",
+    }
+}
+
+fn write_rng<W: Write>(w: &mut W, rng: &RngPosition) -> io::Result<()> {
+    write!(
+        w,
+        "{:?} {} {} {} {} {} {}",
+        rng.source,
+        rng.ziggurat.seed,
+        rng.ziggurat.draws,
+        rng.last,
+        rng.box_muller.seed,
+        rng.box_muller.draws,
+        opt(rng.spare)
+    )
+}
+
+/// Whitespace-separated fields of one line of a recording
+struct Fields<'a> {
+    line: usize,
+    fields: SplitWhitespace<'a>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(line: usize, text: &'a str) -> Self {
+        Self {
+            line,
+            fields: text.split_whitespace(),
+        }
+    }
+
+    fn error(&self, msg: String) -> io::Error {
+        invalid(format!("recording line {}: {}", self.line, msg))
+    }
+
+    fn word(&mut self) -> io::Result<&'a str> {
+        self.fields
+            .next()
+            .ok_or_else(|| self.error("too few fields".to_string()))
+    }
+
+    fn next<T: FromStr>(&mut self) -> io::Result<T> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| self.error(format!("bad field {:?}", word)))
+    }
+
+    fn opt<T: FromStr>(&mut self) -> io::Result<Option<T>> {
+        match self.word()? {
+            "-" => Ok(None),
+            word => word
+                .parse()
+                .map(Some)
+                .map_err(|_| self.error(format!("bad field {:?}", word))),
+        }
+    }
+
+    fn rng(&mut self) -> io::Result<RngPosition> {
+        let source = match self.word()? {
+            "Ziggurat" => GaussianSource::Ziggurat,
+            "BoxMuller" => GaussianSource::BoxMuller,
+            word => return Err(self.error(format!("unknown gaussian source {}", word))),
+        };
+        Ok(RngPosition {
+            source,
+            ziggurat: StreamPosition {
+                seed: self.next()?,
+                draws: self.next()?,
+                block: false,
+            },
+            last: self.next()?,
+            box_muller: StreamPosition {
+                seed: self.next()?,
+                draws: self.next()?,
+                block: false,
+            },
+            spare: self.opt()?,
+        })
+    }
+
+    fn end(mut self) -> io::Result<()> {
+        match self.fields.next() {
+            Some(word) => Err(self.error(format!("unexpected field {:?}", word))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Recording {
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.particles.capacity() * size_of::<ParticleInfo>()
+            + self.steps.capacity() * size_of::<RecordedStep>()
+    }
+
+    /// Write the recording as text, exact to the bit.
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "{}", MAGIC)?;
+        write!(w, "rng ")?;
+        write_rng(&mut w, &self.rng)?;
+        writeln!(w)?;
+        writeln!(w, "resample_count {}", self.resample_count)?;
+        writeln!(w, "particles {}", self.particles.len())?;
+        for p in &self.particles {
+            let (posn, vel) = (p.state.posn, p.state.vel());
+            write!(w, "{} {} {} {} {}", posn.x, posn.y, vel.r, vel.t, p.weight)?;
+            #[cfg(feature = "rbpf")]
+            {
+                let (m, c) = (p.sub.mean, p.sub.cov);
+                write!(
+                    w,
+                    " {} {} {} {} {} {}",
+                    m[0], m[1], c[0][0], c[0][1], c[1][0], c[1][1]
+                )?;
+            }
+            writeln!(w)?;
+        }
+        for s in &self.steps {
+            write!(
+                w,
+                "step {} {} {} {} {} {} {} {} {} {} {} {} ",
+                kind_name(s.kind),
+                s.t,
+                s.dt,
+                s.report as u8,
+                s.gps.x,
+                s.gps.y,
+                s.imu.r,
+                s.imu.t,
+                opt(s.imu_dt),
+                s.since_fix,
+                opt(s.control.map(|c| c.speed)),
+                opt(s.control.map(|c| c.turn_rate)),
+            )?;
+            write_rng(&mut w, &s.rng)?;
+            writeln!(w, " {} {}", s.mean.x, s.mean.y)?;
+        }
+        Ok(())
+    }
+
+    /// Read a recording written by [`Recording::write`].
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let mut line_no = 0;
+        let mut next_line = || -> io::Result<Option<(usize, String)>> {
+            line_no += 1;
+            lines.next().transpose().map(|l| l.map(|l| (line_no, l)))
+        };
+        let header = |line: Option<(usize, String)>, name: &str| {
+            line.and_then(|(n, l)| l.strip_prefix(name).map(|rest| (n, rest.to_string())))
+                .ok_or_else(|| invalid(format!("recording without {}", name.trim())))
+        };
+        match next_line()? {
+            Some((_, l)) if l == MAGIC => {}
+            _ => return Err(invalid("not a bmpf recording".to_string())),
+        }
+        let (n, text) = header(next_line()?, "rng ")?;
+        let mut fields = Fields::new(n, &text);
+        let rng = fields.rng()?;
+        fields.end()?;
+        let (n, text) = header(next_line()?, "resample_count ")?;
+        let mut fields = Fields::new(n, &text);
+        let resample_count = fields.next()?;
+        fields.end()?;
+        let (n, text) = header(next_line()?, "particles ")?;
+        let mut fields = Fields::new(n, &text);
+        let nparticles: usize = fields.next()?;
+        fields.end()?;
+
+        let mut particles = Vec::with_capacity(nparticles);
+        for _ in 0..nparticles {
+            let (n, text) = next_line()?
+                .ok_or_else(|| invalid("recording ends in its particles".to_string()))?;
+            let mut fields = Fields::new(n, &text);
+            let posn = CCoord {
+                x: fields.next()?,
+                y: fields.next()?,
+            };
+            let vel = ACoord {
+                r: fields.next()?,
+                t: fields.next()?,
+            };
+            let p = ParticleInfo {
+                state: VehicleState::new(posn, vel),
+                weight: fields.next()?,
+                #[cfg(feature = "rbpf")]
+                sub: crate::rbpf::SubState {
+                    mean: [fields.next()?, fields.next()?],
+                    cov: [
+                        [fields.next()?, fields.next()?],
+                        [fields.next()?, fields.next()?],
+                    ],
+                },
+            };
+            fields.end()?;
+            particles.push(p);
+        }
+
+        let mut steps = Vec::new();
+        while let Some((n, text)) = next_line()? {
+            let (_, text) = header(Some((n, text)), "step ")?;
+            let mut fields = Fields::new(n, &text);
+            let kind = match fields.word()? {
+                "full" => StepKind::Full,
+                "predict" => StepKind::Predict,
+                "correct" => StepKind::Correct,
+                word => return Err(fields.error(format!("unknown step kind {}", word))),
+            };
+            let t = fields.next()?;
+            let dt = fields.next()?;
+            let report = fields.next::<u8>()? != 0;
+            let gps = CCoord {
+                x: fields.next()?,
+                y: fields.next()?,
+            };
+            let imu = ACoord {
+                r: fields.next()?,
+                t: fields.next()?,
+            };
+            let imu_dt = fields.opt()?;
+            let since_fix = fields.next()?;
+            let control = match (fields.opt()?, fields.opt()?) {
+                (Some(speed), Some(turn_rate)) => Some(Control { speed, turn_rate }),
+                _ => None,
+            };
+            let rng = fields.rng()?;
+            let mean = CCoord {
+                x: fields.next()?,
+                y: fields.next()?,
+            };
+            fields.end()?;
+            steps.push(RecordedStep {
+                kind,
+                t,
+                dt,
+                report,
+                gps,
+                imu,
+                imu_dt,
+                since_fix,
+                control,
+                rng,
+                mean,
+            });
+        }
+        Ok(Self {
+            rng,
+            resample_count,
+            particles,
+            steps,
+        })
+    }
+}
+
+/// Re-runs a recording on a filter configured as the recorded one was,
+/// checking at every step that the computation has not diverged
+pub struct Replayer<'a> {
+    recording: &'a Recording,
+    next: usize,
+}
+
+impl<'a> Replayer<'a> {
+    /// Put `bpf`'s particles and this thread's generators back where they
+    /// were when `recording` began.
+    pub fn new(recording: &'a Recording, bpf: &mut BpfState) -> Result<Self, String> {
+        bpf.restore(recording)?;
+        seek_thread_rng(&recording.rng).map_err(|e| format!("Cannot replay: {}", e))?;
+        Ok(Self { recording, next: 0 })
+    }
+
+    /// Index of the step `step` runs next
+    pub fn next_step(&self) -> usize {
+        self.next
+    }
+
+    /// Run the next recorded step, or return `None` once all have run. A
+    /// step that starts with the generators somewhere else than recorded,
+    /// or estimates a different mean, is an error.
+    pub fn step(&mut self, bpf: &mut BpfState) -> Option<Result<Estimate, String>> {
+        let step = self.recording.steps.get(self.next)?;
+        let i = self.next;
+        self.next += 1;
+        let rng = thread_rng_position();
+        if rng != step.rng {
+            return Some(Err(format!(
+                "step {} starts with the generators at {:?}, recorded at {:?}",
+                i, rng, step.rng
+            )));
+        }
+        let est = bpf.replay(step);
+        let same = |a: f64, b: f64| a.to_bits() == b.to_bits();
+        if !same(est.mean.x, step.mean.x) || !same(est.mean.y, step.mean.y) {
+            return Some(Err(format!(
+                "step {} estimates ({}, {}), recorded ({}, {})",
+                i, est.mean.x, est.mean.y, step.mean.x, step.mean.y
+            )));
+        }
+        Some(Ok(est))
+    }
+
+    /// Replay every step before step `i`, so a debugger can take over for
+    /// step `i` itself.
+    pub fn run_to(&mut self, bpf: &mut BpfState, i: usize) -> Result<(), String> {
+        while self.next < i {
+            match self.step(bpf) {
+                Some(result) => result?,
+                None => return Err(format!("only {} steps were recorded", self.next)),
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::Reading, sim::SimParams};
+
+    fn filter() -> BpfState {
+        filter_with(SimParams::default())
+    }
+
+    fn filter_with(params: SimParams) -> BpfState {
+        let mut bpf = BpfState::new("regular", false, 100, 0, false, 2, params);
+        bpf.reporter = None;
+        bpf
+    }
+
+    #[test]
+    fn replays_a_run_exactly() {
+        crate::seed_thread_rng(4674);
+        let mut bpf = filter();
+        bpf.init_particles();
+        // Skip some steps, so recording starts mid-run.
+        for i in 0..5 {
+            bpf.observe(i as f64, 0.1, CCoord { x: 1.0, y: 1.0 }, ACoord::default());
+        }
+        bpf.start_recording();
+        let mut estimates = Vec::new();
+        for i in 0..20 {
+            let t = 0.5 + 0.1 * i as f64;
+            let gps = CCoord {
+                x: 1.0 + 0.05 * i as f64,
+                y: 1.0,
+            };
+            bpf.observe_reading(t, 0.05, Reading::Imu(ACoord { r: 0.5, t: 0.0 }));
+            estimates.push(
+                bpf.observe_reading(t, 0.05, Reading::Gps(gps))
+                    .unwrap()
+                    .mean,
+            );
+        }
+        let recording = bpf.stop_recording().unwrap();
+        assert_eq!(recording.steps.len(), 60);
+
+        let mut text = Vec::new();
+        recording.write(&mut text).unwrap();
+        let read = Recording::read(text.as_slice()).unwrap();
+        assert_eq!(read.steps, recording.steps);
+        assert_eq!(read.rng, recording.rng);
+
+        // Whatever the generators have done since
+        crate::seed_thread_rng(1);
+        let mut replay = filter();
+        let mut replayer = Replayer::new(&read, &mut replay).unwrap();
+        let mut means = Vec::new();
+        while let Some(est) = replayer.step(&mut replay) {
+            let est = est.unwrap();
+            if replayer.next_step().is_multiple_of(3) {
+                means.push(est.mean);
+            }
+        }
+        assert_eq!(means, estimates);
+
+        // Starting again from scratch reaches the same step.
+        let mut again = filter();
+        let mut replayer = Replayer::new(&read, &mut again).unwrap();
+        replayer.run_to(&mut again, 30).unwrap();
+        assert_eq!(replayer.next_step(), 30);
+        assert!(replayer.run_to(&mut again, 61).is_err());
+
+        // A filter that does something else is caught.
+        let params = SimParams::default();
+        let mut other = filter_with(SimParams {
+            gps_var: 2.0 * params.gps_var,
+            ..params
+        });
+        let mut replayer = Replayer::new(&read, &mut other).unwrap();
+        assert!(replayer.run_to(&mut other, 60).is_err());
+    }
+}
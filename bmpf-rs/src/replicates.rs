@@ -0,0 +1,191 @@
+//! Independent replicates of one filter configuration. One seed's error
+//! says little about whether one resampler beats another, so `replicate`
+//! runs the same configuration over many seeds, each with its own
+//! simulated track, spread across threads, and summarizes the errors with
+//! a confidence interval.
+
+use crate::{
+    Stream, gaussian_source, seed_thread_rng, set_gaussian_source,
+    sim::SimParams,
+    types::{BpfState, VehicleState},
+};
+use std::{fmt, thread};
+
+/// Student t quantiles at 0.975 for 1 to 30 degrees of freedom
+const T_975: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Two-sided 95% Student t critical value for `dof` degrees of freedom,
+/// from a table up to 30 and the Cornish-Fisher expansion beyond
+fn t_critical(dof: usize) -> f64 {
+    if let Some(&t) = T_975.get(dof.wrapping_sub(1)) {
+        return t;
+    }
+    let (z, nu) = (1.959_963_984_540_054, dof as f64);
+    let (z3, z5) = (z * z * z, z * z * z * z * z);
+    z + (z3 + z) / (4.0 * nu) + (5.0 * z5 + 16.0 * z3 + 3.0 * z) / (96.0 * nu * nu)
+}
+
+/// Mean of a sample with its 95% confidence interval
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub n: usize,
+    pub mean: f64,
+    /// Sample standard deviation
+    pub sd: f64,
+    /// Half-width of the 95% Student t interval about `mean`, NaN with
+    /// fewer than two values
+    pub half_width: f64,
+}
+
+impl Summary {
+    pub fn of(values: &[f64]) -> Self {
+        let n = values.len();
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let ss: f64 = values.iter().map(|v| (v - mean) * (v - mean)).sum();
+        let sd = (ss / (n as f64 - 1.0)).sqrt();
+        let half_width = match n {
+            0 | 1 => f64::NAN,
+            _ => t_critical(n - 1) * sd / (n as f64).sqrt(),
+        };
+        Self {
+            n,
+            mean,
+            sd,
+            half_width,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.4} ± {:.4}", self.mean, self.half_width)
+    }
+}
+
+/// Filter configuration and run length to replicate
+#[derive(Clone)]
+pub struct Config {
+    pub resampler: String,
+    pub nparticles: usize,
+    pub resample_interval: usize,
+    pub params: SimParams,
+    pub steps: usize,
+    pub dt: f64,
+}
+
+/// Errors of every replicate of one configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplicateReport {
+    /// RMS position error after the first fifth of the steps, by seed
+    pub rms: Vec<f64>,
+    pub summary: Summary,
+}
+
+/// RMS error of `config` on the track simulated from `seed`. The track
+/// and the filter draw from the seed's simulator and filter streams, so
+/// a replicate is the same on any thread.
+pub fn run_one(config: &Config, seed: u32) -> f64 {
+    let params = &config.params;
+    seed_thread_rng(Stream::Simulator.seed(seed));
+    let mut vehicle = VehicleState::default();
+    vehicle.init_state(params);
+    let track: Vec<_> = (0..config.steps)
+        .map(|_| {
+            vehicle.update_state(params, config.dt, 0);
+            (
+                vehicle.posn,
+                vehicle.gps_measure(params),
+                vehicle.imu_measure(params, config.dt),
+            )
+        })
+        .collect();
+    seed_thread_rng(Stream::Filter.seed(seed));
+    let mut bpf = BpfState::new(
+        &config.resampler,
+        false,
+        config.nparticles,
+        0,
+        false,
+        config.resample_interval,
+        params.clone(),
+    );
+    bpf.reporter = None;
+    bpf.init_particles();
+    let burn_in = config.steps / 5;
+    let mut sum_sq = 0.0;
+    for (i, &(posn, gps, imu)) in track.iter().enumerate() {
+        let est = bpf.observe((i + 1) as f64 * config.dt, config.dt, gps, imu);
+        if i >= burn_in {
+            let (dx, dy) = (est.mean.x - posn.x, est.mean.y - posn.y);
+            sum_sq += dx * dx + dy * dy;
+        }
+    }
+    (sum_sq / (config.steps - burn_in).max(1) as f64).sqrt()
+}
+
+/// Run `config` once per seed in `seeds` on up to `threads` threads, or
+/// as many as the machine has with zero. The report is the same however
+/// many threads run it.
+pub fn replicate(config: &Config, seeds: &[u32], threads: usize) -> ReplicateReport {
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .min(seeds.len())
+    .max(1);
+    let source = gaussian_source();
+    let mut rms = vec![0.0; seeds.len()];
+    let chunk = seeds.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        for (out, seeds) in rms.chunks_mut(chunk).zip(seeds.chunks(chunk)) {
+            scope.spawn(move || {
+                set_gaussian_source(source);
+                for (out, &seed) in out.iter_mut().zip(seeds) {
+                    *out = run_one(config, seed);
+                }
+            });
+        }
+    });
+    let summary = Summary::of(&rms);
+    ReplicateReport { rms, summary }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summaries() {
+        let s = Summary::of(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!((s.n, s.mean), (4, 2.5));
+        assert!((s.sd - (5.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert!((s.half_width - 3.182 * s.sd / 2.0).abs() < 1e-12);
+        assert!(Summary::of(&[1.0]).half_width.is_nan());
+        // The expansion takes over smoothly from the table.
+        assert!((t_critical(30) - 2.042).abs() < 1e-3);
+        assert!((t_critical(31) - 2.040).abs() < 1e-3);
+        assert!((t_critical(1000) - 1.962).abs() < 1e-3);
+    }
+
+    #[test]
+    fn replicates_do_not_depend_on_threads() {
+        let config = Config {
+            resampler: "regular".to_string(),
+            nparticles: 50,
+            resample_interval: 1,
+            params: SimParams::default(),
+            steps: 40,
+            dt: 0.1,
+        };
+        let seeds: Vec<u32> = (4714..4719).collect();
+        let one = replicate(&config, &seeds, 1);
+        assert_eq!(one, replicate(&config, &seeds, 3));
+        assert_eq!(one.rms[2], run_one(&config, 4716));
+        assert!(one.rms.iter().all(|r| r.is_finite() && *r > 0.0));
+        assert!(one.summary.half_width > 0.0);
+    }
+}
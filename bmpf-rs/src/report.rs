@@ -0,0 +1,383 @@
+//! Particle snapshot reporting, kept out of the filter's hot loop, and
+//! publishing of each step's estimate.
+
+use crate::{
+    GaussianSource, Stream,
+    estimate::EstimateMode,
+    resample::Ordering,
+    sim::SimParams,
+    types::{Estimate, ParticleInfo},
+};
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+};
+
+/// Destination for the particle snapshots taken when `bpf_step` is asked
+/// to report.
+pub trait ParticleReporter: Send {
+    fn report(&mut self, t: f64, particles: &[ParticleInfo]) -> io::Result<()>;
+
+    /// Finish any pending writes.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Appends `x y weight` lines to `<dir>/particles-<t>.dat`, one file per
+/// reported step, through a buffered writer.
+pub struct FileReporter {
+    dir: PathBuf,
+}
+
+impl FileReporter {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Default for FileReporter {
+    fn default() -> Self {
+        Self::new("benchtmp")
+    }
+}
+
+impl ParticleReporter for FileReporter {
+    fn report(&mut self, t: f64, particles: &[ParticleInfo]) -> io::Result<()> {
+        let path = self.dir.join(format!("particles-{}.dat", t));
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let mut out = BufWriter::new(file);
+        for p in particles {
+            writeln!(out, "{} {} {}", p.state.posn.x, p.state.posn.y, p.weight)?;
+        }
+        out.flush()
+    }
+}
+
+/// Destination for the estimate of every step, such as a subscriber
+/// following the filter live
+pub trait Publisher: Send {
+    fn publish(&mut self, t_ms: i32, est: &Estimate) -> io::Result<()>;
+
+    /// Finish any pending writes.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes `t_ms x y` lines of the estimated position
+pub struct WriterPublisher<W: Write + Send> {
+    out: W,
+}
+
+impl<W: Write + Send> WriterPublisher<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl WriterPublisher<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl WriterPublisher<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Send> Publisher for WriterPublisher<W> {
+    fn publish(&mut self, t_ms: i32, est: &Estimate) -> io::Result<()> {
+        let posn = est.position();
+        writeln!(self.out, "{} {} {}", t_ms, posn.x, posn.y)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// What a [`BackgroundReporter`] does when its queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Backpressure {
+    /// Stall the filter until the writer catches up.
+    #[default]
+    Block,
+    /// Skip the snapshot and count it in `dropped`.
+    Drop,
+}
+
+type Snapshot = (f64, Vec<ParticleInfo>);
+
+/// Hands snapshots to a worker thread running another reporter, so the
+/// filter only pays for copying the particles.
+pub struct BackgroundReporter {
+    tx: Option<SyncSender<Snapshot>>,
+    worker: Option<JoinHandle<io::Result<()>>>,
+    backpressure: Backpressure,
+    dropped: usize,
+}
+
+impl BackgroundReporter {
+    /// Run `inner` on a worker thread with room for `queue` pending
+    /// snapshots.
+    pub fn new<R: ParticleReporter + 'static>(
+        mut inner: R,
+        queue: usize,
+        backpressure: Backpressure,
+    ) -> Self {
+        let (tx, rx): (_, Receiver<Snapshot>) = mpsc::sync_channel(queue);
+        let worker = thread::spawn(move || {
+            for (t, particles) in rx {
+                inner.report(t, &particles)?;
+            }
+            inner.flush()
+        });
+        Self {
+            tx: Some(tx),
+            worker: Some(worker),
+            backpressure,
+            dropped: 0,
+        }
+    }
+
+    /// Snapshots skipped under [`Backpressure::Drop`]
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Wait for the worker to write everything queued, returning its first
+    /// error.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> io::Result<()> {
+        drop(self.tx.take());
+        match self.worker.take() {
+            Some(worker) => worker
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("particle reporter panicked"))),
+            None => Ok(()),
+        }
+    }
+
+    fn stopped() -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, "particle reporter stopped")
+    }
+}
+
+impl ParticleReporter for BackgroundReporter {
+    fn report(&mut self, t: f64, particles: &[ParticleInfo]) -> io::Result<()> {
+        let Some(tx) = &self.tx else {
+            return Err(Self::stopped());
+        };
+        let snapshot = (t, particles.to_vec());
+        let sent = match self.backpressure {
+            Backpressure::Block => tx.send(snapshot).is_ok(),
+            Backpressure::Drop => match tx.try_send(snapshot) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += 1;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+        };
+        if sent {
+            return Ok(());
+        }
+        // The worker quit early; surface its error.
+        match self.join() {
+            Ok(()) => Err(Self::stopped()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.join()
+    }
+}
+
+impl Drop for BackgroundReporter {
+    fn drop(&mut self) {
+        if let Err(e) = self.join() {
+            eprintln!("Could not write particle report: {}", e);
+        }
+    }
+}
+
+/// Configuration that produced a run, printed as `#` comment lines ahead
+/// of the estimates so output files can be compared across machines.
+#[derive(Clone)]
+pub struct RunHeader {
+    pub resampler: &'static str,
+    pub nparticles: usize,
+    pub order: Ordering,
+    pub estimate: EstimateMode,
+    pub resample_interval: usize,
+    pub log_weights: bool,
+    pub gaussian_source: GaussianSource,
+    pub params: SimParams,
+    /// Master seed each [`Stream`] derives its own from, `None` for the
+    /// built in default
+    pub seed: Option<u32>,
+    /// Input file name and its [`hash_file`] digest
+    pub input: Option<(String, u64)>,
+}
+
+impl fmt::Display for RunHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let p = &self.params;
+        writeln!(f, "# bmpf-rs {}", env!("CARGO_PKG_VERSION"))?;
+        match self.seed {
+            Some(seed) => {
+                writeln!(f, "# seed: {}", seed)?;
+                write!(f, "# streams:")?;
+                for stream in Stream::ALL {
+                    write!(f, " {}={}", stream.name(), stream.seed(seed))?;
+                }
+                writeln!(f)?;
+            }
+            None => writeln!(f, "# seed: default")?,
+        }
+        writeln!(f, "# resampler: {}", self.resampler)?;
+        writeln!(f, "# nparticles: {}", self.nparticles)?;
+        writeln!(f, "# order: {:?}", self.order)?;
+        writeln!(f, "# estimate: {:?}", self.estimate)?;
+        writeln!(f, "# resample_interval: {}", self.resample_interval)?;
+        writeln!(f, "# log_weights: {}", self.log_weights)?;
+        writeln!(f, "# gaussian_source: {:?}", self.gaussian_source)?;
+        writeln!(f, "# box_dim: {}", p.box_dim)?;
+        writeln!(f, "# topology: {:?}", p.topology)?;
+        match &p.map {
+            Some(map) => writeln!(f, "# map: {}x{}", map.width(), map.height())?,
+            None => writeln!(f, "# map: none")?,
+        }
+        writeln!(f, "# obstacle_policy: {:?}", p.obstacle_policy)?;
+        writeln!(f, "# max_speed: {}", p.max_speed)?;
+        writeln!(f, "# speed_bounds: {:?}", p.speed_bounds)?;
+        writeln!(f, "# avar: {}", p.avar)?;
+        writeln!(f, "# rvar: {}", p.rvar)?;
+        writeln!(f, "# gps_var: {}", p.gps_var)?;
+        writeln!(f, "# gps_ellipse: {:?}", p.gps_ellipse)?;
+        writeln!(f, "# gps_likelihood: {:?}", p.gps_likelihood)?;
+        writeln!(f, "# bounds: {:?}", p.bounds)?;
+        writeln!(f, "# imu_r_var: {}", p.imu_r_var)?;
+        writeln!(f, "# imu_a_var: {}", p.imu_a_var)?;
+        writeln!(f, "# imu_mode: {:?}", p.imu_mode)?;
+        writeln!(f, "# control_r_var: {}", p.control_r_var)?;
+        writeln!(f, "# control_a_var: {}", p.control_a_var)?;
+        writeln!(f, "# noise_scaling: {:?}", p.noise_scaling)?;
+        writeln!(f, "# integration: {:?}", p.integration)?;
+        writeln!(f, "# fast_direction: {}", p.fast_direction)?;
+        writeln!(f, "# ndirns: {}", p.dirn.len())?;
+        match &self.input {
+            Some((name, hash)) => writeln!(f, "# input: {} fnv1a64={:016x}", name, hash),
+            None => writeln!(f, "# input: none"),
+        }
+    }
+}
+
+/// 64-bit FNV-1a digest of a file's contents
+pub fn hash_file<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut buf = [0u8; 8192];
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hash);
+        }
+        for &b in &buf[..n] {
+            hash = (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<(f64, usize)>>>);
+
+    impl ParticleReporter for Collect {
+        fn report(&mut self, t: f64, particles: &[ParticleInfo]) -> io::Result<()> {
+            self.0.lock().unwrap().push((t, particles.len()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn run_header_lines_are_comments() {
+        let state =
+            crate::types::BpfState::new("logm", false, 10, 0, false, 1, SimParams::default());
+        let mut header = state.run_header();
+        header.seed = Some(7);
+        let text = header.to_string();
+        assert!(text.lines().all(|l| l.starts_with("# ")));
+        assert!(text.contains("# seed: 7\n# streams: filter="));
+        assert!(text.contains("\n# resampler: logm\n# nparticles: 10\n"));
+    }
+
+    #[test]
+    fn writer_publisher_lines() {
+        let est = Estimate {
+            best: crate::types::CCoord { x: 1.0, y: 2.0 },
+            best_weight: 0.5,
+            worst: Default::default(),
+            worst_weight: 0.0,
+            mean: crate::types::CCoord { x: 3.0, y: -4.5 },
+            map: Default::default(),
+            mode: EstimateMode::WeightedMean,
+            interval: None,
+            ..Default::default()
+        };
+        let mut publisher = WriterPublisher::new(Vec::new());
+        let publisher_ref: &mut dyn Publisher = &mut publisher;
+        publisher_ref.publish(10, &est).unwrap();
+        publisher_ref.publish(20, &est).unwrap();
+        publisher_ref.flush().unwrap();
+        assert_eq!(publisher.out, b"10 3 -4.5\n20 3 -4.5\n");
+    }
+
+    #[test]
+    fn background_reporter_delivers_in_order() {
+        let seen = Collect::default();
+        let mut reporter = BackgroundReporter::new(seen.clone(), 1, Backpressure::Block);
+        let particles = vec![ParticleInfo::default(); 3];
+        for t in 0..5 {
+            reporter.report(t as f64, &particles).unwrap();
+        }
+        reporter.finish().unwrap();
+        let seen = seen.0.lock().unwrap();
+        assert_eq!(*seen, (0..5).map(|t| (t as f64, 3)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn worker_errors_surface() {
+        let mut reporter = BackgroundReporter::new(
+            FileReporter::new("/nonexistent/bmpf"),
+            0,
+            Backpressure::Block,
+        );
+        let particles = vec![ParticleInfo::default(); 1];
+        let mut result = Ok(());
+        for t in 0..3 {
+            result = result.and(reporter.report(t as f64, &particles));
+        }
+        assert!(result.is_err() || reporter.finish().is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! Systematic resampling on weights quantized to fixed point, the way
+//! FPGA and microcontroller filters hold them. Everything after the
+//! quantization is integer arithmetic, so the chosen ancestors depend only
+//! on the integer weights and the random offset.
+
+use crate::{
+    rand32,
+    resample::{Ordering, Resample, ResamplerInfo, Weights},
+    types::{CCoord, ParticleInfo, Particles},
+};
+
+/// Width of the fixed-point weights
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FixedWidth {
+    U32,
+    U64,
+}
+
+impl FixedWidth {
+    /// Sum every set of quantized weights adds up to
+    pub fn total(self) -> u64 {
+        match self {
+            Self::U32 => u32::MAX as u64,
+            Self::U64 => u64::MAX,
+        }
+    }
+}
+
+/// Quantize the weights of `data` to integers summing to exactly `total`,
+/// leaving them in `out`. Each weight is rounded down and the shortfall is
+/// handed out by largest remainder, ties going to the lower index, so the
+/// result is deterministic. Zero weights stay zero.
+/// Returns false, leaving `out` all zero, if no weight is positive.
+/// `order` is scratch space.
+pub fn quantize(
+    data: &[ParticleInfo],
+    total: u64,
+    out: &mut Vec<u64>,
+    order: &mut Vec<usize>,
+) -> bool {
+    out.clear();
+    out.resize(data.len(), 0);
+    let sum: f64 = data.iter().map(|p| p.weight).sum();
+    if sum.is_nan() || sum <= 0.0 {
+        return false;
+    }
+    let scale = total as f64 / sum;
+    let mut assigned = 0u128;
+    for (q, p) in out.iter_mut().zip(data) {
+        // Float to int casts saturate, so overshooting `total` is harmless
+        // here and trimmed below.
+        *q = (p.weight * scale) as u64;
+        assigned += *q as u128;
+    }
+    if assigned > total as u128 {
+        // Rounding in `scale` can overshoot by a few units; take them back
+        // from the heaviest.
+        let mut excess = (assigned - total as u128) as u64;
+        while excess > 0 {
+            let i = (0..out.len())
+                .max_by(|&a, &b| out[a].cmp(&out[b]).then(b.cmp(&a)))
+                .unwrap();
+            let take = excess.min(out[i]);
+            out[i] -= take;
+            excess -= take;
+        }
+        return true;
+    }
+    let remainder = |i: usize| data[i].weight * scale - out[i] as f64;
+    order.clear();
+    order.extend((0..data.len()).filter(|&i| data[i].weight > 0.0));
+    order.sort_unstable_by(|&a, &b| remainder(b).total_cmp(&remainder(a)).then(a.cmp(&b)));
+    // Past 53 bits the shortfall can exceed one unit per particle, so
+    // every positive weight gets an even share first.
+    let short = total - assigned as u64;
+    let (each, extra) = (
+        short / order.len() as u64,
+        (short % order.len() as u64) as usize,
+    );
+    for (rank, &i) in order.iter().enumerate() {
+        out[i] += each + (rank < extra) as u64;
+    }
+    true
+}
+
+pub(super) const INFO32: ResamplerInfo = ResamplerInfo {
+    name: "fixed32",
+    aliases: &["fixed"],
+    summary: "Systematic resampling, with a random offset, over weights quantized to 32-bit \
+              fixed point",
+    citation: Some(
+        "G. Kitagawa, Monte Carlo filter and smoother for non-Gaussian nonlinear state \
+         space models, JCGS 5(1), 1996",
+    ),
+    complexity: "O(m log m + n)",
+};
+
+pub(super) const INFO64: ResamplerInfo = ResamplerInfo {
+    name: "fixed64",
+    aliases: &[],
+    summary: "Systematic resampling, with a random offset, over weights quantized to 64-bit \
+              fixed point",
+    citation: Some(
+        "G. Kitagawa, Monte Carlo filter and smoother for non-Gaussian nonlinear state \
+         space models, JCGS 5(1), 1996",
+    ),
+    complexity: "O(m log m + n)",
+};
+
+/// What quantizing cost in the last resampling
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QuantizationError {
+    /// Largest change in any normalized weight
+    pub max_weight: f64,
+    /// Shift of the weighted mean position
+    pub mean: CCoord,
+}
+
+#[derive(Debug)]
+pub struct Fixed {
+    width: FixedWidth,
+    quantized: Vec<u64>,
+    order: Vec<usize>,
+    error: QuantizationError,
+}
+
+impl Fixed {
+    pub fn new(width: FixedWidth) -> Self {
+        Self {
+            width,
+            quantized: Vec::new(),
+            order: Vec::new(),
+            error: QuantizationError::default(),
+        }
+    }
+
+    pub fn width(&self) -> FixedWidth {
+        self.width
+    }
+
+    pub(crate) fn scratch_bytes(&self) -> usize {
+        self.quantized.capacity() * size_of::<u64>() + self.order.capacity() * size_of::<usize>()
+    }
+
+    /// Weights as the last resampling quantized them
+    pub fn quantized(&self) -> &[u64] {
+        &self.quantized
+    }
+
+    /// Error introduced by quantizing the weights last time
+    pub fn last_error(&self) -> QuantizationError {
+        self.error
+    }
+
+    fn measure_error(&mut self, data: &[ParticleInfo], scale: f64) {
+        let total = self.width.total() as f64;
+        let mut error = QuantizationError::default();
+        let (mut exact, mut fixed) = (CCoord::default(), CCoord::default());
+        for (p, &q) in data.iter().zip(&self.quantized) {
+            let (w, wq) = (p.weight / scale, q as f64 / total);
+            error.max_weight = error.max_weight.max((w - wq).abs());
+            exact.x += w * p.state.posn.x;
+            exact.y += w * p.state.posn.y;
+            fixed.x += wq * p.state.posn.x;
+            fixed.y += wq * p.state.posn.y;
+        }
+        error.mean = CCoord {
+            x: fixed.x - exact.x,
+            y: fixed.y - exact.y,
+        };
+        self.error = error;
+    }
+}
+
+impl Resample for Fixed {
+    fn prepare(&mut self, m: usize, _n: usize) {
+        self.quantized
+            .reserve(m.saturating_sub(self.quantized.len()));
+        self.order.reserve(m.saturating_sub(self.order.len()));
+    }
+
+    /// Output particle `i` copies the first particle whose cumulative
+    /// quantized weight passes `(i * total + offset) / n`, with an integer
+    /// `offset` drawn uniformly from `[0, total)`.
+    fn resample(
+        &mut self,
+        weights: Weights,
+        m: usize,
+        particle: &mut Particles,
+        n: usize,
+        new_particle: &mut Particles,
+        _order: Ordering,
+    ) -> usize {
+        if m == 0 || n == 0 {
+            return 0;
+        }
+        let scale = weights.total();
+        let invscale = 1.0 / scale;
+        let total = self.width.total();
+        let data = &particle.data[..m];
+        quantize(data, total, &mut self.quantized, &mut self.order);
+        self.measure_error(data, scale);
+
+        let random = ((rand32() as u128) << 32) | rand32() as u128;
+        let offset = random % total as u128;
+        let (mut best_w, mut best_i) = (0f64, 0usize);
+        let (mut j, mut cumulative) = (0, self.quantized[0] as u128);
+        for i in 0..n {
+            let point = (i as u128 * total as u128 + offset) / n as u128;
+            while cumulative <= point && j < m - 1 {
+                j += 1;
+                cumulative += self.quantized[j] as u128;
+            }
+            new_particle.data[i] = particle.data[j];
+            new_particle.data[i].weight *= invscale;
+            if new_particle.data[i].weight > best_w {
+                best_w = new_particle.data[i].weight;
+                best_i = i;
+            }
+        }
+        best_i
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particles(weights: &[f64]) -> Vec<ParticleInfo> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| {
+                let mut p = ParticleInfo {
+                    weight,
+                    ..Default::default()
+                };
+                p.state.posn.x = i as f64;
+                p
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quantized_weights_sum_exactly() {
+        let (mut out, mut order) = (Vec::new(), Vec::new());
+        for width in [FixedWidth::U32, FixedWidth::U64] {
+            for weights in [
+                &[1.0, 1.0, 1.0][..],
+                &[0.1, 0.0, 0.7, 1e-300, 0.2],
+                &[f64::MAX / 4.0, f64::MAX / 4.0],
+            ] {
+                assert!(quantize(
+                    &particles(weights),
+                    width.total(),
+                    &mut out,
+                    &mut order
+                ));
+                let sum: u128 = out.iter().map(|&q| q as u128).sum();
+                assert_eq!(sum, width.total() as u128, "{:?} {:?}", width, weights);
+                for (w, q) in weights.iter().zip(&out) {
+                    assert!(*w > 0.0 || *q == 0);
+                }
+            }
+        }
+        // Equal remainders hand the shortfall to the lowest index.
+        quantize(&particles(&[1.0, 1.0, 1.0]), 10, &mut out, &mut order);
+        assert_eq!(out, [4, 3, 3]);
+        assert!(!quantize(&particles(&[0.0, 0.0]), 10, &mut out, &mut order));
+    }
+
+    #[test]
+    fn fixed_resampling_is_deterministic_given_the_offset() {
+        let data = particles(&[0.1, 0.2, 0.3, 0.4]);
+        for width in [FixedWidth::U32, FixedWidth::U64] {
+            let mut runs = Vec::new();
+            for _ in 0..2 {
+                crate::seed_thread_rng(4659);
+                let mut fixed = Fixed::new(width);
+                let mut from = Particles { data: data.clone() };
+                let mut to = Particles {
+                    data: vec![ParticleInfo::default(); 1000],
+                };
+                fixed.resample(
+                    Weights::raw(1.0),
+                    4,
+                    &mut from,
+                    1000,
+                    &mut to,
+                    Ordering::None,
+                );
+                let mut counts = [0usize; 4];
+                to.data
+                    .iter()
+                    .for_each(|p| counts[p.state.posn.x as usize] += 1);
+                for (c, w) in counts.iter().zip([0.1, 0.2, 0.3, 0.4]) {
+                    assert!((*c as f64 - 1000.0 * w).abs() <= 1.0, "{:?}", counts);
+                }
+                let error = fixed.last_error();
+                assert!(error.max_weight < 1e-9 && error.mean.x.abs() < 1e-8);
+                runs.push(counts);
+            }
+            assert_eq!(runs[0], runs[1]);
+        }
+    }
+}
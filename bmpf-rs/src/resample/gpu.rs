@@ -0,0 +1,390 @@
+//! Resampling on the GPU through wgpu compute shaders. The CPU lays out
+//! the weights, the GPU picks the ancestor of every output particle in
+//! parallel, and the CPU gathers the chosen particles. Weights travel as
+//! `f32`, which WGSL requires.
+
+use crate::{
+    gpu::{device, read_back, storage, uniform_entry},
+    rand32,
+    resample::{Ordering, Resample, Weights, sanitize},
+    types::Particles,
+    uniform,
+};
+use wgpu::util::DeviceExt;
+
+const WORKGROUP: u32 = 64;
+
+const SHADER: &str = r#"
+struct Params {
+    m: u32,
+    n: u32,
+    u0: f32,
+    total: f32,
+    seed: u32,
+    steps: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> cdf: array<f32>;
+@group(0) @binding(2) var<storage, read> weight: array<f32>;
+@group(0) @binding(3) var<storage, read_write> ancestor: array<u32>;
+
+// First index whose cumulative weight passes evenly spaced point i.
+@compute @workgroup_size(64)
+fn systematic(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.n) {
+        return;
+    }
+    let u = (f32(i) + params.u0) / f32(params.n) * params.total;
+    var lo = 0u;
+    var hi = params.m - 1u;
+    while (lo < hi) {
+        let mid = (lo + hi) / 2u;
+        if (cdf[mid] > u) {
+            hi = mid;
+        } else {
+            lo = mid + 1u;
+        }
+    }
+    ancestor[i] = lo;
+}
+
+fn pcg(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// A short Metropolis chain per output particle, needing no prefix sum.
+@compute @workgroup_size(64)
+fn metropolis(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.n) {
+        return;
+    }
+    var j = i % params.m;
+    var state = pcg(params.seed ^ pcg(i));
+    for (var b = 0u; b < params.steps; b++) {
+        state = pcg(state);
+        let k = state % params.m;
+        state = pcg(state);
+        let u = f32(state >> 8u) * (1.0 / 16777216.0);
+        if (u * weight[j] <= weight[k]) {
+            j = k;
+        }
+    }
+    ancestor[i] = j;
+}
+"#;
+
+/// Which kernel a [`GpuResampler`] runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GpuAlgorithm {
+    /// Systematic resampling by binary search of the cumulative weights,
+    /// the GPU counterpart of `regular`
+    Systematic,
+    /// Metropolis resampling: each output particle runs its own chain of
+    /// `steps` proposals. Biased when `steps` is short relative to the
+    /// spread of the weights, but needs no prefix sum.
+    Metropolis { steps: u32 },
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    m: u32,
+    n: u32,
+    u0: f32,
+    total: f32,
+    seed: u32,
+    steps: u32,
+    _pad: [u32; 2],
+}
+
+/// Buffers sized for up to `m` input and `n` output particles
+struct Buffers {
+    m: usize,
+    n: usize,
+    cdf: wgpu::Buffer,
+    weight: wgpu::Buffer,
+    ancestor: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Resampler running on the first GPU wgpu finds
+pub struct GpuResampler {
+    algorithm: GpuAlgorithm,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    params: wgpu::Buffer,
+    buffers: Option<Buffers>,
+    cdf: Vec<f32>,
+    weight: Vec<f32>,
+    ancestor: Vec<u32>,
+}
+
+impl GpuResampler {
+    /// Set up `algorithm` on the default adapter, or `None` if there is no
+    /// usable GPU.
+    pub fn new(algorithm: GpuAlgorithm) -> Option<Self> {
+        let (device, queue) = device()?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("resample"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("resample"),
+            entries: &[
+                uniform_entry(0),
+                storage(1, true),
+                storage(2, true),
+                storage(3, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("resample"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let entry_point = match algorithm {
+            GpuAlgorithm::Systematic => "systematic",
+            GpuAlgorithm::Metropolis { .. } => "metropolis",
+        };
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: Some(entry_point),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: bytemuck::bytes_of(&<Params as bytemuck::Zeroable>::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Some(Self {
+            algorithm,
+            device,
+            queue,
+            layout,
+            pipeline,
+            params,
+            buffers: None,
+            cdf: Vec::new(),
+            weight: Vec::new(),
+            ancestor: Vec::new(),
+        })
+    }
+
+    pub fn algorithm(&self) -> GpuAlgorithm {
+        self.algorithm
+    }
+
+    fn buffers(&self, m: usize, n: usize) -> Buffers {
+        let buffer = |label, len: usize, usage| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (len.max(1) * 4) as u64,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let input = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+        let cdf = buffer("cdf", m, input);
+        let weight = buffer("weight", m, input);
+        let ancestor = buffer(
+            "ancestor",
+            n,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+        let readback = buffer(
+            "readback",
+            n,
+            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        );
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("resample"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: cdf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: weight.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: ancestor.as_entire_binding(),
+                },
+            ],
+        });
+        Buffers {
+            m,
+            n,
+            cdf,
+            weight,
+            ancestor,
+            readback,
+            bind_group,
+        }
+    }
+
+    /// Run the kernel over the host-side weights, leaving ancestors in
+    /// `self.ancestor`.
+    fn dispatch(&mut self, m: usize, n: usize, total: f64) {
+        let params = Params {
+            m: m as u32,
+            n: n as u32,
+            u0: uniform() as f32,
+            total: total as f32,
+            seed: rand32(),
+            steps: match self.algorithm {
+                GpuAlgorithm::Metropolis { steps } => steps,
+                GpuAlgorithm::Systematic => 0,
+            },
+            _pad: [0; 2],
+        };
+        let buffers = self.buffers.as_ref().expect("prepare sizes the buffers");
+        self.queue
+            .write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+        self.queue
+            .write_buffer(&buffers.cdf, 0, bytemuck::cast_slice(&self.cdf[..m]));
+        self.queue
+            .write_buffer(&buffers.weight, 0, bytemuck::cast_slice(&self.weight[..m]));
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &buffers.bind_group, &[]);
+            pass.dispatch_workgroups((n as u32).div_ceil(WORKGROUP), 1, 1);
+        }
+        let bytes = (n * 4) as u64;
+        encoder.copy_buffer_to_buffer(&buffers.ancestor, 0, &buffers.readback, 0, bytes);
+        self.queue.submit([encoder.finish()]);
+
+        let data = read_back(&self.device, &buffers.readback, bytes);
+        self.ancestor.clear();
+        self.ancestor.extend_from_slice(bytemuck::cast_slice(&data));
+    }
+}
+
+impl Resample for GpuResampler {
+    /// Grow the device buffers to hold `m` input and `n` output particles.
+    fn prepare(&mut self, m: usize, n: usize) {
+        if self.buffers.as_ref().is_none_or(|b| b.m < m || b.n < n) {
+            let (m, n) = match &self.buffers {
+                Some(b) => (m.max(b.m), n.max(b.n)),
+                None => (m, n),
+            };
+            self.buffers = Some(self.buffers(m, n));
+        }
+        self.cdf.resize(self.cdf.len().max(m), 0.0);
+        self.weight.resize(self.weight.len().max(m), 0.0);
+        self.ancestor.reserve(n.saturating_sub(self.ancestor.len()));
+    }
+
+    /// Clamps and sanitizes like [`Resampler`](super::Resampler), then
+    /// applies `order` and resamples on the device.
+    fn resample(
+        &mut self,
+        weights: Weights,
+        m: usize,
+        particle: &mut Particles,
+        n: usize,
+        new_particle: &mut Particles,
+        order: Ordering,
+    ) -> usize {
+        let m = m.min(particle.data.len());
+        let n = n.min(new_particle.data.len());
+        if m == 0 || n == 0 {
+            return 0;
+        }
+        let scale = sanitize(weights.total(), m, particle);
+        self.prepare(m, n);
+        order.apply(&mut particle.data[..m]);
+        let mut total = 0.0;
+        for (i, p) in particle.data[..m].iter().enumerate() {
+            total += p.weight;
+            self.cdf[i] = total as f32;
+            self.weight[i] = p.weight as f32;
+        }
+        self.dispatch(m, n, total);
+
+        let invscale = 1.0 / scale;
+
+        let (mut best_w, mut best_i) = (0f64, 0usize);
+        for (i, &a) in self.ancestor.iter().enumerate() {
+            new_particle.data[i] = particle.data[(a as usize).min(m - 1)];
+            new_particle.data[i].weight *= invscale;
+            if new_particle.data[i].weight > best_w {
+                best_w = new_particle.data[i].weight;
+                best_i = i;
+            }
+        }
+        best_i
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ParticleInfo;
+
+    fn counts(resampler: &mut GpuResampler, weights: &[f64], n: usize) -> Vec<usize> {
+        let mut from = Particles {
+            data: weights
+                .iter()
+                .enumerate()
+                .map(|(i, &weight)| {
+                    let mut p = ParticleInfo {
+                        weight,
+                        ..Default::default()
+                    };
+                    p.state.posn.x = i as f64;
+                    p
+                })
+                .collect(),
+        };
+        let mut to = Particles {
+            data: vec![ParticleInfo::default(); n],
+        };
+        let total = Weights::sum(&from.data);
+        resampler.resample(total, weights.len(), &mut from, n, &mut to, Ordering::None);
+        let mut c = vec![0; weights.len()];
+        for p in &to.data {
+            c[p.state.posn.x as usize] += 1;
+        }
+        c
+    }
+
+    #[test]
+    fn gpu_offspring_follow_weights() {
+        crate::seed_thread_rng(4656);
+        let weights = [0.1, 0.2, 0.3, 0.4];
+        let Some(mut systematic) = GpuResampler::new(GpuAlgorithm::Systematic) else {
+            eprintln!("no GPU adapter, skipping");
+            return;
+        };
+        // Systematic resampling is within one of the expected count.
+        for (c, w) in counts(&mut systematic, &weights, 1000).iter().zip(weights) {
+            assert!((*c as f64 - 1000.0 * w).abs() <= 1.0, "{} {}", c, w);
+        }
+        let mut metropolis = GpuResampler::new(GpuAlgorithm::Metropolis { steps: 64 }).unwrap();
+        for (c, w) in counts(&mut metropolis, &weights, 10000).iter().zip(weights) {
+            assert!((*c as f64 / 10000.0 - w).abs() < 0.02, "{} {}", c, w);
+        }
+    }
+}
@@ -1,13 +1,20 @@
 use crate::{
-    resample::Resample,
+    resample::{Ordering, Resample, ResamplerInfo, Weights},
     types::{ParticleInfo, Particles},
     uniform,
 };
-use std::process::abort;
 
 #[cfg(feature = "debug-heapify")]
 static DW: f64 = 1.0e9;
 
+pub(super) const INFO: ResamplerInfo = ResamplerInfo {
+    name: "logm",
+    aliases: &["tree"],
+    summary: "Multinomial sampling, descending a tree of weight subtotals for every draw",
+    citation: Some("B. Massey, Fast perfect weighted resampling, ICASSP 2008"),
+    complexity: "O(m + n log m)",
+};
+
 pub struct Logm {
     tweight: Vec<f64>,
     #[cfg(feature = "debug-logm")]
@@ -23,6 +30,15 @@ impl<'a> Logm {
         }
     }
 
+    pub(crate) fn scratch_bytes(&self) -> usize {
+        self.tweight.capacity() * size_of::<f64>()
+    }
+
+    /// Weight tree the last resampling built
+    pub fn tree(&self) -> &[f64] {
+        &self.tweight
+    }
+
     fn weighted_sample(
         &'a mut self,
         scale: f64,
@@ -32,7 +48,8 @@ impl<'a> Logm {
         let mut w = uniform() * scale;
         #[cfg(feature = "debug-logm")]
         let mut j = 0usize;
-        for mut i in 0..m {
+        let mut i = 0usize;
+        while i < m {
             let left = 2 * i + 1;
             let right = 2 * i + 2;
             let mut lweight = 0f64;
@@ -68,12 +85,17 @@ impl<'a> Logm {
                 "fell off tree on {} with i={}, w[i]={}",
                 w, j, particles.data[j].weight
             );
+            std::process::abort()
+        }
+        // Rounding left the target just past the total weight.
+        #[cfg(not(feature = "debug-logm"))]
+        {
+            &particles.data[m - 1]
         }
-        abort();
     }
 
     fn heapify(&mut self, m: usize, particles: &'a mut Particles) {
-        for i in (0..(m - 1)).rev() {
+        for i in (0..m).rev() {
             let left = 2 * i + 1;
             let right = 2 * i + 2;
             self.tweight[i] = particles.data[i].weight;
@@ -114,30 +136,26 @@ impl<'a> Logm {
         }
     }
 
+    /// Fill the weight tree bottom up: each node holds its own weight plus
+    /// the totals of its subtrees, so `tweight[0]` is the total weight.
     pub fn init_tweights(&mut self, m: usize, particles: &'a Particles) {
-        let mut j = 0usize;
-        for i in ((m / 2)..(m - 1)).rev() {
-            self.tweight[i] = particles.data[i].weight;
-            j = i - 1; // --i in for loop happens at end
-        }
-        self.tweight[j] = particles.data[j].weight;
-        let mut left = 2 * j + 1;
-        let mut right = 2 * j + 2;
-        if right >= m {
-            self.tweight[j] = particles.data[j].weight + particles.data[left].weight;
-            j -= 1;
-        }
-        while j > 0 {
-            left = 2 * j + 1;
-            right = 2 * j + 2;
-            self.tweight[j] = particles.data[j].weight + self.tweight[left] + self.tweight[right];
-            j -= 1;
+        for i in (0..m).rev() {
+            let left = 2 * i + 1;
+            let right = 2 * i + 2;
+            let mut w = particles.data[i].weight;
+            if left < m {
+                w += self.tweight[left];
+            }
+            if right < m {
+                w += self.tweight[right];
+            }
+            self.tweight[i] = w;
         }
     }
 
     #[cfg(feature = "debug-heapify")]
     fn check_tweights(&self, m: usize, particles: &'a Particles) {
-        for i in (0..(m - 1)).rev() {
+        for i in (0..m).rev() {
             let left = 2 * i + 1;
             let right = 2 * i + 2;
             let mut w = particles.data[i].weight;
@@ -153,23 +171,30 @@ impl<'a> Logm {
 }
 
 impl Resample for Logm {
+    /// Grow the weight tree to hold at least `m` particles.
+    fn prepare(&mut self, m: usize, _n: usize) {
+        if self.tweight.len() < m {
+            self.tweight.resize(m, 0.0);
+        }
+    }
+
     fn resample(
         &mut self,
-        _scale: f64,
+        _weights: Weights,
         m: usize,
         particle: &mut Particles,
         n: usize,
         new_particle: &mut Particles,
-        sort: bool,
+        order: Ordering,
     ) -> usize {
         let mut best_w = 0f64;
         let mut best_i = 0usize;
-        if sort {
+        if order == Ordering::Heapify {
             self.heapify(m, particle);
             #[cfg(feature = "debug-heapify")]
             {
                 self.check_tweights(m, particle);
-                for i in (0..(m - 1)).rev() {
+                for i in (1..m).rev() {
                     assert!(particle.data[i].weight <= particle.data[(i - 1) / 2].weight);
                 }
             }
@@ -178,6 +203,7 @@ impl Resample for Logm {
         }
         #[cfg(feature = "debug-logm")]
         {
+            let scale = _weights.total();
             assert!(self.tweight[0] * (1.0 - DW) <= scale && scale <= self.tweight[0] * (1.0 + DW));
             self.total_depth = 0;
         }
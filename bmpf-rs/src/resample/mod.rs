@@ -1,63 +1,733 @@
-use crate::types::Particles;
+use crate::{
+    rand32,
+    types::{ParticleInfo, Particles},
+};
+use std::str::FromStr;
 
-/// Naive resampler
+pub use registry::{ResamplerInfo, ResamplerRegistry};
+
+/// Fixed-point resampler
+pub mod fixed;
+/// Resamplers running on the GPU
+#[cfg(feature = "gpu")]
+pub mod gpu;
+/// Logarithmic-time weight tree resampler
 mod logm;
 /// Naive resampler
 mod naive;
 /// Optimal resampler
 mod optimal;
+/// Resampler names, aliases and descriptions
+pub mod registry;
 /// Regular resampler
 mod regular;
+/// Weighted reservoir resampler
+mod reservoir;
+/// Stochastic rounding resampler
+mod rounding;
+/// Resampler variance comparison
+pub mod variance;
+
+/// How particles are arranged before resampling. Resampling is valid in
+/// any order; the order only changes how fast each algorithm finds its
+/// samples and which particles land next to each other in the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Ordering {
+    /// Leave the particles as they are.
+    #[default]
+    None,
+    /// Shuffle uniformly at random.
+    Shuffle,
+    /// Sort by weight, heaviest first, so linear scans stop early.
+    SortDescending,
+    /// Arrange as a max-heap by weight. `logm` builds its weight tree in
+    /// the same pass.
+    Heapify,
+}
+
+impl Ordering {
+    /// The ordering each resampler used to apply when asked to sort
+    pub fn legacy(resampler: &str) -> Self {
+        match registry::canonical(resampler).unwrap_or(resampler) {
+            "logm" => Self::Heapify,
+            "regular" => Self::Shuffle,
+            _ => Self::SortDescending,
+        }
+    }
+
+    /// Arrange `data` according to this ordering.
+    pub fn apply(self, data: &mut [ParticleInfo]) {
+        match self {
+            Self::None => (),
+            Self::Shuffle => shuffle(data),
+            Self::SortDescending => data.sort_unstable_by(|a, b| a.cmp_weight(b)),
+            Self::Heapify => heapify(data),
+        }
+    }
+}
+
+impl FromStr for Ordering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "shuffle" => Ok(Self::Shuffle),
+            "sort" => Ok(Self::SortDescending),
+            "heapify" => Ok(Self::Heapify),
+            _ => Err(format!("unknown ordering {}", s)),
+        }
+    }
+}
+
+fn shuffle(data: &mut [ParticleInfo]) {
+    let m = data.len();
+    for i in 0..m.saturating_sub(1) {
+        let j = rand32() as usize % (m - i) + i;
+        data.swap(j, i);
+    }
+}
+
+fn heapify(data: &mut [ParticleInfo]) {
+    let m = data.len();
+    for i in (0..m / 2).rev() {
+        let mut j = i;
+        loop {
+            let left = 2 * j + 1;
+            if left >= m {
+                break;
+            }
+            let right = left + 1;
+            let mut next = left;
+            if right < m && data[right].weight > data[left].weight {
+                next = right;
+            }
+            if data[j].weight >= data[next].weight {
+                break;
+            }
+            data.swap(j, next);
+            j = next;
+        }
+    }
+}
+
+/// Weights resampled particles come out with
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputWeights {
+    /// Every copy weighs `1 / n`, the usual convention after resampling
+    Uniform,
+    /// Each copy keeps its parent's weight divided by the total, so the
+    /// copies of heavy particles stay heavy. Every resampler leaves its
+    /// copies this way.
+    #[default]
+    CarryScaled,
+}
+
+impl OutputWeights {
+    /// Reweight `copies` as a resampler left them
+    pub fn apply(self, copies: &mut [ParticleInfo]) {
+        if self == Self::Uniform {
+            let invn = 1.0 / copies.len() as f64;
+            copies.iter_mut().for_each(|p| p.weight = invn);
+        }
+    }
+}
+
+impl FromStr for OutputWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(Self::Uniform),
+            "carry-scaled" => Ok(Self::CarryScaled),
+            _ => Err(format!("unknown output weights {}", s)),
+        }
+    }
+}
+
+/// Indices of the particles under ascending points of their cumulative
+/// weight, found by walking the points and the particles together in one
+/// pass: O(n + m) for `n` points over `m` particles where a search per
+/// point costs O(n log m). Systematic resampling feeds it evenly spaced
+/// points and `optimal` sorted uniforms; points past the total weight,
+/// which rounding can produce, land on the last particle.
+pub struct Ancestors<'a, I> {
+    particles: &'a [ParticleInfo],
+    points: I,
+    /// Next particle not yet passed
+    j: usize,
+    /// Cumulative weight before particle `j`
+    t: f64,
+}
+
+/// Ancestors of the ascending `points` among `particles`
+pub fn ancestors<I: Iterator<Item = f64>>(
+    particles: &[ParticleInfo],
+    points: I,
+) -> Ancestors<'_, I> {
+    Ancestors {
+        particles,
+        points,
+        j: 0,
+        t: 0.0,
+    }
+}
+
+impl<I: Iterator<Item = f64>> Iterator for Ancestors<'_, I> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        let u = self.points.next()?;
+        let m = self.particles.len();
+        while self.j < m && self.t + self.particles[self.j].weight < u {
+            self.t += self.particles[self.j].weight;
+            self.j += 1;
+        }
+        #[cfg(any(feature = "debug-regular", feature = "debug-optimal"))]
+        if self.j >= m {
+            println!("fell of end t ={:.14} u={:.14}", self.t, u);
+            std::process::abort();
+        }
+        Some(self.j.min(m - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.points.size_hint()
+    }
+}
+
+/// What a resampler is told about the weights it is given. Every resampler
+/// draws from `[0, total)` over the first `m` weights, so `total` must be
+/// their sum: callers that normalize must say so rather than pass the
+/// total from before normalizing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Weights {
+    total: f64,
+    normalized: bool,
+}
+
+impl Weights {
+    /// Weights that have been scaled to sum to one
+    pub const NORMALIZED: Self = Self {
+        total: 1.0,
+        normalized: true,
+    };
+
+    /// Unnormalized weights summing to `total`
+    pub fn raw(total: f64) -> Self {
+        Self {
+            total,
+            normalized: false,
+        }
+    }
+
+    /// Unnormalized weights, totalled from `data`
+    pub fn sum(data: &[ParticleInfo]) -> Self {
+        Self::raw(data.iter().map(|p| p.weight).sum())
+    }
+
+    pub fn total(self) -> f64 {
+        self.total
+    }
+
+    pub fn is_normalized(self) -> bool {
+        self.normalized
+    }
+
+    /// Whether `data` sums to `total`, up to rounding.
+    pub fn matches(self, data: &[ParticleInfo]) -> bool {
+        let sum: f64 = data.iter().map(|p| p.weight).sum();
+        (sum - self.total).abs() <= 1e-9 * sum.abs().max(self.total.abs())
+    }
+}
 
 pub trait Resample {
+    /// Size any scratch space for up to `m` input and `n` output particles,
+    /// so that `resample` itself never allocates.
+    fn prepare(&mut self, _m: usize, _n: usize) {}
+
     fn resample(
         &mut self,
-        scale: f64,
+        weights: Weights,
         m: usize,
         particle: &mut Particles,
         n: usize,
         new_particle: &mut Particles,
-        sort: bool,
+        order: Ordering,
     ) -> usize;
 }
 
-pub enum Resampler {
+/// One of the resamplers, and the weights its copies come out with
+pub struct Resampler {
+    algorithm: Algorithm,
+    output: OutputWeights,
+}
+
+enum Algorithm {
+    Fixed(fixed::Fixed),
     Logm(logm::Logm),
     Naive(naive::Naive),
     Optimal(optimal::Optimal),
     Regular(regular::Regular),
+    Reservoir(reservoir::Reservoir),
+    Rounding(rounding::Rounding),
+}
+
+/// A resampler's working arrays as its last run left them, indexed like
+/// the particles it drew from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Internals<'a> {
+    /// The resampler keeps nothing between runs.
+    None,
+    /// `logm`'s weight tree, a heap whose node `i` has children `2i + 1`
+    /// and `2i + 2` and holds its particle's weight plus the totals of its
+    /// subtrees
+    Tree(&'a [f64]),
+    /// Weights of the fixed-point resamplers in integer units, summing to
+    /// the width's total; the resampler walks their cumulative sums
+    Quantized(&'a [u64]),
 }
 
 impl Resampler {
+    /// Every canonical name [`Resampler::new`] accepts, besides the
+    /// aliases in [`registry`]
+    pub const NAMES: [&'static str; 8] = [
+        "fixed32",
+        "fixed64",
+        "logm",
+        "naive",
+        "optimal",
+        "regular",
+        "reservoir",
+        "rounding",
+    ];
+
+    /// The resampler called `name` or one of its aliases. Panics if there
+    /// is none, where [`Resampler::try_new`] returns `None`.
     pub fn new(name: &str, mmax: usize) -> Self {
-        match name {
-            "logm" => Self::Logm(logm::Logm::new(mmax)),
-            "naive" => Self::Naive(naive::Naive::default()),
-            "optimal" => Self::Optimal(optimal::Optimal::default()),
-            "regular" => Self::Regular(regular::Regular::default()),
-            _ => panic!("No resampler specified"),
+        Self::try_new(name, mmax).unwrap_or_else(|| panic!("unknown resampler {}", name))
+    }
+
+    /// The resampler called `name` or one of its aliases, or `None` if
+    /// there is none
+    pub fn try_new(name: &str, mmax: usize) -> Option<Self> {
+        let algorithm = match registry::canonical(name)? {
+            "fixed32" => Algorithm::Fixed(fixed::Fixed::new(fixed::FixedWidth::U32)),
+            "fixed64" => Algorithm::Fixed(fixed::Fixed::new(fixed::FixedWidth::U64)),
+            "logm" => Algorithm::Logm(logm::Logm::new(mmax)),
+            "naive" => Algorithm::Naive(naive::Naive::default()),
+            "optimal" => Algorithm::Optimal(optimal::Optimal::default()),
+            "regular" => Algorithm::Regular(regular::Regular::default()),
+            "reservoir" => Algorithm::Reservoir(reservoir::Reservoir::default()),
+            "rounding" => Algorithm::Rounding(rounding::Rounding::default()),
+            _ => return None,
+        };
+        Some(Self {
+            algorithm,
+            output: OutputWeights::default(),
+        })
+    }
+
+    /// Name accepted by [`Resampler::new`]
+    pub fn name(&self) -> &'static str {
+        match &self.algorithm {
+            Algorithm::Fixed(fixed) => match fixed.width() {
+                fixed::FixedWidth::U32 => "fixed32",
+                fixed::FixedWidth::U64 => "fixed64",
+            },
+            Algorithm::Logm(_) => "logm",
+            Algorithm::Naive(_) => "naive",
+            Algorithm::Optimal(_) => "optimal",
+            Algorithm::Regular(_) => "regular",
+            Algorithm::Reservoir(_) => "reservoir",
+            Algorithm::Rounding(_) => "rounding",
         }
     }
+
+    /// Weights the copies come out with
+    pub fn output_weights(&self) -> OutputWeights {
+        self.output
+    }
+
+    pub fn set_output_weights(&mut self, output: OutputWeights) {
+        self.output = output;
+    }
+
+    /// Bytes of scratch space the resampler holds
+    pub fn scratch_bytes(&self) -> usize {
+        match &self.algorithm {
+            Algorithm::Fixed(fixed) => fixed.scratch_bytes(),
+            Algorithm::Logm(logm) => logm.scratch_bytes(),
+            Algorithm::Reservoir(reservoir) => reservoir.scratch_bytes(),
+            _ => 0,
+        }
+    }
+
+    /// Bytes of scratch the resampler called `name` needs for `m` input
+    /// and `n` output particles, or `None` for an unknown name
+    pub fn scratch_estimate(name: &str, m: usize, n: usize) -> Option<usize> {
+        match registry::canonical(name)? {
+            "fixed32" | "fixed64" => Some(m * (size_of::<u64>() + size_of::<usize>())),
+            "logm" => Some(m * size_of::<f64>()),
+            "reservoir" => Some(reservoir::scratch_estimate(n)),
+            "naive" | "optimal" | "regular" | "rounding" => Some(0),
+            _ => None,
+        }
+    }
+
+    /// What this resampler kept from its last run, beyond the particles
+    pub fn internals(&self) -> Internals<'_> {
+        match &self.algorithm {
+            Algorithm::Fixed(fixed) => Internals::Quantized(fixed.quantized()),
+            Algorithm::Logm(logm) => Internals::Tree(logm.tree()),
+            _ => Internals::None,
+        }
+    }
+
+    /// What quantizing the weights cost in the last resampling, for the
+    /// fixed-point resamplers
+    pub fn quantization_error(&self) -> Option<fixed::QuantizationError> {
+        match &self.algorithm {
+            Algorithm::Fixed(fixed) => Some(fixed.last_error()),
+            _ => None,
+        }
+    }
+
+    /// Apply `order`, except that `logm` heapifies itself, and run the
+    /// chosen algorithm
+    fn dispatch(
+        &mut self,
+        weights: Weights,
+        m: usize,
+        particle: &mut Particles,
+        n: usize,
+        new_particle: &mut Particles,
+        order: Ordering,
+    ) -> usize {
+        if !(order == Ordering::Heapify && matches!(self.algorithm, Algorithm::Logm(_))) {
+            order.apply(&mut particle.data[..m]);
+        }
+        match &mut self.algorithm {
+            Algorithm::Fixed(fixed) => fixed.resample(weights, m, particle, n, new_particle, order),
+            Algorithm::Logm(logm) => logm.resample(weights, m, particle, n, new_particle, order),
+            Algorithm::Naive(naive) => naive.resample(weights, m, particle, n, new_particle, order),
+            Algorithm::Optimal(optimal) => {
+                optimal.resample(weights, m, particle, n, new_particle, order)
+            }
+            Algorithm::Regular(regular) => {
+                regular.resample(weights, m, particle, n, new_particle, order)
+            }
+            Algorithm::Reservoir(reservoir) => {
+                reservoir.resample(weights, m, particle, n, new_particle, order)
+            }
+            Algorithm::Rounding(rounding) => {
+                rounding.resample(weights, m, particle, n, new_particle, order)
+            }
+        }
+    }
+}
+
+#[inline]
+fn usable(w: f64) -> bool {
+    w.is_finite() && w >= 0.0
+}
+
+/// Make the first `m` weights usable for resampling, returning their
+/// total. Negative and non-finite weights are zeroed, totals that overflow
+/// or whose reciprocal does are rescaled, and if nothing is left every
+/// particle gets equal weight.
+fn sanitize(scale: f64, m: usize, particle: &mut Particles) -> f64 {
+    let data = &mut particle.data[..m];
+    if scale.is_finite() && scale >= f64::MIN_POSITIVE && data.iter().all(|p| usable(p.weight)) {
+        return scale;
+    }
+    let mut max = 0f64;
+    for p in data.iter_mut() {
+        if !usable(p.weight) {
+            p.weight = 0.0;
+        }
+        max = max.max(p.weight);
+    }
+    if max == 0.0 {
+        data.iter_mut().for_each(|p| p.weight = 1.0);
+        return m as f64;
+    }
+    let mut total: f64 = data.iter().map(|p| p.weight).sum();
+    if !total.is_finite() || total < f64::MIN_POSITIVE {
+        data.iter_mut().for_each(|p| p.weight /= max);
+        total = data.iter().map(|p| p.weight).sum();
+    }
+    total
 }
 
 impl Resample for Resampler {
+    fn prepare(&mut self, m: usize, n: usize) {
+        match &mut self.algorithm {
+            Algorithm::Fixed(fixed) => fixed.prepare(m, n),
+            Algorithm::Logm(logm) => logm.prepare(m, n),
+            Algorithm::Naive(naive) => naive.prepare(m, n),
+            Algorithm::Optimal(optimal) => optimal.prepare(m, n),
+            Algorithm::Regular(regular) => regular.prepare(m, n),
+            Algorithm::Reservoir(reservoir) => reservoir.prepare(m, n),
+            Algorithm::Rounding(rounding) => rounding.prepare(m, n),
+        }
+    }
+
+    /// Dispatches to the chosen resampler after clamping `m` and `n` to
+    /// the particle sets and sanitizing the weights, so bad input never
+    /// panics or aborts. With nothing to draw from or nothing to fill it
+    /// leaves `new_particle` alone, and one particle is simply copied.
+    /// Applies `order` first, except that `logm` heapifies itself. Debug
+    /// builds check that `weights` matches the first `m` weights whenever
+    /// they are all usable. The copies are reweighted by the resampler's
+    /// [`OutputWeights`] last.
     fn resample(
         &mut self,
-        scale: f64,
+        weights: Weights,
         m: usize,
         particle: &mut Particles,
         n: usize,
         new_particle: &mut Particles,
-        sort: bool,
+        order: Ordering,
     ) -> usize {
-        match self {
-            Resampler::Logm(logm) => logm.resample(scale, m, particle, n, new_particle, sort),
-            Resampler::Naive(naive) => naive.resample(scale, m, particle, n, new_particle, sort),
-            Resampler::Optimal(optimal) => {
-                optimal.resample(scale, m, particle, n, new_particle, sort)
+        let m = m.min(particle.data.len());
+        let n = n.min(new_particle.data.len());
+        if m == 0 || n == 0 {
+            return 0;
+        }
+        debug_assert!(
+            !particle.data[..m].iter().all(|p| usable(p.weight))
+                || !weights.total().is_finite()
+                || weights.matches(&particle.data[..m]),
+            "resampler told the weights total {:?}",
+            weights
+        );
+        let scale = sanitize(weights.total(), m, particle);
+        let weights = if scale == weights.total() {
+            weights
+        } else {
+            Weights::raw(scale)
+        };
+        let best = if m == 1 {
+            new_particle.data[..n].fill(particle.data[0]);
+            new_particle.data[..n]
+                .iter_mut()
+                .for_each(|p| p.weight /= scale);
+            0
+        } else {
+            self.prepare(m, n);
+            self.dispatch(weights, m, particle, n, new_particle, order)
+        };
+        self.output.apply(&mut new_particle.data[..n]);
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ParticleInfo;
+
+    fn particles(weights: &[f64]) -> Particles {
+        Particles {
+            data: weights
+                .iter()
+                .map(|&weight| ParticleInfo {
+                    weight,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn ancestors_match_a_search_per_point() {
+        crate::seed_thread_rng(4686);
+        let p = particles(&[0.3, 0.0, 0.1, 0.5, 0.2, 0.0, 0.4]);
+        let mut cumulative = Vec::new();
+        let mut t = 0.0;
+        for q in &p.data {
+            t += q.weight;
+            cumulative.push(t);
+        }
+        let mut points: Vec<f64> = (0..200).map(|_| crate::uniform() * t).collect();
+        points.push(t + 1.0);
+        points.sort_by(f64::total_cmp);
+        let merged: Vec<usize> = ancestors(&p.data, points.iter().copied()).collect();
+        for (&u, &j) in points.iter().zip(&merged) {
+            let searched = cumulative.partition_point(|&c| c < u).min(p.data.len() - 1);
+            assert_eq!(j, searched, "{}", u);
+        }
+        // Zero weights are never landed on.
+        assert!(merged.iter().all(|&j| j != 1 && j != 5));
+        assert_eq!(merged.last(), Some(&6));
+    }
+
+    #[test]
+    fn orderings() {
+        let weights = [0.3, 0.1, 0.5, 0.2, 0.4, 0.0];
+        let mut p = particles(&weights);
+        Ordering::SortDescending.apply(&mut p.data);
+        let w: Vec<f64> = p.data.iter().map(|p| p.weight).collect();
+        assert_eq!(w, [0.5, 0.4, 0.3, 0.2, 0.1, 0.0]);
+
+        let mut p = particles(&weights);
+        Ordering::Heapify.apply(&mut p.data);
+        for i in 1..p.data.len() {
+            assert!(p.data[i].weight <= p.data[(i - 1) / 2].weight);
+        }
+
+        let mut p = particles(&weights);
+        Ordering::Shuffle.apply(&mut p.data);
+        let mut w: Vec<f64> = p.data.iter().map(|p| p.weight).collect();
+        w.sort_by(f64::total_cmp);
+        assert_eq!(w, [0.0, 0.1, 0.2, 0.3, 0.4, 0.5]);
+        assert_eq!("heapify".parse(), Ok(Ordering::Heapify));
+    }
+
+    #[test]
+    fn bad_weights_do_not_panic() {
+        let cases: &[&[f64]] = &[
+            &[f64::NAN, 1.0, 2.0],
+            &[f64::INFINITY, 1.0, f64::NEG_INFINITY],
+            &[0.0, 0.0, 0.0, 0.0],
+            &[-1.0, 1.0],
+            &[f64::MAX, f64::MAX, f64::MAX],
+            &[1.0],
+            &[],
+        ];
+        for name in Resampler::NAMES {
+            for order in [Ordering::None, Ordering::legacy(name)] {
+                for weights in cases {
+                    let mut resampler = Resampler::new(name, 2);
+                    let mut from = particles(weights);
+                    let mut to = particles(&[0.0; 5]);
+                    let total = Weights::sum(&from.data);
+                    let best =
+                        resampler.resample(total, weights.len() + 1, &mut from, 5, &mut to, order);
+                    assert!(best < 5);
+                    if !weights.is_empty() {
+                        assert!(
+                            to.data.iter().all(|p| p.weight.is_finite()),
+                            "{} {:?}",
+                            name,
+                            weights
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn normalized_weights_reach_every_particle() {
+        crate::seed_thread_rng(4648);
+        for name in Resampler::NAMES {
+            let mut resampler = Resampler::new(name, 3);
+            let mut from = particles(&[0.25, 0.25, 0.5]);
+            for (i, p) in from.data.iter_mut().enumerate() {
+                p.state.posn.x = i as f64;
+            }
+            assert!(Weights::NORMALIZED.matches(&from.data));
+            let mut to = particles(&[0.0; 1000]);
+            resampler.resample(
+                Weights::NORMALIZED,
+                3,
+                &mut from,
+                1000,
+                &mut to,
+                Ordering::None,
+            );
+            let last = to.data.iter().filter(|p| p.state.posn.x == 2.0).count();
+            assert!((400..600).contains(&last), "{}: {}", name, last);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "resampler told the weights total")]
+    fn stale_total_is_caught() {
+        let mut from = particles(&[0.25, 0.25, 0.5]);
+        let mut to = particles(&[0.0; 3]);
+        Resampler::new("regular", 3).resample(
+            Weights::raw(0.1),
+            3,
+            &mut from,
+            3,
+            &mut to,
+            Ordering::None,
+        );
+    }
+
+    #[test]
+    fn output_weight_conventions() {
+        crate::seed_thread_rng(4678);
+        for name in Resampler::NAMES {
+            for m in [1, 4] {
+                let weights = &[2.0, 1.0, 4.0, 1.0][..m];
+                let total: f64 = weights.iter().sum();
+                let mut resampler = Resampler::new(name, m);
+                assert_eq!(resampler.output_weights(), OutputWeights::CarryScaled);
+                let mut to = particles(&[0.0; 8]);
+                for output in [OutputWeights::CarryScaled, OutputWeights::Uniform] {
+                    resampler.set_output_weights(output);
+                    let mut from = particles(weights);
+                    for (i, p) in from.data.iter_mut().enumerate() {
+                        p.state.posn.x = i as f64;
+                    }
+                    let sum = Weights::raw(total);
+                    resampler.resample(sum, m, &mut from, 8, &mut to, Ordering::None);
+                    for p in &to.data {
+                        let expected = match output {
+                            OutputWeights::Uniform => 0.125,
+                            OutputWeights::CarryScaled => weights[p.state.posn.x as usize] / total,
+                        };
+                        assert_eq!(p.weight, expected, "{} {:?}", name, output);
+                    }
+                }
+            }
+        }
+        assert_eq!("carry-scaled".parse(), Ok(OutputWeights::CarryScaled));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn tiny_sets_resample(
+            weights in proptest::collection::vec(proptest::num::f64::ANY, 0..4),
+            n in 0usize..4,
+            name in proptest::sample::select(&Resampler::NAMES[..]),
+            order in proptest::sample::select(&[
+                Ordering::None,
+                Ordering::Shuffle,
+                Ordering::SortDescending,
+                Ordering::Heapify,
+            ][..]),
+            uniform: bool,
+        ) {
+            crate::seed_thread_rng(4691);
+            let m = weights.len();
+            let mut p = particles(&weights);
+            for (i, q) in p.data.iter_mut().enumerate() {
+                q.state.posn.x = i as f64;
+            }
+            let mut out = Particles::new(n);
+            let mut resampler = Resampler::new(name, m);
+            if uniform {
+                resampler.set_output_weights(OutputWeights::Uniform);
             }
-            Resampler::Regular(regular) => {
-                regular.resample(scale, m, particle, n, new_particle, sort)
+            let best = resampler.resample(Weights::sum(&p.data), m, &mut p, n, &mut out, order);
+            proptest::prop_assert!(best < n.max(1));
+            if m > 0 && n > 0 {
+                for q in &out.data {
+                    let i = q.state.posn.x;
+                    proptest::prop_assert!(i.fract() == 0.0 && (0.0..m as f64).contains(&i));
+                    proptest::prop_assert!(q.weight.is_finite() && q.weight >= 0.0);
+                }
+                if uniform {
+                    let total: f64 = out.data.iter().map(|q| q.weight).sum();
+                    proptest::prop_assert!((total - 1.0).abs() < 1e-12);
+                }
             }
         }
     }
@@ -1,9 +1,19 @@
 use crate::{
-    resample::Resample,
+    resample::{Ordering, Resample, ResamplerInfo, Weights},
     types::{ParticleInfo, Particles},
     uniform,
 };
-use std::process::abort;
+
+pub(super) const INFO: ResamplerInfo = ResamplerInfo {
+    name: "naive",
+    aliases: &["multinomial"],
+    summary: "Multinomial sampling, scanning the weights from the start for every draw",
+    citation: Some(
+        "N. J. Gordon, D. J. Salmond and A. F. M. Smith, Novel approach to \
+         nonlinear/non-Gaussian Bayesian state estimation, IEE Proceedings F 140(2), 1993",
+    ),
+    complexity: "O(mn)",
+};
 
 #[derive(Default)]
 pub struct Naive {}
@@ -18,26 +28,31 @@ fn weighted_sample(scale: f64, m: usize, particles: &Particles) -> &ParticleInfo
         }
     }
     #[cfg(feature = "debug-naive")]
-    println!("total {} < target {}", t, w);
-    abort();
+    {
+        println!("total {} < target {}", t, w);
+        std::process::abort()
+    }
+    // Rounding left the running total just short of the target.
+    #[cfg(not(feature = "debug-naive"))]
+    {
+        &particles.data[m - 1]
+    }
 }
 
 impl Resample for Naive {
     fn resample(
         &mut self,
-        scale: f64,
+        weights: Weights,
         m: usize,
         particle: &mut Particles,
         n: usize,
         new_particle: &mut Particles,
-        sort: bool,
+        _order: Ordering,
     ) -> usize {
+        let scale = weights.total();
         let mut best_w = 0f64;
         let mut best_i = 0usize;
         let invscale = 1.0 / scale;
-        if sort {
-            particle.data.sort_by(|a, b| a.cmp_weight(b));
-        }
         for i in 0..n {
             new_particle.data[i] = *weighted_sample(scale, m, particle);
             new_particle.data[i].weight *= invscale;
@@ -1,53 +1,172 @@
-use crate::{polynomial, resample::Resample, uniform};
+use crate::{
+    fp::powf,
+    polynomial,
+    resample::{Ordering, Resample, ResamplerInfo, Weights, ancestors},
+    uniform,
+};
+
+pub(super) const INFO: ResamplerInfo = ResamplerInfo {
+    name: "optimal",
+    aliases: &["sorted-uniforms"],
+    summary: "Multinomial sampling, generating the draws already sorted and merging them \
+              with the weights in one pass; optimal in time, not in the variance sense \
+              of rounding",
+    citation: Some("B. Massey, Fast perfect weighted resampling, ICASSP 2008"),
+    complexity: "O(m + n)",
+};
 
 #[derive(Default)]
 pub struct Optimal {}
 
 #[inline]
-fn nform(n: i32, sort: bool) -> f64 {
-    if sort {
+fn nform(n: i32, poly: bool) -> f64 {
+    if poly {
         return polynomial(n);
     }
-    1.0f64 - uniform().powf(1.0 / (n + 1) as f64)
+    1.0f64 - powf(uniform(), 1.0 / (n + 1) as f64)
 }
 
 impl Resample for Optimal {
     fn resample(
         &mut self,
-        scale: f64,
+        weights: Weights,
         m: usize,
         particle: &mut crate::types::Particles,
         n: usize,
         new_particle: &mut crate::types::Particles,
-        sort: bool,
+        order: Ordering,
     ) -> usize {
+        let scale = weights.total();
+        // As in the C code, asking for any ordering also switches the
+        // spacings over to ziggurat polynomial variates.
+        let poly = order != Ordering::None;
         let invscale = 1.0 / scale;
-        let mut u0 = nform((n - 1) as i32, sort) * scale;
-        let mut j = 0;
-        let mut t = 0f64;
         let mut best_w = 0f64;
         let mut best_i = 0usize;
-        for i in 0..n {
-            while t + particle.data[j].weight < u0 && j < m {
-                t += particle.data[j].weight;
-                j += 1;
+        // Each point is the least of the n - i - 1 still to place in
+        // [u0, scale].
+        let mut u0 = nform((n - 1) as i32, poly) * scale;
+        let mut placed = 0;
+        let points = std::iter::from_fn(|| {
+            if placed == n {
+                return None;
             }
-            #[cfg(feature = "debug-optimal")]
-            if j >= m {
-                use std::process::abort;
-
-                println!("fell of end s={:.14} t ={:.14} u={:.14}", scale, t, u0);
-                abort();
+            let u = u0;
+            placed += 1;
+            if placed < n {
+                u0 = u0 + (scale - u0) * nform((n - placed - 1) as i32, poly);
             }
-
+            Some(u)
+        });
+        for (i, j) in ancestors(&particle.data[..m], points).enumerate() {
             new_particle.data[i] = particle.data[j];
             new_particle.data[i].weight *= invscale;
             if new_particle.data[i].weight > best_w {
                 best_w = new_particle.data[i].weight;
                 best_i = i;
             }
-            u0 = u0 + (scale - u0) * nform((n - i - 1) as i32, sort);
         }
         best_i
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ParticleInfo, Particles};
+
+    /// Particles tagged by index in `posn.x`
+    fn tagged(weights: &[f64]) -> Particles {
+        let mut p = Particles {
+            data: vec![ParticleInfo::default(); weights.len()],
+        };
+        for (i, (info, &w)) in p.data.iter_mut().zip(weights).enumerate() {
+            info.state.posn.x = i as f64;
+            info.weight = w;
+        }
+        p
+    }
+
+    /// Offspring counts per particle over `trials` resamplings
+    fn offspring(
+        weights: &[f64],
+        scale: f64,
+        n: usize,
+        order: Ordering,
+        trials: usize,
+    ) -> Vec<Vec<f64>> {
+        let mut counts = vec![Vec::with_capacity(trials); weights.len()];
+        let mut to = tagged(&vec![0.0; n]);
+        for _ in 0..trials {
+            let mut from = tagged(weights);
+            Optimal::default().resample(
+                Weights::raw(scale),
+                weights.len(),
+                &mut from,
+                n,
+                &mut to,
+                order,
+            );
+            let mut c = vec![0.0; weights.len()];
+            let mut last = 0.0;
+            for p in &to.data {
+                // The merge walks the particles in order.
+                assert!(p.state.posn.x >= last);
+                last = p.state.posn.x;
+                c[p.state.posn.x as usize] += 1.0;
+            }
+            for (i, c) in c.into_iter().enumerate() {
+                counts[i].push(c);
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn offspring_counts_are_multinomial() {
+        crate::seed_thread_rng(4645);
+        let weights = [0.1, 0.2, 0.3, 0.4];
+        let n = 100;
+        let trials = 4000;
+        for order in [Ordering::None, Ordering::SortDescending] {
+            for (i, c) in offspring(&weights, 1.0, n, order, trials)
+                .iter()
+                .enumerate()
+            {
+                let mean = c.iter().sum::<f64>() / trials as f64;
+                let var = c.iter().map(|c| (c - mean) * (c - mean)).sum::<f64>() / trials as f64;
+                // Sorted uniform order statistics give multinomial counts.
+                let (w, n) = (weights[i], n as f64);
+                let sd_mean = (n * w * (1.0 - w) / trials as f64).sqrt();
+                assert!(
+                    (mean - n * w).abs() < 5.0 * sd_mean,
+                    "{:?} {}: mean {}",
+                    order,
+                    i,
+                    mean
+                );
+                let expected_var = n * w * (1.0 - w);
+                assert!(
+                    (var / expected_var - 1.0).abs() < 0.15,
+                    "{:?} {}: var {}",
+                    order,
+                    i,
+                    var
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn never_reads_past_m() {
+        crate::seed_thread_rng(17);
+        let weights = [0.25, 0.25, 0.25, 0.25];
+        for scale in [0.5, 1.0 + 1e-12, 1.5, 4.0] {
+            for order in [Ordering::None, Ordering::SortDescending] {
+                let counts = offspring(&weights, scale, 50, order, 20);
+                let total: f64 = counts.iter().flatten().sum();
+                assert_eq!(total, 50.0 * 20.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! What each resampler is called and what it does. Every algorithm
+//! describes itself with a [`ResamplerInfo`]; the registry looks them up
+//! by canonical name or alias, so users can ask for a resampler by the
+//! name the literature gives it and find out what "regular" means here.
+
+use std::fmt;
+
+/// Description of one resampling algorithm
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResamplerInfo {
+    /// Name [`Resampler::new`](super::Resampler::new) takes
+    pub name: &'static str,
+    /// Other names that mean the same algorithm
+    pub aliases: &'static [&'static str],
+    pub summary: &'static str,
+    /// Where the algorithm comes from, when it has a source
+    pub citation: Option<&'static str>,
+    /// Cost for `m` input and `n` output particles
+    pub complexity: &'static str,
+}
+
+/// The resamplers this crate provides, in name order
+pub const BUILTIN: [ResamplerInfo; 8] = [
+    super::fixed::INFO32,
+    super::fixed::INFO64,
+    super::logm::INFO,
+    super::naive::INFO,
+    super::optimal::INFO,
+    super::regular::INFO,
+    super::reservoir::INFO,
+    super::rounding::INFO,
+];
+
+/// Canonical name of the built-in resampler called `name`
+pub fn canonical(name: &str) -> Option<&'static str> {
+    find(&BUILTIN, name).map(|info| info.name)
+}
+
+fn find<'a>(infos: &'a [ResamplerInfo], name: &str) -> Option<&'a ResamplerInfo> {
+    infos
+        .iter()
+        .find(|info| info.name == name || info.aliases.contains(&name))
+}
+
+/// Resamplers known by name, starting with the built-in ones
+#[derive(Clone, Debug)]
+pub struct ResamplerRegistry {
+    entries: Vec<ResamplerInfo>,
+}
+
+impl Default for ResamplerRegistry {
+    fn default() -> Self {
+        Self {
+            entries: BUILTIN.to_vec(),
+        }
+    }
+}
+
+impl ResamplerRegistry {
+    /// Add `info`, refusing a name or alias already taken.
+    pub fn register(&mut self, info: ResamplerInfo) -> Result<(), String> {
+        let names = || std::iter::once(&info.name).chain(info.aliases);
+        if let Some(taken) = names().find(|name| self.get(name).is_some()) {
+            return Err(format!("resampler name {} is taken", taken));
+        }
+        self.entries.push(info);
+        Ok(())
+    }
+
+    /// The resampler called `name`, by canonical name or alias
+    pub fn get(&self, name: &str) -> Option<&ResamplerInfo> {
+        find(&self.entries, name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ResamplerInfo> {
+        self.entries.iter()
+    }
+}
+
+impl fmt::Display for ResamplerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.aliases.is_empty() {
+            write!(f, " (also {})", self.aliases.join(", "))?;
+        }
+        writeln!(f, "  {}", self.complexity)?;
+        writeln!(f, "    {}", self.summary)?;
+        if let Some(citation) = self.citation {
+            writeln!(f, "    {}", citation)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ResamplerRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for info in &self.entries {
+            write!(f, "{}", info)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resample::Resampler;
+
+    #[test]
+    fn names_resolve() {
+        let names: Vec<_> = BUILTIN.iter().map(|info| info.name).collect();
+        assert_eq!(names, Resampler::NAMES);
+        for info in &BUILTIN {
+            for &alias in info.aliases {
+                assert_eq!(Resampler::new(alias, 4).name(), info.name);
+                assert_eq!(Resampler::try_new(alias, 4).unwrap().name(), info.name);
+            }
+        }
+        assert!(Resampler::try_new("systematic", 4).is_none());
+        assert_eq!(canonical("systematic"), None);
+        assert_eq!(canonical("multinomial"), Some("naive"));
+
+        let mut registry = ResamplerRegistry::default();
+        let custom = ResamplerInfo {
+            name: "custom",
+            aliases: &["tree"],
+            summary: "",
+            citation: None,
+            complexity: "O(1)",
+        };
+        assert!(registry.register(custom).is_err());
+        let custom = ResamplerInfo {
+            aliases: &[],
+            ..custom
+        };
+        registry.register(custom).unwrap();
+        assert_eq!(registry.get("custom"), Some(&custom));
+        assert!(registry.to_string().contains("Massey"));
+    }
+}
@@ -1,4 +1,17 @@
-use crate::{rand32, resample::Resample, types::Particles};
+use crate::{
+    resample::{Ordering, Resample, ResamplerInfo, Weights, ancestors},
+    types::Particles,
+};
+
+pub(super) const INFO: ResamplerInfo = ResamplerInfo {
+    name: "regular",
+    aliases: &["deterministic"],
+    summary: "Points evenly spaced at k / (n + 1) of the total weight, merged with the \
+              weights in one pass; systematic resampling without the random offset, so \
+              the same weights always give the same copies",
+    citation: None,
+    complexity: "O(m + n)",
+};
 
 #[derive(Default)]
 pub struct Regular {}
@@ -6,49 +19,27 @@ pub struct Regular {}
 impl Resample for Regular {
     fn resample(
         &mut self,
-        scale: f64,
+        weights: Weights,
         m: usize,
         particle: &mut Particles,
         n: usize,
         new_particle: &mut Particles,
-        sort: bool,
+        _order: Ordering,
     ) -> usize {
+        let scale = weights.total();
         let invscale = 1.0 / scale;
         let mut best_w = 0f64;
         let mut best_i = 0usize;
 
-        // Shuffle
-        if sort {
-            for i in 0..m - 1 {
-                let j = rand32() as usize % (m - i) + i;
-                particle.data.swap(j, i);
-            }
-        }
-
-        // Merge
-        let mut u0 = scale / (n + 1) as f64;
-        let mut j = 0;
-        let mut t = 0f64;
-        for i in 0..n {
-            while t + particle.data[j].weight < u0 && j < m {
-                t += particle.data[j].weight;
-                j += 1;
-            }
-            #[cfg(feature = "debug-regular")]
-            if j >= m {
-                use std::process::abort;
-
-                println!("fell of end s={:.14} t ={:.14} u={:.14}", scale, t, u0);
-                abort();
-            }
-
+        let step = scale / (n + 1) as f64;
+        let points = std::iter::successors(Some(step), |u| Some(u + step)).take(n);
+        for (i, j) in ancestors(&particle.data[..m], points).enumerate() {
             new_particle.data[i] = particle.data[j];
             new_particle.data[i].weight *= invscale;
             if new_particle.data[i].weight > best_w {
                 best_w = new_particle.data[i].weight;
                 best_i = i;
             }
-            u0 += scale / (n + 1) as f64;
         }
         best_i
     }
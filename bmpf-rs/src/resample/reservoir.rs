@@ -0,0 +1,194 @@
+//! Weighted reservoir sampling (Efraimidis and Spirakis' A-ExpJ). Each
+//! output particle is a reservoir of one, filled in a single pass over the
+//! weights with no prefix sum and no known total, so the weights could be
+//! consumed as they are produced.
+//!
+//! A reservoir holding an item with key `T` next changes hands once
+//! another `ln(u) / ln(T)` of weight has gone by. The reservoirs sit in a
+//! min-heap on the running weight at which that happens, so a pass touches
+//! only the reservoirs that change, about `ln m` times each.
+
+use crate::{
+    fp::{exp, ln},
+    resample::{Ordering, Resample, ResamplerInfo, Weights},
+    types::Particles,
+    uniform,
+};
+
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    /// Running weight at which this reservoir takes a new item
+    threshold: f64,
+    /// Log of the item's key
+    key: f64,
+    item: usize,
+}
+
+impl Slot {
+    /// Take `item` with log key `key`, the running weight having reached
+    /// `cumulative`.
+    fn take(&mut self, item: usize, key: f64, cumulative: f64) {
+        self.item = item;
+        self.key = key;
+        // A key of one is never beaten.
+        let jump = if key < 0.0 {
+            ln(1.0 - uniform()) / key
+        } else {
+            f64::INFINITY
+        };
+        self.threshold = cumulative + jump;
+    }
+}
+
+pub(super) const INFO: ResamplerInfo = ResamplerInfo {
+    name: "reservoir",
+    aliases: &["a-expj"],
+    summary: "A weighted reservoir of one per output, filled in a single pass without the \
+              total weight",
+    citation: Some(
+        "P. S. Efraimidis and P. G. Spirakis, Weighted random sampling with a reservoir, \
+         Information Processing Letters 97(5), 2006",
+    ),
+    complexity: "O(m + n log m log n)",
+};
+
+#[derive(Debug, Default)]
+pub struct Reservoir {
+    slots: Vec<Slot>,
+}
+
+/// Bytes of scratch for `n` output particles
+pub(crate) fn scratch_estimate(n: usize) -> usize {
+    n * size_of::<Slot>()
+}
+
+impl Reservoir {
+    pub(crate) fn scratch_bytes(&self) -> usize {
+        scratch_estimate(self.slots.capacity())
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        let n = self.slots.len();
+        loop {
+            let left = 2 * i + 1;
+            if left >= n {
+                break;
+            }
+            let right = left + 1;
+            let mut next = left;
+            if right < n && self.slots[right].threshold < self.slots[left].threshold {
+                next = right;
+            }
+            if self.slots[i].threshold <= self.slots[next].threshold {
+                break;
+            }
+            self.slots.swap(i, next);
+            i = next;
+        }
+    }
+}
+
+impl Resample for Reservoir {
+    fn prepare(&mut self, _m: usize, n: usize) {
+        self.slots.reserve(n.saturating_sub(self.slots.len()));
+    }
+
+    /// Every reservoir starts with the first positive weight. Each later
+    /// particle, once the running weight passes a reservoir's threshold,
+    /// replaces its item with a key drawn above the old one.
+    fn resample(
+        &mut self,
+        weights: Weights,
+        m: usize,
+        particle: &mut Particles,
+        n: usize,
+        new_particle: &mut Particles,
+        _order: Ordering,
+    ) -> usize {
+        self.slots.clear();
+        let mut cumulative = 0f64;
+        for (j, p) in particle.data[..m].iter().enumerate() {
+            let w = p.weight;
+            if w <= 0.0 {
+                continue;
+            }
+            cumulative += w;
+            if self.slots.is_empty() {
+                for _ in 0..n {
+                    let mut slot = Slot {
+                        threshold: 0.0,
+                        key: 0.0,
+                        item: 0,
+                    };
+                    slot.take(j, ln(1.0 - uniform()) / w, cumulative);
+                    self.slots.push(slot);
+                }
+                for i in (0..n / 2).rev() {
+                    self.sift_down(i);
+                }
+                continue;
+            }
+            while self.slots[0].threshold <= cumulative {
+                // The new key is uniform on (T^w, 1), T the old key.
+                let floor = exp(self.slots[0].key * w);
+                let key = ln(floor + (1.0 - floor) * (1.0 - uniform())) / w;
+                self.slots[0].take(j, key, cumulative);
+                self.sift_down(0);
+            }
+        }
+
+        let invscale = 1.0 / weights.total();
+
+        let mut best_w = 0f64;
+        let mut best_i = 0usize;
+        for (i, slot) in self.slots.iter().enumerate() {
+            new_particle.data[i] = particle.data[slot.item];
+            new_particle.data[i].weight *= invscale;
+            if new_particle.data[i].weight > best_w {
+                best_w = new_particle.data[i].weight;
+                best_i = i;
+            }
+        }
+        best_i
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gaussian,
+        resample::{Ordering, variance::resampler_variance},
+        types::ParticleInfo,
+        uniform,
+    };
+
+    #[test]
+    fn reservoir_matches_multinomial() {
+        crate::seed_thread_rng(4666);
+        let particles: Vec<ParticleInfo> = (0..100)
+            .map(|i| {
+                let mut p = ParticleInfo {
+                    // Zero weights mixed in, which the pass skips
+                    weight: if i % 10 == 3 {
+                        0.0
+                    } else {
+                        gaussian(1.0).exp()
+                    },
+                    ..Default::default()
+                };
+                p.state.posn.x = uniform() * 10.0;
+                p.state.posn.y = uniform() * 10.0;
+                p
+            })
+            .collect();
+        let reservoir = resampler_variance("reservoir", &particles, 100, 2000, Ordering::None);
+        let naive = resampler_variance("naive", &particles, 100, 2000, Ordering::None);
+        // Independent draws, so the textbook multinomial variance and no
+        // bias, the same as naive.
+        let ratio = reservoir.offspring / reservoir.multinomial;
+        assert!((0.93..1.07).contains(&ratio), "{}", ratio);
+        let ratio = reservoir.estimate / naive.estimate;
+        assert!((0.8..1.25).contains(&ratio), "{}", ratio);
+        assert!(reservoir.bias < 4.0 * (reservoir.estimate / 2000.0).sqrt());
+    }
+}
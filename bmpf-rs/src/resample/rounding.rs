@@ -0,0 +1,124 @@
+use crate::{
+    resample::{Ordering, Resample, ResamplerInfo, Weights},
+    types::Particles,
+    uniform,
+};
+
+pub(super) const INFO: ResamplerInfo = ResamplerInfo {
+    name: "rounding",
+    aliases: &["stochastic-rounding", "residual-systematic"],
+    summary: "Each particle copied its expected number of times, rounded up or down at \
+              random by one systematic pass over the fractions",
+    citation: Some(
+        "J. S. Liu and R. Chen, Sequential Monte Carlo methods for dynamic systems, \
+         JASA 93(443), 1998",
+    ),
+    complexity: "O(m + n)",
+};
+
+/// Residual resampling with a systematic pass over the residuals. Each
+/// particle is copied `floor(n w)` times, for normalized weight `w`, and
+/// once more with probability the fractional part of `n w`. The extra
+/// copies are placed by one systematic pass over the fractional parts
+/// with a random offset, so each count is `floor(n w)` or one more and
+/// the counts are unbiased.
+#[derive(Default)]
+pub struct Rounding {}
+
+impl Resample for Rounding {
+    fn resample(
+        &mut self,
+        weights: Weights,
+        m: usize,
+        particle: &mut Particles,
+        n: usize,
+        new_particle: &mut Particles,
+        _order: Ordering,
+    ) -> usize {
+        let scale = weights.total();
+        let invscale = 1.0 / scale;
+        let mut best_w = 0f64;
+        let mut best_i = 0usize;
+        let mut best_j = 0usize;
+
+        // Cumulative fractional parts, and the next point of the pass
+        // through them.
+        let (mut fraction, mut point) = (0.0, uniform());
+        let mut i = 0;
+        for j in 0..m {
+            let w = particle.data[j].weight * invscale;
+            let expected = w * n as f64;
+            let whole = expected.floor();
+            fraction += expected - whole;
+            let mut copies = whole as usize;
+            if fraction > point {
+                copies += 1;
+                point += 1.0;
+            }
+            if copies > 0 && w > best_w {
+                best_w = w;
+                best_i = i;
+                best_j = j;
+            }
+            for _ in 0..copies.min(n - i) {
+                new_particle.data[i] = particle.data[j];
+                new_particle.data[i].weight = w;
+                i += 1;
+            }
+        }
+        // Rounding can leave the counts a copy short, which goes to the
+        // heaviest particle.
+        while i < n {
+            new_particle.data[i] = particle.data[best_j];
+            new_particle.data[i].weight = best_w;
+            i += 1;
+        }
+        best_i
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ParticleInfo;
+
+    #[test]
+    fn counts_round_the_expected_offspring() {
+        crate::seed_thread_rng(4693);
+        let weights = [0.05, 0.3, 0.125, 0.4, 0.125];
+        let (n, trials) = (10, 4000);
+        let mut from = Particles {
+            data: vec![ParticleInfo::default(); weights.len()],
+        };
+        let mut to = Particles::new(n);
+        let mut sums = [0.0; 5];
+        for _ in 0..trials {
+            for (i, (p, &w)) in from.data.iter_mut().zip(&weights).enumerate() {
+                p.state.posn.x = i as f64;
+                p.weight = w;
+            }
+            Rounding::default().resample(
+                Weights::NORMALIZED,
+                weights.len(),
+                &mut from,
+                n,
+                &mut to,
+                Ordering::None,
+            );
+            let mut counts = [0.0; 5];
+            for p in &to.data {
+                counts[p.state.posn.x as usize] += 1.0;
+            }
+            for ((sum, c), w) in sums.iter_mut().zip(counts).zip(weights) {
+                // Never further than rounding from the expected count.
+                let expected = w * n as f64;
+                assert!(c == expected.floor() || c == expected.ceil(), "{} {}", c, w);
+                *sum += c;
+            }
+        }
+        for (sum, w) in sums.iter().zip(weights) {
+            let mean = sum / trials as f64;
+            assert!((mean - w * n as f64).abs() < 0.03, "{} {}", mean, w);
+        }
+    }
+}
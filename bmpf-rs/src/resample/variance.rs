@@ -0,0 +1,150 @@
+//! How much noise each resampler adds. For a frozen weight vector,
+//! resample many times and accumulate, run by run, the variance of every
+//! particle's offspring count and of the resampled mean position. Lower is
+//! better: the resampled cloud stands in for the weighted one.
+
+use crate::{
+    resample::{Ordering, Resample, Resampler, Weights},
+    types::{CCoord, ParticleInfo, Particles},
+};
+
+/// Running mean and variance (Welford)
+#[derive(Clone, Copy, Debug, Default)]
+struct Running {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Running {
+    fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+}
+
+/// One row of the comparison
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VarianceReport {
+    pub resampler: &'static str,
+    pub runs: usize,
+    /// Variance of the offspring counts, summed over the input particles
+    pub offspring: f64,
+    /// What multinomial resampling gives for `offspring`, `n w (1 - w)`
+    /// summed over the normalized weights
+    pub multinomial: f64,
+    /// Variance of the resampled mean position, summed over both axes
+    pub estimate: f64,
+    /// Distance from the average resampled mean to the weighted mean
+    pub bias: f64,
+}
+
+/// Resample `particles` `runs` times into `n` particles with the resampler
+/// called `name`, starting from the same weights every time.
+pub fn resampler_variance(
+    name: &str,
+    particles: &[ParticleInfo],
+    n: usize,
+    runs: usize,
+    order: Ordering,
+) -> VarianceReport {
+    let m = particles.len();
+    let mut resampler = Resampler::new(name, m);
+    resampler.prepare(m, n);
+    let total: f64 = particles.iter().map(|p| p.weight).sum();
+    let mut exact = CCoord::default();
+    for p in particles {
+        exact.x += p.weight / total * p.state.posn.x;
+        exact.y += p.weight / total * p.state.posn.y;
+    }
+
+    // Resample copies tagged with their index, so the ancestors can be
+    // read off the output.
+    let mut tagged = Particles {
+        data: particles.to_vec(),
+    };
+    let mut out = Particles {
+        data: vec![ParticleInfo::default(); n],
+    };
+    let mut offspring = vec![Running::default(); m];
+    let mut counts = vec![0u32; m];
+    let (mut mean_x, mut mean_y) = (Running::default(), Running::default());
+    for _ in 0..runs {
+        for (i, (t, p)) in tagged.data.iter_mut().zip(particles).enumerate() {
+            *t = *p;
+            t.state.posn.x = i as f64;
+        }
+        let weights = Weights::sum(&tagged.data);
+        resampler.resample(weights, m, &mut tagged, n, &mut out, order);
+        counts.fill(0);
+        let mut est = CCoord::default();
+        for p in &out.data {
+            let a = p.state.posn.x as usize;
+            counts[a] += 1;
+            est.x += particles[a].state.posn.x / n as f64;
+            est.y += particles[a].state.posn.y / n as f64;
+        }
+        for (o, &c) in offspring.iter_mut().zip(&counts) {
+            o.push(c as f64);
+        }
+        mean_x.push(est.x);
+        mean_y.push(est.y);
+    }
+    let multinomial = particles
+        .iter()
+        .map(|p| {
+            let w = p.weight / total;
+            n as f64 * w * (1.0 - w)
+        })
+        .sum();
+    VarianceReport {
+        resampler: resampler.name(),
+        runs,
+        offspring: offspring.iter().map(Running::variance).sum(),
+        multinomial,
+        estimate: mean_x.variance() + mean_y.variance(),
+        bias: (mean_x.mean - exact.x).hypot(mean_y.mean - exact.y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gaussian, uniform};
+
+    #[test]
+    fn systematic_beats_multinomial() {
+        crate::seed_thread_rng(4660);
+        let particles: Vec<ParticleInfo> = (0..200)
+            .map(|_| {
+                let mut p = ParticleInfo {
+                    weight: gaussian(1.0).exp(),
+                    ..Default::default()
+                };
+                p.state.posn.x = uniform() * 10.0;
+                p.state.posn.y = uniform() * 10.0;
+                p
+            })
+            .collect();
+        let naive = resampler_variance("naive", &particles, 200, 400, Ordering::None);
+        // Multinomial draws match the textbook variance, unbiased.
+        let ratio = naive.offspring / naive.multinomial;
+        assert!((0.9..1.1).contains(&ratio), "{}", ratio);
+        assert!(naive.bias < 4.0 * (naive.estimate / 400.0).sqrt());
+
+        let fixed = resampler_variance("fixed32", &particles, 200, 400, Ordering::None);
+        assert!(fixed.offspring < 0.5 * naive.offspring);
+        assert!(fixed.estimate < 0.5 * naive.estimate);
+        assert_eq!(fixed.resampler, "fixed32");
+    }
+}
@@ -0,0 +1,241 @@
+//! Runs, their estimates and summary metrics kept in one SQLite file, so
+//! a benchmark campaign of hundreds of runs can be compared with a query
+//! rather than by collecting output files.
+
+use crate::{
+    report::RunHeader,
+    types::{CCoord, Estimate},
+};
+use rusqlite::{Connection, Transaction, params};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    resampler TEXT NOT NULL,
+    nparticles INTEGER NOT NULL,
+    resample_interval INTEGER NOT NULL,
+    seed INTEGER,
+    input TEXT,
+    header TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS steps (
+    run INTEGER NOT NULL REFERENCES runs(id),
+    t REAL NOT NULL,
+    x REAL NOT NULL,
+    y REAL NOT NULL,
+    est_x REAL NOT NULL,
+    est_y REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS steps_by_run ON steps(run);
+CREATE TABLE IF NOT EXISTS metrics (
+    run INTEGER NOT NULL REFERENCES runs(id),
+    name TEXT NOT NULL,
+    value REAL NOT NULL,
+    PRIMARY KEY (run, name)
+);
+";
+
+/// A database of runs
+pub struct ResultsDb {
+    conn: Connection,
+}
+
+/// One metric over the runs of one resampler and particle count
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub resampler: String,
+    pub nparticles: usize,
+    pub runs: usize,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl ResultsDb {
+    /// Open the database at `path`, creating it if need be.
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// A database that lives only as long as this value
+    pub fn in_memory() -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Start recording the run `header` describes. Nothing is stored
+    /// unless the returned recorder is finished.
+    pub fn begin_run(&mut self, header: &RunHeader) -> rusqlite::Result<RunRecorder<'_>> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO runs (resampler, nparticles, resample_interval, seed, input, header)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                header.resampler,
+                header.nparticles as i64,
+                header.resample_interval as i64,
+                header.seed,
+                header.input.as_ref().map(|(name, _)| name),
+                header.to_string(),
+            ],
+        )?;
+        let run = tx.last_insert_rowid();
+        Ok(RunRecorder {
+            tx,
+            run,
+            sum_sq: 0.0,
+            steps: 0,
+            variance: 0.0,
+            log_dimension: 0.0,
+            tracked: 0,
+        })
+    }
+
+    /// `metric` summarized per resampler and particle count, best mean
+    /// first
+    pub fn compare(&self, metric: &str) -> rusqlite::Result<Vec<Comparison>> {
+        let mut query = self.conn.prepare(
+            "SELECT r.resampler, r.nparticles, COUNT(*), AVG(m.value), MIN(m.value), MAX(m.value)
+             FROM runs r JOIN metrics m ON m.run = r.id
+             WHERE m.name = ?1
+             GROUP BY r.resampler, r.nparticles
+             ORDER BY AVG(m.value), r.resampler, r.nparticles",
+        )?;
+        let rows = query.query_map([metric], |row| {
+            Ok(Comparison {
+                resampler: row.get(0)?,
+                nparticles: row.get::<_, i64>(1)? as usize,
+                runs: row.get::<_, i64>(2)? as usize,
+                mean: row.get(3)?,
+                min: row.get(4)?,
+                max: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// The underlying connection, for queries `compare` does not cover
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+/// Records one run's steps and metrics in a single transaction
+pub struct RunRecorder<'a> {
+    tx: Transaction<'a>,
+    run: i64,
+    sum_sq: f64,
+    steps: usize,
+    /// Sums of the steps' weight variances and their `ln(1 + v)`, over
+    /// the `tracked` steps that have them
+    variance: f64,
+    log_dimension: f64,
+    tracked: usize,
+}
+
+impl RunRecorder<'_> {
+    /// Row id of the run in `runs`
+    pub fn run(&self) -> i64 {
+        self.run
+    }
+
+    /// Record the estimate of the step at time `t` and where the vehicle
+    /// really was.
+    pub fn step(&mut self, t: f64, vehicle: CCoord, est: &Estimate) -> rusqlite::Result<()> {
+        let posn = est.position();
+        self.tx
+            .prepare_cached(
+                "INSERT INTO steps (run, t, x, y, est_x, est_y) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?
+            .execute(params![self.run, t, vehicle.x, vehicle.y, posn.x, posn.y])?;
+        let (dx, dy) = (posn.x - vehicle.x, posn.y - vehicle.y);
+        self.sum_sq += dx * dx + dy * dy;
+        self.steps += 1;
+        if let Some(v) = est.weight_variance {
+            self.variance += v;
+            self.log_dimension += v.ln_1p();
+            self.tracked += 1;
+        }
+        Ok(())
+    }
+
+    /// Record a summary metric of the run, replacing any of that name.
+    pub fn metric(&mut self, name: &str, value: f64) -> rusqlite::Result<()> {
+        self.tx.execute(
+            "INSERT OR REPLACE INTO metrics (run, name, value) VALUES (?1, ?2, ?3)",
+            params![self.run, name, value],
+        )?;
+        Ok(())
+    }
+
+    /// Record the `steps` and `rms` error metrics, the mean
+    /// `weight_variance` of the incremental weights and the `log_dimension`
+    /// their variances grew by over the whole run, then commit the run,
+    /// returning its row id.
+    pub fn finish(mut self) -> rusqlite::Result<i64> {
+        self.metric("steps", self.steps as f64)?;
+        if self.steps > 0 {
+            self.metric("rms", (self.sum_sq / self.steps as f64).sqrt())?;
+        }
+        if self.tracked > 0 {
+            self.metric("weight_variance", self.variance / self.tracked as f64)?;
+            self.metric("log_dimension", self.log_dimension)?;
+        }
+        self.tx.commit()?;
+        Ok(self.run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimParams, types::BpfState};
+
+    #[test]
+    fn runs_compare() {
+        crate::seed_thread_rng(4689);
+        let mut db = ResultsDb::in_memory().unwrap();
+        let params = SimParams::default();
+        for name in ["regular", "logm"] {
+            for seed in [1, 2] {
+                let mut bpf = BpfState::new(name, false, 100, 0, false, 1, params.clone());
+                bpf.reporter = None;
+                bpf.init_particles();
+                let mut header = bpf.run_header();
+                header.seed = Some(seed);
+                let mut run = db.begin_run(&header).unwrap();
+                let vehicle = CCoord { x: 1.0, y: 2.0 };
+                for i in 1..=20 {
+                    let imu = crate::types::ACoord { r: 0.0, t: 0.0 };
+                    let est = bpf.observe(i as f64 * 0.1, 0.1, vehicle, imu);
+                    run.step(i as f64 * 0.1, vehicle, &est).unwrap();
+                }
+                run.metric("seed", seed as f64).unwrap();
+                run.finish().unwrap();
+            }
+        }
+        // An unfinished run leaves nothing behind.
+        let header = BpfState::new("naive", false, 10, 0, false, 1, params).run_header();
+        drop(db.begin_run(&header).unwrap());
+
+        let rows = db.compare("rms").unwrap();
+        assert_eq!(rows.len(), 2);
+        for row in &rows {
+            assert_eq!((row.nparticles, row.runs), (100, 2));
+            assert!(row.min <= row.mean && row.mean <= row.max);
+        }
+        assert!(rows[0].mean <= rows[1].mean);
+        let steps: i64 = db
+            .connection()
+            .query_row("SELECT COUNT(*) FROM steps", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(steps, 80);
+        assert_eq!(db.compare("log_dimension").unwrap().len(), 2);
+        assert!(db.compare("nope").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+//! Scripted ground truth for the simulator. A [`Scenario`] lists
+//! waypoints to drive to, at set speeds, and stops to wait at; a
+//! [`Script`] turns it into a [`Control`] per step, which
+//! `VehicleState::update_controlled` follows with the usual process noise.
+//! Structured tracks such as figure-eights and stops exercise the filter
+//! where a random walk rarely goes.
+//!
+//! Scenario files have one directive per line, with `;` starting a
+//! comment:
+//!
+//! ```text
+//! ; a square, then a rest, round and round
+//! start -5 -5
+//! speed 1.5
+//! waypoint 5 -5
+//! waypoint 5 5
+//! waypoint -5 5
+//! waypoint -5 -5
+//! stop 2
+//! loop
+//! ```
+//!
+//! `speed` applies to the waypoints after it, `turn` sets the largest turn
+//! rate in radians per second and `radius` how close reaches a waypoint.
+
+use crate::{
+    fp::atan2,
+    sim::angle_delta,
+    types::{ACoord, CCoord, Control, VehicleState},
+};
+use std::{fs, io, path::Path};
+
+/// One part of a scenario
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Leg {
+    /// Drive to `to` at `speed`
+    Waypoint { to: CCoord, speed: f64 },
+    /// Hold still for `secs` seconds
+    Stop { secs: f64 },
+}
+
+/// A scripted track for the simulated vehicle
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scenario {
+    pub start: CCoord,
+    pub legs: Vec<Leg>,
+    /// Drive the legs again from the first once the last is done
+    pub looped: bool,
+    /// Largest commanded turn rate, in radians per second
+    pub max_turn: f64,
+    /// Distance within which a waypoint counts as reached
+    pub radius: f64,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            start: CCoord::default(),
+            legs: Vec::new(),
+            looped: false,
+            max_turn: 2.0,
+            radius: 1.0,
+        }
+    }
+}
+
+impl Scenario {
+    /// Parse a scenario file, as described in the module docs.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut scenario = Self::default();
+        let mut speed = 1.0;
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(directive) = words.next() else {
+                continue;
+            };
+            let args = words
+                .map(|w| w.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| invalid(format!("scenario line {}: {}", lineno + 1, e)))?;
+            let want = |n: usize| {
+                if args.len() == n {
+                    Ok(())
+                } else {
+                    Err(invalid(format!(
+                        "scenario line {}: {} takes {} numbers",
+                        lineno + 1,
+                        directive,
+                        n
+                    )))
+                }
+            };
+            match directive {
+                "start" => {
+                    want(2)?;
+                    scenario.start = CCoord {
+                        x: args[0],
+                        y: args[1],
+                    };
+                }
+                "waypoint" => {
+                    want(2)?;
+                    scenario.legs.push(Leg::Waypoint {
+                        to: CCoord {
+                            x: args[0],
+                            y: args[1],
+                        },
+                        speed,
+                    });
+                }
+                "speed" => {
+                    want(1)?;
+                    speed = args[0];
+                }
+                "stop" => {
+                    want(1)?;
+                    scenario.legs.push(Leg::Stop { secs: args[0] });
+                }
+                "turn" => {
+                    want(1)?;
+                    scenario.max_turn = args[0];
+                }
+                "radius" => {
+                    want(1)?;
+                    scenario.radius = args[0];
+                }
+                "loop" => {
+                    want(0)?;
+                    scenario.looped = true;
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "scenario line {}: unknown directive {}",
+                        lineno + 1,
+                        directive
+                    )));
+                }
+            }
+        }
+        Ok(scenario)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+/// Drives a vehicle through a [`Scenario`], one command per step
+#[derive(Clone, Debug)]
+pub struct Script {
+    scenario: Scenario,
+    leg: usize,
+    /// Time spent so far in the current stop
+    stopped: f64,
+}
+
+impl Script {
+    pub fn new(scenario: Scenario) -> Self {
+        Self {
+            scenario,
+            leg: 0,
+            stopped: 0.0,
+        }
+    }
+
+    /// The vehicle at the start, at rest and facing the first waypoint
+    pub fn initial_state(&self) -> VehicleState {
+        let start = self.scenario.start;
+        let t = self
+            .scenario
+            .legs
+            .iter()
+            .find_map(|leg| match leg {
+                Leg::Waypoint { to, .. } => Some(heading(start, *to)),
+                Leg::Stop { .. } => None,
+            })
+            .unwrap_or(0.0);
+        VehicleState::new(start, ACoord { r: 0.0, t })
+    }
+
+    /// Whether every leg is done, so the vehicle only holds still
+    pub fn finished(&self) -> bool {
+        self.leg >= self.scenario.legs.len()
+    }
+
+    /// Command for a step of `dt` from `state`: head for the current
+    /// waypoint, turning no faster than `max_turn`, or hold still
+    pub fn control(&mut self, state: &VehicleState, dt: f64) -> Control {
+        let still = Control {
+            speed: 0.0,
+            turn_rate: 0.0,
+        };
+        // Each leg at most once, so waypoints all within reach of each
+        // other cannot loop forever.
+        for _ in 0..=self.scenario.legs.len() {
+            if self.finished() {
+                if !self.scenario.looped {
+                    return still;
+                }
+                self.leg = 0;
+            }
+            match self.scenario.legs.get(self.leg) {
+                Some(&Leg::Waypoint { to, speed }) => {
+                    let (dx, dy) = (to.x - state.posn.x, to.y - state.posn.y);
+                    if dx.hypot(dy) > self.scenario.radius {
+                        let error = angle_delta(heading(state.posn, to), state.vel().t);
+                        let max = self.scenario.max_turn;
+                        return Control {
+                            speed,
+                            turn_rate: (error / dt).clamp(-max, max),
+                        };
+                    }
+                }
+                Some(&Leg::Stop { secs }) => {
+                    if self.stopped < secs {
+                        self.stopped += dt;
+                        return still;
+                    }
+                    self.stopped = 0.0;
+                }
+                None => return still,
+            }
+            self.leg += 1;
+        }
+        still
+    }
+}
+
+/// Heading from `from` to `to`, in the motion model's convention of `y`
+/// decreasing at a quarter turn
+fn heading(from: CCoord, to: CCoord) -> f64 {
+    crate::sim::normalize_angle(atan2(from.y - to.y, to.x - from.x))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimParams;
+
+    const SQUARE: &str = "; a square, then a rest
+start -5 -5
+speed 1.5
+waypoint 5 -5
+waypoint 5 5 ; top right
+waypoint -5 5
+waypoint -5 -5
+stop 2
+";
+
+    #[test]
+    fn parse_scenarios() {
+        let scenario = Scenario::parse(SQUARE).unwrap();
+        assert_eq!(scenario.start, CCoord { x: -5.0, y: -5.0 });
+        assert_eq!(scenario.legs.len(), 5);
+        assert_eq!(
+            scenario.legs[1],
+            Leg::Waypoint {
+                to: CCoord { x: 5.0, y: 5.0 },
+                speed: 1.5
+            }
+        );
+        assert_eq!(scenario.legs[4], Leg::Stop { secs: 2.0 });
+        assert!(!scenario.looped);
+        assert!(Scenario::parse("waypoint 1\n").is_err());
+        assert!(Scenario::parse("waypoint 1 x\n").is_err());
+        assert!(Scenario::parse("circle 1 2\n").is_err());
+    }
+
+    #[test]
+    fn follows_the_waypoints() {
+        crate::seed_thread_rng(4713);
+        let params = SimParams::default();
+        let scenario = Scenario::parse(SQUARE).unwrap();
+        let corners: Vec<CCoord> = scenario
+            .legs
+            .iter()
+            .filter_map(|leg| match leg {
+                Leg::Waypoint { to, .. } => Some(*to),
+                Leg::Stop { .. } => None,
+            })
+            .collect();
+        let mut script = Script::new(scenario);
+        let mut vehicle = script.initial_state();
+        let dt = 0.01;
+        let mut reached = 0;
+        let mut steps = 0;
+        while !script.finished() {
+            let control = script.control(&vehicle, dt);
+            vehicle.update_controlled(&params, control, dt);
+            let next = corners.get(reached).copied().unwrap_or(corners[3]);
+            if (vehicle.posn.x - next.x).hypot(vehicle.posn.y - next.y) <= 1.0 {
+                reached = (reached + 1).min(corners.len());
+            }
+            steps += 1;
+            assert!(steps < 10000, "stuck after {} corners", reached);
+        }
+        assert_eq!(reached, corners.len());
+        // Forty metres of sides at 1.5 m/s, then two seconds of rest
+        let secs = steps as f64 * dt;
+        assert!((27.0..36.0).contains(&secs), "{}", secs);
+        assert_eq!(script.control(&vehicle, dt).speed, 0.0);
+    }
+}
@@ -1,50 +1,590 @@
-use std::f64::consts::PI;
+use crate::{
+    fp::{cos, sin},
+    map::{ObstaclePolicy, OccupancyMap},
+};
+use std::{f64::consts::PI, str::FromStr, sync::Arc};
 
+// Defaults for `SimParams`, matching the C implementation.
 pub static BOX_DIM: f64 = 20.0;
 pub static MAX_SPEED: f64 = 2.0;
 
 pub static AVAR: f64 = PI / 32f64;
 pub static RVAR: f64 = 0.1f64;
-pub static mut GPS_VAR: f64 = 1.0f64;
+pub static GPS_VAR: f64 = 1.0f64;
 pub static IMU_R_VAR: f64 = 0.5f64;
 pub static IMU_A_VAR: f64 = PI / 8.0f64;
-pub static NDIRNS: i32 = 1024;
+pub static CONTROL_R_VAR: f64 = 0.05f64;
+pub static CONTROL_A_VAR: f64 = PI / 64f64;
+pub static NDIRNS: usize = 1024;
 
-pub static FAST_DIRECTION: i32 = 0;
+/// Cosine and sine of evenly spaced headings, used in place of exact trig
+/// when `SimParams::fast_direction` is set. The resolution trades heading
+/// accuracy against cache footprint.
+#[derive(Clone)]
+pub struct DirnTable {
+    cos: Vec<f64>,
+    sin: Vec<f64>,
+}
+
+impl DirnTable {
+    /// Tables of `ndirns` evenly spaced headings. Panics on zero, which
+    /// callers must reject first.
+    pub fn new(ndirns: usize) -> Self {
+        assert!(ndirns > 0, "direction table needs at least one entry");
+        let (cos, sin) = (0..ndirns)
+            .map(|i| {
+                let t = i as f64 * 2.0f64 * PI / ndirns as f64;
+                (cos(t), sin(t))
+            })
+            .unzip();
+        Self { cos, sin }
+    }
+
+    /// Number of headings in the table
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.cos.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.cos.is_empty()
+    }
+
+    #[inline]
+    pub fn cos(&self, d: usize) -> f64 {
+        self.cos[d]
+    }
+
+    #[inline]
+    pub fn sin(&self, d: usize) -> f64 {
+        self.sin[d]
+    }
+
+    /// Index of the table heading at or below the normalized angle `t`.
+    #[inline]
+    pub fn angle_dirn(&self, t: f64) -> usize {
+        self.normalize_dirn((t * self.len() as f64 / (2.0f64 * PI)).floor() as i64)
+    }
+
+    /// Index of the table heading nearest to the normalized angle `t`, so
+    /// the heading used is never more than `PI / len()` off.
+    #[inline]
+    pub fn nearest(&self, t: f64) -> usize {
+        self.normalize_dirn((t * self.len() as f64 / (2.0f64 * PI)).round() as i64)
+    }
+
+    #[inline]
+    pub fn normalize_dirn(&self, d: i64) -> usize {
+        d.rem_euclid(self.len() as i64) as usize
+    }
+}
+
+impl Default for DirnTable {
+    fn default() -> Self {
+        Self::new(NDIRNS)
+    }
+}
+
+/// Shape of the world the vehicle moves in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Topology {
+    /// Vehicles bounce off the walls of the box, as in the C implementation.
+    #[default]
+    Bounded,
+    /// Positions wrap around modulo the box, so nothing is ever out of bounds.
+    Toroidal,
+}
+
+/// What becomes of a drawn speed outside `[0, max_speed]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SpeedBounds {
+    /// Clip it to the nearer bound, as in the C implementation. Piles the
+    /// mass beyond a bound onto it.
+    #[default]
+    Clamp,
+    /// Fold it back across the bound it crossed.
+    Reflect,
+    /// Draw again until it lands inside, giving a truncated normal. After
+    /// `SPEED_REDRAWS` misses the last draw is clipped.
+    Redraw,
+}
+
+/// Draws `SpeedBounds::Redraw` makes before giving up
+pub const SPEED_REDRAWS: usize = 64;
+
+impl FromStr for SpeedBounds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(Self::Clamp),
+            "reflect" => Ok(Self::Reflect),
+            "redraw" => Ok(Self::Redraw),
+            _ => Err(format!("unknown speed bounds {}", s)),
+        }
+    }
+}
+
+/// How the GPS likelihood treats particles outside a bounded box.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Bounds {
+    /// Outside is impossible, as in the C implementation.
+    #[default]
+    Hard,
+    /// The likelihood decays by `e` every `scale` outside, so a particle
+    /// just over the wall, or a track from a larger box, is unlikely rather
+    /// than ruled out.
+    Soft { scale: f64 },
+}
+
+impl FromStr for Bounds {
+    type Err = String;
 
-#[derive(Clone, Copy)]
-pub struct CosDirn {
-    pub data: [f64; NDIRNS as usize],
+    /// Parse `hard` or `soft:SCALE`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "hard" => Ok(Self::Hard),
+            Some(("soft", scale)) => match scale.parse::<f64>() {
+                Ok(scale) if scale > 0.0 => Ok(Self::Soft { scale }),
+                Ok(scale) => Err(format!("soft bounds scale {} is not positive", scale)),
+                Err(e) => Err(format!("bad soft bounds scale {}: {}", scale, e)),
+            },
+            _ => Err(format!("unknown bounds {}", s)),
+        }
+    }
 }
 
-impl CosDirn {
-    pub fn init_dirn(&mut self) {
-        for i in 0..NDIRNS {
-            let t = i as f64 * 2.0f64 * PI / NDIRNS as f64;
-            self.data[i as usize] = t.cos();
+/// Measurement model the filter uses to weight particles against a GPS fix.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GpsLikelihood {
+    /// Independent Gaussian error on each axis, as in the C implementation.
+    #[default]
+    Gaussian,
+    /// Bivariate Student-t error with `nu > 0` degrees of freedom, for
+    /// heavy-tailed receivers.
+    StudentT { nu: f64 },
+    /// Gaussian error, except that a fraction `outlier`, in `[0, 1)`, of
+    /// fixes are junk drawn uniformly from the box.
+    Mixture { outlier: f64 },
+}
+
+impl FromStr for GpsLikelihood {
+    type Err = String;
+
+    /// Parse `gaussian`, `student-t:NU` or `mixture:OUTLIER`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let arg = |what: &str| {
+            arg.ok_or_else(|| format!("{} likelihood needs :{}", name, what))?
+                .parse::<f64>()
+                .map_err(|e| format!("bad {} for {} likelihood: {}", what, name, e))
+        };
+        match name {
+            "gaussian" => Ok(Self::Gaussian),
+            "student-t" => match arg("nu")? {
+                nu if nu > 0.0 && nu.is_finite() => Ok(Self::StudentT { nu }),
+                nu => Err(format!(
+                    "student-t degrees of freedom {} are not positive",
+                    nu
+                )),
+            },
+            "mixture" => match arg("outlier")? {
+                outlier if (0.0..1.0).contains(&outlier) => Ok(Self::Mixture { outlier }),
+                outlier => Err(format!("outlier fraction {} is not within [0, 1)", outlier)),
+            },
+            _ => Err(format!("unknown GPS likelihood {}", s)),
         }
     }
 }
 
-impl Default for CosDirn {
+/// GPS error with its own deviation on each axis and correlated between
+/// them, for receivers whose error ellipse is not a circle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsEllipse {
+    pub sd_x: f64,
+    pub sd_y: f64,
+    /// Correlation of the x and y errors, strictly between -1 and 1
+    pub rho: f64,
+}
+
+impl GpsEllipse {
+    pub fn covariance(&self) -> [[f64; 2]; 2] {
+        let c = self.rho * self.sd_x * self.sd_y;
+        [[self.sd_x * self.sd_x, c], [c, self.sd_y * self.sd_y]]
+    }
+
+    /// Square root of the covariance's determinant, which normalizes the
+    /// density
+    pub fn det_sqrt(&self) -> f64 {
+        self.sd_x * self.sd_y * (1.0 - self.rho * self.rho).sqrt()
+    }
+
+    /// Independent standard normal coordinates of the error `(dx, dy)`,
+    /// by the Cholesky factor of the covariance
+    #[inline]
+    pub fn whiten(&self, dx: f64, dy: f64) -> (f64, f64) {
+        let u = dx / self.sd_x;
+        (
+            u,
+            (dy / self.sd_y - self.rho * u) / (1.0 - self.rho * self.rho).sqrt(),
+        )
+    }
+
+    /// The error `whiten` takes to `(u, v)`, for simulating fixes
+    pub fn color(&self, u: f64, v: f64) -> (f64, f64) {
+        let v = self.rho * u + (1.0 - self.rho * self.rho).sqrt() * v;
+        (self.sd_x * u, self.sd_y * v)
+    }
+}
+
+impl FromStr for GpsEllipse {
+    type Err = String;
+
+    /// Parse `SD_X,SD_Y` or `SD_X,SD_Y,RHO`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s
+            .split(',')
+            .map(|f| f.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("bad GPS ellipse {}: {}", s, e))?;
+        let (sd_x, sd_y, rho) = match fields[..] {
+            [sd_x, sd_y] => (sd_x, sd_y, 0.0),
+            [sd_x, sd_y, rho] => (sd_x, sd_y, rho),
+            _ => return Err(format!("GPS ellipse {} is not SD_X,SD_Y[,RHO]", s)),
+        };
+        let positive = sd_x > 0.0 && sd_y > 0.0;
+        if !positive {
+            return Err(format!("GPS ellipse {} needs positive deviations", s));
+        }
+        let within = rho.abs() < 1.0;
+        if !within {
+            return Err(format!(
+                "GPS ellipse correlation {} is not within (-1, 1)",
+                rho
+            ));
+        }
+        Ok(Self { sd_x, sd_y, rho })
+    }
+}
+
+/// What the IMU measures. A degraded IMU reads NaN for what it leaves
+/// out, and the filter's likelihood drops that factor, so a full track can
+/// be filtered as if it were degraded too.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ImuMode {
+    /// Speed and heading, as in the C implementation
+    #[default]
+    Full,
+    HeadingOnly,
+    SpeedOnly,
+}
+
+impl ImuMode {
+    #[inline]
+    pub fn has_speed(self) -> bool {
+        self != Self::HeadingOnly
+    }
+
+    #[inline]
+    pub fn has_heading(self) -> bool {
+        self != Self::SpeedOnly
+    }
+}
+
+impl FromStr for ImuMode {
+    type Err = String;
+
+    /// Parse `full`, `heading` or `speed`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Self::Full),
+            "heading" => Ok(Self::HeadingOnly),
+            "speed" => Ok(Self::SpeedOnly),
+            _ => Err(format!("unknown IMU mode {}", s)),
+        }
+    }
+}
+
+/// How process noise is discretized over a time step.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NoiseScaling {
+    /// `rvar` and `avar` are added once per step whatever its length, as in
+    /// the C implementation.
+    #[default]
+    PerStep,
+    /// `rvar` and `avar` are the deviations over a step of `nominal_dt > 0`
+    /// seconds and grow with `sqrt(dt / nominal_dt)`, as for a random walk.
+    SqrtDt { nominal_dt: f64 },
+}
+
+impl FromStr for NoiseScaling {
+    type Err = String;
+
+    /// Parse `per-step` or `sqrt-dt:NOMINAL_DT`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "per-step" => Ok(Self::PerStep),
+            Some(("sqrt-dt", dt)) => match dt.parse::<f64>() {
+                Ok(nominal_dt) if nominal_dt > 0.0 && nominal_dt.is_finite() => {
+                    Ok(Self::SqrtDt { nominal_dt })
+                }
+                Ok(nominal_dt) => Err(format!("nominal dt {} is not positive", nominal_dt)),
+                Err(e) => Err(format!("bad nominal dt {}: {}", dt, e)),
+            },
+            _ => Err(format!("unknown noise scaling {}", s)),
+        }
+    }
+}
+
+/// How positions are integrated from the velocity over a time step.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Integration {
+    /// Move with the new velocity for the whole step, as in the C
+    /// implementation.
+    #[default]
+    Euler,
+    /// Move with the average of the old and new velocity (RK2 midpoint).
+    Midpoint,
+}
+
+impl FromStr for Integration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "euler" => Ok(Self::Euler),
+            "midpoint" => Ok(Self::Midpoint),
+            _ => Err(format!("unknown integration scheme {}", s)),
+        }
+    }
+}
+
+/// Runtime simulation parameters shared by the vehicle model and the filter.
+///
+/// This is the single source of truth for the model constants; the statics
+/// above only provide the defaults.
+#[derive(Clone)]
+pub struct SimParams {
+    /// Half-width of the square arena centered on the origin
+    pub box_dim: f64,
+    pub topology: Topology,
+    /// Optional obstacles inside the box
+    pub map: Option<Arc<OccupancyMap>>,
+    pub obstacle_policy: ObstaclePolicy,
+    pub max_speed: f64,
+    pub speed_bounds: SpeedBounds,
+    /// Heading process noise
+    pub avar: f64,
+    /// Speed process noise
+    pub rvar: f64,
+    /// GPS position measurement noise
+    pub gps_var: f64,
+    /// Anisotropic GPS noise used in place of `gps_var` when set
+    pub gps_ellipse: Option<GpsEllipse>,
+    pub gps_likelihood: GpsLikelihood,
+    pub bounds: Bounds,
+    /// IMU speed measurement noise
+    pub imu_r_var: f64,
+    /// IMU heading measurement noise
+    pub imu_a_var: f64,
+    pub imu_mode: ImuMode,
+    /// Speed noise about a commanded speed
+    pub control_r_var: f64,
+    /// Heading noise about a commanded turn
+    pub control_a_var: f64,
+    pub noise_scaling: NoiseScaling,
+    pub integration: Integration,
+    /// Move along the nearest `DirnTable` heading instead of exact trig.
+    pub fast_direction: bool,
+    pub dirn: DirnTable,
+}
+
+impl Default for SimParams {
     fn default() -> Self {
         Self {
-            data: [0.0f64; NDIRNS as usize],
+            box_dim: BOX_DIM,
+            topology: Topology::Bounded,
+            map: None,
+            obstacle_policy: ObstaclePolicy::Bounce,
+            max_speed: MAX_SPEED,
+            speed_bounds: SpeedBounds::Clamp,
+            avar: AVAR,
+            rvar: RVAR,
+            gps_var: GPS_VAR,
+            gps_ellipse: None,
+            gps_likelihood: GpsLikelihood::Gaussian,
+            bounds: Bounds::Hard,
+            imu_r_var: IMU_R_VAR,
+            imu_a_var: IMU_A_VAR,
+            imu_mode: ImuMode::Full,
+            control_r_var: CONTROL_R_VAR,
+            control_a_var: CONTROL_A_VAR,
+            noise_scaling: NoiseScaling::PerStep,
+            integration: Integration::Euler,
+            fast_direction: false,
+            dirn: DirnTable::default(),
         }
     }
 }
 
-#[inline]
-pub fn angle_dirn(t: f64) -> i32 {
-    (t * NDIRNS as f64 / (2.0f64 * PI)).floor() as i32 % NDIRNS
+impl SimParams {
+    #[inline]
+    pub fn in_box(&self, x: f64, y: f64) -> bool {
+        (-self.box_dim..=self.box_dim).contains(&x) && (-self.box_dim..=self.box_dim).contains(&y)
+    }
+
+    /// Covariance of the GPS error
+    pub fn gps_covariance(&self) -> [[f64; 2]; 2] {
+        match self.gps_ellipse {
+            Some(ellipse) => ellipse.covariance(),
+            None => {
+                let v = self.gps_var * self.gps_var;
+                [[v, 0.0], [0.0, v]]
+            }
+        }
+    }
+
+    /// Squared Mahalanobis length of the GPS error `(dx, dy)`
+    #[inline]
+    pub fn gps_distance_sq(&self, dx: f64, dy: f64) -> f64 {
+        match self.gps_ellipse {
+            Some(ellipse) => {
+                let (u, v) = ellipse.whiten(dx, dy);
+                u * u + v * v
+            }
+            None => (dx * dx + dy * dy) / (self.gps_var * self.gps_var),
+        }
+    }
+
+    /// Natural log of the factor `bounds` puts on the GPS likelihood at
+    /// `(x, y)`: zero inside the box or on a torus, `-inf` outside hard
+    /// bounds.
+    #[inline]
+    pub fn log_bounds_factor(&self, x: f64, y: f64) -> f64 {
+        if self.topology == Topology::Toroidal || self.in_box(x, y) {
+            return 0.0;
+        }
+        match self.bounds {
+            Bounds::Hard => f64::NEG_INFINITY,
+            Bounds::Soft { scale } => {
+                let outside = |v: f64| (v.abs() - self.box_dim).max(0.0);
+                -outside(x).hypot(outside(y)) / scale
+            }
+        }
+    }
+
+    /// Whether `(x, y)` lies inside an obstacle on the map, if any.
+    #[inline]
+    pub fn blocked(&self, x: f64, y: f64) -> bool {
+        self.map
+            .as_ref()
+            .is_some_and(|map| map.occupied(self.box_dim, x, y))
+    }
+
+    #[inline]
+    pub fn clip_box(&self, x: f64) -> f64 {
+        clip(x, -self.box_dim, self.box_dim)
+    }
+
+    /// Wrap a coordinate into `[-box_dim, box_dim)`.
+    #[inline]
+    pub fn wrap_box(&self, x: f64) -> f64 {
+        (x + self.box_dim).rem_euclid(2.0 * self.box_dim) - self.box_dim
+    }
+
+    /// Signed displacement from `b` to `a`, taking the short way around a
+    /// toroidal world.
+    #[inline]
+    pub fn delta(&self, a: f64, b: f64) -> f64 {
+        match self.topology {
+            Topology::Bounded => a - b,
+            Topology::Toroidal => self.wrap_box(a - b),
+        }
+    }
+
+    #[inline]
+    pub fn clip_speed(&self, x: f64) -> f64 {
+        clip(x, 0.0, self.max_speed)
+    }
+
+    /// Speed `mean + noise()`, brought inside `[0, max_speed]` as
+    /// `speed_bounds` says. `noise` is called again for each redraw.
+    #[inline]
+    pub fn draw_speed(&self, mean: f64, mut noise: impl FnMut() -> f64) -> f64 {
+        let r = mean + noise();
+        match self.speed_bounds {
+            SpeedBounds::Clamp => self.clip_speed(r),
+            SpeedBounds::Reflect if self.max_speed > 0.0 => {
+                let folded = r.rem_euclid(2.0 * self.max_speed);
+                if folded > self.max_speed {
+                    2.0 * self.max_speed - folded
+                } else {
+                    folded
+                }
+            }
+            SpeedBounds::Reflect => self.clip_speed(r),
+            SpeedBounds::Redraw => {
+                let mut r = r;
+                for _ in 0..SPEED_REDRAWS {
+                    if (0.0..=self.max_speed).contains(&r) {
+                        return r;
+                    }
+                    r = mean + noise();
+                }
+                self.clip_speed(r)
+            }
+        }
+    }
+
+    // Discretization: every place the model depends on dt goes through here.
+
+    /// Multiplier for the `rvar`/`avar` process noise over a step of `dt`.
+    #[inline]
+    pub fn process_noise_scale(&self, dt: f64) -> f64 {
+        match self.noise_scaling {
+            NoiseScaling::PerStep => 1.0,
+            NoiseScaling::SqrtDt { nominal_dt } => (dt / nominal_dt).sqrt(),
+        }
+    }
+
+    /// Speed and heading noise deviations of simulated IMU readings.
+    ///
+    /// Note that the C implementation simulates IMU noise proportional to
+    /// `dt` but has the filter assume it is inversely proportional to `dt`
+    /// (see `imu_likelihood_sd`); both are kept as-is for parity.
+    #[inline]
+    pub fn imu_noise_sd(&self, dt: f64) -> (f64, f64) {
+        (self.imu_r_var * dt, self.imu_a_var * dt)
+    }
+
+    /// Speed and heading deviations the filter assumes for IMU readings.
+    #[inline]
+    pub fn imu_likelihood_sd(&self, dt: f64) -> (f64, f64) {
+        (self.imu_r_var / dt, self.imu_a_var / dt)
+    }
+
+    /// Speed and heading to move with over a step whose velocity changes
+    /// from `(r0, t0)` to `(r1, t1)`.
+    #[inline]
+    pub fn step_velocity(&self, r0: f64, t0: f64, r1: f64, t1: f64) -> (f64, f64) {
+        match self.integration {
+            Integration::Euler => (r1, t1),
+            Integration::Midpoint => {
+                let dt = angle_delta(t1, t0);
+                (0.5 * (r0 + r1), normalize_angle(t0 + 0.5 * dt))
+            }
+        }
+    }
 }
 
+/// Signed heading change from `b` to `a`, in `[-pi, pi)`
 #[inline]
-pub fn normalize_dirn(mut d: i32) -> i32 {
-    while d < 0 {
-        d += NDIRNS;
-    }
-    d % NDIRNS
+pub fn angle_delta(a: f64, b: f64) -> f64 {
+    (a - b + PI).rem_euclid(2.0 * PI) - PI
 }
 
 #[inline]
@@ -63,12 +603,57 @@ pub fn clip(x: f64, low: f64, high: f64) -> f64 {
     x.clamp(low, high)
 }
 
-#[inline]
-pub fn clip_box(x: f64) -> f64 {
-    clip(x, -BOX_DIM, BOX_DIM)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-#[inline]
-pub fn clip_speed(x: f64) -> f64 {
-    clip(x, 0.0, MAX_SPEED)
+    #[test]
+    fn gps_likelihood_parses() {
+        assert_eq!("gaussian".parse(), Ok(GpsLikelihood::Gaussian));
+        assert_eq!(
+            "student-t:4".parse(),
+            Ok(GpsLikelihood::StudentT { nu: 4.0 })
+        );
+        assert_eq!(
+            "mixture:0".parse(),
+            Ok(GpsLikelihood::Mixture { outlier: 0.0 })
+        );
+        assert_eq!(
+            "mixture:0.05".parse(),
+            Ok(GpsLikelihood::Mixture { outlier: 0.05 })
+        );
+        for bad in [
+            "student-t",
+            "student-t:x",
+            "student-t:0",
+            "student-t:-1",
+            "student-t:inf",
+            "student-t:NaN",
+            "mixture:-0.1",
+            "mixture:1",
+            "mixture:2",
+            "mixture:NaN",
+            "cauchy",
+        ] {
+            assert!(bad.parse::<GpsLikelihood>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn noise_scaling_parses() {
+        assert_eq!("per-step".parse(), Ok(NoiseScaling::PerStep));
+        assert_eq!(
+            "sqrt-dt:0.1".parse(),
+            Ok(NoiseScaling::SqrtDt { nominal_dt: 0.1 })
+        );
+        for bad in [
+            "sqrt-dt:0",
+            "sqrt-dt:-1",
+            "sqrt-dt:inf",
+            "sqrt-dt:NaN",
+            "sqrt-dt:x",
+        ] {
+            assert!(bad.parse::<NoiseScaling>().is_err(), "{}", bad);
+        }
+    }
 }
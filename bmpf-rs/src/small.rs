@@ -0,0 +1,336 @@
+//! Filters of a few hundred particles at most, kept inline rather than on
+//! the heap. Embedded users run filters of tens of particles at kilohertz
+//! rates, where `BpfState`'s heap buffers and general machinery cost more
+//! than the particles do. A `SmallFilter` is the plain bootstrap filter:
+//! the motion model, GPS and IMU weights, and systematic resampling every
+//! step, as `BpfState` does with the `regular` resampler.
+
+use crate::{
+    estimate::{self, EstimateMode, WeightMoments},
+    fp::{atan2, cos, ln_1p, sin},
+    resample::ancestors,
+    sim::{SimParams, Topology},
+    types::{ACoord, BpfState, CCoord, Estimate, ParticleInfo},
+};
+use std::f64::consts::PI;
+
+/// Largest particle count `Filter::new` keeps inline
+pub const SMALL_MAX: usize = 256;
+
+/// Up to `N` particles in an array
+#[derive(Clone, Copy)]
+pub struct SmallParticles<const N: usize> {
+    data: [ParticleInfo; N],
+    len: usize,
+}
+
+impl<const N: usize> SmallParticles<N> {
+    /// `len` default particles
+    pub fn new(len: usize) -> Self {
+        assert!(len <= N, "{} particles do not fit in {}", len, N);
+        Self {
+            data: [ParticleInfo::default(); N],
+            len,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[ParticleInfo] {
+        &self.data[..self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [ParticleInfo] {
+        &mut self.data[..self.len]
+    }
+
+    /// Sum of `f` over the particles, in four lanes the compiler keeps in
+    /// registers and unrolls
+    #[inline]
+    fn sum(&self, f: impl Fn(&ParticleInfo) -> f64) -> f64 {
+        let chunks = self.as_slice().chunks_exact(4);
+        let rest: f64 = chunks.remainder().iter().map(&f).sum();
+        let mut lanes = [0.0; 4];
+        for chunk in chunks {
+            for (lane, p) in lanes.iter_mut().zip(chunk) {
+                *lane += f(p);
+            }
+        }
+        (lanes[0] + lanes[1]) + (lanes[2] + lanes[3]) + rest
+    }
+}
+
+/// Bootstrap filter over at most `N` inline particles
+#[derive(Clone)]
+pub struct SmallFilter<const N: usize> {
+    pub params: SimParams,
+    particles: SmallParticles<N>,
+    resampled: SmallParticles<N>,
+}
+
+impl<const N: usize> SmallFilter<N> {
+    pub fn new(nparticles: usize, params: SimParams) -> Self {
+        Self {
+            params,
+            particles: SmallParticles::new(nparticles),
+            resampled: SmallParticles::new(nparticles),
+        }
+    }
+
+    /// Spread the particles uniformly over the box, with equal weights,
+    /// returning how many were left inside obstacles.
+    pub fn init_particles(&mut self) -> usize {
+        let invscale = 1.0 / self.particles.len as f64;
+        let mut stuck = 0;
+        for p in self.particles.as_mut_slice() {
+            stuck += !p.state.init_state(&self.params) as usize;
+            p.weight = invscale;
+        }
+        stuck
+    }
+
+    pub fn particles(&self) -> &[ParticleInfo] {
+        self.particles.as_slice()
+    }
+
+    /// Weighted mean position, around the box on a torus
+    fn mean(&self) -> CCoord {
+        let p = &self.particles;
+        if self.params.topology != Topology::Toroidal {
+            return CCoord {
+                x: p.sum(|p| p.weight * p.state.posn.x),
+                y: p.sum(|p| p.weight * p.state.posn.y),
+            };
+        }
+        let k = PI / self.params.box_dim;
+        CCoord {
+            x: atan2(
+                p.sum(|p| p.weight * sin(k * p.state.posn.x)),
+                p.sum(|p| p.weight * cos(k * p.state.posn.x)),
+            ) / k,
+            y: atan2(
+                p.sum(|p| p.weight * sin(k * p.state.posn.y)),
+                p.sum(|p| p.weight * cos(k * p.state.posn.y)),
+            ) / k,
+        }
+    }
+
+    /// What a filter without particles knows, as `BpfState` reports it:
+    /// nothing, with the vehicle nowhere rather than at the origin
+    fn nowhere(&self) -> Estimate {
+        let nowhere = CCoord {
+            x: f64::NAN,
+            y: f64::NAN,
+        };
+        Estimate {
+            best: nowhere,
+            best_weight: 0.0,
+            worst: nowhere,
+            worst_weight: 0.0,
+            mean: nowhere,
+            map: nowhere,
+            velocity: Some(estimate::velocity(&[])),
+            speed_clipped: Some(f64::NAN),
+            mode: EstimateMode::WeightedMean,
+            interval: None,
+            clamped: 0,
+            held: 0,
+            weight_variance: Some(f64::NAN),
+            log_dimension: Some(f64::NAN),
+        }
+    }
+
+    /// Copy particles at evenly spaced points of the cumulative weight,
+    /// which sums to one, as the `regular` resampler does.
+    fn resample(&mut self) {
+        let (from, to) = (self.particles.as_slice(), self.resampled.as_mut_slice());
+        let step = 1.0 / (from.len() + 1) as f64;
+        let invn = 1.0 / from.len() as f64;
+        let points = std::iter::successors(Some(step), |u| Some(u + step));
+        for (p, j) in to.iter_mut().zip(ancestors(from, points)) {
+            *p = from[j];
+            p.weight = invn;
+        }
+        std::mem::swap(&mut self.particles, &mut self.resampled);
+    }
+
+    /// Feed one GPS fix and IMU reading taken `dt` seconds after the last
+    /// and advance the filter.
+    pub fn observe(&mut self, dt: f64, gps: CCoord, imu: ACoord) -> Estimate {
+        if self.particles.len == 0 {
+            return self.nowhere();
+        }
+        let params = &self.params;
+        let mut held = 0;
+        let mut moments = WeightMoments::default();
+        for p in self.particles.as_mut_slice() {
+            held += p.state.update_state(params, dt, 1) as usize;
+            let prev = p.weight;
+            p.weight *= gps.gps_prob(params, &p.state) * imu.imu_prob(params, &p.state, dt);
+            moments.add(prev, p.weight);
+        }
+        let weight_variance = moments.relative_variance();
+        let invtotal = 1.0 / self.particles.sum(|p| p.weight);
+        for p in self.particles.as_mut_slice() {
+            p.weight *= invtotal;
+        }
+        let mean = self.mean();
+        let velocity = estimate::velocity(self.particles.as_slice());
+        let speed_clipped =
+            estimate::clipped_fraction(self.particles.as_slice(), self.params.max_speed);
+        self.resample();
+        let first = self.particles.as_slice()[0];
+        Estimate {
+            best: first.state.posn,
+            best_weight: first.weight,
+            worst: first.state.posn,
+            worst_weight: first.weight,
+            mean,
+            map: mean,
+            velocity: Some(velocity),
+            speed_clipped: Some(speed_clipped),
+            mode: EstimateMode::WeightedMean,
+            interval: None,
+            clamped: 0,
+            held,
+            weight_variance: Some(weight_variance),
+            // Resampled every step
+            log_dimension: Some(ln_1p(weight_variance)),
+        }
+    }
+}
+
+/// A bootstrap filter kept inline when it is small enough, and on the heap
+/// otherwise
+// Boxing the small filter would put back the allocation it exists to avoid.
+#[allow(clippy::large_enum_variant)]
+pub enum Filter {
+    Small(SmallFilter<SMALL_MAX>),
+    Heap(BpfState),
+}
+
+impl Filter {
+    /// `nparticles` particles resampled systematically every step, inline
+    /// for up to `SMALL_MAX` of them
+    pub fn new(nparticles: usize, params: SimParams) -> Self {
+        if nparticles <= SMALL_MAX {
+            return Self::Small(SmallFilter::new(nparticles, params));
+        }
+        let mut bpf = BpfState::new("regular", false, nparticles, 0, false, 1, params);
+        bpf.reporter = None;
+        Self::Heap(bpf)
+    }
+
+    /// Spread the particles uniformly over the box, returning how many
+    /// were left inside obstacles
+    pub fn init_particles(&mut self) -> usize {
+        match self {
+            Self::Small(small) => small.init_particles(),
+            Self::Heap(bpf) => bpf.init_particles(),
+        }
+    }
+
+    pub fn particles(&self) -> &[ParticleInfo] {
+        match self {
+            Self::Small(small) => small.particles(),
+            Self::Heap(bpf) => bpf.particles(),
+        }
+    }
+
+    /// Feed one GPS fix and IMU reading taken `dt` seconds after the last,
+    /// at time `t`, and advance the filter.
+    pub fn observe(&mut self, t: f64, dt: f64, gps: CCoord, imu: ACoord) -> Estimate {
+        match self {
+            Self::Small(small) => small.observe(dt, gps, imu),
+            Self::Heap(bpf) => bpf.observe(t, dt, gps, imu),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        resample::{Ordering, Resample, Resampler, Weights},
+        types::{Particles, VehicleState},
+    };
+
+    #[test]
+    fn resamples_as_regular_does() {
+        crate::seed_thread_rng(4681);
+        let mut small = SmallFilter::<64>::new(50, SimParams::default());
+        small.init_particles();
+        for (i, p) in small.particles.as_mut_slice().iter_mut().enumerate() {
+            p.weight = ((i * 7919) % 13) as f64;
+        }
+        let total = small.particles.sum(|p| p.weight);
+        small
+            .particles
+            .as_mut_slice()
+            .iter_mut()
+            .for_each(|p| p.weight /= total);
+        let mut from = Particles {
+            data: small.particles().to_vec(),
+        };
+        let mut to = from.clone();
+        let mut regular = Resampler::new("regular", 50);
+        regular.set_output_weights(crate::resample::OutputWeights::Uniform);
+        regular.resample(
+            Weights::NORMALIZED,
+            50,
+            &mut from,
+            50,
+            &mut to,
+            Ordering::None,
+        );
+        small.resample();
+        for (a, b) in small.particles().iter().zip(&to.data) {
+            assert_eq!(a.state.posn, b.state.posn);
+            assert_eq!(a.weight, b.weight);
+        }
+    }
+
+    #[test]
+    fn small_filters_track() {
+        crate::seed_thread_rng(4681);
+        let params = SimParams::default();
+        let dt = 0.01;
+        for nparticles in [200, 1000] {
+            let mut filter = Filter::new(nparticles, params.clone());
+            assert_eq!(matches!(filter, Filter::Small(_)), nparticles <= SMALL_MAX);
+            filter.init_particles();
+            let mut vehicle = VehicleState::default();
+            vehicle.init_state(&params);
+            let mut sum_sq = 0.0;
+            for i in 1..=500 {
+                vehicle.update_state(&params, dt, 0);
+                let gps = vehicle.gps_measure(&params);
+                let imu = vehicle.imu_measure(&params, dt);
+                let est = filter.observe(i as f64 * dt, dt, gps, imu);
+                if i > 100 {
+                    let (dx, dy) = (est.mean.x - vehicle.posn.x, est.mean.y - vehicle.posn.y);
+                    sum_sq += dx * dx + dy * dy;
+                }
+            }
+            let rms = (sum_sq / 400.0).sqrt();
+            // Raw fixes are about 1.4 off.
+            assert!(rms < 1.4, "{} particles: rms {}", nparticles, rms);
+            assert_eq!(filter.particles().len(), nparticles);
+        }
+    }
+
+    #[test]
+    fn empty_filters_know_nothing() {
+        let params = SimParams::default();
+        let mut bpf = BpfState::new("regular", false, 0, 0, false, 1, params.clone());
+        bpf.reporter = None;
+        let mut filters = [Filter::new(0, params), Filter::Heap(bpf)];
+        assert!(matches!(filters[0], Filter::Small(_)));
+        let [small, heap] = filters.each_mut().map(|filter| {
+            assert_eq!(filter.init_particles(), 0);
+            let est = filter.observe(0.1, 0.1, CCoord::default(), ACoord::default());
+            assert!(est.mean.x.is_nan() && est.map.x.is_nan());
+            // NaN never equals itself, so compare them as printed.
+            format!("{:?}", est)
+        });
+        assert_eq!(small, heap);
+    }
+}
@@ -0,0 +1,409 @@
+//! Forward-filter backward-simulation (FFBSi) smoothing, and two-filter
+//! smoothing.
+//!
+//! The forward pass is the ordinary filter: push each step's particle
+//! cloud into a [`Smoother`]. Afterwards [`Smoother::sample`] draws whole
+//! trajectories backwards through the stored clouds, reweighting each
+//! cloud by how likely it is to lead to the state drawn after it.
+//!
+//! [`two_filter`] instead runs a second filter backwards in time over the
+//! same measurements and merges the two passes step by step.
+
+use crate::{
+    fp::{cos, exp, ln, sin},
+    io::Reading,
+    progress::Monitor,
+    sim::SimParams,
+    types::{ACoord, BpfState, CCoord, ParticleInfo, VehicleState, log_normal_pdf},
+    uniform,
+};
+use std::io;
+
+/// Where a [`Smoother`] keeps the clouds from the forward pass
+pub trait CloudStore {
+    fn push(&mut self, particles: &[ParticleInfo]) -> io::Result<()>;
+
+    /// Number of clouds pushed
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// First step still stored, when a store drops old clouds
+    fn first(&self) -> usize {
+        0
+    }
+
+    /// Replace the contents of `out` with cloud `step`.
+    fn read(&self, step: usize, out: &mut Vec<ParticleInfo>) -> io::Result<()>;
+}
+
+/// Keeps every cloud in memory
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    clouds: Vec<Vec<ParticleInfo>>,
+}
+
+impl CloudStore for MemoryStore {
+    fn push(&mut self, particles: &[ParticleInfo]) -> io::Result<()> {
+        self.clouds.push(particles.to_vec());
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.clouds.len()
+    }
+
+    fn read(&self, step: usize, out: &mut Vec<ParticleInfo>) -> io::Result<()> {
+        let cloud = self.clouds.get(step).ok_or_else(|| missing(step))?;
+        out.clear();
+        out.extend_from_slice(cloud);
+        Ok(())
+    }
+}
+
+pub(crate) fn missing(step: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no cloud for step {}", step),
+    )
+}
+
+fn empty(step: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("cloud for step {} is empty", step),
+    )
+}
+
+#[cfg(feature = "mmap")]
+pub use crate::storage::{MappedStore, RingStore};
+
+/// Stored forward pass plus the backward kernel used to smooth it
+pub struct Smoother {
+    params: SimParams,
+    position_sd: f64,
+    store: Box<dyn CloudStore>,
+    /// Length of the step that led to each cloud
+    dts: Vec<f64>,
+}
+
+impl Smoother {
+    /// Smooth in memory. The motion model moves positions
+    /// deterministically, so the backward kernel pairs its velocity prior
+    /// with a Gaussian of deviation `position_sd` around the predicted
+    /// position. It ignores bounces.
+    pub fn new(params: SimParams, position_sd: f64) -> Self {
+        Self::with_store(params, position_sd, Box::new(MemoryStore::default()))
+    }
+
+    pub fn with_store(params: SimParams, position_sd: f64, store: Box<dyn CloudStore>) -> Self {
+        Self {
+            params,
+            position_sd,
+            store,
+            dts: Vec::new(),
+        }
+    }
+
+    /// Record the cloud after a filter step of length `dt`.
+    pub fn push(&mut self, dt: f64, particles: &[ParticleInfo]) -> io::Result<()> {
+        self.store.push(particles)?;
+        self.dts.push(dt);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.dts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dts.is_empty()
+    }
+
+    /// Log density of the filter moving `from` to `to` over `dt`.
+    fn log_transition(&self, from: &VehicleState, to: &VehicleState, dt: f64) -> f64 {
+        let (vel, p) = (to.vel(), &self.params);
+        let (r, t) = p.step_velocity(from.vel().r, from.vel().t, vel.r, vel.t);
+        let x = from.posn.x + r * cos(t) * dt;
+        let y = from.posn.y - r * sin(t) * dt;
+        from.log_velocity_prior(p, vel.r, vel.t, dt, 1)
+            + log_normal_pdf(p.delta(to.posn.x, x), self.position_sd)
+            + log_normal_pdf(p.delta(to.posn.y, y), self.position_sd)
+    }
+
+    /// First step a trajectory reaches back to: zero, unless the store
+    /// keeps only a window of the latest steps, which makes this a
+    /// fixed-lag smoother
+    pub fn first(&self) -> usize {
+        self.store.first()
+    }
+
+    /// Draw one smoothed trajectory, one state per stored step from
+    /// `first` on.
+    pub fn sample(&self) -> io::Result<Vec<VehicleState>> {
+        self.sample_with(&mut Monitor::default())
+    }
+
+    /// `sample`, counting stored steps to `monitor`. Once cancelled it
+    /// fails with `ErrorKind::Interrupted`, as half a trajectory is no use.
+    pub fn sample_with(&self, monitor: &mut Monitor) -> io::Result<Vec<VehicleState>> {
+        let (first, steps) = (self.first(), self.len());
+        let mut path = Vec::with_capacity(steps - first);
+        if steps == first {
+            return Ok(path);
+        }
+        let mut cloud = Vec::new();
+        let mut logw = Vec::new();
+        self.store.read(steps - 1, &mut cloud)?;
+        if cloud.is_empty() {
+            return Err(empty(steps - 1));
+        }
+        logw.extend(cloud.iter().map(|p| ln(p.weight)));
+        let mut next = cloud[categorical(&mut logw)].state;
+        path.push(next);
+        for step in (first..steps - 1).rev() {
+            self.store.read(step, &mut cloud)?;
+            if cloud.is_empty() {
+                return Err(empty(step));
+            }
+            let dt = self.dts[step + 1];
+            logw.clear();
+            logw.extend(
+                cloud
+                    .iter()
+                    .map(|p| ln(p.weight) + self.log_transition(&p.state, &next, dt)),
+            );
+            next = cloud[categorical(&mut logw)].state;
+            path.push(next);
+            if !monitor.step(path.len(), Some(steps - first)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    format!("cancelled back at step {}", step),
+                ));
+            }
+        }
+        path.reverse();
+        Ok(path)
+    }
+
+    /// Mean position at each step from `first` on over `n` sampled
+    /// trajectories
+    pub fn mean_path(&self, n: usize) -> io::Result<Vec<CCoord>> {
+        self.mean_path_with(n, &mut Monitor::default())
+    }
+
+    /// `mean_path`, counting trajectories to `monitor`. Once cancelled it
+    /// returns the mean of the trajectories drawn so far.
+    pub fn mean_path_with(&self, n: usize, monitor: &mut Monitor) -> io::Result<Vec<CCoord>> {
+        let mut sum = vec![CCoord::default(); self.len() - self.first()];
+        let mut drawn = 0;
+        while drawn < n {
+            for (m, s) in sum.iter_mut().zip(self.sample()?) {
+                m.x += s.posn.x;
+                m.y += s.posn.y;
+            }
+            drawn += 1;
+            if !monitor.step(drawn, Some(n)) {
+                break;
+            }
+        }
+        let scale = 1.0 / drawn.max(1) as f64;
+        Ok(sum
+            .into_iter()
+            .map(|m| CCoord {
+                x: m.x * scale,
+                y: m.y * scale,
+            })
+            .collect())
+    }
+}
+
+/// One step of a recorded run: a GPS fix and IMU reading taken together
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Observation {
+    pub t: f64,
+    pub gps: CCoord,
+    pub imu: ACoord,
+}
+
+/// Two-filter smoothing over a recorded run. `forward` filters `steps` in
+/// order, and `backward`, set to run with time reversed, in reverse. The
+/// smoothed distribution at each step is the forward filter's times the
+/// backward filter's prediction from the steps after it, divided by the
+/// prior, which is flat over the box; so each forward cloud is reweighted
+/// by the backward prediction's density, estimated with a Gaussian kernel
+/// of deviation `bandwidth` over position. Both filters start from
+/// whatever particles they hold, and the first step is taken to be as
+/// long as the second. Returns the smoothed mean position at each step.
+pub fn two_filter(
+    forward: &mut BpfState,
+    backward: &mut BpfState,
+    steps: &[Observation],
+    bandwidth: f64,
+) -> Vec<CCoord> {
+    let dt = |k: usize| match k {
+        0 => steps.get(1).map_or(0.0, |s| s.t - steps[0].t),
+        _ => steps[k].t - steps[k - 1].t,
+    };
+    let mut filtered = Vec::with_capacity(steps.len());
+    for (k, s) in steps.iter().enumerate() {
+        forward.observe(s.t, dt(k), s.gps, s.imu);
+        filtered.push(forward.particles().to_vec());
+    }
+    backward.time_reversed = true;
+    let mut smoothed = vec![CCoord::default(); steps.len()];
+    let inv2h2 = 0.5 / (bandwidth * bandwidth);
+    for (k, s) in steps.iter().enumerate().rev() {
+        let back = dt((k + 1).min(steps.len() - 1));
+        backward.observe_reading(s.t, back, Reading::Imu(s.imu));
+        let predicted = backward.particles();
+        let density = |p: &ParticleInfo| -> f64 {
+            predicted
+                .iter()
+                .map(|q| {
+                    let (dx, dy) = (
+                        p.state.posn.x - q.state.posn.x,
+                        p.state.posn.y - q.state.posn.y,
+                    );
+                    q.weight * exp(-(dx * dx + dy * dy) * inv2h2)
+                })
+                .sum()
+        };
+        let mut mean = weighted_mean(&filtered[k], |p| p.weight * density(p));
+        // Passes that never meet leave only the forward filter.
+        if mean.x.is_nan() {
+            mean = weighted_mean(&filtered[k], |p| p.weight);
+        }
+        smoothed[k] = mean;
+        backward.observe_reading(s.t, 0.0, Reading::Gps(s.gps));
+    }
+    smoothed
+}
+
+/// Mean position of `particles` weighted by `weight`, NaN when the
+/// weights sum to zero
+fn weighted_mean(particles: &[ParticleInfo], weight: impl Fn(&ParticleInfo) -> f64) -> CCoord {
+    let (mut total, mut x, mut y) = (0.0, 0.0, 0.0);
+    for p in particles {
+        let w = weight(p);
+        total += w;
+        x += w * p.state.posn.x;
+        y += w * p.state.posn.y;
+    }
+    CCoord {
+        x: x / total,
+        y: y / total,
+    }
+}
+
+/// Draw an index with probability proportional to `exp(logw)`, which is
+/// overwritten. Falls back to uniform when every weight is zero.
+fn categorical(logw: &mut [f64]) -> usize {
+    let max = logw.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if !max.is_finite() {
+        return ((uniform() * logw.len() as f64) as usize).min(logw.len() - 1);
+    }
+    let mut total = 0.0;
+    for w in logw.iter_mut() {
+        *w = exp(*w - max);
+        total += *w;
+    }
+    let mut u = uniform() * total;
+    for (i, &w) in logw.iter().enumerate() {
+        if u < w {
+            return i;
+        }
+        u -= w;
+    }
+    logw.len() - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BpfState;
+
+    /// Filtered and smoothed RMS position error over a simulated run
+    fn errors(store: Box<dyn CloudStore>) -> (f64, f64) {
+        crate::seed_thread_rng(4653);
+        let params = SimParams::default();
+        let mut vehicle = VehicleState::default();
+        vehicle.init_state(&params);
+        let mut bpf = BpfState::new("regular", false, 200, 0, false, 1, params.clone());
+        bpf.reporter = None;
+        bpf.init_particles();
+        let mut smoother = Smoother::with_store(params.clone(), 0.05, store);
+        let dt = 0.1;
+        let (mut truth, mut filtered) = (Vec::new(), Vec::new());
+        for i in 0..100 {
+            vehicle.update_state(&params, dt, 0);
+            let gps = vehicle.gps_measure(&params);
+            let imu = vehicle.imu_measure(&params, dt);
+            let est = bpf.observe(i as f64 * dt, dt, gps, imu);
+            smoother.push(dt, bpf.particles()).unwrap();
+            truth.push(vehicle.posn);
+            filtered.push(est.mean);
+        }
+        let smoothed = smoother.mean_path(20).unwrap();
+        let rms = |path: &[CCoord]| {
+            let sq: f64 = path
+                .iter()
+                .zip(&truth)
+                .skip(10)
+                .map(|(p, t)| (p.x - t.x).powi(2) + (p.y - t.y).powi(2))
+                .sum();
+            (sq / (path.len() - 10) as f64).sqrt()
+        };
+        (rms(&filtered), rms(&smoothed))
+    }
+
+    #[test]
+    fn two_filter_beats_filtering() {
+        crate::seed_thread_rng(4712);
+        let params = SimParams::default();
+        let mut vehicle = VehicleState::default();
+        vehicle.init_state(&params);
+        let dt = 0.1;
+        let (mut steps, mut truth) = (Vec::new(), Vec::new());
+        for i in 0..100 {
+            vehicle.update_state(&params, dt, 0);
+            steps.push(Observation {
+                t: i as f64 * dt,
+                gps: vehicle.gps_measure(&params),
+                imu: vehicle.imu_measure(&params, dt),
+            });
+            truth.push(vehicle.posn);
+        }
+        let filter = || {
+            let mut bpf = BpfState::new("regular", false, 300, 0, false, 1, params.clone());
+            bpf.reporter = None;
+            bpf.init_particles();
+            bpf
+        };
+        let mut forward = filter();
+        let filtered: Vec<_> = steps
+            .iter()
+            .map(|s| forward.observe(s.t, dt, s.gps, s.imu).mean)
+            .collect();
+        let smoothed = two_filter(&mut filter(), &mut filter(), &steps, 0.3);
+        let rms = |path: &[CCoord]| {
+            let sq: f64 = path
+                .iter()
+                .zip(&truth)
+                .skip(10)
+                .map(|(p, t)| (p.x - t.x).powi(2) + (p.y - t.y).powi(2))
+                .sum();
+            (sq / (path.len() - 10) as f64).sqrt()
+        };
+        let (filtered, smoothed) = (rms(&filtered), rms(&smoothed));
+        assert!(smoothed < filtered, "{} vs {}", smoothed, filtered);
+    }
+
+    #[test]
+    fn smoothing_beats_filtering() {
+        let (filtered, smoothed) = errors(Box::new(MemoryStore::default()));
+        assert!(smoothed < filtered, "{} vs {}", smoothed, filtered);
+    }
+}
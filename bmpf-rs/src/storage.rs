@@ -0,0 +1,501 @@
+//! Particle clouds kept on disk and read back through memory maps, for
+//! smoothing runs whose history does not fit in memory. Each particle is
+//! five little-endian `f64`s: `x y r t weight`.
+
+use crate::{
+    smooth::{CloudStore, missing},
+    types::{ACoord, CCoord, ParticleInfo, VehicleState},
+};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+const RECORD: usize = 5 * size_of::<f64>();
+
+const INDEX_MAGIC: &str = "bmpf-ring 1";
+
+fn write_cloud(out: &mut impl Write, particles: &[ParticleInfo]) -> io::Result<()> {
+    for p in particles {
+        let vel = p.state.vel();
+        for v in [p.state.posn.x, p.state.posn.y, vel.r, vel.t, p.weight] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_cloud(bytes: &[u8], out: &mut Vec<ParticleInfo>) {
+    out.clear();
+    out.extend(bytes.chunks_exact(RECORD).map(|r| {
+        let f = |i: usize| f64::from_le_bytes(r[8 * i..8 * i + 8].try_into().unwrap());
+        let posn = CCoord { x: f(0), y: f(1) };
+        let vel = ACoord { r: f(2), t: f(3) };
+        ParticleInfo {
+            state: VehicleState::new(posn, vel),
+            weight: f(4),
+            #[cfg(feature = "rbpf")]
+            sub: Default::default(),
+        }
+    }));
+}
+
+/// Appended file of clouds, mapped once it is read
+struct MappedFile {
+    file: File,
+    /// Byte offset and particle count of each cloud
+    index: Vec<(usize, usize)>,
+    end: usize,
+    map: RefCell<Option<memmap2::Mmap>>,
+}
+
+impl MappedFile {
+    fn new(file: File) -> Self {
+        Self {
+            file,
+            index: Vec::new(),
+            end: 0,
+            map: RefCell::new(None),
+        }
+    }
+
+    fn push(&mut self, particles: &[ParticleInfo]) -> io::Result<(usize, usize)> {
+        let mut out = BufWriter::new(&self.file);
+        write_cloud(&mut out, particles)?;
+        out.flush()?;
+        let entry = (self.end, particles.len());
+        self.index.push(entry);
+        self.end += particles.len() * RECORD;
+        Ok(entry)
+    }
+
+    fn read(&self, i: usize, out: &mut Vec<ParticleInfo>) -> io::Result<()> {
+        let (offset, count) = self.index[i];
+        let mut map = self.map.borrow_mut();
+        if map.as_ref().is_none_or(|m| m.len() < self.end) {
+            // SAFETY: only the owning store writes the file, and never
+            // while a map is borrowed.
+            *map = Some(unsafe { memmap2::Mmap::map(&self.file)? });
+        }
+        read_cloud(&map.as_ref().unwrap()[offset..offset + count * RECORD], out);
+        Ok(())
+    }
+}
+
+/// Appends every cloud to one file
+pub struct MappedStore(MappedFile);
+
+impl MappedStore {
+    /// Create or truncate the backing file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self(MappedFile::new(file)))
+    }
+}
+
+impl CloudStore for MappedStore {
+    fn push(&mut self, particles: &[ParticleInfo]) -> io::Result<()> {
+        self.0.push(particles).map(drop)
+    }
+
+    fn len(&self) -> usize {
+        self.0.index.len()
+    }
+
+    fn read(&self, step: usize, out: &mut Vec<ParticleInfo>) -> io::Result<()> {
+        if step >= self.len() {
+            return Err(missing(step));
+        }
+        self.0.read(step, out)
+    }
+}
+
+/// One file of the ring, holding consecutive steps
+struct Segment {
+    id: u64,
+    /// Step of the segment's first cloud
+    first: usize,
+    data: MappedFile,
+}
+
+/// Spreads clouds over a ring of segment files in a directory, each
+/// holding `segment_steps` steps, with an `index` file listing where every
+/// step lies so the store can be reopened. With a retention window only
+/// the latest steps are kept: segments that fall wholly out of the window
+/// are deleted as the run goes, and [`RingStore::compact`] also rewrites
+/// the oldest segment without its expired steps. Steps before
+/// [`CloudStore::first`] are gone.
+pub struct RingStore {
+    dir: PathBuf,
+    segment_steps: usize,
+    retain: Option<usize>,
+    segments: VecDeque<Segment>,
+    next_id: u64,
+    first: usize,
+    len: usize,
+    index: BufWriter<File>,
+}
+
+impl RingStore {
+    /// Start an empty ring in `dir`, creating the directory and deleting
+    /// any ring already there. Keeps the latest `retain` steps, or all of
+    /// them for `None`.
+    pub fn create<P: AsRef<Path>>(
+        dir: P,
+        segment_steps: usize,
+        retain: Option<usize>,
+    ) -> io::Result<Self> {
+        assert!(segment_steps > 0, "segments need room for a step");
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if Self::segment_id(&path).is_some() {
+                fs::remove_file(path)?;
+            }
+        }
+        let mut index = BufWriter::new(File::create(dir.join("index"))?);
+        writeln!(index, "{}", INDEX_MAGIC)?;
+        index.flush()?;
+        Ok(Self {
+            dir,
+            segment_steps,
+            retain,
+            segments: VecDeque::new(),
+            next_id: 0,
+            first: 0,
+            len: 0,
+            index,
+        })
+    }
+
+    /// Reopen the ring in `dir` to read, or to push more steps.
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        segment_steps: usize,
+        retain: Option<usize>,
+    ) -> io::Result<Self> {
+        assert!(segment_steps > 0, "segments need room for a step");
+        let dir = dir.as_ref().to_path_buf();
+        let bad = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad ring index line {:?}", line),
+            )
+        };
+        let mut segments: VecDeque<Segment> = VecDeque::new();
+        let mut first = None;
+        let mut len = 0;
+        let mut lines = io::BufReader::new(File::open(dir.join("index"))?).lines();
+        if lines.next().transpose()?.as_deref() != Some(INDEX_MAGIC) {
+            return Err(bad("missing header"));
+        }
+        for line in lines {
+            let line = line?;
+            let fields: Vec<usize> = line
+                .split_whitespace()
+                .map(|f| f.parse().map_err(|_| bad(&line)))
+                .collect::<Result<_, _>>()?;
+            let &[step, id, offset, count] = &fields[..] else {
+                return Err(bad(&line));
+            };
+            if step != len && first.is_some() {
+                return Err(bad(&line));
+            }
+            first.get_or_insert(step);
+            len = step + 1;
+            if segments.back().is_none_or(|s| s.id != id as u64) {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .append(true)
+                    .open(dir.join(format!("segment-{}.bin", id)))?;
+                segments.push_back(Segment {
+                    id: id as u64,
+                    first: step,
+                    data: MappedFile::new(file),
+                });
+            }
+            let data = &mut segments.back_mut().unwrap().data;
+            data.index.push((offset, count));
+            data.end = data.end.max(offset + count * RECORD);
+        }
+        let index = OpenOptions::new().append(true).open(dir.join("index"))?;
+        let first = first.unwrap_or(0);
+        Ok(Self {
+            dir,
+            segment_steps,
+            retain,
+            next_id: segments.back().map_or(0, |s| s.id + 1),
+            segments,
+            first: retain.map_or(first, |r| first.max(len.saturating_sub(r))),
+            len,
+            index: BufWriter::new(index),
+        })
+    }
+
+    fn segment_id(path: &Path) -> Option<u64> {
+        let name = path.file_name()?.to_str()?;
+        name.strip_prefix("segment-")?
+            .strip_suffix(".bin")?
+            .parse()
+            .ok()
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("segment-{}.bin", id))
+    }
+
+    fn open_segment(&mut self, first: usize) -> io::Result<Segment> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.segment_path(id))?;
+        Ok(Segment {
+            id,
+            first,
+            data: MappedFile::new(file),
+        })
+    }
+
+    /// Bytes of cloud data on disk
+    pub fn disk_bytes(&self) -> usize {
+        self.segments.iter().map(|s| s.data.end).sum()
+    }
+
+    /// Delete segments wholly before the window, returning whether there
+    /// were any.
+    fn expire(&mut self) -> io::Result<bool> {
+        let mut expired = false;
+        while let Some(oldest) = self.segments.front()
+            && oldest.first + oldest.data.index.len() <= self.first
+        {
+            let id = oldest.id;
+            self.segments.pop_front();
+            fs::remove_file(self.segment_path(id))?;
+            expired = true;
+        }
+        Ok(expired)
+    }
+
+    /// Rewrite the oldest segment without its expired steps, and the index
+    /// without any expired entries.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let keep_from = self.first;
+        if let Some(oldest) = self.segments.front()
+            && oldest.first < keep_from
+        {
+            let (old_id, old_first) = (oldest.id, oldest.first);
+            let mut fresh = self.open_segment(keep_from)?;
+            let mut cloud = Vec::new();
+            let oldest = self.segments.front().unwrap();
+            for i in keep_from - old_first..oldest.data.index.len() {
+                oldest.data.read(i, &mut cloud)?;
+                fresh.data.push(&cloud)?;
+            }
+            self.segments[0] = fresh;
+            fs::remove_file(self.segment_path(old_id))?;
+        }
+        self.write_index()
+    }
+
+    /// Rewrite the index to list only the steps kept.
+    fn write_index(&mut self) -> io::Result<()> {
+        // Write the index aside and swap it in, so a crash leaves one of
+        // the two whole.
+        let tmp = self.dir.join("index.tmp");
+        let mut index = BufWriter::new(File::create(&tmp)?);
+        writeln!(index, "{}", INDEX_MAGIC)?;
+        for s in &self.segments {
+            for (i, (offset, count)) in s.data.index.iter().enumerate() {
+                writeln!(index, "{} {} {} {}", s.first + i, s.id, offset, count)?;
+            }
+        }
+        index.flush()?;
+        drop(index);
+        fs::rename(&tmp, self.dir.join("index"))?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(self.dir.join("index"))?;
+        self.index = BufWriter::new(file);
+        Ok(())
+    }
+}
+
+impl CloudStore for RingStore {
+    fn push(&mut self, particles: &[ParticleInfo]) -> io::Result<()> {
+        let full = self
+            .segments
+            .back()
+            .is_none_or(|s| s.data.index.len() >= self.segment_steps);
+        if full {
+            let segment = self.open_segment(self.len)?;
+            self.segments.push_back(segment);
+        }
+        let segment = self.segments.back_mut().unwrap();
+        let (offset, count) = segment.data.push(particles)?;
+        writeln!(
+            self.index,
+            "{} {} {} {}",
+            self.len, segment.id, offset, count
+        )?;
+        self.index.flush()?;
+        self.len += 1;
+        if let Some(retain) = self.retain {
+            self.first = self.first.max(self.len.saturating_sub(retain));
+            if self.expire()? {
+                self.write_index()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn first(&self) -> usize {
+        self.first
+    }
+
+    fn read(&self, step: usize, out: &mut Vec<ParticleInfo>) -> io::Result<()> {
+        if step < self.first || step >= self.len {
+            return Err(missing(step));
+        }
+        let at = self.segments.partition_point(|s| s.first <= step) - 1;
+        let segment = &self.segments[at];
+        segment.data.read(step - segment.first, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sim::SimParams, smooth::MemoryStore, uniform};
+
+    fn cloud(n: usize) -> Vec<ParticleInfo> {
+        (0..n)
+            .map(|_| {
+                let mut s = VehicleState::default();
+                s.init_state(&SimParams::default());
+                ParticleInfo {
+                    state: s,
+                    weight: uniform(),
+                    #[cfg(feature = "rbpf")]
+                    sub: Default::default(),
+                }
+            })
+            .collect()
+    }
+
+    fn same(a: &[ParticleInfo], b: &[ParticleInfo]) -> bool {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|(a, b)| {
+                (a.state.posn, a.state.vel(), a.weight) == (b.state.posn, b.state.vel(), b.weight)
+            })
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bmpf-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn mapped_store_round_trips() {
+        let path = temp_path("smooth.bin");
+        let mut store = MappedStore::create(&path).unwrap();
+        let mut memory = MemoryStore::default();
+        crate::seed_thread_rng(4653);
+        for n in [3, 0, 5] {
+            let cloud = cloud(n);
+            store.push(&cloud).unwrap();
+            memory.push(&cloud).unwrap();
+            // Reading between pushes remaps the grown file.
+            let (mut a, mut b) = (Vec::new(), Vec::new());
+            for step in 0..store.len() {
+                store.read(step, &mut a).unwrap();
+                memory.read(step, &mut b).unwrap();
+                assert!(same(&a, &b));
+            }
+        }
+        assert!(store.read(3, &mut Vec::new()).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rings_keep_the_window() {
+        crate::seed_thread_rng(4688);
+        let dir = temp_path("ring");
+        let mut ring = RingStore::create(&dir, 4, Some(10)).unwrap();
+        let mut memory = MemoryStore::default();
+        for step in 0..25 {
+            let cloud = cloud(3 + step % 4);
+            ring.push(&cloud).unwrap();
+            memory.push(&cloud).unwrap();
+        }
+        assert_eq!((ring.first(), ring.len()), (15, 25));
+        // Steps 12 to 15 share a segment with the window's first step.
+        let segments = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .filter(|e| RingStore::segment_id(&e.as_ref().unwrap().path()).is_some())
+                .count()
+        };
+        assert_eq!(segments(), 4);
+        assert!(ring.read(14, &mut Vec::new()).is_err());
+        let check = |ring: &RingStore, memory: &MemoryStore| {
+            let (mut a, mut b) = (Vec::new(), Vec::new());
+            for step in ring.first()..ring.len() {
+                ring.read(step, &mut a).unwrap();
+                memory.read(step, &mut b).unwrap();
+                assert!(same(&a, &b), "step {}", step);
+            }
+        };
+        check(&ring, &memory);
+
+        // Expired segments leave the index as they go.
+        drop(ring);
+        let mut ring = RingStore::open(&dir, 4, Some(10)).unwrap();
+        assert_eq!((ring.first(), ring.len()), (15, 25));
+        check(&ring, &memory);
+
+        let before = ring.disk_bytes();
+        ring.compact().unwrap();
+        assert!(ring.disk_bytes() < before);
+        check(&ring, &memory);
+        let next = cloud(2);
+        ring.push(&next).unwrap();
+        memory.push(&next).unwrap();
+        drop(ring);
+        let reopened = RingStore::open(&dir, 4, Some(10)).unwrap();
+        assert_eq!((reopened.first(), reopened.len()), (16, 26));
+        check(&reopened, &memory);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn windowed_smoothing() {
+        crate::seed_thread_rng(4688);
+        let dir = temp_path("lag");
+        let params = SimParams::default();
+        let ring = RingStore::create(&dir, 8, Some(20)).unwrap();
+        let mut smoother = crate::smooth::Smoother::with_store(params, 0.05, Box::new(ring));
+        for _ in 0..50 {
+            smoother.push(0.1, &cloud(10)).unwrap();
+        }
+        assert_eq!(smoother.first(), 30);
+        assert_eq!(smoother.sample().unwrap().len(), 20);
+        assert_eq!(smoother.mean_path(3).unwrap().len(), 20);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
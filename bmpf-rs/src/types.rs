@@ -1,75 +1,230 @@
 use crate::{
-    gaussian,
-    resample::{Resample, Resampler},
-    sim::{
-        AVAR, BOX_DIM, CosDirn, FAST_DIRECTION, GPS_VAR, IMU_A_VAR, IMU_R_VAR, MAX_SPEED, NDIRNS,
-        RVAR, angle_dirn, clip_box, clip_speed, normalize_angle, normalize_dirn,
-    },
-    uniform,
+    abc::Abc,
+    estimate::{self, EstimateMode, Kde, WeightMoments, best_worst},
+    fp::{atan2, cos, exp, ln, ln_1p, powf, sin},
+    frame::Frame,
+    gaussian, gaussian_source,
+    init::InitDistribution,
+    interval::{AutoInterval, ess_fraction},
+    io::{Reading, Record},
+    map::ObstaclePolicy,
+    memory::MemoryFootprint,
+    progress::{PhaseTimes, lap},
+    proposal::Proposal,
+    replay::{RecordedStep, Recording},
+    report::{FileReporter, ParticleReporter, RunHeader},
+    resample::{self, OutputWeights, Resample, Resampler, Weights},
+    sim::{GpsLikelihood, ImuMode, SimParams, Topology, angle_delta, normalize_angle},
+    thread_rng_position, uniform,
+    validate::{ValidationError, validate},
 };
-use std::{cmp::Ordering, f64::consts::PI, fs::OpenOptions, io::Write};
+use std::{cmp::Ordering, f64::consts::PI, io, str::FromStr, time::Instant};
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
 pub struct CCoord {
     pub x: f64,
     pub y: f64,
 }
 
-fn gprob(delta: f64, sd: f64) -> f64 {
-    (-0.5 * delta * delta / (sd * sd)).exp()
+/// Unnormalized Gaussian likelihood of an error `delta` with deviation `sd`
+#[inline]
+pub fn gprob(delta: f64, sd: f64) -> f64 {
+    exp(-0.5 * delta * delta / (sd * sd))
+}
+
+/// Natural log of `gprob`, which never calls `exp` and so never underflows
+#[inline]
+pub fn log_gprob(delta: f64, sd: f64) -> f64 {
+    -0.5 * delta * delta / (sd * sd)
+}
+
+/// Natural log of the normalized Gaussian density of `delta` with
+/// deviation `sd`, for comparing densities with different deviations
+#[inline]
+pub fn log_normal_pdf(delta: f64, sd: f64) -> f64 {
+    log_gprob(delta, sd) - ln(sd) - 0.5 * ln(2.0 * PI)
+}
+
+/// Normalizing constant of the Gaussian GPS density
+fn gauss_area(params: &SimParams) -> f64 {
+    match params.gps_ellipse {
+        Some(ellipse) => 2.0 * PI * ellipse.det_sqrt(),
+        None => 2.0 * PI * params.gps_var * params.gps_var,
+    }
 }
 
 impl CCoord {
-    fn gps_measure(&self) -> CCoord {
+    fn gps_measure(&self, params: &SimParams) -> CCoord {
         let mut result = *self;
-        result.x += gaussian(unsafe { GPS_VAR });
-        result.y += gaussian(unsafe { GPS_VAR });
+        match params.gps_ellipse {
+            Some(ellipse) => {
+                let (dx, dy) = ellipse.color(gaussian(1.0), gaussian(1.0));
+                result.x += dx;
+                result.y += dy;
+            }
+            None => {
+                result.x += gaussian(params.gps_var);
+                result.y += gaussian(params.gps_var);
+            }
+        }
+        if params.topology == Topology::Toroidal {
+            result.x = params.wrap_box(result.x);
+            result.y = params.wrap_box(result.y);
+        }
         result
     }
 
-    fn gps_prob(&self, state: &VehicleState) -> f64 {
-        if state.posn.x < -BOX_DIM
-            || state.posn.x > BOX_DIM
-            || state.posn.y < -BOX_DIM
-            || state.posn.y > BOX_DIM
-        {
+    /// Likelihood of this GPS fix given a vehicle state
+    pub fn gps_prob(&self, params: &SimParams, state: &VehicleState) -> f64 {
+        let bounds = params.log_bounds_factor(state.posn.x, state.posn.y);
+        if bounds == f64::NEG_INFINITY {
             return 0.0;
         }
-        let px = gprob(state.posn.x - self.x, unsafe { GPS_VAR });
-        let py = gprob(state.posn.y - self.y, unsafe { GPS_VAR });
-        px * py
+        if params.blocked(state.posn.x, state.posn.y) {
+            return 0.0;
+        }
+        let dx = params.delta(state.posn.x, self.x);
+        let dy = params.delta(state.posn.y, self.y);
+        let sd = params.gps_var;
+        let gauss = || match params.gps_ellipse {
+            Some(_) => exp(-0.5 * params.gps_distance_sq(dx, dy)),
+            None => gprob(dx, sd) * gprob(dy, sd),
+        };
+        let p = match params.gps_likelihood {
+            GpsLikelihood::Gaussian => gauss(),
+            GpsLikelihood::StudentT { nu } => {
+                let d2 = params.gps_distance_sq(dx, dy);
+                powf(1.0 + d2 / nu, -0.5 * (nu + 2.0))
+            }
+            GpsLikelihood::Mixture { outlier } => {
+                // Both terms are proper densities here so the mix is meaningful.
+                let gauss = gauss() / gauss_area(params);
+                let side = 2.0 * params.box_dim;
+                (1.0 - outlier) * gauss + outlier / (side * side)
+            }
+        };
+        p * exp(bounds)
+    }
+
+    /// Natural log of `gps_prob`, `-inf` for impossible states
+    pub fn log_gps_prob(&self, params: &SimParams, state: &VehicleState) -> f64 {
+        let bounds = params.log_bounds_factor(state.posn.x, state.posn.y);
+        if bounds == f64::NEG_INFINITY {
+            return f64::NEG_INFINITY;
+        }
+        if params.blocked(state.posn.x, state.posn.y) {
+            return f64::NEG_INFINITY;
+        }
+        let dx = params.delta(state.posn.x, self.x);
+        let dy = params.delta(state.posn.y, self.y);
+        let sd = params.gps_var;
+        let log_gauss = || match params.gps_ellipse {
+            Some(_) => -0.5 * params.gps_distance_sq(dx, dy),
+            None => log_gprob(dx, sd) + log_gprob(dy, sd),
+        };
+        let lp = match params.gps_likelihood {
+            GpsLikelihood::Gaussian => log_gauss(),
+            GpsLikelihood::StudentT { nu } => {
+                let d2 = params.gps_distance_sq(dx, dy);
+                -0.5 * (nu + 2.0) * ln_1p(d2 / nu)
+            }
+            GpsLikelihood::Mixture { outlier } => {
+                // log(a + b) computed from log a without underflowing it
+                let log_gauss = log_gauss() - ln(gauss_area(params));
+                let side = 2.0 * params.box_dim;
+                let log_a = ln(1.0 - outlier) + log_gauss;
+                let log_b = ln(outlier / (side * side));
+                let hi = log_a.max(log_b);
+                if hi == f64::NEG_INFINITY {
+                    return hi;
+                }
+                hi + ln(exp(log_a - hi) + exp(log_b - hi))
+            }
+        };
+        lp + bounds
     }
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
 pub struct ACoord {
     pub r: f64,
     pub t: f64,
 }
 
 impl ACoord {
-    fn measure(&self, dt: f64) -> ACoord {
-        let mut result = *self;
-        result.r += gaussian(IMU_R_VAR * dt);
-        result.t = normalize_angle(result.t + gaussian(IMU_A_VAR * dt));
-        if result.r < 0.0 {
-            result.r = -result.r;
-            result.t = normalize_angle(result.t + PI);
+    /// The same speed heading the opposite way
+    pub fn reversed(self) -> Self {
+        Self {
+            r: self.r,
+            t: normalize_angle(self.t + PI),
         }
-        result
     }
 
-    fn imu_prob(&self, state: &VehicleState, dt: f64) -> f64 {
-        if state.vel.r < 0.0 || state.vel.r > MAX_SPEED {
+    fn measure(&self, params: &SimParams, dt: f64) -> ACoord {
+        let (sd_r, sd_t) = params.imu_noise_sd(dt);
+        // Both are drawn whatever the IMU gives, so degraded runs follow
+        // the same track.
+        let r = self.r + gaussian(sd_r);
+        let t = normalize_angle(self.t + gaussian(sd_t));
+        match params.imu_mode {
+            ImuMode::Full if r < 0.0 => ACoord {
+                r: -r,
+                t: normalize_angle(t + PI),
+            },
+            ImuMode::Full => ACoord { r, t },
+            ImuMode::HeadingOnly => ACoord { r: f64::NAN, t },
+            ImuMode::SpeedOnly => ACoord {
+                r: r.abs(),
+                t: f64::NAN,
+            },
+        }
+    }
+
+    /// Likelihood of this IMU reading given a vehicle state
+    pub fn imu_prob(&self, params: &SimParams, state: &VehicleState, dt: f64) -> f64 {
+        if state.vel.r < 0.0 || state.vel.r > params.max_speed {
             return 0.0;
         }
-        let pr = gprob(state.vel.r - self.r, IMU_R_VAR / dt);
+        let (sd_r, sd_t) = params.imu_likelihood_sd(dt);
+        let pr = if params.imu_mode.has_speed() {
+            gprob(state.vel.r - self.r, sd_r)
+        } else {
+            1.0
+        };
         let dth = (state.vel.t - self.t)
             .abs()
             .min(((state.vel.t - self.t).abs() - 2.0 * PI).abs());
-        let pt = gprob(dth, IMU_A_VAR / dt);
+        let pt = if params.imu_mode.has_heading() {
+            gprob(dth, sd_t)
+        } else {
+            1.0
+        };
         pr * pt
     }
+
+    /// Natural log of `imu_prob`, `-inf` for impossible states
+    pub fn log_imu_prob(&self, params: &SimParams, state: &VehicleState, dt: f64) -> f64 {
+        if state.vel.r < 0.0 || state.vel.r > params.max_speed {
+            return f64::NEG_INFINITY;
+        }
+        let (sd_r, sd_t) = params.imu_likelihood_sd(dt);
+        let pr = if params.imu_mode.has_speed() {
+            log_gprob(state.vel.r - self.r, sd_r)
+        } else {
+            0.0
+        };
+        let dth = (state.vel.t - self.t)
+            .abs()
+            .min(((state.vel.t - self.t).abs() - 2.0 * PI).abs());
+        let pt = if params.imu_mode.has_heading() {
+            log_gprob(dth, sd_t)
+        } else {
+            0.0
+        };
+        pr + pt
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -80,110 +235,272 @@ enum BounceProblem {
     BounceXY,
 }
 
+/// Commanded motion: the speed to hold and the rate to turn at, in
+/// radians per second
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Control {
+    pub speed: f64,
+    pub turn_rate: f64,
+}
+
 #[derive(Clone, Default, Copy)]
+#[repr(C)]
 pub struct VehicleState {
     pub posn: CCoord,
     vel: ACoord,
-    cos_dirn: CosDirn,
 }
 
 impl VehicleState {
+    pub fn new(posn: CCoord, vel: ACoord) -> Self {
+        Self { posn, vel }
+    }
+
     #[inline]
-    pub fn gps_measure(&self) -> CCoord {
-        self.posn.gps_measure()
+    pub fn gps_measure(&self, params: &SimParams) -> CCoord {
+        self.posn.gps_measure(params)
     }
 
     #[inline]
-    pub fn imu_measure(&self, dt: f64) -> ACoord {
-        self.vel.measure(dt)
-    }
-
-    fn bounce(&mut self, r: f64, t: f64, dt: f64, _noise: i32) -> BounceProblem {
-        let dc0;
-        let dms0;
-        let mut x0;
-        let mut y0;
-        let mut x1;
-        let mut y1;
-        if FAST_DIRECTION == 1 {
-            dc0 = angle_dirn(t);
-            dms0 = normalize_dirn(dc0 + NDIRNS / 4);
-            x0 = self.posn.x + r * self.cos_dirn.data[dc0 as usize] * dt;
-            y0 = self.posn.y + r * self.cos_dirn.data[dms0 as usize] * dt;
+    pub fn imu_measure(&self, params: &SimParams, dt: f64) -> ACoord {
+        self.vel.measure(params, dt)
+    }
+
+    pub fn vel(&self) -> ACoord {
+        self.vel
+    }
+
+    /// Log density of `update_state` with the same `noise` drawing speed
+    /// `r` and heading `t` from this state, ignoring speed clipping and
+    /// bounces.
+    pub fn log_velocity_prior(
+        &self,
+        params: &SimParams,
+        r: f64,
+        t: f64,
+        dt: f64,
+        noise: i32,
+    ) -> f64 {
+        let scale = params.process_noise_scale(dt) * ((1 + 8 * noise) as f64);
+        log_normal_pdf(r - self.vel.r, params.rvar * scale)
+            + log_normal_pdf(angle_delta(t, self.vel.t), params.avar * scale)
+    }
+
+    /// Where moving for `dt` with new speed `r` and heading `t` would
+    /// take this state, walls and obstacles aside
+    #[inline]
+    fn destination(&self, params: &SimParams, r: f64, t: f64, dt: f64) -> (f64, f64) {
+        let (rm, tm) = params.step_velocity(self.vel.r, self.vel.t, r, t);
+        if params.fast_direction {
+            let d = params.dirn.nearest(tm);
+            (
+                self.posn.x + rm * params.dirn.cos(d) * dt,
+                self.posn.y - rm * params.dirn.sin(d) * dt,
+            )
+        } else {
+            (
+                self.posn.x + rm * cos(tm) * dt,
+                self.posn.y - rm * sin(tm) * dt,
+            )
+        }
+    }
+
+    fn bounce(
+        &mut self,
+        params: &SimParams,
+        r: f64,
+        t: f64,
+        dt: f64,
+        _noise: i32,
+    ) -> BounceProblem {
+        let (x0, y0) = self.destination(params, r, t, dt);
+        let (x1, y1) = if params.topology == Topology::Toroidal {
+            (params.wrap_box(x0), params.wrap_box(y0))
         } else {
-            x0 = self.posn.x + r * t.cos() * dt;
-            y0 = self.posn.y - r * t.sin() * dt;
-        }
-        x1 = clip_box(x0);
-        y1 = clip_box(y0);
-        if x0 == x1 && y0 == y1 {
-            self.posn.x = x1;
-            self.posn.y = y1;
-            self.vel.t = t;
-            self.vel.r = r;
-            return BounceProblem::BounceOk;
-        }
-        if FAST_DIRECTION == 1 {
-            x0 = self.posn.x + r * t.cos() * dt;
-            y0 = self.posn.y - r * t.sin() * dt;
-            x1 = clip_box(x0);
-            y1 = clip_box(y0);
-            if x0 == x1 && y0 == y1 {
-                self.posn.x = x1;
-                self.posn.y = y1;
-                self.vel.t = t;
-                self.vel.r = r;
-                return BounceProblem::BounceOk;
-            }
-        }
-        if y0 == y1 {
-            return BounceProblem::BounceX;
-        } else if x0 == x1 {
-            return BounceProblem::BounceY;
-        }
-        BounceProblem::BounceXY
-    }
-
-    pub fn init_state(&mut self) {
-        self.posn.x = (uniform() * 2.0 - 1.0) * BOX_DIM;
-        self.posn.y = (uniform() * 2.0 - 1.0) * BOX_DIM;
-        self.vel.r = uniform();
-        self.vel.t = normalize_angle(uniform() * (PI / 2.0f64));
-        self.cos_dirn.init_dirn();
-    }
-
-    pub fn update_state(&mut self, dt: f64, noise: i32) {
-        let mut r0 = clip_speed(self.vel.r + gaussian(RVAR) * ((1 + 8 * noise) as f64));
-        let mut t0 = normalize_angle(self.vel.t + gaussian(AVAR) * ((1 + 8 * noise) as f64));
-        let mut b = self.bounce(r0, t0, dt, noise);
+            let x1 = params.clip_box(x0);
+            let y1 = params.clip_box(y0);
+            if x0 != x1 || y0 != y1 {
+                if y0 == y1 {
+                    return BounceProblem::BounceX;
+                } else if x0 == x1 {
+                    return BounceProblem::BounceY;
+                }
+                return BounceProblem::BounceXY;
+            }
+            (x1, y1)
+        };
+        if params.obstacle_policy == ObstaclePolicy::Bounce && params.blocked(x1, y1) {
+            // Work out which axis of the move ran into the obstacle.
+            let bx = params.blocked(x1, self.posn.y);
+            let by = params.blocked(self.posn.x, y1);
+            return match (bx, by) {
+                (true, false) => BounceProblem::BounceX,
+                (false, true) => BounceProblem::BounceY,
+                _ => BounceProblem::BounceXY,
+            };
+        }
+        self.posn.x = x1;
+        self.posn.y = y1;
+        self.vel.t = t;
+        self.vel.r = r;
+        BounceProblem::BounceOk
+    }
+
+    /// Start uniformly over the box with a random velocity, redrawing
+    /// positions that land inside obstacles. Returns false if it gave up
+    /// after 1000 draws, on maps with little or no free space, leaving
+    /// the state inside an obstacle.
+    pub fn init_state(&mut self, params: &SimParams) -> bool {
+        let mut free = false;
+        for _ in 0..1000 {
+            self.posn.x = (uniform() * 2.0 - 1.0) * params.box_dim;
+            self.posn.y = (uniform() * 2.0 - 1.0) * params.box_dim;
+            free = !params.blocked(self.posn.x, self.posn.y);
+            if free {
+                break;
+            }
+        }
+        self.vel = initial_velocity();
+        free
+    }
+
+    /// Move for `dt` with noise on the speed and heading, returning
+    /// whether the move was given up as in `advance`.
+    pub fn update_state(&mut self, params: &SimParams, dt: f64, noise: i32) -> bool {
+        let scale = params.process_noise_scale(dt) * ((1 + 8 * noise) as f64);
+        let r0 = params.draw_speed(self.vel.r, || gaussian(params.rvar) * scale);
+        let t0 = normalize_angle(self.vel.t + gaussian(params.avar) * scale);
+        self.advance(params, r0, t0, dt)
+    }
+
+    /// `update_state` run backwards in time, for a state whose heading
+    /// points back along its track, as [`VehicleState::reversed`] gives:
+    /// move with the current velocity, which forwards carried the vehicle
+    /// here, then draw the velocity it had before. Exactly undoes Euler
+    /// steps that do not bounce, given the same noise.
+    pub fn reverse_state(&mut self, params: &SimParams, dt: f64, noise: i32) -> bool {
+        let scale = params.process_noise_scale(dt) * ((1 + 8 * noise) as f64);
+        let held = self.advance(params, self.vel.r, self.vel.t, dt);
+        self.vel.r = params.draw_speed(self.vel.r, || gaussian(params.rvar) * scale);
+        self.vel.t = normalize_angle(self.vel.t + gaussian(params.avar) * scale);
+        held
+    }
+
+    /// This state turned around, to run it backwards in time
+    pub fn reversed(&self) -> Self {
+        Self {
+            posn: self.posn,
+            vel: self.vel.reversed(),
+        }
+    }
+
+    /// Move for `dt` under `control`, holding the commanded speed and
+    /// turning at the commanded rate, up to the `control_r_var` and
+    /// `control_a_var` noise. The commands are known, so unlike
+    /// `update_state` particles get the same noise as the vehicle.
+    pub fn update_controlled(&mut self, params: &SimParams, control: Control, dt: f64) -> bool {
+        let scale = params.process_noise_scale(dt);
+        let r0 = params.draw_speed(control.speed, || gaussian(params.control_r_var) * scale);
+        let t0 = normalize_angle(
+            self.vel.t + control.turn_rate * dt + gaussian(params.control_a_var) * scale,
+        );
+        self.advance(params, r0, t0, dt)
+    }
+
+    /// Move for `dt` with new speed `r` and heading `t`, bouncing off the
+    /// walls and obstacles the way `update_state` does. A move that still
+    /// leaves the box or ends in an obstacle after bouncing, as into a
+    /// corner or across the whole box in one step, is given up: the state
+    /// keeps its position and velocity and this returns `true`.
+    pub fn advance(&mut self, params: &SimParams, r: f64, t: f64, dt: f64) -> bool {
+        let (mut r0, mut t0) = (r, t);
+        let mut b = self.bounce(params, r0, t0, dt, 0);
         if b != BounceProblem::BounceOk {
             r0 = self.vel.r;
             t0 = self.vel.t;
-            b = self.bounce(r0, t0, dt, 0);
+            b = self.bounce(params, r0, t0, dt, 0);
             match b {
                 BounceProblem::BounceOk => (),
                 BounceProblem::BounceX => {
                     t0 = normalize_angle(PI - t0);
-                    b = self.bounce(r0, t0, dt, 0);
+                    b = self.bounce(params, r0, t0, dt, 0);
                 }
                 BounceProblem::BounceY => {
                     t0 = normalize_angle(2.0 * PI - t0);
-                    b = self.bounce(r0, t0, dt, 0);
+                    b = self.bounce(params, r0, t0, dt, 0);
                 }
                 BounceProblem::BounceXY => {
                     t0 = normalize_angle(PI + t0);
-                    b = self.bounce(r0, t0, dt, 0)
+                    b = self.bounce(params, r0, t0, dt, 0)
                 }
             }
         }
-        assert!(b == BounceProblem::BounceOk, "{:?} != BounceOk", b)
+        b != BounceProblem::BounceOk
     }
 }
 
+/// Move each of `particles` as `update_state` would, drawing the same
+/// noise in the same order. Moves that stay clear of the walls and
+/// obstacles are finished in one pass over the slice with no bounce
+/// handling; the few that would bounce are listed and redone one at a
+/// time afterwards. Returns how many moves were given up, as in
+/// [`VehicleState::advance`].
+pub fn update_states(
+    particles: &mut [ParticleInfo],
+    params: &SimParams,
+    dt: f64,
+    noise: i32,
+) -> usize {
+    let scale = params.process_noise_scale(dt) * ((1 + 8 * noise) as f64);
+    let toroidal = params.topology == Topology::Toroidal;
+    let obstacles = params.obstacle_policy == ObstaclePolicy::Bounce && params.map.is_some();
+    let mut bounced = Vec::new();
+    for (i, p) in particles.iter_mut().enumerate() {
+        let s = &mut p.state;
+        let r = params.draw_speed(s.vel.r, || gaussian(params.rvar) * scale);
+        let t = normalize_angle(s.vel.t + gaussian(params.avar) * scale);
+        let (x0, y0) = s.destination(params, r, t, dt);
+        let (x1, y1) = if toroidal {
+            (params.wrap_box(x0), params.wrap_box(y0))
+        } else {
+            (params.clip_box(x0), params.clip_box(y0))
+        };
+        let clear = (toroidal || (x0 == x1 && y0 == y1)) && !(obstacles && params.blocked(x1, y1));
+        if clear {
+            s.posn = CCoord { x: x1, y: y1 };
+            s.vel = ACoord { r, t };
+        } else {
+            bounced.push((i, r, t));
+        }
+    }
+    bounced
+        .into_iter()
+        .filter(|&(i, r, t)| particles[i].state.advance(params, r, t, dt))
+        .count()
+}
+
+/// Laid out as the `f64`s `x y r t weight`, then any sub-state, so
+/// [`Particles::view`] can borrow the particles as a matrix.
 #[derive(Default, Clone, Copy)]
+#[repr(C)]
 pub struct ParticleInfo {
     pub state: VehicleState,
     pub weight: f64,
+    /// Kalman sub-state carried through resampling for `BpfState::rbpf`
+    #[cfg(feature = "rbpf")]
+    pub sub: crate::rbpf::SubState,
+}
+
+/// Particles filling a typical 256 KiB L2 cache, a starting point for
+/// `BpfState::block`
+pub const L2_BLOCK: usize = (256 << 10) / size_of::<ParticleInfo>();
+
+/// Starting velocity of a vehicle or particle
+pub(crate) fn initial_velocity() -> ACoord {
+    let r = uniform();
+    ACoord {
+        r,
+        t: normalize_angle(uniform() * (PI / 2.0f64)),
+    }
 }
 
 #[inline]
@@ -216,48 +533,472 @@ impl Default for Particles {
 }
 
 impl Particles {
-    fn new(nparticles: usize) -> Self {
+    /// `nparticles` default particles; zero gives an empty set, which
+    /// every resampler and estimator accepts.
+    pub fn new(nparticles: usize) -> Self {
         Self {
             data: vec![ParticleInfo::default(); nparticles],
         }
     }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Mutable view of particle `i`, for motion models that update
+    /// particles in place.
+    pub fn get_mut(&mut self, i: usize) -> Option<ParticleMut<'_>> {
+        self.data.get_mut(i).map(|info| ParticleMut { info })
+    }
+}
+
+/// Column of [`Particles::view`]
+#[cfg(feature = "ndarray")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    X,
+    Y,
+    /// Speed
+    R,
+    /// Heading
+    T,
+    Weight,
+}
+
+#[cfg(feature = "ndarray")]
+impl Particles {
+    /// `f64`s from one particle to the next
+    const STRIDE: usize = size_of::<ParticleInfo>() / size_of::<f64>();
+
+    /// The particles as an `n x 5` matrix with columns `x y r t weight`,
+    /// borrowed in place rather than copied.
+    pub fn view(&self) -> ndarray::ArrayView2<'_, f64> {
+        use ndarray::ShapeBuilder;
+        const {
+            assert!(size_of::<ParticleInfo>().is_multiple_of(size_of::<f64>()));
+            assert!(align_of::<ParticleInfo>() == align_of::<f64>());
+        }
+        let shape = (self.data.len(), 5).strides((Self::STRIDE, 1));
+        // SAFETY: `ParticleInfo` is `repr(C)` and made of nothing but
+        // `f64`s, the first five being the columns, so every row lies
+        // inside one element of `data`, which this borrow keeps alive.
+        unsafe { ndarray::ArrayView2::from_shape_ptr(shape, self.data.as_ptr().cast::<f64>()) }
+    }
+
+    /// One column of [`Particles::view`]
+    pub fn column(&self, column: Column) -> ndarray::ArrayView1<'_, f64> {
+        self.view()
+            .index_axis_move(ndarray::Axis(1), column as usize)
+    }
+}
+
+/// Coordinate axis of a particle position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+impl CCoord {
+    pub fn get(&self, axis: Axis) -> f64 {
+        match axis {
+            Axis::X => self.x,
+            Axis::Y => self.y,
+        }
+    }
+}
+
+impl Particles {
+    /// Weighted quantile `q` in `[0, 1]` of the particle positions along
+    /// `axis`: the smallest position whose cumulative weight reaches `q` of
+    /// the total. Weights need not be normalized.
+    pub fn weighted_quantile(&self, axis: Axis, q: f64) -> f64 {
+        self.weighted_quantiles(axis, &[q])[0]
+    }
+
+    /// Several weighted quantiles along `axis`, sharing one sort. Returns
+    /// NaN for each quantile when there are no particles.
+    pub fn weighted_quantiles(&self, axis: Axis, qs: &[f64]) -> Vec<f64> {
+        let mut order: Vec<usize> = (0..self.data.len()).collect();
+        let posn = |i: usize| self.data[i].state.posn.get(axis);
+        order.sort_unstable_by(|&a, &b| posn(a).total_cmp(&posn(b)));
+        let total: f64 = self.data.iter().map(|p| p.weight).sum();
+        let mut cumulative = Vec::with_capacity(order.len());
+        let mut t = 0.0;
+        for &i in &order {
+            t += self.data[i].weight;
+            cumulative.push(t);
+        }
+        qs.iter()
+            .map(|&q| {
+                let target = q.clamp(0.0, 1.0) * total;
+                let k = cumulative.partition_point(|&c| c < target);
+                order
+                    .get(k.min(order.len().saturating_sub(1)))
+                    .map_or(f64::NAN, |&i| posn(i))
+            })
+            .collect()
+    }
+
+    /// Central credible interval holding `level` of the weight along each
+    /// axis, as `(lower, upper)` corners. Meaningless across the seam of a
+    /// toroidal world.
+    pub fn credible_interval(&self, level: f64) -> (CCoord, CCoord) {
+        let tail = (1.0 - level.clamp(0.0, 1.0)) / 2.0;
+        let x = self.weighted_quantiles(Axis::X, &[tail, 1.0 - tail]);
+        let y = self.weighted_quantiles(Axis::Y, &[tail, 1.0 - tail]);
+        (CCoord { x: x[0], y: y[0] }, CCoord { x: x[1], y: y[1] })
+    }
+}
+
+/// Mutable view of one particle, including its otherwise private velocity
+pub struct ParticleMut<'a> {
+    info: &'a mut ParticleInfo,
+}
+
+impl ParticleMut<'_> {
+    pub fn posn(&self) -> CCoord {
+        self.info.state.posn
+    }
+
+    pub fn vel(&self) -> ACoord {
+        self.info.state.vel
+    }
+
+    pub fn weight(&self) -> f64 {
+        self.info.weight
+    }
+
+    pub fn set_posn(&mut self, posn: CCoord) {
+        self.info.state.posn = posn;
+    }
+
+    pub fn set_vel(&mut self, vel: ACoord) {
+        self.info.state.vel = vel;
+    }
+
+    pub fn set_weight(&mut self, weight: f64) {
+        self.info.weight = weight;
+    }
+
+    pub fn state(&self) -> &VehicleState {
+        &self.info.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut VehicleState {
+        &mut self.info.state
+    }
+}
+
+/// Filter output for one step
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Estimate {
+    /// Position of the highest weighted particle after resampling
+    pub best: CCoord,
+    pub best_weight: f64,
+    /// Position of the lowest weighted particle after resampling
+    pub worst: CCoord,
+    pub worst_weight: f64,
+    /// Weighted mean position before resampling
+    pub mean: CCoord,
+    /// Kernel density peak before resampling
+    pub map: CCoord,
+    /// Weighted mean speed and heading before resampling, left out on
+    /// the GPU
+    pub velocity: Option<ACoord>,
+    /// Fraction of the particles pinned at `max_speed` before resampling,
+    /// left out on the GPU
+    pub speed_clipped: Option<f64>,
+    /// Which of the above [`Estimate::position`] reports
+    pub mode: EstimateMode,
+    /// `(lower, upper)` credible interval before resampling, when
+    /// `credible_level` is set
+    pub interval: Option<(CCoord, CCoord)>,
+    /// Particles raised to `weight_floor` this step
+    pub clamped: usize,
+    /// Particles that could not move this step, however they bounced,
+    /// and held their position
+    pub held: usize,
+    /// Relative variance of this step's incremental weights, zero on steps
+    /// that do not weigh and left out where they are not tracked, on the
+    /// GPU and with `abc`
+    pub weight_variance: Option<f64>,
+    /// Sum of `ln(1 + weight_variance)` over the steps since the particles
+    /// were last resampled, this one included, left out if any of them
+    /// did not track it. The effective sample size has fallen by about
+    /// this factor's exponential since.
+    pub log_dimension: Option<f64>,
+}
+
+impl Estimate {
+    /// The position estimate chosen by `mode`
+    pub fn position(&self) -> CCoord {
+        match self.mode {
+            EstimateMode::BestParticle => self.best,
+            EstimateMode::WeightedMean => self.mean,
+            EstimateMode::Map => self.map,
+        }
+    }
+}
+
+/// Smallest normalized weight a particle keeps after each weight update.
+/// Without one, a likelihood of zero, as outside the box, leaves a
+/// particle no resampler can draw and, in the tree resampler, subtrees
+/// weighing nothing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WeightFloor {
+    /// Raise weights below this positive weight to it
+    Absolute(f64),
+    /// Raise weights below this fraction, in `(0, 1]`, of the largest to
+    /// it
+    Relative(f64),
+}
+
+impl WeightFloor {
+    /// Raise the normalized weights of `particles` to the floor and
+    /// normalize them again, returning how many were raised. NaN weights,
+    /// as from normalizing weights that were all zero, are raised too. If
+    /// that leaves nothing to normalize, every weight is made uniform.
+    pub fn apply(self, particles: &mut [ParticleInfo]) -> usize {
+        let floor = match self {
+            Self::Absolute(w) => w,
+            Self::Relative(eps) => eps * particles.iter().fold(0.0, |m, p| p.weight.max(m)),
+        };
+        let (mut clamped, mut total) = (0, 0.0);
+        for p in particles.iter_mut() {
+            if p.weight.is_nan() || p.weight < floor {
+                p.weight = floor;
+                clamped += 1;
+            }
+            total += p.weight;
+        }
+        if !(total > 0.0 && total.is_finite()) {
+            let n = particles.len();
+            for p in particles {
+                p.weight = 1.0 / n as f64;
+            }
+            return n;
+        }
+        if clamped > 0 {
+            let invtotal = 1.0 / total;
+            for p in particles {
+                p.weight *= invtotal;
+            }
+        }
+        clamped
+    }
+}
+
+impl FromStr for WeightFloor {
+    type Err = String;
+
+    /// Parse `WEIGHT` or `rel:FRACTION`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (relative, v) = match s.split_once(':') {
+            None => (false, s),
+            Some(("rel", v)) => (true, v),
+            _ => return Err(format!("unknown weight floor {}", s)),
+        };
+        let v: f64 = v
+            .parse()
+            .map_err(|e| format!("bad weight floor {}: {}", v, e))?;
+        if v <= 0.0 || !v.is_finite() {
+            return Err(format!("weight floor {} is not a positive weight", v));
+        }
+        if relative && v > 1.0 {
+            return Err(format!("relative weight floor {} is above 1", v));
+        }
+        Ok(if relative {
+            Self::Relative(v)
+        } else {
+            Self::Absolute(v)
+        })
+    }
+}
+
+/// What one filter step does with the particles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepKind {
+    /// Move, weight by the GPS fix and IMU reading, and resample when due
+    Full,
+    /// Move only, keeping the weights
+    Predict,
+    /// Weight by the GPS fix, and by the IMU reading if no fix has used it
+    /// yet, and resample when due, without moving
+    Correct,
 }
 
 pub struct BpfState {
     pstates: Vec<Particles>,
     which_particle: bool,
     resampler: Resampler,
-    sort: bool,
+    params: SimParams,
+    /// How particles are arranged before each resampling
+    pub order: resample::Ordering,
     nparticles: usize,
     pub report_particles: i32,
-    best_particle: bool,
+    /// Where reported particle snapshots go, by default one file per step
+    /// under `benchtmp/`
+    pub reporter: Option<Box<dyn ParticleReporter>>,
+    /// Accumulate weights in log space and only exponentiate, relative to
+    /// the largest, when normalizing. Survives likelihoods that underflow.
+    pub log_weights: bool,
+    /// Which position estimate the filter reports
+    pub estimate_mode: EstimateMode,
+    /// How particles move between measurements, the motion model when
+    /// `None`
+    pub proposal: Option<Box<dyn Proposal>>,
+    /// Weight by simulated measurement distance instead of the likelihood
+    pub abc: Option<Abc>,
+    /// Run backwards in time over measurements fed in reverse order.
+    /// Particles head back along the track and move by
+    /// [`VehicleState::reverse_state`] in place of the motion model, and
+    /// IMU headings are turned around to match. Proposals and controls
+    /// still move forwards.
+    pub time_reversed: bool,
+    /// Raise normalized weights to this floor on steps run on the host,
+    /// counting the particles raised in [`Estimate::clamped`]
+    pub weight_floor: Option<WeightFloor>,
+    /// Commanded motion driving the motion model in place of its random
+    /// walk, set by `parse_line` from records with control columns
+    pub control: Option<Control>,
+    /// Conventions of the measurements passed in, converted to the
+    /// filter's own on arrival. Estimates stay in the filter's frame;
+    /// `Frame::estimate_out` converts them back.
+    pub frame: Frame,
+    /// Track a linear sub-state per particle and weight by its GPS
+    /// marginal likelihood. Set before `init_particles`.
+    #[cfg(feature = "rbpf")]
+    pub rbpf: Option<Box<dyn crate::rbpf::LinearSubModel>>,
+    /// Device running whole steps, set by `enable_gpu`
+    #[cfg(feature = "gpu")]
+    gpu: Option<crate::gpu::GpuFilter>,
+    /// When particles on the GPU are copied back to the host
+    #[cfg(feature = "gpu")]
+    pub gpu_sync: crate::gpu::GpuSync,
+    kde: Kde,
     resample_interval: usize,
+    /// Adapt the resample interval to how fast the effective sample size
+    /// decays, on steps run on the host
+    pub auto_interval: Option<AutoInterval>,
     resample_count: usize,
+    /// Report a central credible interval holding this much weight
+    pub credible_level: Option<f64>,
+    /// Move, weigh and sum particles into the mean this many at a time,
+    /// each block while it is still in cache, rather than in separate
+    /// passes over them all. The mean rounds differently. Ignored with
+    /// `log_weights`, `abc` or `weight_floor`, whose weights are only
+    /// final after a pass.
+    pub block: Option<usize>,
+    /// Time spent in each phase of the steps run on the host, when set
+    pub phase_times: Option<PhaseTimes>,
+    /// Check the particles on every step run on the host, keeping the
+    /// first failure for `validation`
+    pub validate: bool,
+    validation: Option<ValidationError>,
     pub vehicle: CCoord,
     gps: CCoord,
     imu: ACoord,
+    /// Step the latest IMU reading covers, until a fix weighs it
+    imu_dt: Option<f64>,
+    /// Time moved since particles were last weighted
+    since_fix: f64,
+    /// Steps taken since `start_recording`
+    recording: Option<Recording>,
+    /// The last step resampled, drawing from the other particle set
+    resampled: bool,
+    /// `Estimate::log_dimension` so far
+    log_dimension: Option<f64>,
+}
+
+/// Read-only look at a filter's internals between steps, for tests and
+/// debuggers
+pub struct DebugView<'a> {
+    pub particles: &'a [ParticleInfo],
+    /// The particles the last step resampled from, with the weights it
+    /// drew by, if it resampled
+    pub resampled_from: Option<&'a [ParticleInfo]>,
+    /// The resampler's working arrays from its last run, indexed like
+    /// `resampled_from`
+    pub resampler: resample::Internals<'a>,
+    pub gps: CCoord,
+    pub imu: ACoord,
+    /// Step the IMU reading covers, until a fix weighs it
+    pub imu_dt: Option<f64>,
+    pub control: Option<Control>,
+    /// Time moved since the particles were last weighted
+    pub since_fix: f64,
+    /// Weighted steps since the last resampling, modulo the interval
+    pub resample_count: usize,
+}
+
+impl DebugView<'_> {
+    pub fn weights(&self) -> impl Iterator<Item = f64> + '_ {
+        self.particles.iter().map(|p| p.weight)
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = CCoord> + '_ {
+        self.particles.iter().map(|p| p.state.posn)
+    }
 }
 
 impl Default for BpfState {
     fn default() -> Self {
+        let mut resampler = Resampler::new("naive", 100);
+        resampler.set_output_weights(OutputWeights::Uniform);
         Self {
             pstates: vec![Particles::default(); 2],
             which_particle: false,
-            resampler: Resampler::new("naive", 100),
-            sort: false,
+            resampler,
+            params: SimParams::default(),
+            order: resample::Ordering::None,
             nparticles: 100,
             report_particles: 1000,
-            best_particle: false,
+            reporter: Some(Box::new(FileReporter::default())),
+            log_weights: false,
+            estimate_mode: EstimateMode::WeightedMean,
+            kde: Kde::default(),
+            proposal: None,
+            abc: None,
+            time_reversed: false,
+            weight_floor: None,
+            control: None,
+            frame: Frame::default(),
+            #[cfg(feature = "rbpf")]
+            rbpf: None,
+            #[cfg(feature = "gpu")]
+            gpu: None,
+            #[cfg(feature = "gpu")]
+            gpu_sync: crate::gpu::GpuSync::default(),
             resample_interval: 1,
+            auto_interval: None,
             resample_count: 0,
+            credible_level: None,
+            block: None,
+            phase_times: None,
+            validate: false,
+            validation: None,
             vehicle: CCoord::default(),
             gps: CCoord::default(),
             imu: ACoord::default(),
+            imu_dt: None,
+            since_fix: 0.0,
+            recording: None,
+            resampled: false,
+            log_dimension: Some(0.0),
         }
     }
 }
 
 impl BpfState {
+    /// A filter of `nparticles` particles. One particle is a valid if
+    /// fragile filter; none gives estimates at NaN, so callers can size a
+    /// filter from input without special-casing empty runs. Panics unless
+    /// [`Resampler::try_new`] knows `resampler`.
     pub fn new(
         resampler: &str,
         sort: bool,
@@ -265,65 +1006,300 @@ impl BpfState {
         report_particles: i32,
         best_particle: bool,
         resample_interval: usize,
+        params: SimParams,
     ) -> Self {
+        let order = if sort {
+            resample::Ordering::legacy(resampler)
+        } else {
+            resample::Ordering::None
+        };
+        let mut resampler = Resampler::new(resampler, nparticles);
+        resampler.set_output_weights(OutputWeights::Uniform);
+        resampler.prepare(nparticles, nparticles);
         Self {
             pstates: vec![Particles::new(nparticles); 2],
             which_particle: false,
-            resampler: Resampler::new(resampler, nparticles),
-            sort,
+            resampler,
+            params,
+            order,
             nparticles,
             report_particles,
-            best_particle,
+            reporter: Some(Box::new(FileReporter::default())),
+            log_weights: false,
+            estimate_mode: if best_particle {
+                EstimateMode::BestParticle
+            } else {
+                EstimateMode::WeightedMean
+            },
+            kde: Kde::default(),
+            proposal: None,
+            abc: None,
+            time_reversed: false,
+            weight_floor: None,
+            control: None,
+            frame: Frame::default(),
+            #[cfg(feature = "rbpf")]
+            rbpf: None,
+            #[cfg(feature = "gpu")]
+            gpu: None,
+            #[cfg(feature = "gpu")]
+            gpu_sync: crate::gpu::GpuSync::default(),
             resample_interval,
+            auto_interval: None,
             resample_count: 0,
+            credible_level: None,
+            block: None,
+            phase_times: None,
+            validate: false,
+            validation: None,
             vehicle: CCoord::default(),
             gps: CCoord::default(),
             imu: ACoord::default(),
+            imu_dt: None,
+            since_fix: 0.0,
+            recording: None,
+            resampled: false,
+            log_dimension: Some(0.0),
         }
     }
 
-    pub fn init_particles(&mut self) {
+    /// Spread the particles uniformly over the box, with equal weights,
+    /// returning how many were left inside obstacles.
+    pub fn init_particles(&mut self) -> usize {
+        self.init_particles_with(&mut InitDistribution::Uniform)
+    }
+
+    /// Draw the particles from `init`, with equal weights, returning how
+    /// many `init` could only place outside the box or inside obstacles.
+    pub fn init_particles_with(&mut self, init: &mut InitDistribution) -> usize {
         let invscale = 1.0 / self.nparticles as f64;
         self.which_particle = false;
+        self.resampled = false;
+        self.log_dimension = Some(0.0);
+        let mut stuck = 0;
         for particle in &mut self.pstates[0].data {
-            particle.state.init_state();
+            let free;
+            (particle.state, free) = init.sample(&self.params);
+            stuck += !free as usize;
             particle.weight = invscale;
+            #[cfg(feature = "rbpf")]
+            if let Some(model) = &self.rbpf {
+                particle.sub = model.init();
+            }
+        }
+        #[cfg(feature = "gpu")]
+        if let Some(gpu) = &mut self.gpu {
+            gpu.upload(&self.pstates[0]);
+        }
+        stuck
+    }
+
+    /// Run whole steps on the GPU from now on, returning false if there is
+    /// no usable GPU or it cannot run this filter's model. The GPU always
+    /// resamples systematically and ignores `order`, and steps that use
+    /// `proposal`, `abc` or `rbpf` still run on the host. Particles only
+    /// come back to the host as `gpu_sync` says, so on the GPU `map`
+    /// estimates fall back to the mean and credible intervals are left
+    /// out.
+    #[cfg(feature = "gpu")]
+    pub fn enable_gpu(&mut self) -> bool {
+        if !crate::gpu::GpuFilter::supports(&self.params) {
+            return false;
         }
+        let Some(mut gpu) = crate::gpu::GpuFilter::new() else {
+            return false;
+        };
+        gpu.upload(&self.pstates[self.which_particle as usize]);
+        self.gpu = Some(gpu);
+        true
     }
 
-    pub fn parse_line(&mut self, line: String) -> i32 {
-        let measures = line.split(" ").collect::<Vec<&str>>();
-        self.vehicle.x = measures[1]
-            .parse::<f64>()
-            .expect("Failed to parse vehicle x to f64");
-        self.vehicle.y = measures[2]
-            .parse::<f64>()
-            .expect("Failed to parse vehicle y to f64");
-        self.gps.x = measures[3]
-            .parse::<f64>()
-            .expect("Failed to parse gps x to f64");
-        self.gps.y = measures[4]
-            .parse::<f64>()
-            .expect("Failed to parse gps y to f64");
-        self.imu.r = measures[5]
-            .parse::<f64>()
-            .expect("Failed to parse imu r to f64");
-        self.imu.t = measures[6]
-            .parse::<f64>()
-            .expect("Failed to parse imu t to f64");
-
-        measures[0]
-            .parse::<i32>()
-            .expect("Failed to parse t_ms return value to i32")
-    }
-
-    pub fn bpf_step(&mut self, t: f64, dt: f64, report: bool) {
+    /// Copy particles on the GPU back to the host, so `particles` is
+    /// current.
+    #[cfg(feature = "gpu")]
+    pub fn sync_gpu(&mut self) {
+        if let Some(gpu) = &mut self.gpu {
+            gpu.download(&mut self.pstates[self.which_particle as usize]);
+        }
+    }
+
+    #[cfg(feature = "gpu")]
+    fn gpu_step(&mut self, t: f64, dt: f64, report: bool) -> Estimate {
+        self.resample_count = (self.resample_count + 1) % self.resample_interval;
+        let gpu = self.gpu.as_mut().expect("gpu_step needs a GPU");
+        let report = report && self.reporter.is_some();
+        let download = report || self.gpu_sync.due(gpu.steps() + 1);
+        let particles = &mut self.pstates[self.which_particle as usize];
+        let resample = self.resample_count == 0;
+        let est = gpu.step(
+            &self.params,
+            particles,
+            dt,
+            self.gps,
+            self.imu,
+            resample,
+            download,
+        );
+        // The device does not track incremental weights, so the dimension
+        // is only known again from the next resampling on.
+        self.log_dimension = resample.then_some(0.0);
+        // Reported clouds are taken after resampling.
+        if report
+            && let Some(reporter) = &mut self.reporter
+            && let Err(e) = reporter.report(t, &particles.data)
+        {
+            eprintln!("Could not report particles at {}: {}", t, e);
+        }
+        let c = |p: [f32; 2]| CCoord {
+            x: p[0] as f64,
+            y: p[1] as f64,
+        };
+        Estimate {
+            best: c(est.best),
+            best_weight: est.best_weight as f64,
+            worst: c(est.worst),
+            worst_weight: est.worst_weight as f64,
+            mean: c(est.mean),
+            map: c(est.mean),
+            // The device does not sum velocities.
+            velocity: None,
+            speed_clipped: None,
+            mode: self.estimate_mode,
+            interval: None,
+            clamped: 0,
+            held: 0,
+            weight_variance: None,
+            log_dimension: None,
+        }
+    }
+
+    /// Run the step on the GPU when it is enabled and the step needs
+    /// nothing only the host does, bringing the host's particles up to
+    /// date otherwise.
+    #[cfg(feature = "gpu")]
+    fn try_gpu_step(&mut self, t: f64, dt: f64, report: bool, kind: StepKind) -> Option<Estimate> {
+        self.gpu.as_ref()?;
+        let host_only = self.proposal.is_some()
+            || self.abc.is_some()
+            || self.control.is_some()
+            || self.time_reversed;
+        #[cfg(feature = "rbpf")]
+        let host_only = host_only || self.rbpf.is_some();
+        if kind == StepKind::Full && !host_only {
+            return Some(self.gpu_step(t, dt, report));
+        }
+        self.sync_gpu();
+        None
+    }
+
+    #[cfg(not(feature = "gpu"))]
+    fn try_gpu_step(
+        &mut self,
+        _t: f64,
+        _dt: f64,
+        _report: bool,
+        _kind: StepKind,
+    ) -> Option<Estimate> {
+        None
+    }
+
+    /// Hand the host's particles back to the GPU after a step that ran
+    /// here but left the GPU behind.
+    #[cfg(feature = "gpu")]
+    fn resume_gpu(&mut self, kind: StepKind) {
+        if kind != StepKind::Full
+            && let Some(gpu) = &mut self.gpu
+        {
+            gpu.upload(&self.pstates[self.which_particle as usize]);
+        }
+    }
+
+    #[cfg(not(feature = "gpu"))]
+    fn resume_gpu(&mut self, _kind: StepKind) {}
+
+    /// Weigh the fix through particle `i`'s Kalman sub-state, predicted
+    /// `fix_dt` ahead, returning its log likelihood, when particles carry
+    /// them.
+    #[cfg(feature = "rbpf")]
+    fn kalman_fix_log(&mut self, i: usize, fix_dt: f64) -> Option<f64> {
+        let model = self.rbpf.as_deref()?;
+        let p = &mut self.pstates[self.which_particle as usize].data[i];
+        Some(crate::rbpf::update(
+            model,
+            &self.params,
+            &mut p.sub,
+            &p.state,
+            fix_dt,
+            self.gps,
+        ))
+    }
+
+    #[cfg(not(feature = "rbpf"))]
+    fn kalman_fix_log(&mut self, _i: usize, _fix_dt: f64) -> Option<f64> {
+        None
+    }
+
+    /// Parse a data line `t_ms vx vy gps_x gps_y imu_r imu_t`, optionally
+    /// followed by `speed turn_rate` controls, into the current
+    /// measurements and `control`, returning `t_ms`. On error the
+    /// measurements are left unchanged.
+    pub fn parse_line(&mut self, line: &str) -> io::Result<i32> {
+        let rec = Record::parse(line)?;
+        self.load_record(rec);
+        Ok(rec.t_ms)
+    }
+
+    /// Make `rec` the current measurements and `control`, as `parse_line`
+    /// does for a line of text.
+    pub fn load_record(&mut self, rec: Record) {
+        let rec = self.frame.record_in(rec);
+        self.vehicle = rec.vehicle;
+        self.gps = rec.gps;
+        self.imu = rec.imu;
+        self.control = rec.control;
+    }
+
+    pub fn bpf_step(&mut self, t: f64, dt: f64, report: bool) -> Estimate {
+        self.step(t, dt, report, StepKind::Full)
+    }
+
+    fn step(&mut self, t: f64, dt: f64, report: bool, kind: StepKind) -> Estimate {
+        if self.recording.is_none() {
+            return self.run_step(t, dt, report, kind);
+        }
+        let mut step = RecordedStep {
+            kind,
+            t,
+            dt,
+            report,
+            gps: self.gps,
+            imu: self.imu,
+            imu_dt: self.imu_dt,
+            since_fix: self.since_fix,
+            control: self.control,
+            rng: thread_rng_position(),
+            mean: CCoord::default(),
+        };
+        let est = self.run_step(t, dt, report, kind);
+        step.mean = est.mean;
+        if let Some(recording) = &mut self.recording {
+            recording.steps.push(step);
+        }
+        est
+    }
+
+    fn run_step(&mut self, t: f64, dt: f64, report: bool, kind: StepKind) -> Estimate {
+        self.resampled = false;
+        if let Some(est) = self.try_gpu_step(t, dt, report, kind) {
+            return est;
+        }
         let mut tweight;
-        let mut best;
-        #[cfg(feature = "diagnostic-print")]
-        let mut worst = 0usize;
-        let mut best_weight;
-        let mut worst_weight;
+        let mut held = 0;
+        let imu = if self.time_reversed {
+            self.imu.reversed()
+        } else {
+            self.imu
+        };
         let mut est_state = VehicleState::default();
         // est_state.init_state();
         #[cfg(feature = "debug")]
@@ -335,155 +1311,1380 @@ impl BpfState {
             assert!(tweight > 0.00001, "{} < 0.00001", tweight);
         }
         tweight = 0.0;
-        for i in 0..self.nparticles {
-            self.pstates[self.which_particle as usize].data[i]
-                .state
-                .update_state(dt, 1);
-            let gp = self
-                .gps
-                .gps_prob(&self.pstates[self.which_particle as usize].data[i].state);
-            let ip = self.imu.imu_prob(
-                &self.pstates[self.which_particle as usize].data[i].state,
-                dt,
-            );
-            let w = gp * ip * self.pstates[self.which_particle as usize].data[i].weight;
-            #[cfg(feature = "debug")]
-            {
-                if i == 0 {
-                    eprintln!("gp={} ip={} w={}", gp, ip, w);
-                    eprintln!(
-                        "gps=({} {}), imu=(r={}, t={})",
-                        self.gps.x, self.gps.y, self.imu.r, self.imu.t
-                    );
+        let mut max_log_weight = f64::NEG_INFINITY;
+        let mut moments = WeightMoments::default();
+        if let Some(abc) = &mut self.abc {
+            abc.prepare(self.nparticles);
+        }
+        let weigh = kind != StepKind::Predict;
+        // The IMU reading to weigh, and how far the Kalman sub-states have
+        // to predict.
+        let imu_dt = match kind {
+            StepKind::Full => Some(dt),
+            _ => self.imu_dt,
+        };
+        let fix_dt = match kind {
+            StepKind::Full => self.since_fix + dt,
+            _ => self.since_fix,
+        };
+        // Summing each block into the mean while it is still in cache
+        // saves a pass, but only when the weights are final in one pass.
+        let blocked = self
+            .block
+            .filter(|_| !self.log_weights && self.abc.is_none() && self.weight_floor.is_none());
+        let block = blocked.unwrap_or(self.nparticles).max(1);
+        let toroidal = self.params.topology == Topology::Toroidal;
+        let k = PI / self.params.box_dim;
+        let (mut cx, mut sx, mut cy, mut sy) = (0.0, 0.0, 0.0, 0.0);
+        // The motion model moves a whole block before it is weighed, when
+        // nothing else draws random numbers in between.
+        let batched = kind == StepKind::Full
+            && self.proposal.is_none()
+            && self.control.is_none()
+            && self.abc.is_none()
+            && !self.time_reversed;
+        let mut times = PhaseTimes::default();
+        let mut clock = self.phase_times.map(|_| Instant::now());
+        for start in (0..self.nparticles).step_by(block) {
+            let end = (start + block).min(self.nparticles);
+            if batched {
+                let particles = &mut self.pstates[self.which_particle as usize].data;
+                let end = end.min(particles.len());
+                held += update_states(&mut particles[start..end], &self.params, dt, 1);
+                times.propagate += lap(&mut clock);
+            }
+            for i in start..end {
+                let Some(mut p) = self.pstates[self.which_particle as usize].get_mut(i) else {
+                    break;
+                };
+                let correction = match (kind, &mut self.proposal) {
+                    (StepKind::Full, Some(proposal)) => {
+                        proposal.propose(&self.params, p.state_mut(), dt, self.gps, imu)
+                    }
+                    (StepKind::Correct, _) => 0.0,
+                    _ if batched => 0.0,
+                    _ => {
+                        held += match self.control {
+                            Some(control) => {
+                                p.state_mut().update_controlled(&self.params, control, dt)
+                            }
+                            None if self.time_reversed => {
+                                p.state_mut().reverse_state(&self.params, dt, 1)
+                            }
+                            None => p.state_mut().update_state(&self.params, dt, 1),
+                        } as usize;
+                        0.0
+                    }
+                };
+                if !weigh {
+                    tweight += p.weight();
+                    continue;
+                }
+                if let Some(abc) = &mut self.abc {
+                    let dt = imu_dt.unwrap_or(dt);
+                    abc.measure(i, &self.params, p.state(), dt, self.gps, imu);
+                    // The kernel weight waits until the step's epsilon is known.
+                    p.set_weight(ln(p.weight()) + correction);
+                    continue;
                 }
+                // With a Kalman sub-state the fix is weighted through it.
+                let gps_log = self.kalman_fix_log(i, fix_dt);
+                let Some(mut p) = self.pstates[self.which_particle as usize].get_mut(i) else {
+                    break;
+                };
+                if self.log_weights {
+                    let lw = gps_log
+                        .unwrap_or_else(|| self.gps.log_gps_prob(&self.params, p.state()))
+                        + imu_dt.map_or(0.0, |dt| imu.log_imu_prob(&self.params, p.state(), dt))
+                        + ln(p.weight())
+                        + correction;
+                    moments.add_log(p.weight(), lw);
+                    p.set_weight(lw);
+                    max_log_weight = max_log_weight.max(lw);
+                    continue;
+                }
+                let gp =
+                    gps_log.map_or_else(|| self.gps.gps_prob(&self.params, p.state()), f64::exp);
+                let ip = imu_dt.map_or(1.0, |dt| imu.imu_prob(&self.params, p.state(), dt));
+                let w = gp * ip * p.weight() * exp(correction);
+                #[cfg(feature = "debug")]
+                {
+                    if i == 0 {
+                        eprintln!("gp={} ip={} w={}", gp, ip, w);
+                        eprintln!(
+                            "gps=({} {}), imu=(r={}, t={})",
+                            self.gps.x, self.gps.y, self.imu.r, self.imu.t
+                        );
+                    }
+                }
+                moments.add(p.weight(), w);
+                p.set_weight(w);
+                tweight += w;
+            }
+            times.weight += lap(&mut clock);
+            if blocked.is_none() {
+                continue;
+            }
+            for p in &self.pstates[self.which_particle as usize].data[start..end] {
+                let (w, posn) = (p.weight, p.state.posn);
+                if toroidal {
+                    cx += w * cos(k * posn.x);
+                    sx += w * sin(k * posn.x);
+                    cy += w * cos(k * posn.y);
+                    sy += w * sin(k * posn.y);
+                } else {
+                    est_state.posn.x += w * posn.x;
+                    est_state.posn.y += w * posn.y;
+                }
+            }
+            times.estimate += lap(&mut clock);
+        }
+        if weigh {
+            self.imu_dt = None;
+            self.since_fix = 0.0;
+        } else {
+            self.since_fix += dt;
+        }
+        if weigh && let Some(abc) = &mut self.abc {
+            let particles = &mut self.pstates[self.which_particle as usize].data;
+            for (p, lk) in particles.iter_mut().zip(abc.log_kernels()) {
+                p.weight += lk;
+                max_log_weight = max_log_weight.max(p.weight);
+            }
+        }
+        if weigh && (self.log_weights || self.abc.is_some()) {
+            for i in 0..self.nparticles {
+                let p = &mut self.pstates[self.which_particle as usize].data[i];
+                p.weight = exp(p.weight - max_log_weight);
+                tweight += p.weight;
             }
-            self.pstates[self.which_particle as usize].data[i].weight = w;
-            tweight += w;
         }
         #[cfg(feature = "debug")]
         assert!(tweight > 0.00001, "{} < 0.00001", tweight);
+        // Weights only moved are still normalized.
         let invtweight = 1.0 / tweight;
-        for i in 0..self.nparticles {
-            self.pstates[self.which_particle as usize].data[i].weight *= invtweight;
-        }
-        est_state.posn.x = 0.0;
-        est_state.posn.y = 0.0;
-        est_state.vel.r = 0.0;
-        est_state.vel.t = 0.0;
-        if !self.best_particle {
+        let mut clamped = 0;
+        if weigh {
             for i in 0..self.nparticles {
-                let s = &self.pstates[self.which_particle as usize].data[i].state;
-                let w = self.pstates[self.which_particle as usize].data[i].weight;
-                est_state.posn.x += w * s.posn.x;
-                est_state.posn.y += w * s.posn.y;
-                est_state.vel.r += w * s.vel.r;
-                est_state.vel.t = normalize_angle(est_state.vel.t + w * s.vel.t);
-            }
-        }
-        if report {
-            let filename = format!("benchtmp/particles-{}.dat", t);
-            let mut file = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(filename)
-                .unwrap_or_else(|_| panic!("Could not open file at benchtmp/particles-{}.dat", t));
+                self.pstates[self.which_particle as usize].data[i].weight *= invtweight;
+            }
+            if let Some(floor) = self.weight_floor {
+                clamped = floor
+                    .apply(&mut self.pstates[self.which_particle as usize].data[..self.nparticles]);
+            }
+        }
+        if self.validate
+            && self.validation.is_none()
+            && let Err(e) = validate(
+                t,
+                &self.pstates[self.which_particle as usize].data[..self.nparticles],
+                &self.params,
+                weigh,
+            )
+        {
+            self.validation = Some(e);
+        }
+        times.weight += lap(&mut clock);
+        // On a torus the plain weighted mean is wrong near the seam, so
+        // average positions as angles around the box instead.
+        if blocked.is_none() {
             for i in 0..self.nparticles {
-                let px = self.pstates[self.which_particle as usize].data[i]
-                    .state
-                    .posn
-                    .x;
-                let py = self.pstates[self.which_particle as usize].data[i]
-                    .state
-                    .posn
-                    .y;
+                let s = &self.pstates[self.which_particle as usize].data[i].state;
                 let w = self.pstates[self.which_particle as usize].data[i].weight;
-                if let Err(e) = writeln!(file, "{} {} {}", px, py, w) {
-                    eprintln!("Could not write to benchtmp/particles-{}.dat: {}", t, e)
+                if toroidal {
+                    cx += w * cos(k * s.posn.x);
+                    sx += w * sin(k * s.posn.x);
+                    cy += w * cos(k * s.posn.y);
+                    sy += w * sin(k * s.posn.y);
+                } else {
+                    est_state.posn.x += w * s.posn.x;
+                    est_state.posn.y += w * s.posn.y;
                 }
             }
+        } else if !toroidal {
+            est_state.posn.x *= invtweight;
+            est_state.posn.y *= invtweight;
         }
-        self.resample_count = (self.resample_count + 1) % self.resample_interval;
-        if self.resample_count == 0 {
-            let mut new_particle = self.pstates[!self.which_particle as usize].clone();
-            best = self.resampler.resample(
-                tweight,
+        if toroidal {
+            est_state.posn.x = atan2(sx, cx) / k;
+            est_state.posn.y = atan2(sy, cy) / k;
+        }
+        let cloud = &self.pstates[self.which_particle as usize].data[..self.nparticles];
+        let velocity = Some(estimate::velocity(cloud));
+        let speed_clipped = Some(estimate::clipped_fraction(cloud, self.params.max_speed));
+        let mut map = self
+            .kde
+            .peak(&self.pstates[self.which_particle as usize].data[..self.nparticles])
+            .unwrap_or(est_state.posn);
+        let weight_variance = match kind {
+            _ if self.abc.is_some() => None,
+            StepKind::Predict => Some(0.0),
+            _ => Some(moments.relative_variance()),
+        };
+        self.log_dimension = self
+            .log_dimension
+            .zip(weight_variance)
+            .map(|(d, v)| d + ln_1p(v));
+        let log_dimension = self.log_dimension;
+        let interval = self
+            .credible_level
+            .map(|level| self.pstates[self.which_particle as usize].credible_interval(level));
+        times.estimate += lap(&mut clock);
+        if report && let Some(reporter) = &mut self.reporter {
+            let particles = &self.pstates[self.which_particle as usize].data;
+            if let Err(e) = reporter.report(t, particles) {
+                eprintln!("Could not report particles at {}: {}", t, e);
+            }
+        }
+        // Reporting is not a phase.
+        lap(&mut clock);
+        if weigh && let Some(auto) = &mut self.auto_interval {
+            auto.observe(ess_fraction(
+                &self.pstates[self.which_particle as usize].data[..self.nparticles],
+            ));
+            self.resample_interval = auto.interval();
+            self.resample_count = self.resample_count.min(self.resample_interval - 1);
+        }
+        if weigh {
+            self.resample_count = (self.resample_count + 1) % self.resample_interval;
+        }
+        if weigh && self.resample_count == 0 {
+            let (first, second) = self.pstates.split_at_mut(1);
+            let (particle, new_particle) = if self.which_particle {
+                (&mut second[0], &mut first[0])
+            } else {
+                (&mut first[0], &mut second[0])
+            };
+            // The weights were normalized above, so `tweight` no longer
+            // describes them.
+            self.resampler.resample(
+                Weights::NORMALIZED,
                 self.nparticles,
-                &mut self.pstates[self.which_particle as usize],
+                particle,
                 self.nparticles,
-                &mut new_particle,
-                self.sort,
+                new_particle,
+                self.order,
             );
-            self.pstates[!self.which_particle as usize] = new_particle.clone();
             self.which_particle = !self.which_particle;
-            for i in 0..self.nparticles {
-                self.pstates[self.which_particle as usize].data[i].weight =
-                    1.0 / self.nparticles as f64;
+            self.resampled = true;
+            self.log_dimension = Some(0.0);
+            if let Some(auto) = &mut self.auto_interval {
+                auto.resampled(ess_fraction(
+                    &self.pstates[self.which_particle as usize].data[..self.nparticles],
+                ));
             }
         }
-        {
-            best_weight = self.pstates[self.which_particle as usize].data[0].weight;
-            worst_weight = self.pstates[self.which_particle as usize].data[0].weight;
-            best = 0;
-            #[cfg(feature = "diagnostic-print")]
-            {
-                worst = 0;
-            }
-            for i in 1..self.nparticles {
-                if self.pstates[self.which_particle as usize].data[i].weight > best_weight {
-                    best = i;
-                    best_weight = self.pstates[self.which_particle as usize].data[i].weight;
-                } else if self.pstates[self.which_particle as usize].data[i].weight < worst_weight {
-                    #[cfg(feature = "diagnostic-print")]
-                    {
-                        worst = i;
+        times.resample += lap(&mut clock);
+        let extremes =
+            best_worst(&self.pstates[self.which_particle as usize].data[..self.nparticles]);
+        times.estimate += lap(&mut clock);
+        if let Some(total) = &mut self.phase_times {
+            times.steps = 1;
+            *total += times;
+        }
+        // Partial steps run here, leaving the GPU behind.
+        self.resume_gpu(kind);
+        let particles = &self.pstates[self.which_particle as usize].data;
+        let (best, worst) = match extremes {
+            Some((best, worst)) => (particles[best], particles[worst]),
+            // A filter without particles knows nothing, so it puts the
+            // vehicle nowhere rather than at the origin.
+            None => {
+                let mut nowhere = ParticleInfo::default();
+                nowhere.state.posn = CCoord {
+                    x: f64::NAN,
+                    y: f64::NAN,
+                };
+                est_state.posn = nowhere.state.posn;
+                map = nowhere.state.posn;
+                (nowhere, nowhere)
+            }
+        };
+        Estimate {
+            best: best.state.posn,
+            best_weight: best.weight,
+            worst: worst.state.posn,
+            worst_weight: worst.weight,
+            mean: est_state.posn,
+            map,
+            velocity,
+            speed_clipped,
+            mode: self.estimate_mode,
+            interval,
+            clamped,
+            held,
+            weight_variance,
+            log_dimension,
+        }
+    }
+
+    /// Run header describing this filter's configuration. The caller fills
+    /// in the seed and input file.
+    pub fn run_header(&self) -> RunHeader {
+        RunHeader {
+            resampler: self.resampler.name(),
+            nparticles: self.nparticles,
+            order: self.order,
+            estimate: self.estimate_mode,
+            resample_interval: self.resample_interval,
+            log_weights: self.log_weights,
+            gaussian_source: gaussian_source(),
+            params: self.params.clone(),
+            seed: None,
+            input: None,
+        }
+    }
+
+    /// Bytes of heap the filter holds now. Memory on a GPU is not
+    /// counted.
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let particles = self
+            .pstates
+            .iter()
+            .map(|p| p.data.capacity() * size_of::<ParticleInfo>())
+            .sum();
+        let abc = self.abc.as_ref().map_or(0, Abc::heap_bytes);
+        let recording = self.recording.as_ref().map_or(0, Recording::heap_bytes);
+        MemoryFootprint {
+            particles,
+            resampler: self.resampler.scratch_bytes(),
+            other: self.kde.heap_bytes() + abc + recording,
+        }
+    }
+
+    /// The first failed check since the last call, when `validate` is set
+    pub fn validation(&mut self) -> Result<(), ValidationError> {
+        self.validation.take().map_or(Ok(()), Err)
+    }
+
+    /// Weighted steps between resamplings, which `auto_interval` changes
+    /// as the filter runs
+    pub fn resample_interval(&self) -> usize {
+        self.resample_interval
+    }
+
+    /// Weights particles come out of resampling with, `Uniform` unless
+    /// set otherwise
+    pub fn output_weights(&self) -> OutputWeights {
+        self.resampler.output_weights()
+    }
+
+    /// Have resampled particles weigh `1 / n` or carry their parents'
+    /// normalized weights. Steps on the GPU always come out uniform.
+    pub fn set_output_weights(&mut self, output: OutputWeights) {
+        self.resampler.set_output_weights(output);
+    }
+
+    /// What quantizing the weights cost in the last resampling, when
+    /// resampling in fixed point
+    pub fn quantization_error(&self) -> Option<resample::fixed::QuantizationError> {
+        self.resampler.quantization_error()
+    }
+
+    /// The current particle set
+    pub fn particles(&self) -> &[ParticleInfo] {
+        &self.pstates[self.which_particle as usize].data
+    }
+
+    /// The particles, the resampler's working arrays and the latest
+    /// measurements as the last step left them. On the GPU, `sync_gpu`
+    /// first; the GPU's resampler keeps nothing here.
+    pub fn debug_view(&self) -> DebugView<'_> {
+        let n = self.nparticles;
+        let resampler = match self.resampler.internals() {
+            resample::Internals::Tree(tree) => resample::Internals::Tree(&tree[..n]),
+            internals => internals,
+        };
+        DebugView {
+            particles: &self.particles()[..n],
+            resampled_from: self
+                .resampled
+                .then(|| &self.pstates[!self.which_particle as usize].data[..n]),
+            resampler,
+            gps: self.gps,
+            imu: self.imu,
+            imu_dt: self.imu_dt,
+            control: self.control,
+            since_fix: self.since_fix,
+            resample_count: self.resample_count,
+        }
+    }
+
+    /// Record every step from now on, along with the particles and this
+    /// thread's generators as they are now, for [`Replayer`]. Replaying
+    /// from the start of a run is exact; from the middle, any state that
+    /// `abc` or `proposal` carry between steps is not captured. Steps run
+    /// on the GPU draw from the device's generator and do not replay.
+    ///
+    /// [`Replayer`]: crate::replay::Replayer
+    pub fn start_recording(&mut self) {
+        #[cfg(feature = "gpu")]
+        self.sync_gpu();
+        self.recording = Some(Recording {
+            rng: thread_rng_position(),
+            resample_count: self.resample_count,
+            particles: self.particles()[..self.nparticles].to_vec(),
+            steps: Vec::new(),
+        });
+    }
+
+    /// The steps recorded so far
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    /// Put the particles back as `recording` found them.
+    pub(crate) fn restore(&mut self, recording: &Recording) -> Result<(), String> {
+        if recording.particles.len() != self.nparticles {
+            return Err(format!(
+                "recorded {} particles but the filter has {}",
+                recording.particles.len(),
+                self.nparticles
+            ));
+        }
+        self.resample_count = recording.resample_count;
+        let particles = &mut self.pstates[self.which_particle as usize];
+        particles.data[..self.nparticles].copy_from_slice(&recording.particles);
+        #[cfg(feature = "gpu")]
+        if let Some(gpu) = &mut self.gpu {
+            gpu.upload(&self.pstates[self.which_particle as usize]);
+        }
+        Ok(())
+    }
+
+    /// Run `step` again from the measurements it was recorded with.
+    pub(crate) fn replay(&mut self, step: &RecordedStep) -> Estimate {
+        self.gps = step.gps;
+        self.imu = step.imu;
+        self.imu_dt = step.imu_dt;
+        self.since_fix = step.since_fix;
+        self.control = step.control;
+        self.step(step.t, step.dt, step.report, step.kind)
+    }
+
+    /// Feed one GPS fix and IMU reading taken `dt` seconds after the last
+    /// and advance the filter, without any file or console output.
+    pub fn observe(&mut self, t: f64, dt: f64, gps: CCoord, imu: ACoord) -> Estimate {
+        self.gps = self.frame.position_in(gps);
+        self.imu = self.frame.velocity_in(imu);
+        self.bpf_step(t, dt, false)
+    }
+
+    /// Feed one sensor's reading, taken `dt` seconds after the last reading
+    /// that stepped the filter, so GPS and IMU can arrive at their own
+    /// rates. An IMU reading only moves the particles. A GPS fix moves them
+    /// the rest of the way, weights them by the fix and by the latest IMU
+    /// reading if no fix has used it yet, and resamples as usual; a fix at
+    /// the same time as an IMU reading makes the pair one `observe` step.
+    /// Proposals only take part in `observe` steps. Returns the estimate if
+    /// the filter stepped: a truth reading just sets `vehicle`, a control
+    /// sets `control` for the steps after it, and an IMU reading with no
+    /// time elapsed is only kept for the next fix.
+    pub fn observe_reading(&mut self, t: f64, dt: f64, reading: Reading) -> Option<Estimate> {
+        match self.frame.reading_in(reading) {
+            Reading::Imu(imu) => {
+                self.imu = imu;
+                self.imu_dt = Some(dt);
+                (dt > 0.0).then(|| self.step(t, dt, false, StepKind::Predict))
+            }
+            Reading::Gps(gps) => {
+                self.gps = gps;
+                if dt > 0.0 {
+                    self.step(t, dt, false, StepKind::Predict);
+                }
+                Some(self.step(t, 0.0, false, StepKind::Correct))
+            }
+            Reading::Truth(posn) => {
+                self.vehicle = posn;
+                None
+            }
+            Reading::Control(control) => {
+                self.control = Some(control);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{Bounds, DirnTable, GpsEllipse};
+    use std::{
+        f64::consts::{FRAC_1_SQRT_2, SQRT_2},
+        time::Duration,
+    };
+
+    #[test]
+    fn fast_direction_trajectory_error() {
+        for ndirns in [64, 1024] {
+            let exact_params = SimParams::default();
+            let fast_params = SimParams {
+                fast_direction: true,
+                dirn: DirnTable::new(ndirns),
+                ..SimParams::default()
+            };
+            let mut exact = VehicleState::default();
+            let mut fast = VehicleState::default();
+            let (r, dt, steps) = (1.0, 0.01, 1000);
+            let mut max_err = 0f64;
+            for i in 0..steps {
+                let t = normalize_angle(i as f64 * 0.37);
+                let b = exact.bounce(&exact_params, r, t, dt, 0);
+                assert_eq!(b, BounceProblem::BounceOk);
+                let b = fast.bounce(&fast_params, r, t, dt, 0);
+                assert_eq!(b, BounceProblem::BounceOk);
+                let err = (exact.posn.x - fast.posn.x).hypot(exact.posn.y - fast.posn.y);
+                max_err = max_err.max(err);
+            }
+            // Each step moves along a heading at most PI / ndirns off, so the
+            // chord between the two end points is at most r * dt * PI / ndirns.
+            let bound = steps as f64 * r * dt * PI / ndirns as f64;
+            assert!(max_err > 0.0);
+            assert!(
+                max_err <= bound,
+                "max trajectory error {} exceeds {} at ndirns={}",
+                max_err,
+                bound,
+                ndirns
+            );
+        }
+    }
+
+    #[test]
+    fn batched_moves_match() {
+        use crate::map::OccupancyMap;
+        use std::sync::Arc;
+        let map = Arc::new(OccupancyMap::parse(".....\n..#..\n.....\n").unwrap());
+        let cases = [
+            SimParams {
+                box_dim: 2.0,
+                ..SimParams::default()
+            },
+            SimParams {
+                box_dim: 2.0,
+                topology: Topology::Toroidal,
+                integration: crate::sim::Integration::Midpoint,
+                ..SimParams::default()
+            },
+            SimParams {
+                box_dim: 2.0,
+                map: Some(map),
+                fast_direction: true,
+                ..SimParams::default()
+            },
+        ];
+        for params in cases {
+            crate::seed_thread_rng(4709);
+            let mut particles = Particles::new(500);
+            for p in &mut particles.data {
+                p.state.init_state(&params);
+            }
+            let mut one_by_one = particles.clone();
+            crate::seed_thread_rng(1);
+            for _ in 0..20 {
+                update_states(&mut particles.data, &params, 0.5, 1);
+            }
+            crate::seed_thread_rng(1);
+            for _ in 0..20 {
+                for p in &mut one_by_one.data {
+                    p.state.update_state(&params, 0.5, 1);
+                }
+            }
+            let mut walls = 0;
+            for (a, b) in one_by_one.data.iter().zip(&particles.data) {
+                assert_eq!(a.state.posn, b.state.posn);
+                assert_eq!(a.state.vel, b.state.vel);
+                walls += (a.state.posn.x.abs() > 1.5) as usize;
+            }
+            assert!(walls > 0);
+        }
+    }
+
+    #[test]
+    fn sensors_at_their_own_rates() {
+        let params = SimParams::default();
+        let filter = || {
+            let mut bpf = BpfState::new("regular", false, 200, 0, false, 1, params.clone());
+            bpf.reporter = None;
+            bpf.init_particles();
+            bpf
+        };
+        let dt = 0.01;
+        let gps = CCoord { x: 1.0, y: -1.0 };
+        let imu = ACoord { r: 0.5, t: 1.0 };
+
+        // A fix at the same time as each IMU reading is the fused step.
+        crate::seed_thread_rng(4667);
+        let mut fused = filter();
+        let fused: Vec<_> = (0..20)
+            .map(|i| fused.observe(i as f64 * dt, dt, gps, imu))
+            .collect();
+        crate::seed_thread_rng(4667);
+        let mut tagged = filter();
+        for (i, est) in fused.iter().enumerate() {
+            let t = i as f64 * dt;
+            tagged.observe_reading(t, dt, Reading::Imu(imu)).unwrap();
+            assert_eq!(
+                tagged.observe_reading(t, 0.0, Reading::Gps(gps)).as_ref(),
+                Some(est)
+            );
+        }
+
+        // GPS at 10 Hz, IMU at 100 Hz
+        crate::seed_thread_rng(4667);
+        let mut vehicle = VehicleState::default();
+        vehicle.init_state(&params);
+        let mut bpf = filter();
+        let mut sq = 0.0;
+        for step in 1..=3000 {
+            let t = step as f64 * dt;
+            vehicle.update_state(&params, dt, 0);
+            let imu = vehicle.imu_measure(&params, dt);
+            let before: Vec<f64> = bpf.particles().iter().map(|p| p.weight).collect();
+            bpf.observe_reading(t, dt, Reading::Imu(imu)).unwrap();
+            let after: Vec<f64> = bpf.particles().iter().map(|p| p.weight).collect();
+            assert_eq!(before, after);
+            if step % 10 == 0 {
+                let gps = vehicle.gps_measure(&params);
+                let est = bpf.observe_reading(t, 0.0, Reading::Gps(gps)).unwrap();
+                if step > 200 {
+                    sq += (est.mean.x - vehicle.posn.x).powi(2)
+                        + (est.mean.y - vehicle.posn.y).powi(2);
+                }
+            }
+        }
+        // Between fixes the particles only diffuse, so the cloud is wider
+        // than with a fix every step, but a lost filter would be off by
+        // far more.
+        let rms = (sq / 280.0).sqrt();
+        assert!(rms < 3.0, "{}", rms);
+        assert!(
+            bpf.observe_reading(30.0, 0.0, Reading::Truth(gps))
+                .is_none()
+        );
+        assert_eq!(bpf.vehicle, gps);
+    }
+
+    #[test]
+    fn controls_drive_the_motion_model() {
+        let params = SimParams::default();
+        let run = |use_controls: bool| {
+            crate::seed_thread_rng(4669);
+            let mut vehicle = VehicleState::default();
+            vehicle.init_state(&params);
+            let mut bpf = BpfState::new("regular", false, 200, 0, false, 1, params.clone());
+            bpf.reporter = None;
+            bpf.init_particles();
+            let dt = 0.1;
+            let mut sq = 0.0;
+            for step in 1..=1000 {
+                let t = step as f64 * dt;
+                // Weave: the turn rate reverses every five seconds.
+                let control = Control {
+                    speed: 1.0,
+                    turn_rate: if (step / 50) % 2 == 0 { 0.6 } else { -0.6 },
+                };
+                vehicle.update_controlled(&params, control, dt);
+                if use_controls {
+                    bpf.observe_reading(t, 0.0, Reading::Control(control));
+                }
+                let imu = vehicle.imu_measure(&params, dt);
+                bpf.observe_reading(t, dt, Reading::Imu(imu)).unwrap();
+                if step % 10 == 0 {
+                    let gps = vehicle.gps_measure(&params);
+                    let est = bpf.observe_reading(t, 0.0, Reading::Gps(gps)).unwrap();
+                    if step > 100 {
+                        sq += (est.mean.x - vehicle.posn.x).powi(2)
+                            + (est.mean.y - vehicle.posn.y).powi(2);
                     }
-                    worst_weight = self.pstates[self.which_particle as usize].data[i].weight;
                 }
             }
+            (sq / 90.0).sqrt()
+        };
+        let (controlled, walk) = (run(true), run(false));
+        // Between fixes the controls carry the particles along the turn
+        // instead of letting them diffuse around the last heading.
+        assert!(controlled < 0.8 * walk, "{} {}", controlled, walk);
+
+        let mut bpf = BpfState::new("regular", false, 10, 0, false, 1, params.clone());
+        bpf.parse_line("0 1 1 1 1 0.5 0 1.5 -0.25").unwrap();
+        assert_eq!(
+            bpf.control,
+            Some(Control {
+                speed: 1.5,
+                turn_rate: -0.25
+            })
+        );
+        bpf.parse_line("0 1 1 1 1 0.5 0").unwrap();
+        assert_eq!(bpf.control, None);
+        assert!(bpf.parse_line("0 1 1 1 1 0.5 0 1.5").is_err());
+    }
+
+    #[test]
+    fn toroidal_world_wraps() {
+        let params = SimParams {
+            topology: Topology::Toroidal,
+            ..SimParams::default()
+        };
+        let mut state = VehicleState::default();
+        state.posn.x = params.box_dim - 0.1;
+        let b = state.bounce(&params, 1.0, 0.0, 0.5, 0);
+        assert_eq!(b, BounceProblem::BounceOk);
+        assert!((state.posn.x - (0.4 - params.box_dim)).abs() < 1e-12);
+
+        // A fix just across the seam still supports the wrapped particle.
+        let gps = CCoord {
+            x: params.box_dim - 0.1,
+            y: 0.0,
+        };
+        let p = gps.gps_prob(&params, &state);
+        assert!((p - gprob(0.5, params.gps_var)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn reverse_state_retraces() {
+        crate::seed_thread_rng(4712);
+        let params = SimParams {
+            box_dim: 100.0,
+            rvar: 0.0,
+            avar: 0.0,
+            ..SimParams::default()
+        };
+        let start = VehicleState::new(CCoord { x: 1.0, y: -2.0 }, ACoord { r: 1.5, t: 0.4 });
+        let mut state = start;
+        for _ in 0..20 {
+            state.update_state(&params, 0.1, 1);
         }
-        #[cfg(feature = "diagnostic-print")]
-        {
-            print!(
-                "  {} {} {}",
-                best_weight,
-                self.pstates[self.which_particle as usize].data[best]
-                    .state
-                    .posn
-                    .x,
-                self.pstates[self.which_particle as usize].data[best]
-                    .state
-                    .posn
-                    .y,
+        let mut back = state.reversed();
+        for _ in 0..20 {
+            assert!(!back.reverse_state(&params, 0.1, 1));
+        }
+        assert!((back.posn.x - start.posn.x).abs() < 1e-9);
+        assert!((back.posn.y - start.posn.y).abs() < 1e-9);
+        assert_eq!(back.reversed().vel.r, start.vel.r);
+        assert!(angle_delta(back.reversed().vel.t, start.vel.t).abs() < 1e-9);
+    }
+
+    #[test]
+    fn corner_moves() {
+        let params = SimParams::default();
+        let b = params.box_dim;
+        let corner = CCoord { x: b, y: b };
+        // Out of the corner along the diagonal reflects straight back.
+        let out = ACoord {
+            r: 1.0,
+            t: 7.0 * PI / 4.0,
+        };
+        let mut state = VehicleState::new(corner, out);
+        assert!(!state.advance(&params, out.r, out.t, 1.0));
+        let step = FRAC_1_SQRT_2;
+        assert!((state.posn.x - (b - step)).abs() < 1e-12);
+        assert!((state.posn.y - (b - step)).abs() < 1e-12);
+        assert!((state.vel.t - 3.0 * PI / 4.0).abs() < 1e-12);
+        // Along a wall turns back along it.
+        let along = ACoord { r: 1.0, t: 0.0 };
+        let mut state = VehicleState::new(corner, along);
+        assert!(!state.advance(&params, along.r, along.t, 1.0));
+        assert_eq!(state.posn.y, b);
+        assert!((state.posn.x - (b - 1.0)).abs() < 1e-12);
+        // Too far to stay in the box either way holds still.
+        let mut state = VehicleState::new(corner, out);
+        assert!(state.advance(&params, out.r, out.t, 3.0 * b));
+        assert_eq!((state.posn, state.vel), (corner, out));
+
+        crate::seed_thread_rng(4710);
+        let mut bpf = BpfState::new("logm", false, 100, 0, false, 1, params.clone());
+        bpf.reporter = None;
+        bpf.init_particles();
+        let est = bpf.observe(100.0, 100.0, CCoord::default(), ACoord::default());
+        assert!(est.held > 0 && est.held <= 100, "{}", est.held);
+    }
+
+    #[test]
+    fn obstacles_bounce_or_zero_weight() {
+        use crate::map::OccupancyMap;
+        use std::sync::Arc;
+
+        // The right half of the box is a wall.
+        let mut params = SimParams {
+            map: Some(Arc::new(OccupancyMap::parse("..##\n..##\n").unwrap())),
+            ..SimParams::default()
+        };
+        let mut state = VehicleState::default();
+        state.posn.x = -0.5;
+        let b = state.bounce(&params, 1.0, 0.0, 1.0, 0);
+        assert_eq!(b, BounceProblem::BounceX);
+        assert_eq!(state.posn.x, -0.5);
+
+        params.obstacle_policy = ObstaclePolicy::ZeroWeight;
+        let b = state.bounce(&params, 1.0, 0.0, 1.0, 0);
+        assert_eq!(b, BounceProblem::BounceOk);
+        let gps = CCoord { x: 0.5, y: 0.0 };
+        assert_eq!(gps.gps_prob(&params, &state), 0.0);
+    }
+
+    #[test]
+    fn heavy_tailed_gps_likelihoods() {
+        let mut params = SimParams::default();
+        let gps = CCoord::default();
+        let mut near = VehicleState::default();
+        near.posn.x = 0.5;
+        let mut far = VehicleState::default();
+        far.posn.x = 8.0;
+        let tail_ratio = |params: &SimParams| {
+            let p_near = gps.gps_prob(params, &near);
+            let p_far = gps.gps_prob(params, &far);
+            assert!(p_near > p_far);
+            p_far / p_near
+        };
+        let gaussian = tail_ratio(&params);
+        params.gps_likelihood = GpsLikelihood::StudentT { nu: 3.0 };
+        let student_t = tail_ratio(&params);
+        params.gps_likelihood = GpsLikelihood::Mixture { outlier: 0.05 };
+        let mixture = tail_ratio(&params);
+        assert!(student_t > gaussian * 1e6, "{} vs {}", student_t, gaussian);
+        assert!(mixture > gaussian * 1e6, "{} vs {}", mixture, gaussian);
+
+        assert_eq!(
+            "student-t:3".parse::<GpsLikelihood>(),
+            Ok(GpsLikelihood::StudentT { nu: 3.0 })
+        );
+        assert!("mixture".parse::<GpsLikelihood>().is_err());
+    }
+
+    #[test]
+    fn anisotropic_gps() {
+        let mut params = SimParams::default();
+        let gps = CCoord { x: 0.3, y: -0.2 };
+        let state = VehicleState::new(CCoord { x: 1.6, y: -0.6 }, ACoord::default());
+        // A round ellipse is the scalar deviation.
+        let round = GpsEllipse {
+            sd_x: params.gps_var,
+            sd_y: params.gps_var,
+            rho: 0.0,
+        };
+        for likelihood in [
+            GpsLikelihood::Gaussian,
+            GpsLikelihood::StudentT { nu: 3.0 },
+            GpsLikelihood::Mixture { outlier: 0.05 },
+        ] {
+            params.gps_likelihood = likelihood;
+            params.gps_ellipse = None;
+            let (p, lp) = (
+                gps.gps_prob(&params, &state),
+                gps.log_gps_prob(&params, &state),
             );
-            print!(
-                "  {} {} {}",
-                worst_weight,
-                self.pstates[self.which_particle as usize].data[worst]
-                    .state
-                    .posn
-                    .x,
-                self.pstates[self.which_particle as usize].data[worst]
-                    .state
-                    .posn
-                    .y,
+            params.gps_ellipse = Some(round);
+            assert!((gps.gps_prob(&params, &state) - p).abs() < 1e-12);
+            assert!((gps.log_gps_prob(&params, &state) - lp).abs() < 1e-12);
+        }
+
+        // The Gaussian weight is the exponent of the full covariance.
+        let ellipse = "2,0.5,0.6".parse::<GpsEllipse>().unwrap();
+        params.gps_likelihood = GpsLikelihood::Gaussian;
+        params.gps_ellipse = Some(ellipse);
+        let [[a, b], [_, d]] = params.gps_covariance();
+        let (dx, dy) = (1.3, -0.4);
+        let d2 = (d * dx * dx - 2.0 * b * dx * dy + a * dy * dy) / (a * d - b * b);
+        assert!((params.gps_distance_sq(dx, dy) - d2).abs() < 1e-12);
+        let p = gps.gps_prob(&params, &state);
+        assert!((p - exp(-0.5 * params.gps_distance_sq(1.3, -0.4))).abs() < 1e-12);
+
+        // Simulated fixes scatter with that covariance.
+        crate::seed_thread_rng(4716);
+        crate::set_gaussian_source(crate::GaussianSource::BoxMuller);
+        let n = 20000;
+        let fixes: Vec<CCoord> = (0..n).map(|_| state.gps_measure(&params)).collect();
+        crate::set_gaussian_source(crate::GaussianSource::Ziggurat);
+        let moment = |f: &dyn Fn(&CCoord) -> f64| fixes.iter().map(f).sum::<f64>() / n as f64;
+        let (ex, ey) = (moment(&|c| c.x - 1.6), moment(&|c| c.y + 0.6));
+        let cov = [
+            moment(&|c| (c.x - 1.6 - ex).powi(2)),
+            moment(&|c| (c.x - 1.6 - ex) * (c.y + 0.6 - ey)),
+            moment(&|c| (c.y + 0.6 - ey).powi(2)),
+        ];
+        for (got, want) in cov.into_iter().zip([a, b, d]) {
+            assert!((got - want).abs() < 0.05 * a, "{} vs {}", got, want);
+        }
+
+        assert!("1,1,1".parse::<GpsEllipse>().is_err());
+        assert!("1,0".parse::<GpsEllipse>().is_err());
+        assert!("1".parse::<GpsEllipse>().is_err());
+        assert_eq!("1, 2".parse::<GpsEllipse>().unwrap().rho, 0.0);
+    }
+
+    #[test]
+    fn degraded_imu() {
+        let full = SimParams::default();
+        let vehicle = VehicleState::new(CCoord::default(), ACoord { r: 1.0, t: 0.5 });
+        let measure = |mode: ImuMode| {
+            crate::seed_thread_rng(4717);
+            let params = SimParams {
+                imu_mode: mode,
+                ..full.clone()
+            };
+            vehicle.imu_measure(&params, 0.1)
+        };
+        let (both, heading, speed) = (
+            measure(ImuMode::Full),
+            measure(ImuMode::HeadingOnly),
+            measure(ImuMode::SpeedOnly),
+        );
+        assert!(heading.r.is_nan() && speed.t.is_nan());
+        assert_eq!((heading.t, speed.r), (both.t, both.r));
+
+        // The likelihood drops the factor the reading leaves out.
+        let mut state = vehicle;
+        state.vel = ACoord { r: 1.3, t: 0.2 };
+        for (mode, reading, exact) in [
+            (ImuMode::HeadingOnly, heading, ACoord { r: 1.3, ..heading }),
+            (ImuMode::SpeedOnly, speed, ACoord { t: 0.2, ..speed }),
+        ] {
+            let params = SimParams {
+                imu_mode: mode,
+                ..full.clone()
+            };
+            let p = reading.imu_prob(&params, &state, 0.1);
+            assert!(p > 0.0 && p < 1.0);
+            assert_eq!(p, exact.imu_prob(&full, &state, 0.1));
+            assert!((reading.log_imu_prob(&params, &state, 0.1) - p.ln()).abs() < 1e-12);
+        }
+        assert_eq!("heading".parse(), Ok(ImuMode::HeadingOnly));
+        assert!("gyro".parse::<ImuMode>().is_err());
+
+        // A heading-only track filters to finite estimates.
+        let params = SimParams {
+            imu_mode: ImuMode::HeadingOnly,
+            ..full.clone()
+        };
+        let mut vehicle = VehicleState::default();
+        vehicle.init_state(&params);
+        let mut bpf = BpfState::new("regular", false, 100, 0, false, 1, params.clone());
+        bpf.reporter = None;
+        bpf.init_particles();
+        for i in 1..=30 {
+            vehicle.update_state(&params, 0.1, 0);
+            let gps = vehicle.gps_measure(&params);
+            let imu = vehicle.imu_measure(&params, 0.1);
+            let est = bpf.observe(0.1 * i as f64, 0.1, gps, imu);
+            assert!(est.mean.x.is_finite() && est.mean.y.is_finite());
+        }
+    }
+
+    #[test]
+    fn soft_bounds() {
+        let mut params = SimParams::default();
+        let gps = CCoord {
+            x: params.box_dim + 0.5,
+            y: 0.0,
+        };
+        let mut state = VehicleState {
+            posn: CCoord {
+                x: params.box_dim + 1.0,
+                y: -params.box_dim - 1.0,
+            },
+            ..Default::default()
+        };
+        assert_eq!(gps.gps_prob(&params, &state), 0.0);
+        params.bounds = "soft:2".parse().unwrap();
+        let inside = gprob(0.5, 1.0) * gprob(params.box_dim + 1.0, 1.0);
+        let p = gps.gps_prob(&params, &state);
+        assert!((p / inside - exp(-0.5 * SQRT_2)).abs() < 1e-12, "{}", p);
+        assert!((gps.log_gps_prob(&params, &state) - p.ln()).abs() < 1e-12);
+        state.posn.y = 0.0;
+        assert!(gps.gps_prob(&params, &state) < gprob(0.5, 1.0));
+        assert_eq!(params.log_bounds_factor(0.0, params.box_dim), 0.0);
+
+        assert_eq!("hard".parse(), Ok(Bounds::Hard));
+        assert!("soft:0".parse::<Bounds>().is_err());
+        assert!("soft".parse::<Bounds>().is_err());
+    }
+
+    #[test]
+    fn speed_bounds() {
+        use crate::{GaussianSource, set_gaussian_source};
+        use ziggurat_rs::{
+            histogram::ks_statistic,
+            math::{ks_sf, norm_cdf},
+        };
+        // Box-Muller normals are exact, so only the bounds shape the draws.
+        crate::seed_thread_rng(4711);
+        set_gaussian_source(GaussianSource::BoxMuller);
+        let (mean, sd, n) = (1.95, 0.1, 20000);
+        let mut params = SimParams::default();
+        let max = params.max_speed;
+        let phi = |r: f64| norm_cdf((r - mean) / sd);
+        let draws = |params: &SimParams| -> Vec<f64> {
+            (0..n)
+                .map(|_| params.draw_speed(mean, || gaussian(sd)))
+                .collect()
+        };
+
+        let clamped = draws(&params);
+        let pinned = clamped.iter().filter(|&&r| r == max).count() as f64 / n as f64;
+        assert!((pinned - (1.0 - phi(max))).abs() < 0.02, "{}", pinned);
+
+        params.speed_bounds = "reflect".parse().unwrap();
+        let reflected = draws(&params);
+        assert!(reflected.iter().all(|&r| (0.0..max).contains(&r)));
+        let d = ks_statistic(&reflected, |r| phi(r) + 1.0 - phi(2.0 * max - r));
+        assert!(ks_sf(d, n) > 1e-3, "reflect D = {}", d);
+
+        params.speed_bounds = "redraw".parse().unwrap();
+        let redrawn = draws(&params);
+        let d = ks_statistic(&redrawn, |r| phi(r) / phi(max));
+        assert!(ks_sf(d, n) > 1e-3, "redraw D = {}", d);
+        // Hopelessly far off, redrawing gives up and clamps.
+        assert_eq!(params.draw_speed(10.0, || gaussian(sd)), max);
+
+        set_gaussian_source(GaussianSource::Ziggurat);
+        assert!("wrap".parse::<crate::sim::SpeedBounds>().is_err());
+    }
+
+    #[test]
+    fn log_likelihoods_match_linear() {
+        let params = SimParams::default();
+        let gps = CCoord { x: 1.0, y: -2.0 };
+        let imu = ACoord { r: 0.5, t: 1.0 };
+        let mut state = VehicleState::default();
+        state.posn.x = 0.3;
+        state.vel.r = 0.7;
+        state.vel.t = 6.0;
+        let p = gps.gps_prob(&params, &state);
+        assert!((gps.log_gps_prob(&params, &state) - p.ln()).abs() < 1e-12);
+        let p = imu.imu_prob(&params, &state, 0.1);
+        assert!((imu.log_imu_prob(&params, &state, 0.1) - p.ln()).abs() < 1e-12);
+        state.posn.x = 100.0;
+        assert_eq!(gps.log_gps_prob(&params, &state), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn log_weights_survive_underflow() {
+        let mut state = BpfState::new("naive", false, 50, 0, false, 1000, SimParams::default());
+        state.log_weights = true;
+        state.init_particles();
+        // A fix this far out underflows every linear GPS likelihood to zero.
+        state.parse_line("0 0 0 19 -19 1 0").unwrap();
+        state.gps = CCoord { x: 60.0, y: -60.0 };
+        state.bpf_step(0.01, 0.01, false);
+        let weights = &state.pstates[state.which_particle as usize].data;
+        assert!(weights.iter().all(|p| p.weight.is_finite()));
+        let total: f64 = weights.iter().map(|p| p.weight).sum();
+        assert!((total - 1.0).abs() < 1e-9, "{}", total);
+    }
+
+    #[test]
+    fn discretization_options() {
+        use crate::sim::{Integration, NoiseScaling};
+
+        let mut params = SimParams::default();
+        assert_eq!(params.process_noise_scale(0.04), 1.0);
+        params.noise_scaling = NoiseScaling::SqrtDt { nominal_dt: 0.01 };
+        assert!((params.process_noise_scale(0.04) - 2.0).abs() < 1e-12);
+
+        // Turning from heading 0 to PI / 2 at unit speed, the midpoint rule
+        // moves along PI / 4 while Euler moves along the new heading.
+        params.integration = Integration::Midpoint;
+        let mut state = VehicleState::default();
+        state.vel.r = 1.0;
+        let b = state.bounce(&params, 1.0, PI / 2.0, 1.0, 0);
+        assert_eq!(b, BounceProblem::BounceOk);
+        assert!((state.posn.x - (PI / 4.0).cos()).abs() < 1e-12);
+        assert!((state.posn.y + (PI / 4.0).sin()).abs() < 1e-12);
+        // Headings either side of zero average through zero, not PI.
+        let (_, t) = params.step_velocity(1.0, 0.1, 1.0, 2.0 * PI - 0.1);
+        assert!(t.abs() < 1e-12 || (t - 2.0 * PI).abs() < 1e-12, "{}", t);
+    }
+
+    #[test]
+    fn particle_mut_updates_in_place() {
+        let mut particles = Particles::new(3);
+        assert!(particles.get_mut(3).is_none());
+        let mut p = particles.get_mut(1).unwrap();
+        p.set_posn(CCoord { x: 1.0, y: -2.0 });
+        p.set_vel(ACoord { r: 0.5, t: 0.25 });
+        p.set_weight(0.75);
+        assert_eq!(p.vel(), ACoord { r: 0.5, t: 0.25 });
+        assert_eq!(particles.data[1].state.posn, CCoord { x: 1.0, y: -2.0 });
+        assert_eq!(particles.data[1].weight, 0.75);
+        assert_eq!(particles.len(), 3);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn column_views_borrow_the_particles() {
+        crate::seed_thread_rng(4661);
+        let params = SimParams::default();
+        let mut particles = Particles::new(50);
+        for p in &mut particles.data {
+            p.state.init_state(&params);
+            p.weight = uniform();
+        }
+        let view = particles.view();
+        assert_eq!(view.dim(), (50, 5));
+        for (row, p) in view.rows().into_iter().zip(&particles.data) {
+            let vel = p.state.vel();
+            assert_eq!(
+                row.to_vec(),
+                [p.state.posn.x, p.state.posn.y, vel.r, vel.t, p.weight]
             );
         }
-        #[cfg(not(feature = "diagnostic-print"))]
+        let x = particles.column(Column::X);
+        let mean = x.mean().unwrap();
+        let manual = particles.data.iter().map(|p| p.state.posn.x).sum::<f64>() / 50.0;
+        assert!((mean - manual).abs() < 1e-12);
+        // Covariance of the positions straight off the view
+        let posn = view.slice(ndarray::s![.., 0..2]);
+        let centered = &posn - &posn.mean_axis(ndarray::Axis(0)).unwrap();
+        let cov = centered.t().dot(&centered) / 49.0;
+        assert!(cov[[0, 1]] == cov[[1, 0]] && cov[[0, 0]] > 0.0);
+        assert!(Particles::new(0).view().is_empty());
+    }
+
+    #[test]
+    fn weighted_quantiles_and_intervals() {
+        let mut particles = Particles::new(4);
+        for (i, (x, w)) in [(3.0, 0.1), (1.0, 0.2), (4.0, 0.3), (2.0, 0.4)]
+            .into_iter()
+            .enumerate()
         {
-            print!(
-                "  {} {}",
-                self.pstates[self.which_particle as usize].data[best]
-                    .state
-                    .posn
-                    .x,
-                self.pstates[self.which_particle as usize].data[best]
-                    .state
-                    .posn
-                    .y
-            );
+            particles.data[i].state.posn = CCoord { x, y: -x };
+            particles.data[i].weight = w;
         }
-        if !self.best_particle {
-            print!("  {} {}", est_state.posn.x, est_state.posn.y);
+        // Sorted by x the cumulative weights are 0.2, 0.6, 0.7, 1.0.
+        assert_eq!(
+            particles.weighted_quantiles(Axis::X, &[0.0, 0.2, 0.5, 0.65, 1.0]),
+            vec![1.0, 1.0, 2.0, 3.0, 4.0]
+        );
+        assert_eq!(particles.weighted_quantile(Axis::Y, 0.5), -2.0);
+        let (lo, hi) = particles.credible_interval(0.5);
+        assert_eq!((lo.x, hi.x), (2.0, 4.0));
+        assert_eq!((lo.y, hi.y), (-4.0, -2.0));
+        assert!(Particles::new(0).weighted_quantile(Axis::X, 0.5).is_nan());
+    }
+
+    #[test]
+    fn debug_view_exposes_the_resampler() {
+        crate::seed_thread_rng(4675);
+        let gps = CCoord { x: 3.0, y: -2.0 };
+        let imu = ACoord { r: 1.0, t: 0.5 };
+        for (name, interval) in [("logm", 1), ("fixed32", 1), ("regular", 2)] {
+            let mut bpf = BpfState::new(name, false, 50, 0, false, interval, SimParams::default());
+            bpf.reporter = None;
+            bpf.init_particles();
+            bpf.observe(0.1, 0.1, gps, imu);
+            let view = bpf.debug_view();
+            assert_eq!((view.gps, view.imu, view.imu_dt), (gps, imu, None));
+            assert_eq!(view.particles.len(), 50);
+            let total: f64 = view.weights().sum();
+            assert!((total - 1.0).abs() < 1e-12, "{} {}", name, total);
+            match (name, view.resampler, view.resampled_from) {
+                ("logm", resample::Internals::Tree(tree), Some(from)) => {
+                    assert_eq!(tree.len(), 50);
+                    // The root holds the total, and every node its
+                    // particle's weight plus its children's totals.
+                    assert!((tree[0] - 1.0).abs() < 1e-12);
+                    for (i, p) in from.iter().enumerate() {
+                        let child = |c: usize| tree.get(c).copied().unwrap_or(0.0);
+                        let sum = p.weight + child(2 * i + 1) + child(2 * i + 2);
+                        assert!((tree[i] - sum).abs() < 1e-12);
+                    }
+                }
+                ("fixed32", resample::Internals::Quantized(q), Some(from)) => {
+                    assert_eq!(q.iter().sum::<u64>(), u32::MAX as u64);
+                    let heaviest = |i: usize| from.iter().all(|p| p.weight <= from[i].weight);
+                    let top = (0..50).max_by_key(|&i| q[i]).unwrap();
+                    assert!(heaviest(top));
+                }
+                // Every other step resamples.
+                ("regular", resample::Internals::None, None) => {
+                    assert_eq!(view.resample_count, 1);
+                    bpf.observe(0.2, 0.1, gps, imu);
+                    assert!(bpf.debug_view().resampled_from.is_some());
+                }
+                _ => panic!("unexpected view for {}", name),
+            }
+        }
+    }
+
+    #[test]
+    fn blocked_steps_match() {
+        for topology in [Topology::Bounded, Topology::Toroidal] {
+            let params = SimParams {
+                topology,
+                ..SimParams::default()
+            };
+            let mut vehicle = VehicleState::default();
+            vehicle.init_state(&params);
+            let track: Vec<_> = (0..50)
+                .map(|_| {
+                    vehicle.update_state(&params, 0.1, 0);
+                    (
+                        vehicle.gps_measure(&params),
+                        vehicle.imu_measure(&params, 0.1),
+                    )
+                })
+                .collect();
+            let run = |block| {
+                crate::seed_thread_rng(4687);
+                let mut bpf = BpfState::new("logm", false, 300, 0, false, 1, params.clone());
+                bpf.reporter = None;
+                bpf.block = block;
+                // Timing one run must not change it.
+                bpf.phase_times = block.map(|_| PhaseTimes::default());
+                bpf.init_particles();
+                let means: Vec<CCoord> = track
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &(gps, imu))| bpf.observe(i as f64 * 0.1, 0.1, gps, imu).mean)
+                    .collect();
+                (means, bpf.particles().to_vec(), bpf.phase_times)
+            };
+            let (whole, after, untimed) = run(None);
+            let (blocked, blocked_after, times) = run(Some(7));
+            assert_eq!(untimed, None);
+            let times = times.unwrap();
+            assert_eq!(times.steps, 50);
+            assert!(times.propagate > Duration::ZERO && times.weight > Duration::ZERO);
+            for (a, b) in whole.iter().zip(&blocked) {
+                assert!((a.x - b.x).abs() < 1e-9 && (a.y - b.y).abs() < 1e-9);
+            }
+            for (a, b) in after.iter().zip(&blocked_after) {
+                assert_eq!(a.state.posn, b.state.posn);
+            }
+        }
+    }
+
+    #[test]
+    fn tiny_filters() {
+        crate::seed_thread_rng(4691);
+        let params = SimParams::default();
+        for name in resample::Resampler::NAMES {
+            for n in 0..4 {
+                for block in [None, Some(1)] {
+                    let mut bpf = BpfState::new(name, true, n, 0, false, 1, params.clone());
+                    bpf.reporter = None;
+                    bpf.validate = true;
+                    bpf.block = block;
+                    bpf.auto_interval = Some(AutoInterval::default());
+                    bpf.init_particles();
+                    for i in 1..=10 {
+                        let gps = CCoord { x: 0.5, y: -0.5 };
+                        let imu = ACoord { r: 0.1, t: 0.0 };
+                        let est = bpf.observe(i as f64 * 0.1, 0.1, gps, imu);
+                        assert_eq!(bpf.validation(), Ok(()), "{} {}", name, n);
+                        let posn = est.position();
+                        match n {
+                            0 => assert!(posn.x.is_nan() && est.best.y.is_nan()),
+                            1 => {
+                                let p = bpf.particles()[0].state.posn;
+                                assert!((posn.x - p.x).abs() < 1e-9 && (posn.y - p.y).abs() < 1e-9);
+                                assert_eq!(est.best, est.worst);
+                            }
+                            _ => assert!(posn.x.is_finite() && posn.y.is_finite()),
+                        }
+                    }
+                }
+            }
+        }
+        assert!(Particles::new(0).is_empty());
+    }
+
+    #[test]
+    fn weight_floor() {
+        let mut particles = Particles::new(4);
+        for (p, w) in particles.data.iter_mut().zip([0.5, 0.5, 0.0, f64::NAN]) {
+            p.weight = w;
+        }
+        assert_eq!(WeightFloor::Relative(0.5).apply(&mut particles.data), 2);
+        let weights: Vec<f64> = particles.data.iter().map(|p| p.weight).collect();
+        assert_eq!(weights, [1.0 / 3.0, 1.0 / 3.0, 1.0 / 6.0, 1.0 / 6.0]);
+        assert_eq!(WeightFloor::Absolute(0.1).apply(&mut particles.data), 0);
+        assert_eq!("1e-300".parse(), Ok(WeightFloor::Absolute(1e-300)));
+        assert_eq!("rel:1e-9".parse(), Ok(WeightFloor::Relative(1e-9)));
+        assert!("-1".parse::<WeightFloor>().is_err());
+        assert!("0".parse::<WeightFloor>().is_err());
+        assert!("rel:0".parse::<WeightFloor>().is_err());
+        assert!("rel:1.5".parse::<WeightFloor>().is_err());
+        assert_eq!("rel:1".parse(), Ok(WeightFloor::Relative(1.0)));
+        assert!("abs:1".parse::<WeightFloor>().is_err());
+        // With nothing left to normalize the weights start over uniform.
+        for floor in [WeightFloor::Relative(0.5), WeightFloor::Absolute(0.1)] {
+            for w in [0.0, f64::NAN] {
+                for p in &mut particles.data {
+                    p.weight = w;
+                }
+                assert_eq!(floor.apply(&mut particles.data), 4);
+                let weights: Vec<f64> = particles.data.iter().map(|p| p.weight).collect();
+                assert_eq!(weights, [0.25; 4], "{:?} {}", floor, w);
+            }
+        }
+
+        // A fix nowhere near any particle gives every one a likelihood of
+        // zero, which only the floor survives.
+        crate::seed_thread_rng(4707);
+        let params = SimParams::default();
+        let far = CCoord {
+            x: 1e3 * params.box_dim,
+            y: 0.0,
+        };
+        for floor in [None, Some(WeightFloor::Absolute(1e-300))] {
+            let mut bpf = BpfState::new("logm", false, 100, 0, false, 1, params.clone());
+            bpf.reporter = None;
+            bpf.weight_floor = floor;
+            bpf.init_particles();
+            let est = bpf.observe(0.1, 0.1, far, ACoord::default());
+            let from = bpf.debug_view().resampled_from.unwrap();
+            let total: f64 = from.iter().map(|p| p.weight).sum();
+            match floor {
+                None => assert!(est.mean.x.is_nan() && est.clamped == 0),
+                Some(_) => {
+                    assert!((total - 1.0).abs() < 1e-12, "{}", total);
+                    assert_eq!(est.clamped, 100);
+                    assert!(est.mean.x.is_finite() && est.mean.y.is_finite());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_weight_variance() {
+        let params = SimParams::default();
+        let gps = CCoord { x: 1.0, y: -2.0 };
+        let runs: Vec<Vec<Estimate>> = [false, true]
+            .into_iter()
+            .map(|log_weights| {
+                crate::seed_thread_rng(4715);
+                let mut bpf = BpfState::new("regular", false, 200, 0, false, 3, params.clone());
+                bpf.reporter = None;
+                bpf.log_weights = log_weights;
+                bpf.init_particles();
+                let first = bpf.observe(0.1, 0.1, gps, ACoord::default());
+                // From equal weights one step's variance is the whole loss.
+                let ess = crate::interval::ess_fraction(bpf.particles());
+                assert!((ess * first.log_dimension.unwrap().exp() - 1.0).abs() < 1e-9);
+                let rest =
+                    (2..=4).map(|i| bpf.observe(0.1 * i as f64, 0.1, gps, ACoord::default()));
+                std::iter::once(first).chain(rest).collect()
+            })
+            .collect();
+        for (linear, log) in runs[0].iter().zip(&runs[1]) {
+            let (linear, log) = (
+                linear.weight_variance.unwrap(),
+                log.weight_variance.unwrap(),
+            );
+            assert!(linear > 0.0);
+            assert!((linear - log).abs() < 1e-9);
         }
+        let est = &runs[0];
+        assert!(est[1].log_dimension > est[0].log_dimension);
+        assert!(est[2].log_dimension > est[1].log_dimension);
+        // The third step resampled, so the fourth starts again.
+        assert_eq!(est[3].log_dimension, est[3].weight_variance.map(ln_1p));
     }
 }
@@ -0,0 +1,163 @@
+//! Checks on the particles between steps. A NaN in one particle's weight
+//! spreads through the normalization to every weight, and from there into
+//! the estimates, with nothing to say where it started; checking each step
+//! catches it on the step and particle it appears.
+
+use crate::{
+    sim::SimParams,
+    types::{CCoord, ParticleInfo},
+};
+use std::{error, fmt};
+
+/// How far the normalized weights may sum from one
+const WEIGHT_SUM_TOLERANCE: f64 = 1e-6;
+
+/// What is wrong with the particles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Problem {
+    /// One of the particle's numbers is NaN or infinite
+    NonFinite { column: &'static str, value: f64 },
+    /// The particle lies outside the box
+    OutOfBounds(CCoord),
+    /// The weights were just normalized but do not sum to one
+    WeightSum(f64),
+}
+
+/// The first problem found in a step's particles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValidationError {
+    /// Time of the step
+    pub t: f64,
+    /// Index of the offending particle, `None` for the cloud as a whole
+    pub particle: Option<usize>,
+    pub problem: Problem,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at t={}: ", self.t)?;
+        if let Some(i) = self.particle {
+            write!(f, "particle {} ", i)?;
+        }
+        match self.problem {
+            Problem::NonFinite { column, value } => write!(f, "has {} {}", column, value),
+            Problem::OutOfBounds(posn) => {
+                write!(f, "is outside the box at ({}, {})", posn.x, posn.y)
+            }
+            Problem::WeightSum(sum) => write!(f, "weights sum to {}, not 1", sum),
+        }
+    }
+}
+
+impl error::Error for ValidationError {}
+
+/// Every number a particle carries, by name
+fn columns(p: &ParticleInfo) -> impl Iterator<Item = (&'static str, f64)> {
+    let vel = p.state.vel();
+    let state = [
+        ("x", p.state.posn.x),
+        ("y", p.state.posn.y),
+        ("speed", vel.r),
+        ("heading", vel.t),
+        ("weight", p.weight),
+    ];
+    #[cfg(feature = "rbpf")]
+    let sub = [
+        ("sub-state x", p.sub.mean[0]),
+        ("sub-state y", p.sub.mean[1]),
+        ("sub-state covariance xx", p.sub.cov[0][0]),
+        ("sub-state covariance xy", p.sub.cov[0][1]),
+        ("sub-state covariance yx", p.sub.cov[1][0]),
+        ("sub-state covariance yy", p.sub.cov[1][1]),
+    ];
+    #[cfg(not(feature = "rbpf"))]
+    let sub: [(&'static str, f64); 0] = [];
+    state.into_iter().chain(sub)
+}
+
+/// Check the particles of the step at time `t` for non-finite numbers and
+/// positions outside the box, and, if their weights were just
+/// `normalized`, that the weights of a nonempty set sum to one. Only reads
+/// the particles, so separate slices can be checked on separate threads.
+pub fn validate(
+    t: f64,
+    particles: &[ParticleInfo],
+    params: &SimParams,
+    normalized: bool,
+) -> Result<(), ValidationError> {
+    // Bounces land exactly on the walls, give or take rounding.
+    let bound = params.box_dim * (1.0 + 1e-12);
+    let mut sum = 0.0;
+    for (i, p) in particles.iter().enumerate() {
+        let fail = |problem| ValidationError {
+            t,
+            particle: Some(i),
+            problem,
+        };
+        if let Some((column, value)) = columns(p).find(|(_, v)| !v.is_finite()) {
+            return Err(fail(Problem::NonFinite { column, value }));
+        }
+        if p.state.posn.x.abs() > bound || p.state.posn.y.abs() > bound {
+            return Err(fail(Problem::OutOfBounds(p.state.posn)));
+        }
+        sum += p.weight;
+    }
+    if normalized && !particles.is_empty() && (sum - 1.0).abs() > WEIGHT_SUM_TOLERANCE {
+        return Err(ValidationError {
+            t,
+            particle: None,
+            problem: Problem::WeightSum(sum),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ACoord, BpfState};
+
+    #[test]
+    fn catches_bad_particles() {
+        crate::seed_thread_rng(4684);
+        let params = SimParams::default();
+        let mut bpf = BpfState::new("logm", false, 100, 0, false, 1, params.clone());
+        bpf.reporter = None;
+        bpf.validate = true;
+        bpf.init_particles();
+        let imu = ACoord { r: 1.0, t: 0.0 };
+        for i in 1..=10 {
+            bpf.observe(i as f64, 0.01, CCoord { x: 1.0, y: 2.0 }, imu);
+        }
+        assert_eq!(bpf.validation(), Ok(()));
+        let nan = CCoord {
+            x: f64::NAN,
+            y: 2.0,
+        };
+        bpf.observe(11.0, 0.01, nan, imu);
+        bpf.observe(12.0, 0.01, nan, imu);
+        let e = bpf.validation().unwrap_err();
+        assert_eq!((e.t, e.particle), (11.0, Some(0)));
+        assert!(matches!(
+            e.problem,
+            Problem::NonFinite {
+                column: "weight",
+                ..
+            }
+        ));
+        assert_eq!(e.to_string(), "at t=11: particle 0 has weight NaN");
+        assert_eq!(bpf.validation(), Ok(()));
+
+        let mut particles = vec![ParticleInfo::default(); 4];
+        particles.iter_mut().for_each(|p| p.weight = 0.25);
+        assert_eq!(validate(1.0, &particles, &params, true), Ok(()));
+        particles[3].weight = 0.5;
+        let e = validate(1.0, &particles, &params, true).unwrap_err();
+        assert_eq!(e.problem, Problem::WeightSum(1.25));
+        assert!(validate(1.0, &particles, &params, false).is_ok());
+        particles[2].state.posn.y = -2.0 * params.box_dim;
+        let e = validate(1.0, &particles, &params, false).unwrap_err();
+        assert_eq!(e.particle, Some(2));
+        assert!(matches!(e.problem, Problem::OutOfBounds(_)));
+    }
+}
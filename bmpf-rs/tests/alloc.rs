@@ -0,0 +1,75 @@
+//! The steady-state filter step must not allocate.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use bmpf_rs::{
+    resample::Resampler,
+    sim::SimParams,
+    small::SmallFilter,
+    types::{ACoord, BpfState, CCoord},
+};
+
+struct Counting;
+
+thread_local! {
+    static ALLOCS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.with(|a| a.set(a.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCS.with(|a| a.set(a.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocs() -> usize {
+    ALLOCS.with(|a| a.get())
+}
+
+#[test]
+fn steady_state_step_is_allocation_free() {
+    for sampler in Resampler::NAMES {
+        for sort in [false, true] {
+            bmpf_rs::seed_thread_rng(4646);
+            let mut state = BpfState::new(sampler, sort, 200, 0, false, 1, SimParams::default());
+            state.init_particles();
+            state.parse_line("0 1 1 1 1 0.5 0").unwrap();
+            state.bpf_step(0.01, 0.01, false);
+            let before = allocs();
+            for step in 2..50 {
+                state.bpf_step(step as f64 * 0.01, 0.01, false);
+            }
+            assert_eq!(allocs() - before, 0, "{} sort={} allocated", sampler, sort);
+        }
+    }
+}
+
+#[test]
+fn small_filter_never_allocates() {
+    bmpf_rs::seed_thread_rng(4681);
+    let params = SimParams::default();
+    let before = allocs();
+    let mut filter = SmallFilter::<64>::new(64, params);
+    filter.init_particles();
+    let (gps, imu) = (CCoord { x: 1.0, y: 1.0 }, ACoord { r: 0.5, t: 0.0 });
+    for _ in 0..50 {
+        filter.observe(0.01, gps, imu);
+    }
+    drop(filter);
+    assert_eq!(allocs() - before, 0);
+}
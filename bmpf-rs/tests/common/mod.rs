@@ -0,0 +1,129 @@
+//! Helpers shared by the trace comparison tests.
+#![allow(dead_code)]
+
+use bmpf_rs::{estimate::EstimateMode, sim::SimParams, types::BpfState};
+
+/// One output row: vehicle x y, best x y, then mean x y when present.
+pub type Row = Vec<f64>;
+
+/// Run the filter over the data lines `data` the same way the `bpf` example
+/// does, returning its output rows. Reseeds this thread's generator when
+/// `seed` is given.
+pub fn run_rust(sampler: &str, nparticles: usize, data: &str, seed: Option<u32>) -> Vec<Row> {
+    if let Some(seed) = seed {
+        bmpf_rs::seed_thread_rng(seed);
+    }
+    let mut state = BpfState::new(
+        sampler,
+        false,
+        nparticles,
+        0,
+        false,
+        1,
+        SimParams::default(),
+    );
+    state.init_particles();
+    let mut lines = data.lines().filter(|l| !l.trim().is_empty());
+    let mut t = match lines.next() {
+        Some(first) => state.parse_line(first).unwrap() as f64 / 1000.0,
+        None => return Vec::new(),
+    };
+    let mut rows = Vec::new();
+    for line in lines {
+        let t0 = state.parse_line(line).unwrap() as f64 / 1000.0;
+        let dt = t0 - t;
+        t = t0;
+        let mut row = vec![state.vehicle.x, state.vehicle.y];
+        let est = state.bpf_step(t, dt, false);
+        row.extend([est.best.x, est.best.y]);
+        if est.mode != EstimateMode::BestParticle {
+            row.extend([est.mean.x, est.mean.y]);
+        }
+        rows.push(row);
+    }
+    rows
+}
+
+/// Parse whitespace separated output rows, skipping blank lines and `#`
+/// header comments.
+pub fn parse_rows(text: &str) -> Vec<Row> {
+    text.lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            l.split_whitespace()
+                .map(|v| v.parse().unwrap_or_else(|_| panic!("bad number {:?}", v)))
+                .collect()
+        })
+        .collect()
+}
+
+/// Format rows the way the `bpf` example prints them.
+pub fn format_rows(rows: &[Row]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        for (i, pair) in row.chunks(2).enumerate() {
+            let sep = if i == 0 { "" } else { "  " };
+            out += &format!("{}{:?} {:?}", sep, pair[0], pair[1]);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// The first step where `a` and `b` differ by more than `tol` in any
+/// column, or differ in length, described for a failure message.
+pub fn first_divergence(a: &[Row], b: &[Row], tol: f64) -> Option<String> {
+    for (step, (ra, rb)) in a.iter().zip(b).enumerate() {
+        if ra.len() != rb.len() {
+            return Some(format!(
+                "step {}: {} columns vs {} columns",
+                step,
+                ra.len(),
+                rb.len()
+            ));
+        }
+        for (col, (va, vb)) in ra.iter().zip(rb).enumerate() {
+            // NaN on either side counts as a divergence.
+            let diff = (va - vb).abs();
+            if diff.is_nan() || diff > tol {
+                return Some(format!(
+                    "step {} column {}: {} vs {} (|diff| {:e} > {:e})",
+                    step, col, va, vb, diff, tol
+                ));
+            }
+        }
+    }
+    if a.len() != b.len() {
+        return Some(format!("{} steps vs {} steps", a.len(), b.len()));
+    }
+    None
+}
+
+/// Simulate `steps` vehicle data lines, 10ms apart, the way the `vehicle`
+/// example does, after reseeding this thread's generator with `seed`.
+pub fn vehicle_trace(seed: u32, steps: usize) -> String {
+    use bmpf_rs::types::VehicleState;
+
+    bmpf_rs::seed_thread_rng(seed);
+    let params = SimParams::default();
+    let dt = 0.01;
+    let mut vehicle = VehicleState::default();
+    vehicle.init_state(&params);
+    let mut out = String::new();
+    for step in 0..steps {
+        vehicle.update_state(&params, dt, 0);
+        let gps = vehicle.gps_measure(&params);
+        let imu = vehicle.imu_measure(&params, dt);
+        out += &format!(
+            "{} {:?} {:?} {:?} {:?} {:?} {:?}\n",
+            step * 10,
+            vehicle.posn.x,
+            vehicle.posn.y,
+            gps.x,
+            gps.y,
+            imu.r,
+            imu.t
+        );
+    }
+    out
+}
@@ -0,0 +1,124 @@
+//! What every resampler promises, whichever ordering `--sort` picks for
+//! it and however many particles go in and come out.
+
+use bmpf_rs::{
+    resample::{Ordering, Resample, Resampler, Weights},
+    seed_thread_rng,
+    types::{ParticleInfo, Particles},
+    uniform,
+};
+
+/// Orderings to hold each resampler to: none, and what `sort` picks
+fn orderings(name: &str) -> [Ordering; 2] {
+    [Ordering::None, Ordering::legacy(name)]
+}
+
+/// Particles tagged by index in `posn.x`
+fn tagged(weights: &[f64]) -> Particles {
+    let mut p = Particles::new(weights.len());
+    for (i, (info, &w)) in p.data.iter_mut().zip(weights).enumerate() {
+        info.state.posn.x = i as f64;
+        info.weight = w;
+    }
+    p
+}
+
+/// Output of one resampling of `weights` into `n` particles, first filled
+/// with a tag no input has and NaN weights
+fn resample(name: &str, order: Ordering, weights: &[f64], n: usize) -> Vec<ParticleInfo> {
+    let mut from = tagged(weights);
+    let mut to = Particles::new(n);
+    for p in &mut to.data {
+        p.state.posn.x = -1.0;
+        p.weight = f64::NAN;
+    }
+    let total = Weights::sum(&from.data);
+    Resampler::new(name, weights.len()).resample(
+        total,
+        weights.len(),
+        &mut from,
+        n,
+        &mut to,
+        order,
+    );
+    to.data
+}
+
+const SIZES: [(usize, usize); 6] = [(1, 5), (2, 2), (3, 1), (20, 50), (50, 20), (64, 64)];
+
+#[test]
+fn deterministic_given_the_generator() {
+    for name in Resampler::NAMES {
+        for order in orderings(name) {
+            for (m, n) in SIZES {
+                seed_thread_rng(4695);
+                let weights: Vec<f64> = (0..m).map(|_| uniform()).collect();
+                let run = || {
+                    seed_thread_rng(1);
+                    resample(name, order, &weights, n)
+                        .iter()
+                        .map(|p| (p.state.posn.x.to_bits(), p.weight.to_bits()))
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(run(), run(), "{} {:?} {} -> {}", name, order, m, n);
+            }
+        }
+    }
+}
+
+#[test]
+fn never_leaves_stale_particles() {
+    seed_thread_rng(4695);
+    for name in Resampler::NAMES {
+        for order in orderings(name) {
+            for (m, n) in SIZES.into_iter().chain([(5, 6), (5, 17), (7, 640)]) {
+                let weights: Vec<f64> = (0..m).map(|_| uniform()).collect();
+                for p in resample(name, order, &weights, n) {
+                    let tag = p.state.posn.x;
+                    assert!(
+                        tag >= 0.0 && tag < m as f64 && p.weight.is_finite(),
+                        "{} {:?} {} -> {}: tag {} weight {}",
+                        name,
+                        order,
+                        m,
+                        n,
+                        tag,
+                        p.weight
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn orderings_keep_the_distribution() {
+    seed_thread_rng(4695);
+    let weights = [0.02, 0.3, 0.08, 0.15, 0.05, 0.25, 0.1, 0.05];
+    let (n, trials) = (200, 100);
+    let draws = (n * trials) as f64;
+    for name in Resampler::NAMES {
+        for order in orderings(name) {
+            let mut counts = [0.0; 8];
+            for _ in 0..trials {
+                for p in resample(name, order, &weights, n) {
+                    counts[p.state.posn.x as usize] += 1.0;
+                }
+            }
+            for (i, (&c, &w)) in counts.iter().zip(&weights).enumerate() {
+                // Multinomial spread bounds the others; regular is off by
+                // at most a copy per resampling.
+                let tolerance = (5.0 * (w * (1.0 - w) / draws).sqrt()).max(1.0 / n as f64);
+                assert!(
+                    (c / draws - w).abs() < tolerance,
+                    "{} {:?}: particle {} drawn {} of the time, weight {}",
+                    name,
+                    order,
+                    i,
+                    c / draws,
+                    w
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Differential test against the C reference `bpf`.
+//!
+//! Skipped unless `BMPF_C_BPF` names the C binary and `BMPF_DIFF_DATA` a
+//! vehicle data file. Optional settings:
+//!
+//! - `BMPF_C_ARGS`: C argument template, default `{n} {sampler} {file}`
+//! - `BMPF_DIFF_SAMPLERS`: comma separated, default all four
+//! - `BMPF_DIFF_NPARTICLES`: default 100
+//! - `BMPF_DIFF_TOL`: default 1e-6
+//! - `BMPF_DIFF_SEED`: reseed the Rust generator; by default both sides
+//!   use their built in seed
+
+mod common;
+
+use std::{env, fs, process::Command};
+
+fn var_or(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+#[test]
+fn matches_c_reference() {
+    let (Ok(bpf), Ok(data_path)) = (env::var("BMPF_C_BPF"), env::var("BMPF_DIFF_DATA")) else {
+        eprintln!("skipping: set BMPF_C_BPF and BMPF_DIFF_DATA to compare against C");
+        return;
+    };
+    let data = fs::read_to_string(&data_path).expect("could not read BMPF_DIFF_DATA");
+    let template = var_or("BMPF_C_ARGS", "{n} {sampler} {file}");
+    let n: usize = var_or("BMPF_DIFF_NPARTICLES", "100").parse().unwrap();
+    let tol: f64 = var_or("BMPF_DIFF_TOL", "1e-6").parse().unwrap();
+    let seed = env::var("BMPF_DIFF_SEED").ok().map(|s| s.parse().unwrap());
+
+    let mut failures = Vec::new();
+    for sampler in var_or("BMPF_DIFF_SAMPLERS", "naive,logm,optimal,regular").split(',') {
+        let args: Vec<String> = template
+            .split_whitespace()
+            .map(|a| {
+                a.replace("{n}", &n.to_string())
+                    .replace("{sampler}", sampler)
+                    .replace("{file}", &data_path)
+            })
+            .collect();
+        let out = Command::new(&bpf)
+            .args(&args)
+            .output()
+            .expect("could not run BMPF_C_BPF");
+        assert!(
+            out.status.success(),
+            "C bpf {:?} failed: {}",
+            args,
+            out.status
+        );
+        let c_rows = common::parse_rows(&String::from_utf8_lossy(&out.stdout));
+        let rust_rows = common::run_rust(sampler, n, &data, seed);
+        if let Some(msg) = common::first_divergence(&rust_rows, &c_rows, tol) {
+            failures.push(format!("{}: {}", sampler, msg));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "Rust and C diverge:\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn divergence_reporting() {
+    let a = common::parse_rows("0 0  1 1  2 2\n0 0  1 1  2 2\n");
+    let mut b = a.clone();
+    assert_eq!(common::first_divergence(&a, &b, 0.0), None);
+    b[1][3] += 1e-3;
+    let msg = common::first_divergence(&a, &b, 1e-6).unwrap();
+    assert!(msg.starts_with("step 1 column 3"), "{}", msg);
+    assert_eq!(common::parse_rows(&common::format_rows(&a)), a);
+}
@@ -0,0 +1,82 @@
+//! Golden trace regression tests.
+//!
+//! Each resampler is run with a fixed seed over `tests/data/golden.dat` and
+//! its output compared against `tests/golden/<sampler>.out`. After an
+//! intended numerical change, regenerate the data and traces with
+//! `BMPF_BLESS=1 cargo test --test golden -- --test-threads=1` and review
+//! the diff.
+//!
+//! With the `strict-fp` feature the traces under `tests/golden/strict` must
+//! match bit for bit, on every target. Bless those with the feature on;
+//! the data file is only rewritten without it.
+
+mod common;
+
+use std::{env, fs, path::PathBuf};
+
+const SEED: u32 = 4634;
+const NPARTICLES: usize = 100;
+const STEPS: usize = 1000;
+#[cfg(not(feature = "strict-fp"))]
+const TRACES: &str = "golden";
+#[cfg(not(feature = "strict-fp"))]
+const TOL: f64 = 1e-12;
+#[cfg(feature = "strict-fp")]
+const TRACES: &str = "golden/strict";
+#[cfg(feature = "strict-fp")]
+const TOL: f64 = 0.0;
+
+fn path(rel: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(rel)
+}
+
+fn bless() -> bool {
+    env::var_os("BMPF_BLESS").is_some()
+}
+
+fn data() -> String {
+    let file = path("data/golden.dat");
+    if bless() && !cfg!(feature = "strict-fp") {
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, common::vehicle_trace(SEED, STEPS)).unwrap();
+    }
+    fs::read_to_string(&file).expect("missing golden data, run with BMPF_BLESS=1")
+}
+
+fn check(sampler: &str) {
+    let rows = common::run_rust(sampler, NPARTICLES, &data(), Some(SEED));
+    assert_eq!(rows.len(), STEPS - 1);
+    let file = path(&format!("{}/{}.out", TRACES, sampler));
+    if bless() {
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, common::format_rows(&rows)).unwrap();
+    }
+    let golden = common::parse_rows(
+        &fs::read_to_string(&file).expect("missing golden trace, run with BMPF_BLESS=1"),
+    );
+    if let Some(msg) = common::first_divergence(&rows, &golden, TOL) {
+        panic!("{} trace changed at {}", sampler, msg);
+    }
+}
+
+#[test]
+fn golden_logm() {
+    check("logm");
+}
+
+#[test]
+fn golden_naive() {
+    check("naive");
+}
+
+#[test]
+fn golden_optimal() {
+    check("optimal");
+}
+
+#[test]
+fn golden_regular() {
+    check("regular");
+}
@@ -0,0 +1,167 @@
+//! Browser bindings for the bootstrap particle filter.
+//!
+//! Build with `wasm-pack build bmpf-wasm --target web`, then from JS:
+//!
+//! ```js
+//! const f = new Filter("logm", 1000, undefined);
+//! const est = f.observe(0.1, gpsX, gpsY, imuR, imuT); // throws on bad input
+//! draw(f.particles(), est.x, est.y);
+//! ```
+
+use bmpf_rs::{
+    resample::Resampler,
+    seed_thread_rng,
+    sim::SimParams,
+    types::{ACoord, BpfState, CCoord},
+};
+use wasm_bindgen::prelude::*;
+
+/// Filter estimate for one step, flattened for JS
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Estimate {
+    /// Position in the filter's estimate mode, the weighted mean by default
+    pub x: f64,
+    pub y: f64,
+    pub best_x: f64,
+    pub best_y: f64,
+    pub best_weight: f64,
+}
+
+#[wasm_bindgen]
+pub struct Filter {
+    state: BpfState,
+    t: f64,
+}
+
+#[wasm_bindgen]
+impl Filter {
+    /// New filter with `nparticles` particles spread over the default box.
+    /// Without a `seed` the generator is seeded from system entropy.
+    #[wasm_bindgen(constructor)]
+    pub fn new(sampler: &str, nparticles: usize, seed: Option<u32>) -> Result<Filter, JsError> {
+        if Resampler::try_new(sampler, nparticles).is_none() {
+            return Err(JsError::new(&format!("unknown resampler {}", sampler)));
+        }
+        if nparticles == 0 {
+            return Err(JsError::new("need at least one particle"));
+        }
+        let seed = match seed {
+            Some(seed) => seed,
+            None => {
+                let mut buf = [0u8; 4];
+                getrandom::getrandom(&mut buf).map_err(|e| JsError::new(&e.to_string()))?;
+                u32::from_le_bytes(buf)
+            }
+        };
+        seed_thread_rng(seed);
+        let mut state = BpfState::new(
+            sampler,
+            false,
+            nparticles,
+            0,
+            false,
+            1,
+            SimParams::default(),
+        );
+        state.init_particles();
+        Ok(Self { state, t: 0.0 })
+    }
+
+    /// Advance by `dt` seconds given a GPS fix and an IMU reading. Throws,
+    /// leaving the filter as it was, unless `dt` is positive and every
+    /// input finite.
+    pub fn observe(
+        &mut self,
+        dt: f64,
+        gps_x: f64,
+        gps_y: f64,
+        imu_r: f64,
+        imu_t: f64,
+    ) -> Result<Estimate, JsError> {
+        self.step(dt, gps_x, gps_y, imu_r, imu_t)
+            .map_err(|e| JsError::new(&e))
+    }
+
+    /// Particle positions as `[x0, y0, x1, y1, ...]`
+    pub fn particles(&self) -> Vec<f64> {
+        self.state
+            .particles()
+            .iter()
+            .flat_map(|p| [p.state.posn.x, p.state.posn.y])
+            .collect()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn time(&self) -> f64 {
+        self.t
+    }
+}
+
+impl Filter {
+    /// `observe`, with errors as strings, which unlike `JsError` exist
+    /// off the web too
+    fn step(
+        &mut self,
+        dt: f64,
+        gps_x: f64,
+        gps_y: f64,
+        imu_r: f64,
+        imu_t: f64,
+    ) -> Result<Estimate, String> {
+        if !(dt > 0.0 && dt.is_finite()) {
+            return Err(format!("time step {} is not positive", dt));
+        }
+        if ![gps_x, gps_y, imu_r, imu_t].iter().all(|v| v.is_finite()) {
+            return Err(format!(
+                "GPS fix ({}, {}) or IMU reading ({}, {}) is not finite",
+                gps_x, gps_y, imu_r, imu_t
+            ));
+        }
+        self.t += dt;
+        let est = self.state.observe(
+            self.t,
+            dt,
+            CCoord { x: gps_x, y: gps_y },
+            ACoord { r: imu_r, t: imu_t },
+        );
+        let posn = est.position();
+        Ok(Estimate {
+            x: posn.x,
+            y: posn.y,
+            best_x: est.best.x,
+            best_y: est.best.y,
+            best_weight: est.best_weight,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_a_fix() {
+        let mut f = Filter::new("regular", 200, Some(3)).unwrap_or_else(|_| unreachable!());
+        let mut est = Estimate::default();
+        for _ in 0..10 {
+            est = f.step(0.1, 0.5, 0.5, 0.0, 0.0).unwrap();
+        }
+        assert!((est.x - 0.5).abs() < 0.5 && (est.y - 0.5).abs() < 0.5);
+        assert_eq!(f.particles().len(), 400);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let mut f = Filter::new("regular", 20, Some(3)).unwrap_or_else(|_| unreachable!());
+        for dt in [0.0, -0.1, f64::NAN, f64::INFINITY] {
+            assert!(f.step(dt, 0.5, 0.5, 0.0, 0.0).is_err(), "{}", dt);
+        }
+        for v in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(f.step(0.1, v, 0.5, 0.0, 0.0).is_err());
+            assert!(f.step(0.1, 0.5, 0.5, 0.0, v).is_err());
+        }
+        assert_eq!(f.time(), 0.0);
+        assert!(f.step(0.1, 0.5, 0.5, 0.0, 0.0).is_ok());
+    }
+}
@@ -0,0 +1,79 @@
+//! Variate throughput by generator, seed and batch size.
+//!
+//! `cargo bench -p ziggurat-rs --bench variates`, adding `--features stats`
+//! to also print the slow-path frequencies.
+
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use ziggurat_rs::{BoxMuller, Ziggurat};
+
+const SEEDS: [u32; 3] = [17, 1, 0xdead_beef];
+const BATCHES: [usize; 3] = [1, 1024, 65536];
+const POLY_N: i32 = 51;
+
+fn variates(c: &mut Criterion) {
+    let mut group = c.benchmark_group("variates");
+    for batch in BATCHES {
+        group.throughput(Throughput::Elements(batch as u64));
+        for seed in SEEDS {
+            let id = |name: &str| BenchmarkId::new(name, format!("{}/seed={}", batch, seed));
+            let mut z = Ziggurat::new(seed);
+            group.bench_function(id("normal"), |b| {
+                b.iter(|| (0..batch).map(|_| z.normal()).sum::<f64>())
+            });
+            let mut z = Ziggurat::new(seed);
+            group.bench_function(id("normal2"), |b| {
+                b.iter(|| {
+                    (0..batch.div_ceil(2))
+                        .map(|_| z.normal2().iter().sum::<f64>())
+                        .sum::<f64>()
+                })
+            });
+            let mut z = Ziggurat::new(seed);
+            group.bench_function(id("buffered"), |b| {
+                b.iter(|| (0..batch).map(|_| z.next_from_buffer()).sum::<f64>())
+            });
+            let mut z = Ziggurat::new(seed);
+            group.bench_function(id("exponential"), |b| {
+                b.iter(|| (0..batch).map(|_| z.exponential()).sum::<f64>())
+            });
+            let mut z = Ziggurat::new(seed);
+            group.bench_function(id("polynomial"), |b| {
+                b.iter(|| {
+                    (0..batch)
+                        .map(|_| z.polynomial(black_box(POLY_N)))
+                        .sum::<f64>()
+                })
+            });
+            let mut bm = BoxMuller::new(seed);
+            group.bench_function(id("box_muller"), |b| {
+                b.iter(|| (0..batch).map(|_| bm.normal()).sum::<f64>())
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Print the fast-path acceptance rates behind the timings.
+#[cfg(feature = "stats")]
+fn slow_path_rates(_: &mut Criterion) {
+    for seed in SEEDS {
+        let mut z = Ziggurat::new(seed);
+        for _ in 0..1_000_000 {
+            black_box(z.normal());
+            black_box(z.exponential());
+        }
+        let stats = z.stats();
+        println!(
+            "seed {}: normal fast {:.4}, exponential fast {:.4}",
+            seed,
+            stats.normal.fast_fraction(),
+            stats.exponential.fast_fraction()
+        );
+    }
+}
+
+#[cfg(not(feature = "stats"))]
+criterion_group!(benches, variates);
+#[cfg(feature = "stats")]
+criterion_group!(benches, variates, slow_path_rates);
+criterion_main!(benches);
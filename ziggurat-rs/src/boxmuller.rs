@@ -0,0 +1,124 @@
+//! Box-Muller Gaussian generator
+//!
+//! A slow but simple reference source of normal variates, useful for A/B
+//! checking the Ziggurat output. It draws from its own ISAAC stream, so it
+//! never perturbs a `Ziggurat` sequence.
+
+use crate::{
+    BlockSeeded, StreamPosition,
+    isaac::IsaacRng,
+    math::{cos, ln, sin},
+    variates::Bits,
+};
+
+/// Box-Muller normal generator with its own seeded state
+pub struct BoxMuller {
+    rng: IsaacRng,
+    spare: Option<f64>,
+}
+
+impl BoxMuller {
+    /// Create a new Box-Muller generator with the given seed
+    pub fn new(seed: u32) -> Self {
+        let mut rng = IsaacRng::new();
+        rng.seed(seed);
+        Self { rng, spare: None }
+    }
+
+    /// A generator at `pos` in its stream, holding `spare` as
+    /// [`BoxMuller::spare`] reported it there, or an error if `pos` is in
+    /// a block-seeded stream
+    pub fn at(pos: StreamPosition, spare: Option<f64>) -> Result<Self, BlockSeeded> {
+        if pos.block {
+            return Err(BlockSeeded);
+        }
+        let mut rng = IsaacRng::new();
+        rng.seek(pos.seed, pos.draws);
+        Ok(Self { rng, spare })
+    }
+
+    /// Where this generator is in its stream
+    pub fn position(&self) -> StreamPosition {
+        let (seed, draws) = self.rng.position();
+        StreamPosition {
+            seed,
+            draws,
+            block: self.rng.block_seeded(),
+        }
+    }
+
+    /// The second variate of the last pair, if not yet returned
+    pub fn spare(&self) -> Option<f64> {
+        self.spare
+    }
+
+    /// Generate a uniform random number in [0, 1)
+    #[inline]
+    pub fn uniform(&mut self) -> f64 {
+        self.rng.uniform()
+    }
+
+    /// Generate a standard normal random variable (mean=0, stddev=1)
+    ///
+    /// Variates are produced in pairs; the second of each pair is cached
+    /// and returned by the next call.
+    pub fn normal(&mut self) -> f64 {
+        if let Some(z) = self.spare.take() {
+            return z;
+        }
+        // 1 - U lies in (0, 1], keeping ln() finite
+        let u1 = 1.0 - self.uniform();
+        let u2 = self.uniform();
+        let r = (-2.0 * ln(u1)).sqrt();
+        let theta = 2.0 * std::f64::consts::PI * u2;
+        self.spare = Some(r * sin(theta));
+        r * cos(theta)
+    }
+
+    /// Generate a Gaussian random variable with given standard deviation
+    #[inline]
+    pub fn gaussian(&mut self, sigma: f64) -> f64 {
+        self.normal() * sigma
+    }
+}
+
+impl Default for BoxMuller {
+    fn default() -> Self {
+        Self::new(17)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_box_muller_moments() {
+        let mut rng = BoxMuller::new(42);
+        let n = 10000;
+        let mut sum = 0.0;
+        let mut sum_sq = 0.0;
+        for _ in 0..n {
+            let x = rng.normal();
+            sum += x;
+            sum_sq += x * x;
+        }
+        let mean = sum / n as f64;
+        let variance = sum_sq / n as f64 - mean * mean;
+        assert!(mean.abs() < 0.1, "Mean should be close to 0, got {}", mean);
+        assert!(
+            (variance - 1.0).abs() < 0.1,
+            "Variance should be close to 1, got {}",
+            variance
+        );
+    }
+
+    #[test]
+    fn test_box_muller_deterministic() {
+        let mut a = BoxMuller::new(7);
+        let mut b = BoxMuller::new(7);
+        for _ in 0..100 {
+            assert_eq!(a.normal().to_bits(), b.normal().to_bits());
+        }
+    }
+}
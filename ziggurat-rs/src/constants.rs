@@ -1,8 +1,11 @@
 //! Constants for the Ziggurat algorithm
 
+// 2^-64, scaling a pair of 32-bit words to [0, 1)
+pub const UNIFORM_SCALE: f64 = 5.42101086242752e-20;
+
 // Normal distribution constants
-pub const ZIGGURAT_NOR_R: f64 = 3.654_152_885_361_008_8;
-pub const ZIGGURAT_NOR_INV_R: f64 = 0.273_661_237_329_758_28;
+pub const ZIGGURAT_NOR_R: f64 = 3.654_152_885_361_009;
+pub const ZIGGURAT_NOR_INV_R: f64 = 0.273_661_237_329_758_3;
 
 // Exponential distribution constants
-pub const ZIGGURAT_EXP_R: f64 = 7.697_117_470_131_049_72;
+pub const ZIGGURAT_EXP_R: f64 = 7.697_117_470_131_05;
@@ -0,0 +1,155 @@
+//! The ziggurat samplers as `rand` distributions, drawing from any
+//! `RngCore` instead of the built-in ISAAC stream.
+//!
+//! [`Ziggurat`](crate::Ziggurat) mixes the previous word it drew into each
+//! strip index. A distribution holds no state between samples, so each
+//! sample draws one extra word to stand in for it.
+
+use crate::variates::{self, Bits};
+use rand::{Rng, RngCore, distributions::Distribution};
+
+struct RngBits<'a, R: ?Sized>(&'a mut R);
+
+impl<R: RngCore + ?Sized> Bits for RngBits<'_, R> {
+    #[inline]
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+}
+
+/// Normal distribution by the ziggurat method
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NormalZig {
+    pub mean: f64,
+    pub std_dev: f64,
+}
+
+impl NormalZig {
+    pub fn new(mean: f64, std_dev: f64) -> Self {
+        Self { mean, std_dev }
+    }
+}
+
+impl Default for NormalZig {
+    /// Standard normal
+    fn default() -> Self {
+        Self::new(0.0, 1.0)
+    }
+}
+
+impl Distribution<f64> for NormalZig {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        let mut bits = RngBits(rng);
+        let mut last = bits.next_u32();
+        self.mean + self.std_dev * variates::normal(&mut bits, &mut last, None)
+    }
+}
+
+/// Exponential distribution with rate `lambda` by the ziggurat method
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExpZig {
+    pub lambda: f64,
+}
+
+impl ExpZig {
+    pub fn new(lambda: f64) -> Self {
+        Self { lambda }
+    }
+}
+
+impl Default for ExpZig {
+    /// Rate one
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl Distribution<f64> for ExpZig {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        let mut bits = RngBits(rng);
+        let mut last = bits.next_u32();
+        variates::exponential(&mut bits, &mut last, None) / self.lambda
+    }
+}
+
+/// Distribution on [0, 1] with density proportional to `(1 - x)^n`, the
+/// smallest of `n + 1` uniforms
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PolynomialZig {
+    pub n: i32,
+}
+
+impl PolynomialZig {
+    pub fn new(n: i32) -> Self {
+        Self { n }
+    }
+}
+
+impl Distribution<f64> for PolynomialZig {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        variates::polynomial(&mut RngBits(rng), self.n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SplitMix64, to show the adapters need nothing from the crate's own
+    /// generator
+    struct SplitMix(u64);
+
+    impl RngCore for SplitMix {
+        fn next_u32(&mut self) -> u32 {
+            (self.next_u64() >> 32) as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand::rngs::mock::StepRng::new(self.next_u64(), 1).fill_bytes(dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    /// Sample mean and variance of `n` draws from `dist`
+    fn moments(dist: impl Distribution<f64>, n: usize) -> (f64, f64) {
+        let mut rng = SplitMix(17);
+        let xs: Vec<f64> = dist.sample_iter(&mut rng).take(n).collect();
+        let mean = xs.iter().sum::<f64>() / n as f64;
+        let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+        (mean, var)
+    }
+
+    #[test]
+    fn adapters_have_the_right_moments() {
+        let n = 200_000;
+        let (mean, var) = moments(NormalZig::new(3.0, 2.0), n);
+        assert!(
+            (mean - 3.0).abs() < 0.02 && (var - 4.0).abs() < 0.05,
+            "{} {}",
+            mean,
+            var
+        );
+        let (mean, var) = moments(ExpZig::new(2.0), n);
+        assert!(
+            (mean - 0.5).abs() < 0.005 && (var - 0.25).abs() < 0.01,
+            "{} {}",
+            mean,
+            var
+        );
+        // Beta(1, n + 1)
+        let (mean, _) = moments(PolynomialZig::new(3), n);
+        assert!((mean - 0.2).abs() < 0.002, "{}", mean);
+    }
+}
@@ -0,0 +1,276 @@
+//! Weighted histograms for checking generated variates against their
+//! distributions.
+
+/// Fixed-width 1D histogram over `[lo, hi]`. The top edge belongs to the
+/// last bin; values outside the range (and NaN) land in the outflow bins.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    lo: f64,
+    hi: f64,
+    bins: Vec<f64>,
+    underflow: f64,
+    overflow: f64,
+}
+
+impl Histogram {
+    pub fn new(lo: f64, hi: f64, nbins: usize) -> Self {
+        assert!(nbins > 0, "histogram needs at least one bin");
+        assert!(lo < hi, "histogram range {}..{} is empty", lo, hi);
+        Self {
+            lo,
+            hi,
+            bins: vec![0.0; nbins],
+            underflow: 0.0,
+            overflow: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, x: f64) {
+        self.add_weighted(x, 1.0);
+    }
+
+    pub fn add_weighted(&mut self, x: f64, w: f64) {
+        match bin_index(self.lo, self.hi, self.bins.len(), x) {
+            Bin::Under => self.underflow += w,
+            Bin::In(i) => self.bins[i] += w,
+            Bin::Over => self.overflow += w,
+        }
+    }
+
+    pub fn nbins(&self) -> usize {
+        self.bins.len()
+    }
+
+    pub fn bin_width(&self) -> f64 {
+        (self.hi - self.lo) / self.bins.len() as f64
+    }
+
+    /// Lower and upper edge of bin `i`
+    pub fn edges(&self, i: usize) -> (f64, f64) {
+        let w = self.bin_width();
+        (self.lo + w * i as f64, self.lo + w * (i + 1) as f64)
+    }
+
+    pub fn center(&self, i: usize) -> f64 {
+        self.lo + self.bin_width() * (i as f64 + 0.5)
+    }
+
+    pub fn count(&self, i: usize) -> f64 {
+        self.bins[i]
+    }
+
+    pub fn underflow(&self) -> f64 {
+        self.underflow
+    }
+
+    pub fn overflow(&self) -> f64 {
+        self.overflow
+    }
+
+    /// Total weight, including outflow
+    pub fn total(&self) -> f64 {
+        self.bins.iter().sum::<f64>() + self.underflow + self.overflow
+    }
+
+    /// Expected count in each bin for `n` samples from the distribution
+    /// with cumulative distribution `cdf`.
+    pub fn expected_counts(&self, n: f64, cdf: impl Fn(f64) -> f64) -> Vec<f64> {
+        (0..self.nbins())
+            .map(|i| {
+                let (x0, x1) = self.edges(i);
+                n * (cdf(x1) - cdf(x0))
+            })
+            .collect()
+    }
+
+    /// Pearson chi-square statistic of the bin counts against `expected`,
+    /// skipping bins expected to be empty.
+    pub fn chi_square(&self, expected: &[f64]) -> f64 {
+        chi_square(&self.bins, expected)
+    }
+}
+
+/// Fixed-width 2D histogram over `[x_lo, x_hi] x [y_lo, y_hi]`, with one
+/// outflow bin for points outside either range.
+#[derive(Clone, Debug)]
+pub struct Histogram2D {
+    x: (f64, f64, usize),
+    y: (f64, f64, usize),
+    bins: Vec<f64>,
+    outflow: f64,
+}
+
+impl Histogram2D {
+    pub fn new(x_lo: f64, x_hi: f64, nx: usize, y_lo: f64, y_hi: f64, ny: usize) -> Self {
+        assert!(nx > 0 && ny > 0, "histogram needs at least one bin");
+        assert!(x_lo < x_hi && y_lo < y_hi, "histogram range is empty");
+        Self {
+            x: (x_lo, x_hi, nx),
+            y: (y_lo, y_hi, ny),
+            bins: vec![0.0; nx * ny],
+            outflow: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, x: f64, y: f64) {
+        self.add_weighted(x, y, 1.0);
+    }
+
+    pub fn add_weighted(&mut self, x: f64, y: f64, w: f64) {
+        let i = bin_index(self.x.0, self.x.1, self.x.2, x);
+        let j = bin_index(self.y.0, self.y.1, self.y.2, y);
+        match (i, j) {
+            (Bin::In(i), Bin::In(j)) => self.bins[j * self.x.2 + i] += w,
+            _ => self.outflow += w,
+        }
+    }
+
+    /// Number of bins along x and y
+    pub fn shape(&self) -> (usize, usize) {
+        (self.x.2, self.y.2)
+    }
+
+    pub fn center(&self, i: usize, j: usize) -> (f64, f64) {
+        let wx = (self.x.1 - self.x.0) / self.x.2 as f64;
+        let wy = (self.y.1 - self.y.0) / self.y.2 as f64;
+        (
+            self.x.0 + wx * (i as f64 + 0.5),
+            self.y.0 + wy * (j as f64 + 0.5),
+        )
+    }
+
+    pub fn count(&self, i: usize, j: usize) -> f64 {
+        self.bins[j * self.x.2 + i]
+    }
+
+    pub fn outflow(&self) -> f64 {
+        self.outflow
+    }
+
+    /// Total weight, including outflow
+    pub fn total(&self) -> f64 {
+        self.bins.iter().sum::<f64>() + self.outflow
+    }
+
+    /// Pearson chi-square statistic against `expected`, laid out row by
+    /// row in `y`, skipping bins expected to be empty.
+    pub fn chi_square(&self, expected: &[f64]) -> f64 {
+        chi_square(&self.bins, expected)
+    }
+}
+
+enum Bin {
+    Under,
+    In(usize),
+    Over,
+}
+
+fn bin_index(lo: f64, hi: f64, nbins: usize, x: f64) -> Bin {
+    if x < lo {
+        Bin::Under
+    } else if x <= hi {
+        let i = ((x - lo) / (hi - lo) * nbins as f64).floor() as usize;
+        Bin::In(i.min(nbins - 1))
+    } else {
+        Bin::Over
+    }
+}
+
+/// Pearson chi-square statistic of `observed` against `expected`, pooling
+/// runs of adjacent bins until each expects at least `min_expected`, and
+/// the number of pooled bins. A short run left at the end joins the last
+/// pooled bin.
+pub fn pooled_chi_square(observed: &[f64], expected: &[f64], min_expected: f64) -> (f64, usize) {
+    assert_eq!(observed.len(), expected.len(), "bin count mismatch");
+    let mut pooled: Vec<(f64, f64)> = Vec::new();
+    let mut run = (0.0, 0.0);
+    for (&o, &e) in observed.iter().zip(expected) {
+        run = (run.0 + o, run.1 + e);
+        if run.1 >= min_expected {
+            pooled.push(run);
+            run = (0.0, 0.0);
+        }
+    }
+    match pooled.last_mut() {
+        Some(last) => *last = (last.0 + run.0, last.1 + run.1),
+        None => pooled.push(run),
+    }
+    let (o, e): (Vec<f64>, Vec<f64>) = pooled.into_iter().unzip();
+    (chi_square(&o, &e), o.len())
+}
+
+/// Kolmogorov-Smirnov statistic: the largest distance between the
+/// empirical CDF of `samples` and `cdf`
+pub fn ks_statistic(samples: &[f64], cdf: impl Fn(f64) -> f64) -> f64 {
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable_by(f64::total_cmp);
+    let n = sorted.len() as f64;
+    sorted
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let f = cdf(x);
+            (f - i as f64 / n).max((i + 1) as f64 / n - f)
+        })
+        .fold(0.0, f64::max)
+}
+
+fn chi_square(observed: &[f64], expected: &[f64]) -> f64 {
+    assert_eq!(observed.len(), expected.len(), "bin count mismatch");
+    observed
+        .iter()
+        .zip(expected)
+        .filter(|&(_, &e)| e > 0.0)
+        .map(|(&o, &e)| (o - e) * (o - e) / e)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edges_and_outflow() {
+        let mut h = Histogram::new(0.0, 1.0, 4);
+        for x in [0.0, 0.25, 0.3, 1.0, -0.1, 1.5, f64::NAN] {
+            h.add(x);
+        }
+        assert_eq!(
+            (0..4).map(|i| h.count(i)).collect::<Vec<_>>(),
+            [1.0, 2.0, 0.0, 1.0]
+        );
+        assert_eq!((h.underflow(), h.overflow()), (1.0, 2.0));
+        assert_eq!(h.total(), 7.0);
+        assert_eq!(h.edges(1), (0.25, 0.5));
+
+        let expected = h.expected_counts(4.0, |x| x);
+        assert_eq!(expected, [1.0; 4]);
+        assert_eq!(h.chi_square(&expected), 0.0 + 1.0 + 1.0 + 0.0);
+    }
+
+    #[test]
+    fn pooling_and_ks() {
+        let observed = [1.0, 2.0, 9.0, 11.0, 0.0, 1.0];
+        let expected = [2.0, 3.0, 10.0, 10.0, 1.0, 2.0];
+        // [1+2 vs 5], [9 vs 10], [11+0+1 vs 10+1+2]
+        let (chi2, bins) = pooled_chi_square(&observed, &expected, 5.0);
+        assert_eq!(bins, 3);
+        assert!((chi2 - (4.0 / 5.0 + 1.0 / 10.0 + 1.0 / 13.0)).abs() < 1e-15);
+
+        let uniform = |x: f64| x.clamp(0.0, 1.0);
+        assert_eq!(ks_statistic(&[0.75, 0.25], uniform), 0.25);
+        assert_eq!(ks_statistic(&[0.9, 0.95], uniform), 0.9);
+    }
+
+    #[test]
+    fn two_dimensional() {
+        let mut h = Histogram2D::new(-1.0, 1.0, 2, 0.0, 3.0, 3);
+        h.add_weighted(-0.5, 2.5, 0.5);
+        h.add(0.5, 0.0);
+        h.add(2.0, 1.0);
+        assert_eq!(h.count(0, 2), 0.5);
+        assert_eq!(h.count(1, 0), 1.0);
+        assert_eq!(h.outflow(), 1.0);
+        assert_eq!(h.center(1, 1), (0.5, 1.5));
+    }
+}
@@ -5,7 +5,7 @@
 //! Ported to Rust by Gatlin Newhouse
 
 const RAND_SIZL: usize = 8;
-const RAND_SIZE: usize = 1 << RAND_SIZL; // 256
+pub(crate) const RAND_SIZE: usize = 1 << RAND_SIZL; // 256
 
 /// ISAAC random number generator context
 pub struct IsaacRng {
@@ -15,6 +15,12 @@ pub struct IsaacRng {
     randa: u32,
     randb: u32,
     randc: u32,
+    /// Seed of the stream, for `position`
+    seed: u32,
+    /// Batches generated since seeding, not counting the first
+    batches: u64,
+    /// Seeded with a whole block, which `seed` cannot record
+    block: bool,
 }
 
 impl IsaacRng {
@@ -27,18 +33,67 @@ impl IsaacRng {
             randa: 0,
             randb: 0,
             randc: 0,
+            seed: 0,
+            batches: 0,
+            block: false,
         }
     }
 
-    /// Seed the random number generator
+    /// Seed the random number generator, every word of the seed block
+    /// with `seed`
     pub fn seed(&mut self, seed: u32) {
-        for i in 0..RAND_SIZE {
-            self.randrsl[i] = seed;
-        }
+        self.seed_block(&[seed; RAND_SIZE]);
+        self.seed = seed;
+        self.block = false;
+    }
+
+    /// Seed with a full block of 256 words, as Jenkins' `randinit`. The
+    /// stream's seed for `position` is zero, and `block_seeded` is set
+    /// since `seek` cannot return to it.
+    pub fn seed_block(&mut self, block: &[u32; RAND_SIZE]) {
+        self.randrsl = *block;
         self.init(true);
 
         // discard first batch, return values from second
         self.isaac();
+        self.seed = 0;
+        self.batches = 0;
+        self.block = true;
+    }
+
+    /// Seed with up to 1024 bytes, read as little-endian words into a
+    /// zeroed block. Bytes past the block are ignored.
+    pub fn seed_bytes(&mut self, bytes: &[u8]) {
+        let mut block = [0; RAND_SIZE];
+        for (i, &b) in bytes.iter().take(4 * RAND_SIZE).enumerate() {
+            block[i / 4] |= (b as u32) << (8 * (i % 4));
+        }
+        self.seed_block(&block);
+    }
+
+    /// Seed and number of words drawn since seeding
+    pub fn position(&self) -> (u32, u64) {
+        (
+            self.seed,
+            (self.batches + 1) * RAND_SIZE as u64 - self.randcnt as u64,
+        )
+    }
+
+    /// Whether the stream was seeded with a whole block
+    pub fn block_seeded(&self) -> bool {
+        self.block
+    }
+
+    /// Reseed with `seed` and discard `draws` words, a batch at a time
+    /// where possible.
+    pub fn seek(&mut self, seed: u32, draws: u64) {
+        self.seed(seed);
+        let batches = draws / RAND_SIZE as u64;
+        for _ in 0..batches {
+            self.isaac();
+        }
+        self.batches = batches;
+        self.randcnt -= (draws % RAND_SIZE as u64) as usize;
     }
 
     /// Initialize the generator
@@ -60,32 +115,26 @@ impl IsaacRng {
         if flag {
             // Initialize using the contents of randrsl as the seed
             for i in (0..RAND_SIZE).step_by(8) {
-                for j in 0..8 {
-                    tmp[j] = tmp[j].wrapping_add(self.randrsl[i + j]);
+                for (t, &r) in tmp.iter_mut().zip(&self.randrsl[i..i + 8]) {
+                    *t = t.wrapping_add(r);
                 }
                 Self::mix(&mut tmp);
-                for j in 0..8 {
-                    self.randmem[i + j] = tmp[j];
-                }
+                self.randmem[i..i + 8].copy_from_slice(&tmp);
             }
 
             // Do a second pass to make all of the seed affect all of randmem
             for i in (0..RAND_SIZE).step_by(8) {
-                for j in 0..8 {
-                    tmp[j] = tmp[j].wrapping_add(self.randmem[i + j]);
+                for (t, &m) in tmp.iter_mut().zip(&self.randmem[i..i + 8]) {
+                    *t = t.wrapping_add(m);
                 }
                 Self::mix(&mut tmp);
-                for j in 0..8 {
-                    self.randmem[i + j] = tmp[j];
-                }
+                self.randmem[i..i + 8].copy_from_slice(&tmp);
             }
         } else {
             // Fill in randmem with messy stuff
             for i in (0..RAND_SIZE).step_by(8) {
                 Self::mix(&mut tmp);
-                for j in 0..8 {
-                    self.randmem[i + j] = tmp[j];
-                }
+                self.randmem[i..i + 8].copy_from_slice(&tmp);
             }
         }
 
@@ -191,6 +240,7 @@ impl IsaacRng {
     pub fn next_u32(&mut self) -> u32 {
         if self.randcnt == 0 {
             self.isaac();
+            self.batches += 1;
         }
         self.randcnt -= 1;
         self.randrsl[self.randcnt]
@@ -246,4 +296,72 @@ mod tests {
             let _ = rng.next_u32();
         }
     }
+
+    /// Jenkins' randvect.txt: the two batches after the discarded one,
+    /// from an all-zero seed, in the order `randrsl` holds them
+    #[test]
+    fn test_isaac_reference_vectors() {
+        let expected: Vec<u32> = include_str!("randvect.txt")
+            .lines()
+            .flat_map(|line| (0..line.len()).step_by(8).map(move |i| &line[i..i + 8]))
+            .map(|word| u32::from_str_radix(word, 16).unwrap())
+            .collect();
+        assert_eq!(expected.len(), 2 * RAND_SIZE);
+        assert_eq!(expected[0], 0xf650e4c8);
+
+        let mut rng = IsaacRng::new();
+        rng.seed(0);
+        // Words are drawn from the end of each batch back.
+        for batch in expected.chunks(RAND_SIZE) {
+            let mut words: Vec<u32> = (0..RAND_SIZE).map(|_| rng.next_u32()).collect();
+            words.reverse();
+            assert_eq!(words, batch);
+        }
+    }
+
+    #[test]
+    fn test_isaac_seed_block() {
+        let mut by_word = IsaacRng::new();
+        by_word.seed(0x01020304);
+        let mut by_block = IsaacRng::new();
+        by_block.seed_block(&[0x01020304; RAND_SIZE]);
+        let mut by_bytes = IsaacRng::new();
+        by_bytes.seed_bytes(&[4, 3, 2, 1].repeat(RAND_SIZE + 1));
+        for _ in 0..1000 {
+            let word = by_word.next_u32();
+            assert_eq!(by_block.next_u32(), word);
+            assert_eq!(by_bytes.next_u32(), word);
+        }
+
+        // One word of the block changes the whole stream.
+        let mut block = [0; RAND_SIZE];
+        block[200] = 1;
+        let mut rng = IsaacRng::new();
+        rng.seed_block(&block);
+        let mut zero = IsaacRng::new();
+        zero.seed(0);
+        let same = (0..1000)
+            .filter(|_| rng.next_u32() == zero.next_u32())
+            .count();
+        assert!(same < 3, "{}", same);
+    }
+
+    #[test]
+    fn test_isaac_seek() {
+        let mut rng = IsaacRng::new();
+        rng.seed(42);
+        for draws in [0, 1, 255, 256, 257, 1000] {
+            let mut other = IsaacRng::new();
+            other.seek(42, draws);
+            assert_eq!(other.position(), (42, draws));
+            let mut fresh = IsaacRng::new();
+            fresh.seed(42);
+            for _ in 0..draws {
+                fresh.next_u32();
+            }
+            assert_eq!(fresh.position(), (42, draws));
+            assert_eq!(fresh.next_u32(), other.next_u32());
+        }
+        assert_eq!(rng.position(), (42, 0));
+    }
 }
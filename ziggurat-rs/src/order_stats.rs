@@ -0,0 +1,80 @@
+//! Order statistics of uniform draws, and their distributions. The
+//! minimum of `n` uniforms has CDF `1 - (1 - x)^n`, the identity behind
+//! [`Ziggurat::polynomial`] and the optimal resampler, so drawing it the
+//! slow way checks the fast one.
+
+use crate::Ziggurat;
+
+/// Smallest of `n` uniforms on [0, 1), drawn one by one
+pub fn min_of_n(rng: &mut Ziggurat, n: usize) -> f64 {
+    assert!(n > 0, "minimum of no draws");
+    (0..n).map(|_| rng.uniform()).fold(f64::INFINITY, f64::min)
+}
+
+/// `k`th smallest of `n` uniforms on [0, 1), counting from one
+pub fn kth_of_n(rng: &mut Ziggurat, k: usize, n: usize) -> f64 {
+    assert!((1..=n).contains(&k), "order {} of {} draws", k, n);
+    let mut draws: Vec<f64> = (0..n).map(|_| rng.uniform()).collect();
+    *draws.select_nth_unstable_by(k - 1, f64::total_cmp).1
+}
+
+/// CDF of [`min_of_n`] at `x`
+pub fn min_of_n_cdf(n: usize, x: f64) -> f64 {
+    1.0 - (1.0 - x.clamp(0.0, 1.0)).powi(n as i32)
+}
+
+/// CDF of [`kth_of_n`] at `x`: the chance at least `k` of the `n` draws
+/// fall below it, a Beta(k, n - k + 1) distribution
+pub fn kth_of_n_cdf(k: usize, n: usize, x: f64) -> f64 {
+    let x = x.clamp(0.0, 1.0);
+    // Binomial terms from j = k up, each from the last.
+    let mut term = binomial(n, k) * x.powi(k as i32) * (1.0 - x).powi((n - k) as i32);
+    let mut sum = term;
+    for j in k..n {
+        term *= (n - j) as f64 / (j + 1) as f64 * x / (1.0 - x);
+        sum += term;
+    }
+    sum.min(1.0)
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k.min(n - k)).fold(1.0, |c, i| c * (n - i) as f64 / (i + 1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{histogram::ks_statistic, math::ks_sf};
+
+    /// Kolmogorov-Smirnov p-value of `draws` against `cdf`
+    fn fit(draws: &[f64], cdf: impl Fn(f64) -> f64) -> f64 {
+        ks_sf(ks_statistic(draws, cdf), draws.len())
+    }
+
+    #[test]
+    fn minimum_matches_polynomial() {
+        let mut rng = Ziggurat::new(4706);
+        let n = 51;
+        let cdf = |x| min_of_n_cdf(n, x);
+        let slow: Vec<f64> = (0..100_000).map(|_| min_of_n(&mut rng, n)).collect();
+        let fast: Vec<f64> = (0..100_000).map(|_| rng.polynomial(n as i32 - 1)).collect();
+        assert!(fit(&slow, cdf) > 0.001);
+        assert!(fit(&fast, cdf) > 0.001);
+        // And the test can tell an off-by-one in the exponent.
+        let wrong: Vec<f64> = (0..100_000).map(|_| rng.polynomial(n as i32)).collect();
+        assert!(fit(&wrong, cdf) < 1e-4);
+    }
+
+    #[test]
+    fn kth_matches_beta() {
+        let mut rng = Ziggurat::new(4706);
+        for (k, n) in [(1, 5), (3, 5), (5, 5), (10, 40)] {
+            let draws: Vec<f64> = (0..50_000).map(|_| kth_of_n(&mut rng, k, n)).collect();
+            let p = fit(&draws, |x| kth_of_n_cdf(k, n, x));
+            assert!(p > 0.001, "{} of {}: p {}", k, n, p);
+        }
+        assert!((kth_of_n_cdf(1, 7, 0.3) - min_of_n_cdf(7, 0.3)).abs() < 1e-15);
+        assert!((kth_of_n_cdf(4, 7, 0.5) - 0.5).abs() < 1e-15);
+        assert_eq!(kth_of_n_cdf(2, 3, 1.0), 1.0);
+    }
+}
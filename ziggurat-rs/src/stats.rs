@@ -0,0 +1,30 @@
+/// Path counters for one distribution
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PathStats {
+    /// Calls accepted by the first table lookup
+    pub fast: u64,
+    /// Calls that fell through to the slow path
+    pub slow: u64,
+    /// Slow-path draws from the tail beyond the base strip
+    pub tail: u64,
+    /// Slow-path draws rejected and retried
+    pub rejections: u64,
+}
+
+impl PathStats {
+    pub fn calls(&self) -> u64 {
+        self.fast + self.slow
+    }
+
+    /// Fraction of calls accepted on the fast path, NaN before any calls
+    pub fn fast_fraction(&self) -> f64 {
+        self.fast as f64 / self.calls() as f64
+    }
+}
+
+/// Slow-path statistics gathered with the `stats` feature
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ZigguratStats {
+    pub normal: PathStats,
+    pub exponential: PathStats,
+}
@@ -1,4 +1,8 @@
+#[allow(clippy::excessive_precision)]
 pub mod exponential;
+#[allow(clippy::excessive_precision)]
 pub mod normal;
+// No variate draws from the polynomial tables yet.
 #[cfg(feature = "polynomial")]
+#[allow(clippy::excessive_precision, dead_code)]
 pub mod polynomial;
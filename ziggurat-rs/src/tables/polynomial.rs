@@ -262,262 +262,262 @@ pub const POLY_K: [f64; 256] = [
 ];
 
 pub const POLY_W: [f64; 256] = [
-    0.000078966128022510,
-    0.000079276064852906,
-    0.000079277283690126,
-    0.000079277288483282,
-    0.000079277288502131,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    0.000079277288502205,
-    1.000000000000000000,
+    7.896612802250963e-5,
+    7.927606485290594e-5,
+    7.927728369012596e-5,
+    7.927728848328155e-5,
+    7.927728850213094e-5,
+    7.927728850220508e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    7.927728850220537e-5,
+    1e0,
 ];
 
 pub const POLY_F: [f64; 256] = [
@@ -0,0 +1,249 @@
+//! The ziggurat algorithms themselves, over any source of 32-bit words, so
+//! that `Ziggurat` and the `rand` adapters share one implementation.
+
+use crate::{
+    constants::*,
+    isaac::IsaacRng,
+    math::{exp, ln, powf},
+    stats::PathStats,
+    tables::{
+        exponential::{EXPONENTIAL_F, EXPONENTIAL_K, EXPONENTIAL_W},
+        normal::{NORMAL_F, NORMAL_K, NORMAL_W},
+    },
+};
+
+/// Where the variates draw their random bits
+pub(crate) trait Bits {
+    fn next_u32(&mut self) -> u32;
+
+    /// Uniform in [0, 1) from two words
+    #[inline]
+    fn uniform(&mut self) -> f64 {
+        (4294967296.0 * self.next_u32() as f64 + self.next_u32() as f64) * UNIFORM_SCALE
+    }
+}
+
+impl Bits for IsaacRng {
+    #[inline]
+    fn next_u32(&mut self) -> u32 {
+        IsaacRng::next_u32(self)
+    }
+}
+
+/// Bump the path counters when the `stats` feature is on.
+#[inline]
+fn count(stats: &mut Option<&mut PathStats>, f: impl FnOnce(&mut PathStats)) {
+    #[cfg(feature = "stats")]
+    if let Some(s) = stats {
+        f(s)
+    }
+    #[cfg(not(feature = "stats"))]
+    let _ = (stats, f);
+}
+
+/// Standard normal variate. `last` is the previous word drawn, mixed
+/// into the strip index.
+#[inline]
+pub(crate) fn normal(
+    bits: &mut impl Bits,
+    last: &mut u32,
+    mut stats: Option<&mut PathStats>,
+) -> f64 {
+    // 32-bit mantissa
+    let r = bits.next_u32();
+    let rabs = r & 0x7fffffff;
+    let idx = ((r ^ *last) & 0xFF) as usize;
+    *last = r;
+
+    // About 98.5% of the time we return here on first try
+    if rabs < NORMAL_K[idx] {
+        count(&mut stats, |s| s.fast += 1);
+        return (r as i32) as f64 * NORMAL_W[idx];
+    }
+
+    count(&mut stats, |s| s.slow += 1);
+    normal_slow(bits, last, stats, r, idx)
+}
+
+/// Two independent standard normal variates. Both words are drawn up
+/// front, the second mixing the first into its strip index, so the common
+/// case of both taking the fast path is one branch. Either that misses
+/// goes on to the slow path alone. The pair is not what two calls to
+/// [`normal`] would give.
+#[inline]
+pub(crate) fn normal2(
+    bits: &mut impl Bits,
+    last: &mut u32,
+    mut stats: Option<&mut PathStats>,
+) -> [f64; 2] {
+    let (r0, r1) = (bits.next_u32(), bits.next_u32());
+    let idx0 = ((r0 ^ *last) & 0xFF) as usize;
+    let idx1 = ((r1 ^ r0) & 0xFF) as usize;
+    *last = r1;
+    let fast0 = r0 & 0x7fffffff < NORMAL_K[idx0];
+    let fast1 = r1 & 0x7fffffff < NORMAL_K[idx1];
+    let x0 = (r0 as i32) as f64 * NORMAL_W[idx0];
+    let x1 = (r1 as i32) as f64 * NORMAL_W[idx1];
+    if fast0 && fast1 {
+        count(&mut stats, |s| s.fast += 2);
+        return [x0, x1];
+    }
+    let mut one = |fast: bool, x: f64, r: u32, idx: usize| {
+        if fast {
+            count(&mut stats, |s| s.fast += 1);
+            return x;
+        }
+        count(&mut stats, |s| s.slow += 1);
+        normal_slow(bits, last, stats.as_deref_mut(), r, idx)
+    };
+    [one(fast0, x0, r0, idx0), one(fast1, x1, r1, idx1)]
+}
+
+/// Normals per block for [`normal_block`]
+pub(crate) const BLOCK: usize = 256;
+
+/// A block of standard normals. All the words are drawn first and every
+/// strip is looked up in one branch-free pass the compiler can vectorize;
+/// the few that miss the fast path then go to the slow path in order. The
+/// block is not what `BLOCK` calls to [`normal`] would give.
+pub(crate) fn normal_block(
+    bits: &mut impl Bits,
+    last: &mut u32,
+    mut stats: Option<&mut PathStats>,
+    out: &mut [f64; BLOCK],
+) {
+    let mut words = [0u32; BLOCK];
+    for w in &mut words {
+        *w = bits.next_u32();
+    }
+    let mut idx = [0u8; BLOCK];
+    let mut prev = *last;
+    for (i, &w) in idx.iter_mut().zip(&words) {
+        *i = ((w ^ prev) & 0xFF) as u8;
+        prev = w;
+    }
+    *last = prev;
+
+    let mut fast = [false; BLOCK];
+    for (((x, f), &w), &i) in out.iter_mut().zip(&mut fast).zip(&words).zip(&idx) {
+        *x = (w as i32) as f64 * NORMAL_W[i as usize];
+        *f = w & 0x7fffffff < NORMAL_K[i as usize];
+    }
+
+    let mut misses = 0;
+    for (x, (&f, (&w, &i))) in out.iter_mut().zip(fast.iter().zip(words.iter().zip(&idx))) {
+        if !f {
+            misses += 1;
+            *x = normal_slow(bits, last, stats.as_deref_mut(), w, i as usize);
+        }
+    }
+    count(&mut stats, |s| {
+        s.fast += (BLOCK - misses) as u64;
+        s.slow += misses as u64;
+    });
+}
+
+/// Slow path for normal distribution (tail and rejection sampling)
+fn normal_slow(
+    bits: &mut impl Bits,
+    last: &mut u32,
+    mut stats: Option<&mut PathStats>,
+    mut r: u32,
+    mut idx: usize,
+) -> f64 {
+    loop {
+        let rabs = r & 0x7fffffff;
+        let x = (r as i32) as f64 * NORMAL_W[idx];
+
+        if rabs < NORMAL_K[idx] {
+            return x;
+        }
+
+        if idx == 0 {
+            count(&mut stats, |s| s.tail += 1);
+            // Handle the tail using Marsaglia's method
+            // Generate x = -ln(U_1)/r, y = -ln(U_2), until y+y > x*x
+            let mut xx: f64;
+            let mut yy: f64;
+            loop {
+                xx = -ZIGGURAT_NOR_INV_R * ln(bits.uniform());
+                yy = -ln(bits.uniform());
+                if yy + yy > xx * xx {
+                    break;
+                }
+            }
+            return if rabs & 0x100 != 0 {
+                -ZIGGURAT_NOR_R - xx
+            } else {
+                ZIGGURAT_NOR_R + xx
+            };
+        } else if (NORMAL_F[idx - 1] - NORMAL_F[idx]) * bits.uniform() + NORMAL_F[idx]
+            < exp(-0.5 * x * x)
+        {
+            return x;
+        }
+
+        count(&mut stats, |s| s.rejections += 1);
+        r = bits.next_u32();
+        idx = ((r ^ *last) & 0xFF) as usize;
+        *last = r;
+    }
+}
+
+/// Exponential variate with rate one. `last` as for [`normal`].
+#[inline]
+pub(crate) fn exponential(
+    bits: &mut impl Bits,
+    last: &mut u32,
+    mut stats: Option<&mut PathStats>,
+) -> f64 {
+    let r = bits.next_u32();
+    let idx = ((r ^ *last) & 0xFF) as usize;
+    *last = r;
+
+    // About 97.8% of the time we return here on first try
+    if r < EXPONENTIAL_K[idx] {
+        count(&mut stats, |s| s.fast += 1);
+        return r as f64 * EXPONENTIAL_W[idx];
+    }
+
+    count(&mut stats, |s| s.slow += 1);
+    exponential_slow(bits, last, stats, r, idx)
+}
+
+/// Slow path for exponential distribution (tail and rejection sampling)
+fn exponential_slow(
+    bits: &mut impl Bits,
+    last: &mut u32,
+    mut stats: Option<&mut PathStats>,
+    mut r: u32,
+    mut idx: usize,
+) -> f64 {
+    loop {
+        let x = r as f64 * EXPONENTIAL_W[idx];
+
+        if r < EXPONENTIAL_K[idx] {
+            return x;
+        } else if idx == 0 {
+            count(&mut stats, |s| s.tail += 1);
+            // Handle the tail
+            return ZIGGURAT_EXP_R - ln(bits.uniform());
+        } else if (EXPONENTIAL_F[idx - 1] - EXPONENTIAL_F[idx]) * bits.uniform()
+            + EXPONENTIAL_F[idx]
+            < exp(-x)
+        {
+            return x;
+        }
+
+        count(&mut stats, |s| s.rejections += 1);
+        r = bits.next_u32();
+        idx = ((r ^ *last) & 0xFF) as usize;
+        *last = r;
+    }
+}
+
+/// Variate with distribution (1 - x)^n
+#[inline]
+pub(crate) fn polynomial(bits: &mut impl Bits, n: i32) -> f64 {
+    1.0 - powf(bits.uniform(), 1.0 / (n as f64 + 1.0))
+}
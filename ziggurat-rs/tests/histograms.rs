@@ -0,0 +1,75 @@
+//! The `normaltest` and `polytest` examples as tests: histograms of the
+//! variates held to their distributions by a chi-square bound instead of
+//! by eye.
+
+use ziggurat_rs::{Ziggurat, histogram::Histogram, math::norm_cdf};
+
+const NV: usize = 1_000_000;
+
+/// Chi-square of `NV` draws from `sample` binned over `[lo, hi]` against
+/// `cdf`, with its degrees of freedom
+fn chi_square(
+    mut sample: impl FnMut() -> f64,
+    (lo, hi, nbins): (f64, f64, usize),
+    cdf: impl Fn(f64) -> f64,
+) -> (f64, f64) {
+    let mut hist = Histogram::new(lo, hi, nbins);
+    for _ in 0..NV {
+        hist.add(sample());
+    }
+    let expected = hist.expected_counts(NV as f64, cdf);
+    (hist.chi_square(&expected), (nbins - 1) as f64)
+}
+
+/// Six standard deviations above the mean of a chi-square with `dof`
+/// degrees of freedom, far past anything a fixed seed will see by chance
+fn bound(dof: f64) -> f64 {
+    dof + 6.0 * (2.0 * dof).sqrt()
+}
+
+/// Chi-square of the normal over `[lo, hi]` standard deviations, against
+/// the right scale and against one 1% too wide, which the bound must catch
+fn normal_fits(lo: f64, hi: f64) {
+    for sigma in [1.0, 2.5] {
+        let range = (lo * sigma, hi * sigma, 40);
+        let mut rng = Ziggurat::new(4704);
+        let (chi2, dof) = chi_square(|| rng.gaussian(sigma), range, |x| norm_cdf(x / sigma));
+        assert!(chi2 < bound(dof), "sigma {}: chi-square {}", sigma, chi2);
+
+        let mut rng = Ziggurat::new(4704);
+        let wide = |x| norm_cdf(x / (1.01 * sigma));
+        let (chi2, dof) = chi_square(|| rng.gaussian(sigma), range, wide);
+        assert!(chi2 > bound(dof), "sigma {}: chi-square {}", sigma, chi2);
+    }
+}
+
+#[test]
+fn normal_histogram() {
+    normal_fits(0.0, 4.0);
+}
+
+/// A negative word's variate is `(r - 2^31) w` rather than `-r w`, as in
+/// the C implementation the generator matches draw for draw, which piles
+/// the lower half toward each strip's outer edge: [-3.9, -3.65] gets
+/// about twice its share.
+#[test]
+#[ignore = "the lower half is skewed, as in the C reference"]
+fn normal_histogram_lower_half() {
+    normal_fits(-4.0, 0.0);
+}
+
+#[test]
+fn polynomial_histogram() {
+    for n in [1, 51] {
+        let mut rng = Ziggurat::new(4704);
+        let range = (0.0, (4.0 / (n as f64 + 1.0)).min(1.0), 60);
+        let cdf = |x: f64| 1.0 - (1.0 - x).powi(n + 1);
+        let (chi2, dof) = chi_square(|| rng.polynomial(n), range, cdf);
+        assert!(chi2 < bound(dof), "n {}: chi-square {}", n, chi2);
+
+        let mut rng = Ziggurat::new(4704);
+        let wrong = |x: f64| 1.0 - (1.0 - x).powi(n + 2);
+        let (chi2, dof) = chi_square(|| rng.polynomial(n), range, wrong);
+        assert!(chi2 > bound(dof), "n {}: chi-square {}", n, chi2);
+    }
+}